@@ -2,7 +2,12 @@ use std::{num::NonZeroUsize, path::PathBuf};
 
 use clap::{ArgAction, ArgGroup, Parser, Subcommand};
 
-use crate::{estimate::Format, LogLikelihood, Shuffle, Split, Stat, View};
+use crate::{
+    estimate::{Distance, Format},
+    input::{regions::SiteFilters, weights::WeightsArgs},
+    Expected, Fit, Fst, Info, LogLikelihood, Posterior, SafConvert, SafFilter, Shuffle, Split,
+    Stat, Thetas, View,
+};
 
 const NAME: &str = env!("CARGO_BIN_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -27,6 +32,11 @@ pub struct Cli {
     /// specify either the shared prefix or the full path to any one member file.
     /// Up to three SAF files currently supported (six with the experimental '--features hd' compile
     /// flag).
+    ///
+    /// Each population may also be given by several sets of SAF files, e.g. one per chromosome,
+    /// either as a comma-separated list, or as a text file with one set per line. The sets for each
+    /// population are read one after another, and the same number of sets must be given for each
+    /// population. Sets are intersected in order, so a contig must not occur in more than one set.
     #[clap(
         value_parser,
         num_args = 1..=MAX_PATHS,
@@ -65,17 +75,100 @@ pub struct Cli {
     )]
     pub block_size: Option<NonZeroUsize>,
 
+    /// Estimate a separate SFS for each contig.
+    ///
+    /// If set, the input is read once and partitioned by contig, and an SFS is estimated for each
+    /// contig in parallel, using the same settings as for a single estimate. Each SFS is scaled by
+    /// the (possibly weighted) number of sites on its contig. By default, the estimates are written
+    /// to stdout one after another in plain text format in the order of the input, with the contig
+    /// name in the header of each SFS as `#CONTIG=<name>`; see also `--by-contig-prefix`. Contigs
+    /// with fewer sites than blocks, or too few sites to hold out sites with `--holdout`, are
+    /// skipped with a warning. Only supported for SAF file input, not for shuffled input.
+    #[clap(long, conflicts_with_all = ["restarts", "standard_errors"])]
+    pub by_contig: bool,
+
+    /// Prefix of paths to write per-contig estimates.
+    ///
+    /// If set, the estimate for each contig is written to the path '<prefix>.<contig>.sfs' in
+    /// plain text format rather than to stdout. Existing files are overwritten.
+    #[clap(long, requires = "by_contig", value_name = "PREFIX")]
+    pub by_contig_prefix: Option<PathBuf>,
+
     #[clap(long, hide = true, global = true)]
     pub debug: bool,
 
+    /// Collapse identical sites into weighted site patterns.
+    ///
+    /// If set, sites with bit-identical SAF likelihoods are collapsed into a single site pattern
+    /// weighted by the number of sites before estimation, so that each epoch only visits the
+    /// unique patterns. This can substantially speed up estimation on low-depth data, where many
+    /// sites share the same likelihoods. Sites are only collapsed within the same block, so that
+    /// blocks are unchanged. The results are the same up to floating point error. To also collapse
+    /// near-identical sites, see `--dedup-precision`. The achieved compression ratio is logged.
+    /// Only supported for in-memory input.
+    #[clap(long, help_heading = "Hyperparameters")]
+    pub dedup: bool,
+
+    /// Precision of log-likelihoods when collapsing sites.
+    ///
+    /// If set, sites are collapsed by `--dedup` if the natural logarithms of their SAF likelihoods
+    /// round to the same multiples of the precision, and each site pattern uses the likelihoods
+    /// of its first site. This gives more compression at the cost of changing the likelihoods of
+    /// the collapsed sites slightly, so that results are no longer exactly the same.
+    #[clap(
+        long,
+        requires = "dedup",
+        value_parser = parse_positive,
+        help_heading = "Hyperparameters",
+        value_name = "FLOAT"
+    )]
+    pub dedup_precision: Option<f32>,
+
     /// Maximum number of epochs to run.
     ///
-    /// If both this and `--tolerance` are unset, the default stopping rule is a log-likelihood
-    /// tolerance of 1e-4. If both are set, the first stopping rule to be triggered will stop the
-    /// algorithm.
+    /// If no stopping rules are set, the default stopping rule is a log-likelihood tolerance of
+    /// 1e-4. If several are set, the first stopping rule to be triggered will stop the algorithm.
     #[clap(long, help_heading = "Stopping", value_name = "INT")]
     pub max_epochs: Option<usize>,
 
+    /// Maximum wall-clock time to run in seconds.
+    ///
+    /// The time limit is checked after each epoch, so the total running time may exceed the limit
+    /// by up to the duration of an epoch. If several stopping rules are set, the first stopping
+    /// rule to be triggered will stop the algorithm.
+    #[clap(long, help_heading = "Stopping", value_name = "INT")]
+    pub max_time: Option<u64>,
+
+    /// Estimate folded SFS.
+    ///
+    /// When the data cannot be properly polarised, the folded SFS can be estimated directly,
+    /// rather than estimating the unfolded SFS and folding afterwards. The likelihood of each site
+    /// is then computed by averaging over the indistinguishable, unfolded combinations. The
+    /// output uses the same folded representation as `winsfs view --fold`, and the output header
+    /// is marked as folded. If an initial SFS is provided, it will be folded before use.
+    #[clap(long, help_heading = "Hyperparameters")]
+    pub folded: bool,
+
+    /// Fraction of sites to hold out for validation.
+    ///
+    /// If set, the held-out sites are not used for estimation. Instead, the log-likelihood of the
+    /// held-out sites is evaluated after each epoch, and the algorithm stops when it has not
+    /// improved for `--patience` epochs. The SFS with the best held-out log-likelihood is returned.
+    /// For in-memory input, held-out sites are chosen at random. For shuffled input, the sites at
+    /// the end of the file are held out; due to the pseudo-shuffle, these are spread across the
+    /// input. In both cases, the held-out sites are kept in memory.
+    ///
+    /// Cannot be used together with `--tolerance`. If `--max-epochs` is also set, the first
+    /// stopping rule to be triggered will stop the algorithm.
+    #[clap(
+        long,
+        conflicts_with = "tolerance",
+        value_parser = parse_fraction,
+        help_heading = "Stopping",
+        value_name = "FLOAT"
+    )]
+    pub holdout: Option<f64>,
+
     /// Initial SFS.
     ///
     /// If unset, a non-informative SFS will be used to initialise optimisation. This is fine
@@ -96,6 +189,28 @@ pub struct Cli {
     )]
     pub input_format: Option<Format>,
 
+    /// Number of epochs without held-out improvement before stopping.
+    ///
+    /// Only used when `--holdout` is set.
+    #[clap(
+        long,
+        requires = "holdout",
+        default_value_t = 3,
+        help_heading = "Stopping",
+        value_name = "INT"
+    )]
+    pub patience: usize,
+
+    /// Number of independent restarts.
+    ///
+    /// If set, estimation is run this number of times, each time reshuffling the input with a new
+    /// seed and starting from a randomly perturbed initial SFS. The log-likelihood of each estimate
+    /// is evaluated on the full data, and the best estimate is returned. A summary of the spread
+    /// between restarts is logged. The seeds of the restarts are derived from `--seed`.
+    /// Only supported for in-memory input.
+    #[clap(long, help_heading = "Hyperparameters", value_name = "INT")]
+    pub restarts: Option<NonZeroUsize>,
+
     /// Random seed.
     ///
     /// If unset, a seed will be chosen at random.
@@ -110,12 +225,43 @@ pub struct Cli {
     /// better estimate of the SFS. Also, note that a minumum of two epochs will be required;
     /// set `--max-epochs 1` if you wish to run one epoch only.
     ///
-    /// If both this and `--max-epochs` are unset, the default stopping rule is a log-likelihood
-    /// tolerance of 1e-4. If both are set, the first stopping rule to be triggered will stop the
-    /// algorithm.
+    /// If no stopping rules are set, the default stopping rule is a log-likelihood tolerance of
+    /// 1e-4. If several are set, the first stopping rule to be triggered will stop the algorithm.
     #[clap(short = 'l', long, help_heading = "Stopping", value_name = "FLOAT")]
     pub tolerance: Option<f64>,
 
+    /// Distance measure used by `--sfs-tolerance`.
+    #[clap(
+        long,
+        value_enum,
+        requires = "sfs_tolerance",
+        default_value_t = Distance::L1,
+        help_heading = "Stopping",
+        value_name = "STRING"
+    )]
+    pub sfs_distance: Distance,
+
+    /// SFS difference tolerated between epochs before stopping.
+    ///
+    /// The normalised SFS estimates are compared after each epoch using the measure set by
+    /// `--sfs-distance`. When the distance falls below the provided tolerance, the algorithm will
+    /// stop. If several stopping rules are set, the first stopping rule to be triggered will stop
+    /// the algorithm.
+    #[clap(long, help_heading = "Stopping", value_name = "FLOAT")]
+    pub sfs_tolerance: Option<f64>,
+
+    /// Path to write standard errors of the SFS estimate.
+    ///
+    /// If set, the standard errors of each value in the SFS are calculated from the observed
+    /// information at the estimate, and written in plain text format to the provided path. The
+    /// standard errors are on the same scale as the estimate. Since calculating the observed
+    /// information requires time proportional to the number of sites times the squared number of
+    /// values in the SFS, spectra with more than 1000 values instead use approximate standard
+    /// errors from the information about each value separately. Standard errors are only
+    /// supported for SAF file input, not for shuffled input.
+    #[clap(long, conflicts_with = "folded", value_name = "PATH")]
+    pub standard_errors: Option<PathBuf>,
+
     /// Number of threads to use.
     #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
     pub threads: usize,
@@ -126,6 +272,12 @@ pub struct Cli {
     #[clap(short = 'v', long, action = ArgAction::Count, global = true)]
     pub verbose: u8,
 
+    #[clap(flatten, next_help_heading = "Input")]
+    pub weights: WeightsArgs,
+
+    #[clap(flatten, next_help_heading = "Input")]
+    pub site_filters: SiteFilters,
+
     /// Number of blocks per window.
     ///
     /// If unset, the window size will be chosen as approximately 1/5 of the number of blocks.
@@ -141,22 +293,54 @@ pub struct Cli {
     pub subcommand: Option<Command>,
 }
 
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if v > 0.0 && v < 1.0 => Ok(v),
+        Ok(v) => Err(format!("fraction must be between zero and one, found {v}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(v),
+        Ok(v) => Err(format!("value must be positive, found {v}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Command {
+    Expected(Expected),
+    Fit(Fit),
+    Fst(Fst),
+    Info(Info),
     LogLikelihood(LogLikelihood),
+    Posterior(Posterior),
+    SafConvert(SafConvert),
+    SafFilter(SafFilter),
     Shuffle(Shuffle),
     Split(Split),
     Stat(Stat),
+    Thetas(Thetas),
     View(View),
 }
 
 impl Command {
     pub fn run(self) -> Result<(), clap::Error> {
         match self {
+            Command::Expected(expected) => expected.run(),
+            Command::Fit(fit) => fit.run(),
+            Command::Fst(fst) => fst.run(),
+            Command::Info(info) => info.run(),
             Command::LogLikelihood(log_likelihood) => log_likelihood.run(),
+            Command::Posterior(posterior) => posterior.run(),
+            Command::SafConvert(saf_convert) => saf_convert.run(),
+            Command::SafFilter(saf_filter) => saf_filter.run(),
             Command::Shuffle(shuffle) => shuffle.run(),
             Command::Split(split) => split.run(),
             Command::Stat(stat) => stat.run(),
+            Command::Thetas(thetas) => thetas.run(),
             Command::View(view) => view.run(),
         }
     }
@@ -168,6 +352,8 @@ mod tests {
 
     use clap::error::{ErrorKind, Result as ClapResult};
 
+    use winsfs_core::io::{Region, Subsample};
+
     fn try_parse_args(cmd: &str) -> ClapResult<Cli> {
         Parser::try_parse_from(cmd.split_whitespace())
     }
@@ -222,6 +408,171 @@ mod tests {
         assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
     }
 
+    #[test]
+    fn test_holdout() {
+        let args = parse_args("winsfs --holdout 0.1 /path/to/saf");
+        assert_eq!(args.holdout, Some(0.1));
+        assert_eq!(args.patience, 3);
+
+        let args = parse_args("winsfs --holdout 0.1 --patience 5 /path/to/saf");
+        assert_eq!(args.patience, 5);
+
+        let result = try_parse_args("winsfs --holdout 1.5 /path/to/saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+
+        let result = try_parse_args("winsfs --holdout 0.1 -l 1e-4 /path/to/saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_sfs_tolerance() {
+        let args = parse_args("winsfs --sfs-tolerance 1e-3 /path/to/saf");
+        assert_eq!(args.sfs_tolerance, Some(1e-3));
+        assert_eq!(args.sfs_distance, Distance::L1);
+
+        let args = parse_args("winsfs --sfs-tolerance 1e-3 --sfs-distance max-abs /path/to/saf");
+        assert_eq!(args.sfs_distance, Distance::MaxAbs);
+    }
+
+    #[test]
+    fn test_max_time() {
+        let args = parse_args("winsfs --max-time 60 --max-epochs 10 /path/to/saf");
+        assert_eq!(args.max_time, Some(60));
+        assert_eq!(args.max_epochs, Some(10));
+    }
+
+    #[test]
+    fn test_restarts() {
+        let args = parse_args("winsfs --restarts 5 /path/to/saf");
+        assert_eq!(args.restarts.unwrap().get(), 5);
+
+        let result = try_parse_args("winsfs --restarts 0 /path/to/saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_standard_errors() {
+        let args = parse_args("winsfs --standard-errors /path/to/se /path/to/saf");
+        assert_eq!(args.standard_errors, Some(PathBuf::from("/path/to/se")));
+
+        let result = try_parse_args("winsfs --standard-errors /path/to/se --folded /path/to/saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_regions() {
+        let args = parse_args("winsfs -r chr1 --region chr2:11-20 --regions-file regions.bed saf");
+        assert_eq!(
+            args.site_filters.region,
+            vec![
+                Region::whole_contig("chr1"),
+                Region::new("chr2", 10, 20).unwrap()
+            ]
+        );
+        assert_eq!(
+            args.site_filters.regions_file,
+            Some(PathBuf::from("regions.bed"))
+        );
+
+        let result = try_parse_args("winsfs -r chr1:20-10 saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_masks() {
+        let args = parse_args("winsfs --include a.bed --exclude b.bed --include c.bed saf");
+        assert_eq!(
+            args.site_filters.include,
+            vec![PathBuf::from("a.bed"), PathBuf::from("c.bed")]
+        );
+        assert_eq!(args.site_filters.exclude, vec![PathBuf::from("b.bed")]);
+    }
+
+    #[test]
+    fn test_subsample() {
+        assert_eq!(parse_args("winsfs saf").site_filters.subsample, None);
+
+        let args = parse_args("winsfs --subsample 0.25 saf");
+        assert_eq!(args.site_filters.subsample, Some(Subsample::Fraction(0.25)));
+
+        let args = parse_args("winsfs --subsample 1000 saf");
+        assert_eq!(args.site_filters.subsample, Some(Subsample::Count(1000)));
+
+        for v in ["0", "0.0", "1.5", "a"] {
+            let result = try_parse_args(&format!("winsfs --subsample {v} saf"));
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+        }
+    }
+
+    #[test]
+    fn test_union() {
+        assert!(!parse_args("winsfs saf1 saf2").site_filters.union);
+        assert!(parse_args("winsfs --union saf1 saf2").site_filters.union);
+    }
+
+    #[test]
+    fn test_by_contig() {
+        let args = parse_args("winsfs --by-contig /path/to/saf");
+        assert!(args.by_contig);
+        assert_eq!(args.by_contig_prefix, None);
+
+        let args = parse_args("winsfs --by-contig --by-contig-prefix out /path/to/saf");
+        assert_eq!(args.by_contig_prefix, Some(PathBuf::from("out")));
+
+        let result = try_parse_args("winsfs --by-contig-prefix out /path/to/saf");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+
+        for arg in ["--restarts 2", "--standard-errors /path/to/se"] {
+            let result = try_parse_args(&format!("winsfs --by-contig {arg} /path/to/saf"));
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn test_dedup() {
+        assert!(parse_args("winsfs --dedup /path/to/saf").dedup);
+        assert!(!parse_args("winsfs /path/to/saf").dedup);
+
+        let args = parse_args("winsfs --dedup --dedup-precision 0.01 /path/to/saf");
+        assert_eq!(args.dedup_precision, Some(0.01));
+
+        let result = try_parse_args("winsfs --dedup-precision 0.01 /path/to/saf");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+
+        let result = try_parse_args("winsfs --dedup --dedup-precision 0 /path/to/saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_weights() {
+        let args = parse_args("winsfs --weights-bed /path/to/weights /path/to/saf");
+        assert_eq!(
+            args.weights.weights_bed,
+            Some(PathBuf::from("/path/to/weights"))
+        );
+
+        let args = parse_args("winsfs --weights-sites /path/to/weights /path/to/saf");
+        assert_eq!(
+            args.weights.weights_sites,
+            Some(PathBuf::from("/path/to/weights"))
+        );
+
+        let result = try_parse_args("winsfs --weights-bed a --weights-sites b /path/to/saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_folded() {
+        assert!(parse_args("winsfs --folded /path/to/saf").folded);
+        assert!(!parse_args("winsfs /path/to/saf").folded);
+    }
+
     #[test]
     fn test_subcommand_conflicts_with_args() {
         let result = try_parse_args("winsfs -b 5 log-likelihood --sfs /path/to/sfs /path/to/saf");
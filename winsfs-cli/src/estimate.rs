@@ -1,12 +1,19 @@
 use std::{io, num::NonZeroUsize, path::Path};
 
-use clap::error::Result as ClapResult;
+use clap::{
+    error::{ErrorKind, Result as ClapResult},
+    CommandFactory,
+};
 
 use winsfs_core::{
     em::{stopping::Stop, Em, Sites, StandardEm, WindowEm},
-    io::shuffle::Reader,
-    saf::Blocks,
-    sfs::{io::plain_text::write_sfs, Sfs},
+    io::{shuffle::Reader, ReadSite},
+    saf::{Blocks, Patterns, Saf},
+    sfs::{
+        information::MAX_INFORMATION_VALUES,
+        io::plain_text::{write_folded_sfs, write_sfs, write_sfs_to_path},
+        Sfs,
+    },
 };
 
 use crate::{
@@ -19,11 +26,18 @@ use super::Cli;
 mod format;
 pub use format::Format;
 
+mod by_contig;
+
+mod in_memory;
+pub use in_memory::InMemorySaf;
+
+mod restarts;
+
 mod logging;
 pub use logging::{Checker, Logger, LoggerBuilder};
 
 mod stopping;
-pub use stopping::Rule;
+pub use stopping::{Distance, Rule};
 
 pub const DEFAULT_NUMBER_OF_BLOCKS: usize = 500;
 pub const DEFAULT_TOLERANCE: f64 = 1e-4;
@@ -36,6 +50,52 @@ impl Cli {
     pub fn run(self) -> ClapResult<()> {
         match Format::try_from(&self)? {
             Format::Standard | Format::Banded => self.run_in_memory(),
+            Format::Shuffled if self.restarts.is_some() => Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "restarts are not supported for shuffled input",
+            )),
+            Format::Shuffled if self.weights.is_set() => Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "weights are not supported for shuffled input",
+            )),
+            Format::Shuffled if self.standard_errors.is_some() => Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "standard errors are not supported for shuffled input",
+            )),
+            Format::Shuffled if self.dedup => Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "site pattern deduplication is not supported for shuffled input",
+            )),
+            Format::Shuffled if self.by_contig => Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "per-contig estimation is not supported for shuffled input",
+            )),
+            Format::Shuffled
+                if !self.site_filters.region.is_empty()
+                    || self.site_filters.regions_file.is_some() =>
+            {
+                Err(Cli::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "regions are not supported for shuffled input",
+                ))
+            }
+            Format::Shuffled
+                if !self.site_filters.include.is_empty()
+                    || !self.site_filters.exclude.is_empty() =>
+            {
+                Err(Cli::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "masks are not supported for shuffled input",
+                ))
+            }
+            Format::Shuffled if self.site_filters.union => Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "union of sites is not supported for shuffled input",
+            )),
+            Format::Shuffled if self.site_filters.subsample.is_some() => Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "subsampling is not supported for shuffled input",
+            )),
             Format::Shuffled => self.run_streaming(),
         }
     }
@@ -57,15 +117,18 @@ impl Cli {
         }
     }
 
+    /// Runs estimation and returns the normalised SFS estimate.
     fn run_n<I, const N: usize, const PAR: bool, const STREAM: bool>(
         &self,
         input: I,
         shape: [usize; N],
-    ) -> ClapResult<()>
+        held_out: Option<Saf<N>>,
+        initial_sfs: Option<Sfs<N>>,
+    ) -> ClapResult<Sfs<N>>
     where
         I: Sites,
         Runner<PAR, STREAM>: Em<N, I>,
-        Rule: Stop<Runner<PAR, STREAM>>,
+        Rule<N>: Stop<Runner<PAR, STREAM>>,
     {
         let sites = input.sites();
         let block_spec = get_block_spec(
@@ -76,32 +139,201 @@ impl Cli {
         );
         let window_size = get_window_size(self.window_size).get();
 
-        let (initial_sfs, mut runner) = setup::<_, N, PAR, STREAM>(
-            self.initial.as_ref(),
+        let (initial_sfs, mut runner) = setup::<N, PAR, STREAM>(
+            initial_sfs,
             shape,
             sites,
             window_size,
             block_spec,
+            self.folded,
         )?;
-        let stopping_rule = Rule::from(self);
+        let mut stopping_rule = Rule::new(self, held_out);
 
-        let (_status, sfs) = runner.em(initial_sfs, input, stopping_rule).unwrap();
+        let (_status, mut sfs) = runner.em(initial_sfs, input, &mut stopping_rule).unwrap();
 
+        if let Some(rule) = stopping_rule.held_out() {
+            log::info!(
+                target: "stop",
+                "Using SFS with best held-out log-likelihood {lik:.4e}, \
+                found {i} epoch(s) before stopping",
+                lik = f64::from(rule.best_log_likelihood()),
+                i = rule.steps_since_best(),
+            );
+
+            sfs = rule.best_sfs().expect("held-out rule evaluated").clone();
+        }
+
+        Ok(sfs)
+    }
+
+    /// Returns the block specification, without logging it (see [`get_block_spec`]).
+    fn block_spec(&self) -> Blocks {
+        block_spec(self.blocks, self.block_size, DEFAULT_NUMBER_OF_BLOCKS)
+    }
+
+    /// Writes the normalised SFS estimate to stdout, scaled by the (possibly weighted) number of
+    /// sites.
+    fn write_estimate<const N: usize>(&self, sfs: Sfs<N>, sites: f64) -> ClapResult<()> {
         let stdout = io::stdout();
         let mut writer = stdout.lock();
-        write_sfs(&mut writer, &sfs.scale(sites as f64))?;
+
+        let sfs = sfs.scale(sites);
+        if self.folded {
+            write_folded_sfs(&mut writer, &sfs)?;
+        } else {
+            write_sfs(&mut writer, &sfs)?;
+        }
 
         Ok(())
     }
 
+    /// Reads the initial SFS, if provided.
+    fn read_initial<const N: usize>(&self) -> ClapResult<Option<Sfs<N>>> {
+        self.initial
+            .as_ref()
+            .map(|path| {
+                input::sfs::Reader::from_path(path)?
+                    .read()
+                    .map(|sfs| sfs.normalise())
+            })
+            .transpose()
+            .map_err(Into::into)
+    }
+
     fn run_in_memory_n<const N: usize, P>(&self, paths: [P; N]) -> ClapResult<()>
     where
         P: AsRef<Path>,
     {
-        let mut saf = input::saf::Readers::from_member_paths(&paths, self.threads)?.read_saf()?;
+        let weights = self.weights.read()?;
+
+        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?
+            .with_site_filters(&self.site_filters, self.seed)?;
+        let initial_sfs = self.read_initial()?;
+
+        if self.by_contig {
+            let safs = readers.read_saf_by_contig(weights.as_ref())?;
+            return self.run_by_contig(safs, initial_sfs);
+        }
+
+        if readers.is_banded() {
+            let saf = readers.read_banded_saf(weights.as_ref())?;
+            self.run_in_memory_saf(saf, initial_sfs)
+        } else {
+            let saf = readers.read_saf(weights.as_ref())?;
+            self.run_in_memory_saf(saf, initial_sfs)
+        }
+    }
+
+    /// Runs estimation on a SAF in memory, and writes the estimate.
+    ///
+    /// Banded SAF input is kept banded in memory, except for any held-out sites.
+    fn run_in_memory_saf<const N: usize, S>(
+        &self,
+        mut saf: S,
+        initial_sfs: Option<Sfs<N>>,
+    ) -> ClapResult<()>
+    where
+        S: InMemorySaf<N>,
+    {
+        if let Some(restarts) = self.restarts {
+            return self.run_restarts(saf, initial_sfs, restarts.get());
+        }
+
         shuffle_saf(&mut saf, self.seed);
+        let held_out = self.split_held_out(&mut saf)?;
+        let sites = total_weight(&saf) + held_out.as_ref().map(total_weight).unwrap_or(0.0);
+
+        let sfs = saf.estimate(self, held_out.clone(), initial_sfs)?;
 
-        self.run_n::<_, N, true, false>(saf.view(), saf.shape())
+        self.write_standard_errors(&sfs, &saf, held_out.as_ref(), sites)?;
+        self.write_estimate(sfs, sites)
+    }
+
+    /// Writes the standard errors of the SFS estimate, if `--standard-errors` is set.
+    ///
+    /// The standard errors are calculated using all sites, including any held-out sites, and
+    /// scaled by the (possibly weighted) number of sites to match the scale of the estimate.
+    fn write_standard_errors<const N: usize, S>(
+        &self,
+        sfs: &Sfs<N>,
+        saf: &S,
+        held_out: Option<&Saf<N>>,
+        sites: f64,
+    ) -> ClapResult<()>
+    where
+        S: InMemorySaf<N>,
+    {
+        let path = match self.standard_errors.as_ref() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let values: usize = sfs.shape().iter().product();
+        let standard_errors = if values <= MAX_INFORMATION_VALUES {
+            log::info!(
+                target: "se",
+                "Calculating standard errors from observed information",
+            );
+
+            let to_io_error = |e| io::Error::new(io::ErrorKind::Other, e);
+            let mut information = saf
+                .par_observed_information(sfs.clone())
+                .map_err(to_io_error)?;
+            if let Some(held_out) = held_out {
+                information = information
+                    + sfs
+                        .clone()
+                        .par_observed_information(held_out.view())
+                        .map_err(to_io_error)?;
+            }
+
+            information
+                .covariance()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+                .standard_errors()
+        } else {
+            log::info!(
+                target: "se",
+                "SFS has {values} values, more than the maximum of {MAX_INFORMATION_VALUES} \
+                for the full observed information: calculating approximate standard errors \
+                from marginal information",
+            );
+
+            let mut information = saf.par_marginal_information(sfs.clone());
+            if let Some(held_out) = held_out {
+                information = information + sfs.clone().par_marginal_information(held_out.view());
+            }
+
+            information.standard_errors()
+        }
+        .scale(sites);
+
+        log::info!(
+            target: "se",
+            "Writing standard errors to path:\n\t{}",
+            path.display(),
+        );
+
+        write_sfs_to_path(path, &standard_errors).map_err(Into::into)
+    }
+
+    /// Splits off held-out sites from the end of the SAF, if `--holdout` is set.
+    ///
+    /// Since the SAF is assumed to be shuffled, the held-out sites are random. The held-out sites
+    /// are returned as a full SAF.
+    ///
+    /// An error is returned if the SAF cannot be split into both training and held-out sites,
+    /// see [`held_out_sites`].
+    fn split_held_out<const N: usize, S>(&self, saf: &mut S) -> ClapResult<Option<Saf<N>>>
+    where
+        S: InMemorySaf<N>,
+    {
+        self.holdout
+            .map(|fraction| {
+                let held_out = held_out_sites(saf.sites(), fraction)?;
+                Ok(saf.split_off(saf.sites() - held_out).into())
+            })
+            .transpose()
     }
 
     fn run_streaming(&self) -> ClapResult<()> {
@@ -144,35 +376,53 @@ impl Cli {
         R: io::BufRead + io::Seek,
     {
         let shape = reader.header().shape().to_vec().try_into().unwrap();
-        self.run_n::<_, N, false, true>(&mut reader, shape)
+        let sites = reader.header().sites();
+        let initial_sfs = self.read_initial()?;
+
+        let sfs = if let Some(fraction) = self.holdout {
+            set_threads(self.threads)?;
+
+            let held_out = reader.read_tail(held_out_sites(sites, fraction)?)?;
+            let mut reader = reader.take(sites - held_out.sites());
+
+            self.run_n::<_, N, false, true>(&mut reader, shape, Some(held_out), initial_sfs)?
+        } else {
+            self.run_n::<_, N, false, true>(&mut reader, shape, None, initial_sfs)?
+        };
+
+        self.write_estimate(sfs, sites as f64)
     }
 }
 
-fn setup<P, const D: usize, const PAR: bool, const STREAM: bool>(
-    sfs_path: Option<P>,
+fn setup<const D: usize, const PAR: bool, const STREAM: bool>(
+    initial_sfs: Option<Sfs<D>>,
     shape: [usize; D],
     sites: usize,
     window_size: usize,
     block_spec: Blocks,
-) -> ClapResult<(Sfs<D>, Runner<PAR, STREAM>)>
-where
-    P: AsRef<Path>,
-{
+    folded: bool,
+) -> ClapResult<(Sfs<D>, Runner<PAR, STREAM>)> {
     let block_runner = Logger::builder()
         .log_counter_level(log::Level::Trace)
         .log_sfs_level(log::Level::Trace)
         .log_target("windowem")
         .with_block_logging()
-        .build(StandardEm::<PAR, STREAM>::new());
+        .build(StandardEm::<PAR, STREAM>::new().with_folding(folded));
 
-    let (sfs, runner) = if let Some(path) = sfs_path {
-        let sfs = input::sfs::Reader::from_path(path)?.read()?;
+    if folded {
+        log::debug!(target: "init", "Estimating folded SFS");
+    }
+
+    let (sfs, runner) = if let Some(mut sfs) = initial_sfs {
+        if folded {
+            sfs = sfs.fold();
+        }
 
         let approx_block_size = match block_spec {
             Blocks::Number(number) => sites / number,
             Blocks::Size(size) => size,
         };
-        let block_sfs = sfs.clone().normalise().scale(approx_block_size as f64);
+        let block_sfs = sfs.clone().scale(approx_block_size as f64);
 
         let runner = WindowEm::<_, STREAM>::with_initial_sfs(
             block_runner,
@@ -180,11 +430,14 @@ where
             window_size,
             block_spec,
         );
-        (sfs.normalise(), runner)
+        (sfs, runner)
     } else {
         log::debug!(target: "init", "Creating uniform initial SFS");
 
-        let sfs = Sfs::uniform(shape);
+        let mut sfs = Sfs::uniform(shape);
+        if folded {
+            sfs = sfs.fold();
+        }
         let runner = WindowEm::<_, STREAM>::new(block_runner, window_size, block_spec);
         (sfs, runner)
     };
@@ -199,6 +452,50 @@ where
     Ok((sfs, Checker::new(runner)))
 }
 
+/// Returns the total weight of the sites in the SAF.
+///
+/// If the SAF is unweighted, this is the number of sites.
+fn total_weight<const N: usize, S>(saf: &S) -> f64
+where
+    S: InMemorySaf<N>,
+{
+    match saf.weights() {
+        Some(weights) => weights.iter().map(|&x| f64::from(x)).sum(),
+        None => saf.sites() as f64,
+    }
+}
+
+/// Returns the number of sites to hold out for validation.
+///
+/// An error is returned if the fraction of sites leaves no sites for either training or
+/// validation, including when there are fewer than two sites.
+fn held_out_sites(sites: usize, fraction: f64) -> ClapResult<usize> {
+    let held_out = held_out_count(sites, fraction).ok_or_else(|| {
+        Cli::command().error(
+            ErrorKind::ValueValidation,
+            format!(
+                "cannot hold out fraction {fraction} of {sites} site(s): \
+                at least one site is required for both training and validation"
+            ),
+        )
+    })?;
+
+    log::debug!(
+        target: "init",
+        "Holding out {held_out} of {sites} sites for validation"
+    );
+
+    Ok(held_out)
+}
+
+/// Returns the number of sites to hold out for validation, or `None` if the fraction of sites
+/// leaves no sites for either training or validation.
+fn held_out_count(sites: usize, fraction: f64) -> Option<usize> {
+    let held_out = (sites as f64 * fraction).round() as usize;
+
+    (held_out > 0 && held_out < sites).then_some(held_out)
+}
+
 fn get_window_size(window_size: Option<NonZeroUsize>) -> NonZeroUsize {
     let window_size = match window_size {
         Some(v) => v,
@@ -213,18 +510,38 @@ fn get_window_size(window_size: Option<NonZeroUsize>) -> NonZeroUsize {
     window_size
 }
 
-pub fn get_block_spec(
+/// Logs the number of site patterns and the compression ratio achieved by deduplication.
+fn log_patterns<T>(patterns: &Patterns<T>) {
+    log::info!(
+        target: "init",
+        "Collapsed {sites} sites into {n} site patterns within blocks, \
+        a compression ratio of {ratio:.2}",
+        sites = patterns.sites(),
+        n = patterns.patterns(),
+        ratio = patterns.compression_ratio(),
+    );
+}
+
+fn block_spec(
     blocks: Option<NonZeroUsize>,
     block_size: Option<NonZeroUsize>,
-    sites: usize,
     default_number_of_blocks: usize,
 ) -> Blocks {
-    let spec = match (blocks, block_size) {
+    match (blocks, block_size) {
         (Some(number), None) => Blocks::Number(number.get()),
         (None, Some(block_size)) => Blocks::Size(block_size.get()),
         (None, None) => Blocks::Number(default_number_of_blocks),
         (Some(_), Some(_)) => unreachable!("checked by clap"),
-    };
+    }
+}
+
+pub fn get_block_spec(
+    blocks: Option<NonZeroUsize>,
+    block_size: Option<NonZeroUsize>,
+    sites: usize,
+    default_number_of_blocks: usize,
+) -> Blocks {
+    let spec = block_spec(blocks, block_size, default_number_of_blocks);
 
     // We log the block spec with some precision: it's useful information to output, and also
     // helpful for debugging.
@@ -267,3 +584,22 @@ pub fn get_block_spec(
 
     spec
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_held_out_sites() {
+        assert_eq!(held_out_sites(100, 0.1).unwrap(), 10);
+        assert_eq!(held_out_sites(2, 0.5).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_held_out_sites_errors_without_training_or_validation_sites() {
+        assert!(held_out_sites(0, 0.5).is_err());
+        assert!(held_out_sites(1, 0.5).is_err());
+        assert!(held_out_sites(100, 0.001).is_err());
+        assert!(held_out_sites(10, 0.99).is_err());
+    }
+}
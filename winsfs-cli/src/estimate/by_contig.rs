@@ -0,0 +1,195 @@
+use std::{fs::File, io, num::NonZeroUsize, path::PathBuf};
+
+use clap::error::Result as ClapResult;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use winsfs_core::{
+    saf::{Patterns, Saf},
+    sfs::{
+        io::plain_text::{write_sfs_with_header, Header},
+        Sfs, USfs,
+    },
+};
+
+use crate::{utils::shuffle_saf, Cli};
+
+use super::{held_out_count, log_patterns, total_weight, DEFAULT_NUMBER_OF_BLOCKS};
+
+/// The SFS estimate of a single contig.
+struct Estimate<const D: usize> {
+    contig: String,
+    sfs: USfs<D>,
+}
+
+impl Cli {
+    /// Runs estimation separately for each contig and writes the estimates.
+    ///
+    /// Contigs are estimated in parallel, each with its own shuffle seed derived from `--seed`,
+    /// and any held-out sites are split off per contig. Contigs with too few sites to fill the
+    /// blocks or to split off held-out sites are skipped.
+    pub(super) fn run_by_contig<const N: usize>(
+        &self,
+        safs: Vec<(String, Saf<N>)>,
+        initial_sfs: Option<Sfs<N>>,
+    ) -> ClapResult<()> {
+        let mut rng = match self.seed {
+            Some(v) => StdRng::seed_from_u64(v),
+            None => StdRng::from_entropy(),
+        };
+
+        let contigs = safs.len();
+        log::info!(target: "contig", "Estimating SFS separately for {contigs} contig(s)");
+
+        let safs = safs
+            .into_iter()
+            .map(|(contig, saf)| (contig, saf, rng.gen()))
+            .collect::<Vec<(String, Saf<N>, u64)>>();
+
+        let estimates = safs
+            .into_par_iter()
+            .map(|(contig, saf, seed)| self.estimate_contig(contig, saf, seed, initial_sfs.clone()))
+            .collect::<ClapResult<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        if estimates.len() < contigs {
+            log::warn!(
+                target: "contig",
+                "Skipped {n} of {contigs} contig(s) with too few sites",
+                n = contigs - estimates.len(),
+            );
+        }
+
+        self.write_contig_estimates(estimates)
+    }
+
+    /// Runs estimation for a single contig and returns the SFS estimate scaled by the (possibly
+    /// weighted) number of sites on the contig.
+    ///
+    /// Returns `None` if the contig has fewer sites than blocks, or too few sites to split off
+    /// held-out sites.
+    fn estimate_contig<const N: usize>(
+        &self,
+        contig: String,
+        mut saf: Saf<N>,
+        seed: u64,
+        initial_sfs: Option<Sfs<N>>,
+    ) -> ClapResult<Option<Estimate<N>>> {
+        let min_sites = match self.block_size {
+            Some(_) => 1,
+            None => self
+                .blocks
+                .map_or(DEFAULT_NUMBER_OF_BLOCKS, NonZeroUsize::get),
+        };
+
+        // Held-out sites require at least one site on either side of the split
+        let too_few_to_hold_out = self.holdout.map_or(false, |fraction| {
+            held_out_count(saf.sites(), fraction).is_none()
+        });
+
+        if saf.sites() < min_sites || too_few_to_hold_out {
+            log::warn!(
+                target: "contig",
+                "Skipping contig '{contig}' with {sites} site(s)",
+                sites = saf.sites(),
+            );
+
+            return Ok(None);
+        }
+
+        shuffle_saf(&mut saf, Some(seed));
+        let held_out = self.split_held_out(&mut saf)?;
+        let sites = total_weight(&saf) + held_out.as_ref().map(total_weight).unwrap_or(0.0);
+
+        if saf.sites() < min_sites {
+            log::warn!(
+                target: "contig",
+                "Skipping contig '{contig}' with {sites} site(s) after holding out sites",
+                sites = saf.sites(),
+            );
+
+            return Ok(None);
+        }
+
+        log::info!(
+            target: "contig",
+            "Starting estimation for contig '{contig}' with {sites} site(s)",
+            sites = saf.sites(),
+        );
+
+        let sfs = if self.dedup {
+            let patterns = match self.dedup_precision {
+                Some(precision) => Patterns::from_saf_quantised(&saf, self.block_spec(), precision),
+                None => Patterns::from_saf(&saf, self.block_spec()),
+            };
+            log_patterns(&patterns);
+
+            self.run_n::<_, N, false, false>(patterns.view(), saf.shape(), held_out, initial_sfs)?
+        } else {
+            self.run_n::<_, N, false, false>(saf.view(), saf.shape(), held_out, initial_sfs)?
+        };
+
+        log::info!(target: "contig", "Finished estimation for contig '{contig}'");
+
+        Ok(Some(Estimate {
+            contig,
+            sfs: sfs.scale(sites),
+        }))
+    }
+
+    /// Writes the per-contig estimates to stdout, or to per-contig paths if `--by-contig-prefix`
+    /// is set.
+    fn write_contig_estimates<const N: usize>(
+        &self,
+        estimates: Vec<Estimate<N>>,
+    ) -> ClapResult<()> {
+        match self.by_contig_prefix.as_ref() {
+            Some(prefix) => {
+                for estimate in estimates.iter() {
+                    let mut path = prefix.as_os_str().to_owned();
+                    path.push(format!(".{}.sfs", estimate.contig));
+                    let path = PathBuf::from(path);
+
+                    log::info!(
+                        target: "contig",
+                        "Writing estimate for contig '{contig}' to path:\n\t{path}",
+                        contig = estimate.contig,
+                        path = path.display(),
+                    );
+
+                    let mut writer = File::create(path).map(io::BufWriter::new)?;
+                    self.write_contig_estimate(&mut writer, estimate)?;
+                }
+            }
+            None => {
+                let mut writer = io::stdout().lock();
+
+                for estimate in estimates.iter() {
+                    self.write_contig_estimate(&mut writer, estimate)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a single per-contig estimate with the contig name in the header.
+    fn write_contig_estimate<W, const N: usize>(
+        &self,
+        writer: &mut W,
+        estimate: &Estimate<N>,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let mut header = Header::new(estimate.sfs.shape().to_vec().into_boxed_slice());
+        if self.folded {
+            header = header.folded();
+        }
+
+        write_sfs_with_header(writer, &header.with_contig(&estimate.contig), &estimate.sfs)
+    }
+}
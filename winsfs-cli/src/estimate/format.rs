@@ -5,6 +5,8 @@ use saf::version::Version;
 
 use clap::{error::ErrorKind, CommandFactory, ValueEnum};
 
+use crate::input::saf::member_path_sets;
+
 use super::Cli;
 
 /// The possible input formats for SFS estimation.
@@ -66,6 +68,8 @@ impl TryFrom<&Cli> for Format {
                 if let Some(expected_format) = args.input_format {
                     Ok(expected_format)
                 } else {
+                    // The path may give several sets of SAF files, in which case the first is used
+                    let path = &member_path_sets(path)?[0];
                     Format::infer_from_magic(&mut File::open(path)?).map_err(|e| e.into())
                 }
             }
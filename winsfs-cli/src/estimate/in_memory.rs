@@ -0,0 +1,133 @@
+use clap::error::Result as ClapResult;
+
+use rand::Rng;
+
+use winsfs_core::{
+    em::likelihood::{LogLikelihood, SumOf},
+    saf::{BandedSaf, Patterns, Saf},
+    sfs::{
+        information::{MarginalInformation, ObservedInformation, TooLargeError},
+        Sfs,
+    },
+};
+
+use crate::Cli;
+
+use super::log_patterns;
+
+/// A SAF in memory used for estimation.
+///
+/// Full SAF input is read into a [`Saf`], while banded SAF input is kept banded in a [`BandedSaf`].
+/// This helps reduce code duplication between the two when running estimation in memory.
+pub trait InMemorySaf<const N: usize>: Into<Saf<N>> {
+    /// Returns the number of sites.
+    fn sites(&self) -> usize;
+
+    /// Returns the shape.
+    fn shape(&self) -> [usize; N];
+
+    /// Returns the per-site weights, if any.
+    fn weights(&self) -> Option<&[f32]>;
+
+    /// Shuffles the sites according to a random permutation.
+    fn shuffle<R>(&mut self, rng: &mut R)
+    where
+        R: Rng;
+
+    /// Splits off the sites from `site` and onwards.
+    fn split_off(&mut self, site: usize) -> Self;
+
+    /// Runs estimation and returns the normalised SFS estimate, see [`Cli::run_n`].
+    ///
+    /// If `--dedup` is set, estimation runs on the site patterns within blocks, quantised if
+    /// `--dedup-precision` is set.
+    fn estimate(
+        &self,
+        cli: &Cli,
+        held_out: Option<Saf<N>>,
+        initial_sfs: Option<Sfs<N>>,
+    ) -> ClapResult<Sfs<N>>;
+
+    /// Returns the log-likelihood given the SFS.
+    fn par_log_likelihood(&self, sfs: Sfs<N>) -> SumOf<LogLikelihood>;
+
+    /// Returns the observed information of the SFS.
+    fn par_observed_information(
+        &self,
+        sfs: Sfs<N>,
+    ) -> Result<ObservedInformation<N>, TooLargeError<N>>;
+
+    /// Returns the marginal information of the SFS.
+    fn par_marginal_information(&self, sfs: Sfs<N>) -> MarginalInformation<N>;
+}
+
+macro_rules! impl_in_memory_saf {
+    ($saf:ident, $patterns:ident, $quantised:ident) => {
+        impl<const N: usize> InMemorySaf<N> for $saf<N> {
+            fn sites(&self) -> usize {
+                $saf::sites(self)
+            }
+
+            fn shape(&self) -> [usize; N] {
+                $saf::shape(self)
+            }
+
+            fn weights(&self) -> Option<&[f32]> {
+                $saf::weights(self)
+            }
+
+            fn shuffle<R>(&mut self, rng: &mut R)
+            where
+                R: Rng,
+            {
+                $saf::shuffle(self, rng)
+            }
+
+            fn split_off(&mut self, site: usize) -> Self {
+                $saf::split_off(self, site)
+            }
+
+            fn estimate(
+                &self,
+                cli: &Cli,
+                held_out: Option<Saf<N>>,
+                initial_sfs: Option<Sfs<N>>,
+            ) -> ClapResult<Sfs<N>> {
+                if cli.dedup {
+                    let patterns = match cli.dedup_precision {
+                        Some(precision) => Patterns::$quantised(self, cli.block_spec(), precision),
+                        None => Patterns::$patterns(self, cli.block_spec()),
+                    };
+                    log_patterns(&patterns);
+
+                    cli.run_n::<_, N, true, false>(
+                        patterns.view(),
+                        self.shape(),
+                        held_out,
+                        initial_sfs,
+                    )
+                } else {
+                    cli.run_n::<_, N, true, false>(self.view(), self.shape(), held_out, initial_sfs)
+                }
+            }
+
+            fn par_log_likelihood(&self, sfs: Sfs<N>) -> SumOf<LogLikelihood> {
+                sfs.par_log_likelihood(self.view())
+            }
+
+            fn par_observed_information(
+                &self,
+                sfs: Sfs<N>,
+            ) -> Result<ObservedInformation<N>, TooLargeError<N>> {
+                sfs.par_observed_information(self.view())
+            }
+
+            fn par_marginal_information(&self, sfs: Sfs<N>) -> MarginalInformation<N> {
+                sfs.par_marginal_information(self.view())
+            }
+        }
+    };
+}
+
+impl_in_memory_saf!(Saf, from_saf, from_saf_quantised);
+impl_in_memory_saf!(BandedSaf, from_banded, from_banded_quantised);
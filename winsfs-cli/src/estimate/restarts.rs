@@ -0,0 +1,205 @@
+use clap::error::Result as ClapResult;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::Gamma;
+
+use winsfs_core::sfs::{Sfs, USfs};
+
+use crate::{utils::shuffle_saf, Cli};
+
+use super::{total_weight, InMemorySaf};
+
+/// The SFS estimate of a single restart.
+struct Estimate<const D: usize> {
+    seed: u64,
+    log_likelihood: f64,
+    sfs: Sfs<D>,
+}
+
+impl Cli {
+    /// Runs estimation `restarts` times and writes the estimate with the best log-likelihood.
+    ///
+    /// Each restart reshuffles the SAF using its own seed, and starts from a Dirichlet-perturbed
+    /// initial SFS. The SAF is shared across restarts, as are any held-out sites.
+    pub(super) fn run_restarts<const N: usize, S>(
+        &self,
+        mut saf: S,
+        initial_sfs: Option<Sfs<N>>,
+        restarts: usize,
+    ) -> ClapResult<()>
+    where
+        S: InMemorySaf<N>,
+    {
+        let mut rng = match self.seed {
+            Some(v) => StdRng::seed_from_u64(v),
+            None => StdRng::from_entropy(),
+        };
+
+        // Held-out sites must be the same for all restarts, so these are split off up front
+        let held_out = if self.holdout.is_some() {
+            shuffle_saf(&mut saf, Some(rng.gen()));
+            self.split_held_out(&mut saf)?
+        } else {
+            None
+        };
+        let sites = total_weight(&saf) + held_out.as_ref().map(total_weight).unwrap_or(0.0);
+
+        let initial_sfs = initial_sfs.unwrap_or_else(|| Sfs::uniform(saf.shape()));
+
+        let mut estimates = Vec::with_capacity(restarts);
+        for i in 1..=restarts {
+            let seed = rng.gen();
+
+            log::info!(
+                target: "restart",
+                "Starting restart {i}/{restarts} with seed {seed}"
+            );
+
+            shuffle_saf(&mut saf, Some(seed));
+            let perturbed_sfs = perturb(&initial_sfs, &mut rng);
+
+            let sfs = saf.estimate(self, held_out.clone(), Some(perturbed_sfs))?;
+
+            let likelihood_sfs = if self.folded {
+                sfs.symmetrise()
+            } else {
+                sfs.clone()
+            };
+            let mut log_likelihood =
+                f64::from(saf.par_log_likelihood(likelihood_sfs.clone()).into_sum());
+            if let Some(held_out) = held_out.as_ref() {
+                log_likelihood += f64::from(
+                    likelihood_sfs
+                        .par_log_likelihood(held_out.view())
+                        .into_sum(),
+                );
+            }
+
+            log::info!(
+                target: "restart",
+                "Finished restart {i}/{restarts} with log-likelihood {log_likelihood:.6e}"
+            );
+
+            estimates.push(Estimate {
+                seed,
+                log_likelihood,
+                sfs,
+            });
+        }
+
+        let best = estimates
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.log_likelihood.total_cmp(&b.log_likelihood))
+            .map(|(i, _)| i)
+            .expect("at least one restart");
+
+        log_summary(&estimates, best);
+
+        let sfs = estimates.swap_remove(best).sfs;
+
+        self.write_standard_errors(&sfs, &saf, held_out.as_ref(), sites)?;
+        self.write_estimate(sfs, sites)
+    }
+}
+
+fn log_summary<const D: usize>(estimates: &[Estimate<D>], best: usize) {
+    let n = estimates.len() as f64;
+    let log_likelihoods = estimates.iter().map(|estimate| estimate.log_likelihood);
+
+    let min = log_likelihoods.clone().fold(f64::INFINITY, f64::min);
+    let max = log_likelihoods.clone().fold(f64::NEG_INFINITY, f64::max);
+    let mean = log_likelihoods.clone().sum::<f64>() / n;
+    let sd = (log_likelihoods.map(|x| (x - mean).powi(2)).sum::<f64>() / n).sqrt();
+
+    let best_sfs = &estimates[best].sfs;
+    let max_l1 = estimates
+        .iter()
+        .map(|estimate| {
+            estimate
+                .sfs
+                .iter()
+                .zip(best_sfs.iter())
+                .map(|(a, b)| (a - b).abs())
+                .sum::<f64>()
+        })
+        .fold(0.0, f64::max);
+
+    log::info!(
+        target: "restart",
+        "Best restart {i}/{restarts} with seed {seed} and log-likelihood {lik:.6e}",
+        i = best + 1,
+        restarts = estimates.len(),
+        seed = estimates[best].seed,
+        lik = estimates[best].log_likelihood,
+    );
+
+    log::info!(
+        target: "restart",
+        "Restart log-likelihoods: min {min:.6e}, max {max:.6e}, mean {mean:.6e}, sd {sd:.4e}"
+    );
+
+    log::info!(
+        target: "restart",
+        "Maximum L1 distance between normalised restart estimates and best estimate: {max_l1:.4e}"
+    );
+}
+
+/// Returns a random SFS drawn from a Dirichlet distribution centered on `sfs`.
+///
+/// To avoid drawing values that underflow to zero in bins where `sfs` is small or zero, the
+/// distribution is centered on an even mix of `sfs` and a uniform SFS. The total concentration is
+/// the number of values in the SFS, so that each shape parameter is at least one half, and
+/// perturbing a uniform SFS corresponds to drawing from a flat Dirichlet distribution.
+fn perturb<const D: usize, R>(sfs: &Sfs<D>, rng: &mut R) -> Sfs<D>
+where
+    R: Rng,
+{
+    let concentration = sfs.as_slice().len() as f64;
+
+    let values = sfs
+        .iter()
+        .map(|&p| {
+            let shape = 0.5 * p * concentration + 0.5;
+            let gamma = Gamma::new(shape, 1.0).expect("shape and scale are positive");
+
+            rng.sample(gamma)
+        })
+        .collect();
+
+    USfs::from_vec_shape(values, *sfs.shape())
+        .expect("shape matches by construction")
+        .normalise()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perturb_normalised() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let sfs = Sfs::uniform([5, 3]);
+
+        let perturbed = perturb(&sfs, &mut rng);
+
+        assert_eq!(perturbed.shape(), sfs.shape());
+        assert!((perturbed.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert_ne!(perturbed, sfs);
+    }
+
+    #[test]
+    fn test_perturb_sparse_has_no_zero_bins() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut values = vec![0.0; 1000];
+        values[0] = 1.0;
+        let sfs = USfs::from_vec_shape(values, [1000]).unwrap().normalise();
+
+        for _ in 0..100 {
+            let perturbed = perturb(&sfs, &mut rng);
+
+            assert!(perturbed.iter().all(|&x| x > 0.0));
+            assert!((perturbed.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        }
+    }
+}
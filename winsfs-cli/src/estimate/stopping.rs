@@ -1,9 +1,17 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+
 use winsfs_core::{
     em::{
         likelihood::{LogLikelihood, SumOf},
-        stopping::{Either, Steps, Stop, StoppingRule, WindowLogLikelihoodTolerance},
+        stopping::{
+            HeldOutLogLikelihood, SfsDistance, SfsTolerance, Steps, Stop, StoppingRule, TimeLimit,
+            WindowLogLikelihoodTolerance,
+        },
         WithStatus,
     },
+    saf::Saf,
     sfs::Sfs,
 };
 
@@ -12,75 +20,146 @@ use crate::Cli;
 use super::DEFAULT_TOLERANCE;
 
 /// A stopping rule comprising the possible convergence criteria exposed through the cli.
-pub enum Rule {
-    Steps(Steps),
-    LogLikelihood(WindowLogLikelihoodTolerance),
-    Either(Either<Steps, WindowLogLikelihoodTolerance>),
+///
+/// The algorithm stops as soon as any of the set criteria indicate convergence.
+pub struct Rule<const D: usize> {
+    steps: Option<Steps>,
+    log_likelihood: Option<WindowLogLikelihoodTolerance>,
+    held_out: Option<HeldOutLogLikelihood<D>>,
+    sfs: Option<SfsTolerance>,
+    time: Option<TimeLimit>,
 }
 
-impl StoppingRule for Rule {}
-
-impl<T> Stop<T> for Rule
-where
-    T: WithStatus<Status = Vec<SumOf<LogLikelihood>>>,
-{
-    fn stop<const N: usize>(&mut self, em: &T, status: &T::Status, sfs: &Sfs<N>) -> bool {
-        match self {
-            Self::Steps(rule) => {
-                let stop = rule.stop(em, status, sfs);
-                log_steps(rule);
-                stop
-            }
-            Self::LogLikelihood(rule) => {
-                let stop = rule.stop(em, status, sfs);
-                log_log_likelihood(rule);
-                stop
-            }
-            Self::Either(rule) => {
-                let stop = rule.stop(em, status, sfs);
-                log_steps(rule.left());
-                log_log_likelihood(rule.right());
-                stop
-            }
-        }
+impl<const D: usize> Rule<D> {
+    /// Returns the held-out stopping rule, if any.
+    pub fn held_out(&self) -> Option<&HeldOutLogLikelihood<D>> {
+        self.held_out.as_ref()
     }
-}
 
-impl From<&Cli> for Rule {
-    fn from(args: &Cli) -> Self {
-        match (args.max_epochs, args.tolerance) {
-            (Some(n), Some(v)) => {
-                log::debug!(
-                    target: "stop",
-                    "Stopping rule set to either {n} epochs or log-likelihood tolerance {v:.4e}"
-                );
+    /// Creates a new stopping rule from the cli arguments.
+    ///
+    /// The `held_out` sites must be provided if, and only if, `--holdout` is set.
+    pub fn new(args: &Cli, held_out: Option<Saf<D>>) -> Self {
+        let steps = args.max_epochs.map(|n| {
+            log::debug!(target: "stop", "Stopping rule set to {n} epochs");
 
-                Self::Either(Steps::new(n).or(WindowLogLikelihoodTolerance::new(v)))
-            }
-            (Some(n), None) => {
-                log::debug!(
-                    target: "stop",
-                    "Stopping rule set to {n} epochs"
-                );
+            Steps::new(n)
+        });
 
-                Self::Steps(Steps::new(n))
-            }
-            (None, Some(v)) => {
+        let held_out = held_out.map(|saf| {
+            log::debug!(
+                target: "stop",
+                "Stopping rule set to no held-out log-likelihood improvement for {p} epochs",
+                p = args.patience,
+            );
+
+            HeldOutLogLikelihood::new(saf, args.patience).with_folding(args.folded)
+        });
+
+        let sfs = args.sfs_tolerance.map(|v| {
+            log::debug!(
+                target: "stop",
+                "Stopping rule set to SFS {distance} distance tolerance {v:.4e}",
+                distance = args.sfs_distance.as_str(),
+            );
+
+            SfsTolerance::new(args.sfs_distance.into(), v)
+        });
+
+        let time = args.max_time.map(|secs| {
+            log::debug!(target: "stop", "Stopping rule set to {secs} seconds");
+
+            TimeLimit::new(Duration::from_secs(secs))
+        });
+
+        let log_likelihood = match args.tolerance {
+            Some(v) => {
                 log::debug!(
                     target: "stop",
                     "Stopping rule set to log-likelihood tolerance {v:.4e}"
                 );
 
-                Self::LogLikelihood(WindowLogLikelihoodTolerance::new(v))
+                Some(WindowLogLikelihoodTolerance::new(v))
             }
-            (None, None) => {
+            None if steps.is_none() && held_out.is_none() && sfs.is_none() && time.is_none() => {
                 log::debug!(
                     target: "stop",
                     "Stopping rule set to log-likelihood tolerance {DEFAULT_TOLERANCE} (default)"
                 );
 
-                Self::LogLikelihood(WindowLogLikelihoodTolerance::new(DEFAULT_TOLERANCE))
+                Some(WindowLogLikelihoodTolerance::new(DEFAULT_TOLERANCE))
             }
+            None => None,
+        };
+
+        Self {
+            steps,
+            log_likelihood,
+            held_out,
+            sfs,
+            time,
+        }
+    }
+}
+
+impl<const D: usize> StoppingRule for Rule<D> {}
+
+impl<const D: usize, T> Stop<T> for Rule<D>
+where
+    T: WithStatus<Status = Vec<SumOf<LogLikelihood>>>,
+{
+    fn stop<const N: usize>(&mut self, em: &T, status: &T::Status, sfs: &Sfs<N>) -> bool {
+        // All rules are checked every epoch, since they may keep state between epochs
+        let mut stop = false;
+
+        if let Some(rule) = self.steps.as_mut() {
+            stop |= rule.stop(em, status, sfs);
+            log_steps(rule);
+        }
+        if let Some(rule) = self.log_likelihood.as_mut() {
+            stop |= rule.stop(em, status, sfs);
+            log_log_likelihood(rule);
+        }
+        if let Some(rule) = self.held_out.as_mut() {
+            stop |= rule.stop(em, status, sfs);
+            log_held_out(rule);
+        }
+        if let Some(rule) = self.sfs.as_mut() {
+            stop |= rule.stop(em, status, sfs);
+            log_sfs(rule);
+        }
+        if let Some(rule) = self.time.as_mut() {
+            stop |= rule.stop(em, status, sfs);
+            log_time(rule);
+        }
+
+        stop
+    }
+}
+
+/// The measure of distance between successive SFS estimates exposed through the cli.
+#[derive(ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Distance {
+    /// Sum of absolute differences
+    L1,
+    /// Maximum absolute difference
+    MaxAbs,
+}
+
+impl Distance {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::L1 => "L1",
+            Self::MaxAbs => "max-abs",
+        }
+    }
+}
+
+impl From<Distance> for SfsDistance {
+    fn from(distance: Distance) -> Self {
+        match distance {
+            Distance::L1 => Self::L1,
+            Distance::MaxAbs => Self::MaxAbs,
         }
     }
 }
@@ -104,3 +183,34 @@ fn log_log_likelihood(rule: &WindowLogLikelihoodTolerance) {
         tole = rule.tolerance(),
     )
 }
+
+fn log_held_out<const D: usize>(rule: &HeldOutLogLikelihood<D>) {
+    log::debug!(
+        target: "stop",
+        "Current held-out log-likelihood {lik:.4e}, best {best:.4e}, \
+        {i}/{max} epochs without improvement",
+        lik = f64::from(rule.log_likelihood()),
+        best = f64::from(rule.best_log_likelihood()),
+        i = rule.steps_since_best(),
+        max = rule.patience(),
+    )
+}
+
+fn log_sfs(rule: &SfsTolerance) {
+    log::debug!(
+        target: "stop",
+        "Current SFS Δ={diff:.4e} {sym} {tole:.4e}",
+        diff = rule.difference(),
+        sym = if rule.difference() > rule.tolerance() { '>' } else { '≤' },
+        tole = rule.tolerance(),
+    )
+}
+
+fn log_time(rule: &TimeLimit) {
+    log::debug!(
+        target: "stop",
+        "Elapsed time {elapsed:.1}s/{limit}s",
+        elapsed = rule.elapsed().as_secs_f64(),
+        limit = rule.limit().as_secs(),
+    )
+}
@@ -0,0 +1,167 @@
+use std::io;
+
+use clap::{error::Result as ClapResult, Args};
+
+use winsfs_core::sfs::{
+    expected::{Epoch, History},
+    io::{npy, plain_text},
+};
+
+use crate::input;
+
+/// Calculate expected SFS under piecewise-constant population size history.
+///
+/// The expected unfolded 1D SFS is calculated using the coalescent, following Polanski and Kimmel
+/// (2003). Time is measured in units of 2N generations and population sizes relative to N, where N
+/// is the diploid population size in the most recent epoch. The expected SFS is written to stdout,
+/// and can be compared with an estimated SFS of the same shape, e.g. using `winsfs log-likelihood`.
+#[derive(Args, Debug)]
+pub struct Expected {
+    /// Shape of the SFS.
+    ///
+    /// This is the number of haplotypes plus one, i.e. twice the number of diploid individuals plus
+    /// one, matching the shape of an estimated SFS.
+    #[clap(short = 'S', long, value_name = "INT")]
+    pub shape: usize,
+
+    /// Population mutation rate per site.
+    ///
+    /// This is 4Nμ for the population size N in the most recent epoch.
+    #[clap(short = 'T', long, default_value_t = 0.001, value_name = "FLOAT")]
+    pub theta: f64,
+
+    /// Population size history.
+    ///
+    /// The history is given as epochs going backwards in time, each specified by start time and
+    /// relative population size separated by a colon. Use comma to separate epochs. The first
+    /// epoch must start at time zero, and the last epoch extends infinitely far back in time.
+    /// For example, '0:1,0.1:0.2,0.5:2' specifies a bottleneck to one fifth of the current size
+    /// between 0.1 and 0.5 time units ago, before which the size was twice the current size.
+    /// If unset, the population size is constant.
+    #[clap(
+        short = 'e',
+        long,
+        value_parser = parse_epoch,
+        use_value_delimiter = true,
+        value_name = "TIME:SIZE"
+    )]
+    pub epochs: Vec<Epoch>,
+
+    /// Number of sites to scale the expected SFS by.
+    ///
+    /// By default, the expected SFS is normalised to give the expected proportion of sites in each
+    /// bin. If set, the expected SFS is instead scaled to give the expected number of sites, as
+    /// in an estimated SFS.
+    #[clap(short = 'n', long, value_name = "FLOAT")]
+    pub sites: Option<f64>,
+
+    /// Output format of the SFS.
+    ///
+    /// By default, the output SFS is written in a plain text format, where the first line is a
+    /// header giving the shape of the SFS, and the second line gives the values of the SFS in flat
+    /// row-major order. Alternatively, the SFS can be written in the npy binary format.
+    #[clap(short = 'o', long, value_enum, default_value_t = input::sfs::Format::PlainText)]
+    pub output_format: input::sfs::Format,
+}
+
+impl Expected {
+    pub fn run(self) -> ClapResult<()> {
+        let history = if self.epochs.is_empty() {
+            History::constant()
+        } else {
+            History::new(self.epochs).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+        };
+
+        let sfs = history
+            .expected_sfs([self.shape], self.theta)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .scale(self.sites.unwrap_or(1.0));
+
+        let stdout = io::stdout();
+        let mut writer = stdout.lock();
+
+        match self.output_format {
+            input::sfs::Format::PlainText => plain_text::write_sfs(&mut writer, &sfs),
+            input::sfs::Format::Npy => npy::write_sfs(&mut writer, &sfs),
+        }
+        .map_err(clap::Error::from)
+    }
+}
+
+/// Parses an epoch from start time and size separated by a colon.
+fn parse_epoch(s: &str) -> Result<Epoch, String> {
+    let (start, size) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected epoch as TIME:SIZE, found '{s}'"))?;
+
+    let parse = |x: &str| x.parse::<f64>().map_err(|e| format!("{e} in epoch '{s}'"));
+
+    Ok(Epoch::new(parse(start)?, parse(size)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{error::ErrorKind, Parser};
+
+    use crate::cli::{Cli, Command};
+
+    fn try_parse_args(cmd: &str) -> ClapResult<Expected> {
+        Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
+            Some(Command::Expected(expected)) => expected,
+            _ => panic!(),
+        })
+    }
+
+    fn parse_args(cmd: &str) -> Expected {
+        try_parse_args(cmd).expect("failed to parse subcommand")
+    }
+
+    #[test]
+    fn test_constant() {
+        let args = parse_args("winsfs expected --shape 21");
+        assert_eq!(args.shape, 21);
+        assert_eq!(args.theta, 0.001);
+        assert!(args.epochs.is_empty());
+        assert_eq!(args.sites, None);
+    }
+
+    #[test]
+    fn test_epochs() {
+        let args = parse_args("winsfs expected -S 21 -T 0.01 -e 0:1,0.1:0.2,0.5:2 -n 1e6");
+        assert_eq!(args.theta, 0.01);
+        assert_eq!(
+            args.epochs,
+            vec![
+                Epoch::new(0., 1.),
+                Epoch::new(0.1, 0.2),
+                Epoch::new(0.5, 2.)
+            ]
+        );
+        assert_eq!(args.sites, Some(1e6));
+    }
+
+    #[test]
+    fn test_invalid_epochs() {
+        for epochs in ["0", "0:x", "x:1", "0:1,0.1"] {
+            let result = try_parse_args(&format!("winsfs expected -S 21 -e {epochs}"));
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+        }
+    }
+
+    #[test]
+    fn test_invalid_epoch_error_quotes_epoch() {
+        let error = parse_epoch("0:x").unwrap_err();
+        assert!(error.ends_with("in epoch '0:x'"), "{error}");
+    }
+
+    #[test]
+    fn test_missing_shape() {
+        let result = try_parse_args("winsfs expected");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument,
+        );
+    }
+}
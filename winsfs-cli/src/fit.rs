@@ -0,0 +1,439 @@
+use std::{
+    fmt,
+    fs::File,
+    io,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use clap::{error::Result as ClapResult, Args, ValueEnum};
+
+use winsfs_core::sfs::{
+    expected::{Epoch, History},
+    io::plain_text::{write_folded_sfs, write_sfs},
+    USfs,
+};
+
+use crate::input;
+
+mod nelder_mead;
+use nelder_mead::NelderMead;
+
+/// Bounds on times in fitted models.
+const TIME_BOUNDS: RangeInclusive<f64> = 1e-4..=10.;
+
+/// Bounds on relative sizes in fitted models.
+const SIZE_BOUNDS: RangeInclusive<f64> = 1e-3..=1e3;
+
+/// Tolerance on the log-scale for considering a fitted parameter to be at a bound.
+const BOUND_TOLERANCE: f64 = 1e-3;
+
+/// Fit demographic models to 1D SFS.
+///
+/// Models are fitted by maximising the Poisson composite likelihood of the polymorphic part of the
+/// SFS given the expected SFS under the model, using the Nelder-Mead simplex method with several
+/// starting points. The population mutation rate theta is set to its maximum likelihood value
+/// given the other parameters, and the likelihood is therefore equivalent to the multinomial
+/// likelihood of the polymorphic part of the SFS up to a constant.
+///
+/// Time is measured in units of 2N generations and population sizes relative to N, where N is the
+/// diploid population size at present, and theta is 4Nμ per site. The expected SFS is calculated
+/// as in `winsfs expected`.
+///
+/// For each model, a line is written to stdout with the model name, the number of parameters
+/// (including theta), theta, the time and ancestral relative size parameters (if any), the
+/// log-likelihood, AIC, and whether any parameter is at the bounds of the search space. Parameters
+/// are bounded to times between 1e-4 and 10 and relative sizes between 1e-3 and 1e3. A fit at a
+/// bound is not a proper optimum, and typically means the model fits the data poorly.
+#[derive(Args, Debug)]
+pub struct Fit {
+    /// Input SFS.
+    ///
+    /// The input SFS can be provided here or read from stdin. The SFS should give the number of
+    /// sites in each bin, as output by the main command, and not be normalised.
+    #[clap(value_parser, value_name = "PATH")]
+    pub path: Option<PathBuf>,
+
+    /// Fit models to folded SFS.
+    ///
+    /// If set, both the input SFS and the expected SFS under the models are folded before
+    /// calculating the likelihood. This should be used when the input SFS is folded.
+    #[clap(long)]
+    pub folded: bool,
+
+    /// Models to fit.
+    ///
+    /// More than one model can be fitted. Use comma to separate models.
+    #[clap(
+        short = 'm',
+        long,
+        value_enum,
+        default_value = "constant,two-epoch,exponential",
+        use_value_delimiter = true,
+        value_name = "MODEL(S)"
+    )]
+    pub models: Vec<Model>,
+
+    /// Output prefix for fitted expected SFS.
+    ///
+    /// If set, the expected SFS under each fitted model is written to '<PREFIX>.<MODEL>.sfs',
+    /// scaled by the number of sites in the input SFS.
+    #[clap(short = 'o', long, value_name = "PREFIX")]
+    pub output: Option<PathBuf>,
+}
+
+/// Demographic models that can be fitted.
+#[derive(ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Model {
+    /// Constant population size.
+    Constant,
+    /// Instantaneous change from ancestral size at some time in the past to present size.
+    TwoEpoch,
+    /// Exponential growth from ancestral size at some time in the past to present size.
+    /// The ancestral size must be smaller than the present size.
+    Exponential,
+}
+
+impl Model {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Constant => "constant",
+            Self::TwoEpoch => "two-epoch",
+            Self::Exponential => "exponential",
+        }
+    }
+
+    /// Returns the history of the model given log-scale parameters, if within bounds.
+    fn history(&self, parameters: &[f64]) -> Option<History> {
+        match (self, parameters) {
+            (Self::Constant, []) => Some(History::constant()),
+            (Self::TwoEpoch, &[log_time, log_size]) => {
+                let (time, size) = bounded(log_time, log_size)?;
+
+                History::new(vec![Epoch::new(0., 1.), Epoch::new(time, size)]).ok()
+            }
+            (Self::Exponential, &[log_time, log_size]) if log_size <= 0. => {
+                let (time, size) = bounded(log_time, log_size)?;
+                let growth = -log_size / time;
+
+                History::new(vec![
+                    Epoch::new(0., 1.).with_growth(growth),
+                    Epoch::new(time, size),
+                ])
+                .ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if any of the log-scale parameters is within tolerance of its bounds.
+    fn at_bound(&self, parameters: &[f64]) -> bool {
+        let near = |x: f64, bound: f64| (x - bound.ln()).abs() < BOUND_TOLERANCE;
+        let near_bounds = |x: f64, bounds: &RangeInclusive<f64>| {
+            near(x, *bounds.start()) || near(x, *bounds.end())
+        };
+
+        match (self, parameters) {
+            (Self::Exponential, &[_, log_size]) if near(log_size, 1.) => true,
+            (_, &[log_time, log_size]) => {
+                near_bounds(log_time, &TIME_BOUNDS) || near_bounds(log_size, &SIZE_BOUNDS)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the log-scale starting points for the optimisation.
+    fn starts(&self) -> Vec<Vec<f64>> {
+        let log_times = [0.01f64, 0.1, 1.].map(f64::ln);
+
+        let log_sizes = match self {
+            Self::Constant => return vec![vec![]],
+            Self::TwoEpoch => [0.1f64, 10.].map(f64::ln),
+            Self::Exponential => [0.01f64, 0.1].map(f64::ln),
+        };
+
+        log_times
+            .iter()
+            .flat_map(|&t| log_sizes.iter().map(move |&s| vec![t, s]))
+            .collect()
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Returns the time and size from log-scale parameters, if within bounds.
+fn bounded(log_time: f64, log_size: f64) -> Option<(f64, f64)> {
+    let (time, size) = (log_time.exp(), log_size.exp());
+
+    (TIME_BOUNDS.contains(&time) && SIZE_BOUNDS.contains(&size)).then_some((time, size))
+}
+
+/// A fitted model.
+struct Fitted {
+    model: Model,
+    parameters: Vec<f64>,
+    theta: f64,
+    log_likelihood: f64,
+}
+
+impl Fitted {
+    /// Returns the AIC of the fitted model.
+    fn aic(&self) -> f64 {
+        2. * self.number_of_parameters() as f64 - 2. * self.log_likelihood
+    }
+
+    /// Returns true if any of the fitted parameters is at the bounds of the search space.
+    fn at_bound(&self) -> bool {
+        self.model.at_bound(&self.parameters)
+    }
+
+    /// Returns the number of parameters of the model, including theta.
+    fn number_of_parameters(&self) -> usize {
+        self.parameters.len() + 1
+    }
+}
+
+impl Fit {
+    pub fn run(self) -> ClapResult<()> {
+        let mut observed =
+            input::sfs::Reader::from_path_or_stdin(self.path.clone())?.read::<1>()?;
+
+        if observed.shape()[0] < 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "fitting models requires SFS with at least three values",
+            ))?;
+        }
+
+        if self.folded {
+            observed = observed.fold();
+        }
+
+        println!("#model\tparameters\ttheta\ttime\tsize\tlog_likelihood\taic\tat_bound");
+
+        for &model in self.models.iter() {
+            let fitted = self.fit(model, &observed);
+
+            let [time, size] = match fitted.parameters[..] {
+                [log_time, log_size] => [log_time, log_size].map(|x| format!("{:.6e}", x.exp())),
+                _ => [(); 2].map(|_| String::from("NA")),
+            };
+
+            println!(
+                "{model}\t{parameters}\t{theta:.6e}\t{time}\t{size}\t{log_likelihood:.6}\t{aic:.6}\t\
+                {at_bound}",
+                parameters = fitted.number_of_parameters(),
+                theta = fitted.theta,
+                log_likelihood = fitted.log_likelihood,
+                aic = fitted.aic(),
+                at_bound = fitted.at_bound(),
+            );
+
+            if let Some(prefix) = &self.output {
+                self.write_fitted(prefix, &fitted, &observed)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fits a model to the observed SFS.
+    fn fit(&self, model: Model, observed: &USfs<1>) -> Fitted {
+        log::info!(target: "fit", "Fitting {model} model");
+
+        let objective = |parameters: &[f64]| -> f64 {
+            model
+                .history(parameters)
+                .map(|history| -self.log_likelihood(&history, observed).1)
+                .filter(|v| v.is_finite())
+                .unwrap_or(f64::INFINITY)
+        };
+
+        let optimiser = NelderMead::default();
+
+        let (parameters, _) = model
+            .starts()
+            .iter()
+            .map(|start| optimiser.minimise(objective, start))
+            .inspect(|(parameters, value)| {
+                log::debug!(
+                    target: "fit",
+                    "Local optimum with log-likelihood {ll:.6} at log-parameters {parameters:?}",
+                    ll = -value,
+                )
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+
+        // The objective is infinite out of bounds and starting points are within bounds, so the
+        // best point is within bounds and has a history; this does not mean it is an optimum
+        let history = model.history(&parameters).unwrap();
+        if model.at_bound(&parameters) {
+            log::warn!(
+                target: "fit",
+                "Fitted {model} model has parameters at the bounds of the search space, \
+                so the fit is not a proper optimum"
+            );
+        }
+        let (theta, log_likelihood) = self.log_likelihood(&history, observed);
+
+        Fitted {
+            model,
+            parameters,
+            theta,
+            log_likelihood,
+        }
+    }
+
+    /// Returns the maximum likelihood theta and the corresponding Poisson log-likelihood of the
+    /// observed SFS given the history.
+    fn log_likelihood(&self, history: &History, observed: &USfs<1>) -> (f64, f64) {
+        // Shape is checked to have at least three values, so this cannot fail
+        let mut expected = history.expected_mutations(*observed.shape()).unwrap();
+
+        if self.folded {
+            expected = expected.fold();
+        }
+
+        let sites: f64 = observed.iter().sum();
+
+        let polymorphic = 1..observed.shape()[0] - 1;
+        let observed = &observed.as_slice()[polymorphic.clone()];
+        let expected = &expected.as_slice()[polymorphic];
+
+        let theta = observed.iter().sum::<f64>() / (sites * expected.iter().sum::<f64>());
+
+        let log_likelihood = observed
+            .iter()
+            .zip(expected)
+            .filter(|(_, &e)| e > 0.)
+            .map(|(o, e)| {
+                let mean = sites * theta * e;
+                o * mean.ln() - mean
+            })
+            .sum();
+
+        (theta, log_likelihood)
+    }
+
+    /// Writes the expected SFS under the fitted model.
+    fn write_fitted(&self, prefix: &Path, fitted: &Fitted, observed: &USfs<1>) -> io::Result<()> {
+        let mut path = prefix.as_os_str().to_owned();
+        path.push(format!(".{}.sfs", fitted.model));
+
+        log::debug!(
+            target: "fit",
+            "Writing fitted {model} SFS to path:\n\t{}",
+            PathBuf::from(&path).display(),
+            model = fitted.model,
+        );
+
+        let sites: f64 = observed.iter().sum();
+
+        let sfs = fitted
+            .model
+            .history(&fitted.parameters)
+            .unwrap()
+            .expected_sfs(*observed.shape(), fitted.theta)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .scale(sites);
+
+        let mut writer = File::create(path)?;
+        if self.folded {
+            write_folded_sfs(&mut writer, &sfs.fold())
+        } else {
+            write_sfs(&mut writer, &sfs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{error::ErrorKind, Parser};
+
+    use winsfs_core::sfs1d;
+
+    use crate::cli::{Cli, Command};
+
+    fn try_parse_args(cmd: &str) -> ClapResult<Fit> {
+        Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
+            Some(Command::Fit(fit)) => fit,
+            _ => panic!(),
+        })
+    }
+
+    fn parse_args(cmd: &str) -> Fit {
+        try_parse_args(cmd).expect("failed to parse subcommand")
+    }
+
+    #[test]
+    fn test_default_models() {
+        let args = parse_args("winsfs fit /path/to/sfs");
+        assert_eq!(args.path, Some(PathBuf::from("/path/to/sfs")));
+        assert_eq!(
+            args.models,
+            vec![Model::Constant, Model::TwoEpoch, Model::Exponential]
+        );
+        assert!(!args.folded);
+    }
+
+    #[test]
+    fn test_models() {
+        let args = parse_args("winsfs fit --folded -m two-epoch,constant -o prefix");
+        assert_eq!(args.models, vec![Model::TwoEpoch, Model::Constant]);
+        assert!(args.folded);
+        assert_eq!(args.output, Some(PathBuf::from("prefix")));
+    }
+
+    #[test]
+    fn test_invalid_model() {
+        let result = try_parse_args("winsfs fit -m bottleneck");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn test_recovers_two_epoch() {
+        let (time, size, theta, sites) = (0.2, 0.1, 1e-3, 1e6);
+
+        let history = History::new(vec![Epoch::new(0., 1.), Epoch::new(time, size)]).unwrap();
+        let observed = history.expected_sfs([11], theta).unwrap().scale(sites);
+
+        let fit = parse_args("winsfs fit");
+        let fitted = fit.fit(Model::TwoEpoch, &observed);
+
+        let [fitted_time, fitted_size] = [fitted.parameters[0], fitted.parameters[1]].map(f64::exp);
+        assert!((fitted_time - time).abs() / time < 1e-2);
+        assert!((fitted_size - size).abs() / size < 1e-2);
+        assert!((fitted.theta - theta).abs() / theta < 1e-2);
+
+        assert!(!fitted.at_bound());
+
+        let constant = fit.fit(Model::Constant, &observed);
+        assert!(constant.log_likelihood < fitted.log_likelihood);
+        assert!(!constant.at_bound());
+    }
+
+    #[test]
+    fn test_at_bound() {
+        assert!(Model::TwoEpoch.at_bound(&[1e-4f64.ln(), 0.1f64.ln()]));
+        assert!(Model::TwoEpoch.at_bound(&[0.2f64.ln(), 1e3f64.ln()]));
+        assert!(!Model::TwoEpoch.at_bound(&[0.2f64.ln(), 0.1f64.ln()]));
+        assert!(Model::Exponential.at_bound(&[0.2f64.ln(), 0.]));
+        assert!(!Model::Exponential.at_bound(&[0.2f64.ln(), 0.1f64.ln()]));
+        assert!(!Model::Constant.at_bound(&[]));
+    }
+
+    #[test]
+    fn test_constant_theta() {
+        let observed = sfs1d![1000., 20., 10., 0.];
+
+        let fitted = parse_args("winsfs fit").fit(Model::Constant, &observed);
+        assert!((fitted.theta - 30. / (1030. * 1.5)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,143 @@
+/// A Nelder-Mead simplex optimiser for minimising functions without derivatives.
+///
+/// The standard reflection, expansion, contraction, and shrink coefficients are used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NelderMead {
+    max_iterations: usize,
+    step: f64,
+    tolerance: f64,
+}
+
+impl Default for NelderMead {
+    fn default() -> Self {
+        Self {
+            max_iterations: 2000,
+            step: 0.5,
+            tolerance: 1e-10,
+        }
+    }
+}
+
+impl NelderMead {
+    /// Returns the point minimising `f` and its value, starting from `start`.
+    ///
+    /// The initial simplex is constructed by offsetting each coordinate of `start` by the step.
+    /// The optimisation stops when the absolute difference between the best and worst values in
+    /// the simplex falls below the tolerance, or after the maximum number of iterations. Infinite
+    /// values may be used to constrain the search, but `start` should have a finite value.
+    pub fn minimise<F>(&self, mut f: F, start: &[f64]) -> (Vec<f64>, f64)
+    where
+        F: FnMut(&[f64]) -> f64,
+    {
+        let dim = start.len();
+
+        let mut simplex: Vec<(Vec<f64>, f64)> = Vec::with_capacity(dim + 1);
+        simplex.push((start.to_vec(), f(start)));
+        for i in 0..dim {
+            let mut x = start.to_vec();
+            x[i] += self.step;
+            let value = f(&x);
+            simplex.push((x, value));
+        }
+
+        for _ in 0..self.max_iterations {
+            simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            let (best, worst) = (simplex[0].1, simplex[dim].1);
+            if (worst - best).abs() < self.tolerance {
+                break;
+            }
+
+            let centroid: Vec<f64> = (0..dim)
+                .map(|j| simplex[..dim].iter().map(|(x, _)| x[j]).sum::<f64>() / dim as f64)
+                .collect();
+
+            // Returns the point along the line from the centroid through the worst point
+            let towards_worst = |coefficient: f64, worst: &[f64]| -> Vec<f64> {
+                centroid
+                    .iter()
+                    .zip(worst)
+                    .map(|(c, w)| c + coefficient * (w - c))
+                    .collect()
+            };
+
+            let reflected = towards_worst(-1., &simplex[dim].0);
+            let reflected_value = f(&reflected);
+
+            if reflected_value < best {
+                let expanded = towards_worst(-2., &simplex[dim].0);
+                let expanded_value = f(&expanded);
+
+                simplex[dim] = if expanded_value < reflected_value {
+                    (expanded, expanded_value)
+                } else {
+                    (reflected, reflected_value)
+                };
+            } else if reflected_value < simplex[dim - 1].1 {
+                simplex[dim] = (reflected, reflected_value);
+            } else {
+                let contracted = if reflected_value < worst {
+                    towards_worst(-0.5, &simplex[dim].0)
+                } else {
+                    towards_worst(0.5, &simplex[dim].0)
+                };
+                let contracted_value = f(&contracted);
+
+                if contracted_value < reflected_value.min(worst) {
+                    simplex[dim] = (contracted, contracted_value);
+                } else {
+                    // Shrink towards the best point
+                    let best = simplex[0].0.clone();
+                    for (x, value) in simplex.iter_mut().skip(1) {
+                        x.iter_mut()
+                            .zip(&best)
+                            .for_each(|(x, b)| *x = b + 0.5 * (*x - b));
+                        *value = f(x);
+                    }
+                }
+            }
+        }
+
+        simplex
+            .into_iter()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quadratic() {
+        let (x, value) = NelderMead::default()
+            .minimise(|x| (x[0] - 1.).powi(2) + (x[1] + 2.).powi(2), &[0., 0.]);
+
+        assert!((x[0] - 1.).abs() < 1e-4);
+        assert!((x[1] + 2.).abs() < 1e-4);
+        assert!(value < 1e-8);
+    }
+
+    #[test]
+    fn test_rosenbrock() {
+        let rosenbrock = |x: &[f64]| (1. - x[0]).powi(2) + 100. * (x[1] - x[0].powi(2)).powi(2);
+        let (x, _) = NelderMead::default().minimise(rosenbrock, &[-1., 1.]);
+
+        assert!((x[0] - 1.).abs() < 1e-3);
+        assert!((x[1] - 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_constrained() {
+        let f = |x: &[f64]| if x[0] < 0. { f64::INFINITY } else { x[0] };
+        let (x, _) = NelderMead::default().minimise(f, &[1.]);
+
+        assert!(x[0] >= 0. && x[0] < 1e-6);
+    }
+
+    #[test]
+    fn test_zero_dimensional() {
+        assert_eq!(NelderMead::default().minimise(|_| 1., &[]), (vec![], 1.));
+    }
+}
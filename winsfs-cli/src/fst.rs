@@ -0,0 +1,264 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    num::NonZeroU32,
+    path::PathBuf,
+};
+
+use clap::{error::Result as ClapResult, ArgGroup, Args};
+
+use winsfs_core::{em::EmSite, sfs::USfs};
+
+use crate::{
+    input,
+    posterior::read_prior,
+    windows::{Scan, WindowStatistic, Windows},
+};
+
+/// Header line of the per-site output.
+const SITES_HEADER: &str = "#chrom\tpos\tnumerator\tdenominator";
+
+/// Calculate per-site and window Fst using 2D SFS as prior.
+///
+/// For each intersecting site in the two input SAF files, the 2D SFS is used as a prior to
+/// calculate the posterior distribution of the joint derived allele counts. From this, the
+/// posterior expectations of the numerator and denominator of Hudson's Fst estimator are
+/// calculated, using the same definition as `winsfs stat --statistics fst`. This corresponds to
+/// `realSFS fst index` and `realSFS fst stats2`.
+///
+/// Per-site output is written as BGZF-compressed, tab-separated text to the path given by
+/// '--sites', with a header line starting with '#', followed by one line per site with the contig
+/// name, the one-based position, the numerator, and the denominator. The output can be indexed
+/// with `tabix -s1 -b2 -e2`.
+///
+/// Window output is written to stdout as tab-separated text with a header line starting with '#',
+/// followed by one line per window with the contig name, the zero-based start and exclusive end of
+/// the window, the number of sites in the window, the sums of the numerator and denominator, and
+/// the ratio of the sums as the Fst of the window. Windows without any sites are not written.
+///
+/// The SAF files will be streamed, and therefore the calculation requires only constant memory
+/// usage.
+#[derive(Args, Debug)]
+#[clap(group(ArgGroup::new("output").required(true).multiple(true)))]
+pub struct Fst {
+    /// Input SAF file paths.
+    ///
+    /// For each set of SAF files (conventially named 'prefix'.{saf.idx,saf.pos.gz,saf.gz}),
+    /// specify either the shared prefix or the full path to any one member file. Exactly two SAF
+    /// files must be provided.
+    #[clap(value_parser, num_args = 2, required = true, value_name = "PATHS")]
+    pub paths: Vec<PathBuf>,
+
+    /// Input 2D SFS to use as prior.
+    ///
+    /// The SFS will be normalised, so the input SFS does not need to be normalised. The shape of
+    /// the SFS must match the shape of the SAF files.
+    #[clap(short = 'i', long)]
+    pub sfs: PathBuf,
+
+    /// Output path for per-site values.
+    #[clap(long, group = "output", value_name = "PATH")]
+    pub sites: Option<PathBuf>,
+
+    /// Number of threads to use for reading.
+    ///
+    /// If set to 0, all available cores will be used.
+    #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
+    pub threads: usize,
+
+    /// Window size in base pairs.
+    ///
+    /// Windows start at the beginning of each contig. At least one of this, '--windows', or
+    /// '--sites' must be set.
+    #[clap(
+        short = 'w',
+        long,
+        group = "output",
+        conflicts_with = "windows",
+        value_name = "INT"
+    )]
+    pub window_size: Option<NonZeroU32>,
+
+    /// Step between window starts in base pairs.
+    ///
+    /// Defaults to the window size, so that windows are non-overlapping.
+    #[clap(
+        short = 's',
+        long,
+        requires = "window_size",
+        conflicts_with = "windows",
+        value_name = "INT"
+    )]
+    pub step: Option<NonZeroU32>,
+
+    /// Path to BED file with windows.
+    ///
+    /// Each line should contain contig name, zero-based start, and exclusive end of a window. Any
+    /// further fields are ignored, as are empty lines and lines starting with '#'. Windows may
+    /// overlap.
+    #[clap(long, group = "output", value_name = "PATH")]
+    pub windows: Option<PathBuf>,
+}
+
+impl Fst {
+    pub fn run(self) -> ClapResult<()> {
+        let windows = Windows::from_args(self.window_size, self.step, self.windows.as_deref())?;
+
+        let paths = [&self.paths[0], &self.paths[1]];
+        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?;
+        let prior = read_prior(&self.sfs, readers.shape())?;
+
+        let mut posterior = USfs::zeros(*prior.shape());
+        let mut buf = USfs::zeros(*prior.shape());
+
+        let mut sites_writer = match &self.sites {
+            Some(path) => {
+                log::debug!(
+                    target: "init",
+                    "Writing per-site values to path:\n\t{}",
+                    path.display()
+                );
+
+                let mut writer = bgzf::Writer::new(File::create(path)?);
+                writeln!(writer, "{SITES_HEADER}")?;
+                Some(writer)
+            }
+            None => None,
+        };
+
+        let mut scan = windows
+            .map(|windows| Scan::new(windows, Components::default(), io::stdout().lock()))
+            .transpose()?;
+
+        log::info!(
+            target: "init",
+            "Streaming (intersecting) sites in input SAF files",
+        );
+
+        let sites = readers.for_each_site(|contig, position, site| {
+            posterior.iter_mut().for_each(|v| *v = 0.0);
+            site.posterior_into(&prior, &mut posterior, &mut buf);
+
+            let (numerator, denominator) = posterior.fst_components();
+
+            if let Some(writer) = sites_writer.as_mut() {
+                writeln!(
+                    writer,
+                    "{contig}\t{pos}\t{numerator:.6e}\t{denominator:.6e}",
+                    pos = position + 1,
+                )?;
+            }
+
+            if let Some(scan) = scan.as_mut() {
+                scan.add(
+                    contig,
+                    position,
+                    &Components {
+                        numerator,
+                        denominator,
+                    },
+                )?;
+            }
+
+            Ok(())
+        })?;
+
+        if let Some(writer) = sites_writer {
+            writer.finish()?;
+        }
+
+        log::info!(target: "fst", "Processed {sites} sites");
+
+        if let Some(scan) = scan {
+            let (windows, _) = scan.finish()?;
+
+            log::info!(target: "fst", "Wrote {windows} windows");
+        }
+
+        Ok(())
+    }
+}
+
+/// The numerator and denominator of Fst.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Components {
+    numerator: f64,
+    denominator: f64,
+}
+
+impl WindowStatistic for Components {
+    const HEADER: &'static str = "numerator\tdenominator\tfst";
+
+    fn add(&mut self, other: &Self) {
+        self.numerator += other.numerator;
+        self.denominator += other.denominator;
+    }
+
+    fn write<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        write!(
+            writer,
+            "{numerator:.6e}\t{denominator:.6e}\t{fst:.6}",
+            numerator = self.numerator,
+            denominator = self.denominator,
+            fst = self.numerator / self.denominator,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{error::ErrorKind, Parser};
+
+    use crate::cli::{Cli, Command};
+
+    fn try_parse_args(cmd: &str) -> ClapResult<Fst> {
+        Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
+            Some(Command::Fst(fst)) => fst,
+            _ => panic!(),
+        })
+    }
+
+    fn parse_args(cmd: &str) -> Fst {
+        try_parse_args(cmd).expect("failed to parse subcommand")
+    }
+
+    #[test]
+    fn test_basic() {
+        let args = parse_args("winsfs fst --sfs /path/to/sfs -w 1000 -s 500 saf1 saf2");
+        assert_eq!(
+            args.paths,
+            vec![PathBuf::from("saf1"), PathBuf::from("saf2")]
+        );
+        assert_eq!(args.window_size, NonZeroU32::new(1000));
+        assert_eq!(args.step, NonZeroU32::new(500));
+        assert_eq!(args.sites, None);
+    }
+
+    #[test]
+    fn test_sites_and_windows() {
+        let args =
+            parse_args("winsfs fst --sfs sfs --sites /path/to/sites --windows bed saf1 saf2");
+        assert_eq!(args.sites, Some(PathBuf::from("/path/to/sites")));
+        assert_eq!(args.windows, Some(PathBuf::from("bed")));
+    }
+
+    #[test]
+    fn test_output_required() {
+        let result = try_parse_args("winsfs fst --sfs /path/to/sfs saf1 saf2");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument,
+        );
+    }
+
+    #[test]
+    fn test_two_safs_required() {
+        assert!(try_parse_args("winsfs fst --sfs sfs --sites sites saf1").is_err());
+        assert!(try_parse_args("winsfs fst --sfs sfs --sites sites saf1 saf2 saf3").is_err());
+    }
+}
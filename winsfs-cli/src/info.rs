@@ -0,0 +1,601 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use angsd_saf as saf;
+use saf::{
+    ext::{INDEX_EXT, ITEM_FILE_EXT, POSITIONS_FILE_EXT},
+    record::Band,
+    version::{Version, V3, V4},
+    Index,
+};
+
+use clap::{error::Result as ClapResult, Args};
+
+use crate::{estimate::Format, input::saf::member_path_sets, utils::join};
+
+/// Print diagnostics for a set of SAF files.
+///
+/// For each input SAF file, the SAF version, the number of alleles, and the number of contigs and
+/// sites are reported. Then, for each contig, the number of sites in each population is reported
+/// along with the number of sites intersecting all populations, followed by the totals. For banded
+/// (v4) SAF files, the distribution of band widths is reported at the end.
+///
+/// Output is written to stdout as tab-separated sections, each starting with a header line
+/// starting with '#'. Everything but the number of intersecting sites and the distribution of band
+/// widths is read from the SAF indexes, see also '--index-only'.
+#[derive(Args, Debug)]
+pub struct Info {
+    /// Input SAF file paths.
+    ///
+    /// For each set of SAF files (conventially named 'prefix'.{saf.idx,saf.pos.gz,saf.gz}),
+    /// specify either the shared prefix or the full path to any one member file. Any number of
+    /// populations is supported.
+    ///
+    /// Each population may also be given by several sets of SAF files, e.g. one per chromosome,
+    /// either as a comma-separated list, or as a text file with one set per line.
+    #[clap(value_parser, num_args = 1.., required = true, value_name = "PATHS")]
+    pub paths: Vec<PathBuf>,
+
+    /// Only read the SAF indexes.
+    ///
+    /// By default, the SAF position files are read to count the number of intersecting sites, and
+    /// the SAF item files of banded (v4) SAF files are read for the distribution of band widths.
+    /// If set, only information available from the SAF indexes is reported, including the mean
+    /// band width of banded SAF files.
+    #[clap(long)]
+    pub index_only: bool,
+
+    /// Number of threads to use for reading SAF item files.
+    ///
+    /// If set to 0, all available cores will be used.
+    #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
+    pub threads: usize,
+}
+
+impl Info {
+    pub fn run(self) -> ClapResult<()> {
+        let populations = self
+            .paths
+            .iter()
+            .map(|path| {
+                member_path_sets(path)?
+                    .iter()
+                    .map(SafFile::from_member_path)
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let stdout = io::stdout();
+        let mut writer = stdout.lock();
+
+        write_files(&mut writer, &populations)?;
+        write_shape(&mut writer, &populations)?;
+
+        let contigs = Contigs::new(&populations);
+
+        let counts = if self.index_only {
+            None
+        } else {
+            Some(count_intersecting(&populations, &contigs)?)
+        };
+        write_contigs(&mut writer, &contigs, counts.as_deref())?;
+
+        if !self.index_only && populations.iter().flatten().any(SafFile::is_banded) {
+            let threads =
+                NonZeroUsize::new(self.threads).unwrap_or(thread::available_parallelism()?);
+
+            let histograms = populations
+                .iter()
+                .map(|files| band_width_histogram(files, threads))
+                .collect::<io::Result<Vec<_>>>()?;
+
+            write_band_widths(&mut writer, &histograms)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Summary of a single contig in a SAF index.
+#[derive(Clone, Debug)]
+struct Contig {
+    name: String,
+    sites: usize,
+    position_offset: u64,
+    // Only present for banded SAF files
+    sum_band: Option<usize>,
+}
+
+/// Summary of a single SAF file from its index.
+#[derive(Clone, Debug)]
+struct SafFile {
+    prefix: String,
+    format: Format,
+    alleles: usize,
+    contigs: Vec<Contig>,
+}
+
+impl SafFile {
+    /// Reads the SAF index of the SAF file with the provided member path.
+    fn from_member_path<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let prefix = prefix_from_member_path(path.as_ref())?;
+        let index_path = format!("{prefix}.{INDEX_EXT}");
+
+        let format = Format::infer_from_magic(&mut File::open(&index_path)?)?;
+
+        let (alleles, contigs) = match format {
+            Format::Standard => {
+                let index = Index::<V3>::read_from_path(&index_path)?;
+                let contigs = index
+                    .records()
+                    .iter()
+                    .map(|record| Contig {
+                        name: record.name().to_string(),
+                        sites: record.sites(),
+                        position_offset: record.position_offset(),
+                        sum_band: None,
+                    })
+                    .collect();
+
+                (index.alleles(), contigs)
+            }
+            Format::Banded => {
+                let index = Index::<V4>::read_from_path(&index_path)?;
+                let contigs = index
+                    .records()
+                    .iter()
+                    .map(|record| Contig {
+                        name: record.name().to_string(),
+                        sites: record.sites(),
+                        position_offset: record.position_offset(),
+                        sum_band: Some(record.sum_band()),
+                    })
+                    .collect();
+
+                (index.alleles(), contigs)
+            }
+            Format::Shuffled => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot report info for shuffled format file",
+                ))
+            }
+        };
+
+        Ok(Self {
+            prefix,
+            format,
+            alleles,
+            contigs,
+        })
+    }
+
+    /// Returns `true` if the SAF file is banded.
+    fn is_banded(&self) -> bool {
+        matches!(self.format, Format::Banded)
+    }
+
+    /// Returns the mean band width, if the SAF file is banded.
+    fn mean_band_width(&self) -> Option<f64> {
+        let sum_band = self
+            .contigs
+            .iter()
+            .map(|contig| contig.sum_band)
+            .sum::<Option<usize>>()?;
+
+        Some(sum_band as f64 / self.sites() as f64)
+    }
+
+    /// Opens a reader of the SAF position file.
+    fn positions_reader(&self) -> io::Result<bgzf::Reader<File>> {
+        let path = format!("{}.{POSITIONS_FILE_EXT}", self.prefix);
+
+        File::open(path).map(bgzf::Reader::new)
+    }
+
+    /// Returns the positions of the sites on the contig with the provided ID in the index.
+    ///
+    /// The `reader` should be a reader of the SAF position file, see
+    /// [`SafFile::positions_reader`], and is positioned at the start of the contig before reading.
+    fn read_positions(
+        &self,
+        reader: &mut bgzf::Reader<File>,
+        contig_id: usize,
+    ) -> io::Result<Vec<u32>> {
+        let contig = &self.contigs[contig_id];
+
+        reader.seek(bgzf::VirtualPosition::from(contig.position_offset))?;
+
+        let mut buf = [0; 4];
+        (0..contig.sites)
+            .map(|_| reader.read_exact(&mut buf).map(|_| u32::from_le_bytes(buf)))
+            .collect()
+    }
+
+    /// Returns the total number of sites.
+    fn sites(&self) -> usize {
+        self.contigs.iter().map(|contig| contig.sites).sum()
+    }
+}
+
+/// Returns the shared prefix of SAF file member paths given any one of them, or the prefix itself.
+pub(crate) fn prefix_from_member_path(path: &Path) -> io::Result<String> {
+    let s = path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("SAF file path '{}' is not valid UTF-8", path.display()),
+        )
+    })?;
+
+    Ok([INDEX_EXT, POSITIONS_FILE_EXT, ITEM_FILE_EXT]
+        .into_iter()
+        .find_map(|ext| s.strip_suffix(ext).and_then(|s| s.strip_suffix('.')))
+        .unwrap_or(s)
+        .to_string())
+}
+
+/// Summary of contigs across populations, in order of first occurrence across populations.
+struct Contigs<'a> {
+    names: Vec<&'a str>,
+    // For each contig, the number of sites in each population
+    sites: Vec<Vec<usize>>,
+    // For each contig, the file and contig ID in each population, if present
+    ids: Vec<Vec<Option<(usize, usize)>>>,
+}
+
+impl<'a> Contigs<'a> {
+    /// Collects the contigs from the SAF indexes in each population.
+    fn new(populations: &'a [Vec<SafFile>]) -> Self {
+        let mut lookup = HashMap::new();
+        let mut contigs = Self {
+            names: Vec::new(),
+            sites: Vec::new(),
+            ids: Vec::new(),
+        };
+
+        for (k, files) in populations.iter().enumerate() {
+            for (i, file) in files.iter().enumerate() {
+                for (j, contig) in file.contigs.iter().enumerate() {
+                    let name = contig.name.as_str();
+                    let id = *lookup.entry(name).or_insert_with(|| {
+                        contigs.names.push(name);
+                        contigs.sites.push(vec![0; populations.len()]);
+                        contigs.ids.push(vec![None; populations.len()]);
+                        contigs.names.len() - 1
+                    });
+
+                    contigs.sites[id][k] += contig.sites;
+                    contigs.ids[id][k].get_or_insert((i, j));
+                }
+            }
+        }
+
+        contigs
+    }
+}
+
+/// Returns the number of sites intersecting all populations on each contig, in order of
+/// `contigs`.
+fn count_intersecting(populations: &[Vec<SafFile>], contigs: &Contigs) -> io::Result<Vec<usize>> {
+    let mut readers = populations
+        .iter()
+        .map(|files| {
+            files
+                .iter()
+                .map(SafFile::positions_reader)
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    contigs
+        .names
+        .iter()
+        .zip(contigs.ids.iter())
+        .map(|(name, ids)| {
+            let ids = match ids.iter().copied().collect::<Option<Vec<_>>>() {
+                Some(ids) => ids,
+                None => return Ok(0),
+            };
+
+            log::debug!(target: "info", "Counting intersecting sites on contig '{name}'");
+
+            let positions = populations
+                .iter()
+                .zip(readers.iter_mut())
+                .zip(ids)
+                .map(|((files, readers), (i, j))| files[i].read_positions(&mut readers[i], j))
+                .collect::<io::Result<Vec<_>>>()?;
+
+            Ok(intersect_positions(positions).len())
+        })
+        .collect()
+}
+
+/// Returns the positions shared between all lists of sorted positions.
+fn intersect_positions(positions: Vec<Vec<u32>>) -> Vec<u32> {
+    let mut iter = positions.into_iter();
+
+    let first = iter.next().unwrap_or_default();
+    iter.fold(first, |shared, other| {
+        let mut other = other.into_iter().peekable();
+
+        shared
+            .into_iter()
+            .filter(|&position| {
+                while other.next_if(|&x| x < position).is_some() {}
+                other.next_if_eq(&position).is_some()
+            })
+            .collect()
+    })
+}
+
+/// Returns the number of sites with each band width across the banded SAF files.
+///
+/// Standard SAF files are ignored. The SAF item files are read in full.
+fn band_width_histogram(files: &[SafFile], threads: NonZeroUsize) -> io::Result<Vec<usize>> {
+    let mut histogram = Vec::new();
+
+    for file in files.iter().filter(|file| file.is_banded()) {
+        log::info!(
+            target: "info",
+            "Reading band widths from SAF item file with prefix:\n\t{}",
+            file.prefix
+        );
+
+        let path = format!("{}.{ITEM_FILE_EXT}", file.prefix);
+        let mut reader = bgzf::reader::Builder::default()
+            .set_worker_count(threads)
+            .build_from_reader(File::open(path)?);
+        V4::read_magic(&mut reader)?;
+
+        let mut band = Band::new(0, Vec::new());
+        while V4::read_item(&mut reader, &mut band)?.is_not_done() {
+            if band.len() >= histogram.len() {
+                histogram.resize(band.len() + 1, 0);
+            }
+
+            histogram[band.len()] += 1;
+        }
+    }
+
+    Ok(histogram)
+}
+
+/// Writes the summary of each SAF file.
+fn write_files<W>(writer: &mut W, populations: &[Vec<SafFile>]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let banded = populations.iter().flatten().any(SafFile::is_banded);
+
+    write!(
+        writer,
+        "#population\tprefix\tversion\talleles\tcontigs\tsites"
+    )?;
+    if banded {
+        write!(writer, "\tmean_band_width")?;
+    }
+    writeln!(writer)?;
+
+    for (i, files) in populations.iter().enumerate() {
+        for file in files.iter() {
+            write!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                i + 1,
+                file.prefix,
+                file.format.version_string(),
+                file.alleles,
+                file.contigs.len(),
+                file.sites(),
+            )?;
+
+            if banded {
+                match file.mean_band_width() {
+                    Some(width) => write!(writer, "\t{width:.2}")?,
+                    None => write!(writer, "\tNA")?,
+                }
+            }
+
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the shape of the SFS corresponding to the SAF files.
+///
+/// The shape is given by the first SAF file for each population.
+fn write_shape<W>(writer: &mut W, populations: &[Vec<SafFile>]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let shape = populations.iter().map(|files| files[0].alleles + 1);
+
+    writeln!(writer, "#shape\t{}", join(shape, "/"))
+}
+
+/// Writes the number of sites on each contig in each population, followed by the totals.
+///
+/// The number of intersecting sites on each contig is written if provided.
+fn write_contigs<W>(
+    writer: &mut W,
+    contigs: &Contigs,
+    intersecting: Option<&[usize]>,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let populations = contigs.sites.first().map_or(0, Vec::len);
+    let populations_header = join((1..=populations).map(|i| format!("sites_{i}")), "\t");
+    write!(writer, "#contig\t{populations_header}")?;
+    if intersecting.is_some() {
+        write!(writer, "\tintersecting")?;
+    }
+    writeln!(writer)?;
+
+    let mut totals = vec![0; populations];
+
+    for (i, (name, sites)) in contigs.names.iter().zip(&contigs.sites).enumerate() {
+        totals.iter_mut().zip(sites).for_each(|(x, y)| *x += y);
+
+        write!(writer, "{name}\t{}", join(sites, "\t"))?;
+        if let Some(intersecting) = intersecting {
+            write!(writer, "\t{}", intersecting[i])?;
+        }
+        writeln!(writer)?;
+    }
+
+    write!(writer, "#total\t{}", join(totals, "\t"))?;
+    if let Some(intersecting) = intersecting {
+        write!(writer, "\t{}", intersecting.iter().sum::<usize>())?;
+    }
+    writeln!(writer)
+}
+
+/// Writes the number of sites with each band width in each population.
+///
+/// Only band widths occurring in at least one population are written.
+fn write_band_widths<W>(writer: &mut W, histograms: &[Vec<usize>]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let populations_header = join((1..=histograms.len()).map(|i| format!("sites_{i}")), "\t");
+    writeln!(writer, "#band_width\t{populations_header}")?;
+
+    let max = histograms.iter().map(Vec::len).max().unwrap_or(0);
+    for width in 0..max {
+        let counts = histograms
+            .iter()
+            .map(|histogram| histogram.get(width).copied().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        if counts.iter().any(|&count| count > 0) {
+            writeln!(writer, "{width}\t{}", join(counts, "\t"))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{
+        error::{ErrorKind, Result as ClapResult},
+        Parser,
+    };
+
+    use crate::cli::{Cli, Command};
+
+    fn try_parse_args(cmd: &str) -> ClapResult<Info> {
+        Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
+            Some(Command::Info(info)) => info,
+            _ => panic!(),
+        })
+    }
+
+    fn parse_args(cmd: &str) -> Info {
+        try_parse_args(cmd).expect("failed to parse subcommand")
+    }
+
+    #[test]
+    fn test_basic() {
+        let args = parse_args("winsfs info saf1 saf2 saf3 saf4 saf5 saf6 saf7");
+        assert_eq!(args.paths.len(), 7);
+        assert!(!args.index_only);
+
+        let args = parse_args("winsfs info --index-only saf");
+        assert!(args.index_only);
+    }
+
+    #[test]
+    fn test_missing_safs() {
+        let result = try_parse_args("winsfs info");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument,
+        );
+    }
+
+    fn saf_file(contigs: &[(&str, usize)]) -> SafFile {
+        SafFile {
+            prefix: String::from("prefix"),
+            format: Format::Standard,
+            alleles: 2,
+            contigs: contigs
+                .iter()
+                .map(|&(name, sites)| Contig {
+                    name: name.to_string(),
+                    sites,
+                    position_offset: 0,
+                    sum_band: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_contigs() {
+        let populations = vec![
+            vec![
+                saf_file(&[("chr1", 10), ("chr2", 5)]),
+                saf_file(&[("chr3", 2)]),
+            ],
+            vec![saf_file(&[("chr2", 4), ("chr4", 1), ("chr1", 3)])],
+        ];
+
+        let contigs = Contigs::new(&populations);
+        assert_eq!(contigs.names, vec!["chr1", "chr2", "chr3", "chr4"]);
+        assert_eq!(
+            contigs.sites,
+            vec![vec![10, 3], vec![5, 4], vec![2, 0], vec![0, 1]]
+        );
+        assert_eq!(
+            contigs.ids,
+            vec![
+                vec![Some((0, 0)), Some((0, 2))],
+                vec![Some((0, 1)), Some((0, 0))],
+                vec![Some((1, 0)), None],
+                vec![None, Some((0, 1))],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_intersect_positions() {
+        assert_eq!(
+            intersect_positions(vec![
+                vec![1, 2, 4, 5, 8, 9],
+                vec![0, 2, 3, 5, 8],
+                vec![2, 5, 6, 7, 8, 10],
+            ]),
+            vec![2, 5, 8]
+        );
+        assert_eq!(intersect_positions(vec![vec![1, 2], vec![]]), vec![]);
+        assert_eq!(intersect_positions(vec![vec![1, 2]]), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_prefix_from_member_path() {
+        for path in [
+            "dir/foo.saf.idx",
+            "dir/foo.saf.pos.gz",
+            "dir/foo.saf.gz",
+            "dir/foo",
+        ] {
+            assert_eq!(prefix_from_member_path(Path::new(path)).unwrap(), "dir/foo");
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use std::{fs, io, path::Path};
 
+pub mod regions;
 pub mod saf;
 pub mod sfs;
+pub mod weights;
 
 /// A reader that can either read from stdin or from a file.
 pub enum StdinOrFile {
@@ -0,0 +1,300 @@
+use std::{
+    fs::File,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgAction, Args};
+
+use winsfs_core::io::{Mask, Region, Subsample};
+
+/// Options restricting and filtering the sites read from SAF files.
+///
+/// See [`Readers::with_site_filters`](super::saf::Readers::with_site_filters) for applying the
+/// filters to SAF file readers.
+#[derive(Args, Debug)]
+pub struct SiteFilters {
+    /// Restrict input to region.
+    ///
+    /// Regions should be given as 'chr', 'chr:start', 'chr:start-', or 'chr:start-end', using
+    /// one-based, inclusive positions. The option can be given multiple times to use several
+    /// regions. Only intersecting sites in one of the regions are used, and the SAF index is used to
+    /// seek directly to the contig of each region. Regions are only supported for SAF file input,
+    /// not for shuffled input.
+    #[clap(
+        short = 'r',
+        long,
+        action = ArgAction::Append,
+        value_parser = parse_region,
+        value_name = "REGION"
+    )]
+    pub region: Vec<Region>,
+
+    /// Path to BED file of regions to restrict input to.
+    ///
+    /// Each line should contain contig name, zero-based start, and exclusive end. Any further
+    /// fields are ignored, as are empty lines and lines starting with '#'. May be combined with
+    /// '--region'.
+    #[clap(long, value_name = "PATH")]
+    pub regions_file: Option<PathBuf>,
+
+    /// Path to BED file of regions to include.
+    ///
+    /// Only sites inside the regions are used. The option can be given multiple times, in which
+    /// case only sites inside the regions of all files are used. The format is as for
+    /// '--regions-file'. Unlike '--regions-file', the SAF index is not used to skip data, and all
+    /// sites are read before filtering. The number of sites removed by each file is logged.
+    #[clap(long, action = ArgAction::Append, value_name = "PATH")]
+    pub include: Vec<PathBuf>,
+
+    /// Path to BED file of regions to exclude.
+    ///
+    /// Sites inside the regions are not used, e.g. to mask repeats or regions with low
+    /// mappability. The option can be given multiple times. The format is as for
+    /// '--regions-file'. The number of sites removed by each file is logged. Masks are only
+    /// supported for SAF file input, not for shuffled input.
+    #[clap(long, action = ArgAction::Append, value_name = "PATH")]
+    pub exclude: Vec<PathBuf>,
+
+    /// Read the union of sites rather than the intersection.
+    ///
+    /// By default, only sites present in the input for all populations are used. If set, sites
+    /// missing from some populations are used as well, with flat likelihoods for the populations
+    /// missing the site. Contigs are assumed to occur in the same order in each index. The number
+    /// of sites imputed for each population is logged.
+    #[clap(long)]
+    pub union: bool,
+
+    /// Random subsample of sites to use.
+    ///
+    /// If set to a fraction between zero and one (e.g. '0.1'), each site is used with this
+    /// probability. If set to an integer (e.g. '100000'), this number of sites is used, chosen
+    /// uniformly at random. The subsample is taken while reading, after any regions and masks,
+    /// and is determined by '--seed'. Useful for quick pilot runs. Only supported for SAF file
+    /// input, not for shuffled input.
+    #[clap(long, value_parser = parse_subsample, value_name = "FRACTION|COUNT")]
+    pub subsample: Option<Subsample>,
+}
+
+/// Collects regions from region strings and an optional regions file.
+///
+/// Returns `None` if no regions are given, in which case input should not be restricted.
+pub fn collect_regions<P>(
+    regions: &[Region],
+    regions_file: Option<P>,
+) -> io::Result<Option<Vec<Region>>>
+where
+    P: AsRef<Path>,
+{
+    let mut all = regions.to_vec();
+
+    if let Some(path) = regions_file {
+        all.extend(read_regions_from_path(path)?);
+    } else if regions.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(all))
+}
+
+/// Reads include and exclude masks from BED file paths.
+///
+/// The masks are returned along with their paths, include masks first.
+pub fn read_masks(include: &[PathBuf], exclude: &[PathBuf]) -> io::Result<Vec<(PathBuf, Mask)>> {
+    let include = include.iter().map(|path| {
+        read_regions_from_path(path).map(|regions| (path.clone(), Mask::include(regions)))
+    });
+    let exclude = exclude.iter().map(|path| {
+        read_regions_from_path(path).map(|regions| (path.clone(), Mask::exclude(regions)))
+    });
+
+    include.chain(exclude).collect()
+}
+
+/// Parses a region from a string.
+///
+/// The region should be given as 'chr', 'chr:start', 'chr:start-', or 'chr:start-end', where
+/// positions are one-based and inclusive, as used by e.g. `samtools`.
+pub fn parse_region(s: &str) -> Result<Region, String> {
+    let parse_position = |s: &str| match s.parse::<u32>() {
+        Ok(0) => Err("positions are one-based, found position 0".to_string()),
+        Ok(v) => Ok(v),
+        Err(_) => Err(format!("invalid position '{s}'")),
+    };
+
+    match s.rsplit_once(':') {
+        Some((contig, range)) if !contig.is_empty() => {
+            let (start, end) = match range.split_once('-') {
+                Some((start, "")) => (parse_position(start)?, u32::MAX),
+                Some((start, end)) => (parse_position(start)?, parse_position(end)?),
+                None => (parse_position(range)?, u32::MAX),
+            };
+
+            Region::new(contig, start - 1, end)
+                .ok_or_else(|| format!("empty region '{s}', start must not be after end"))
+        }
+        Some(_) => Err(format!("missing contig name in region '{s}'")),
+        None if s.is_empty() => Err("empty region".to_string()),
+        None => Ok(Region::whole_contig(s)),
+    }
+}
+
+/// Parses a subsample as either a number of sites or a fraction of sites.
+pub fn parse_subsample(s: &str) -> Result<Subsample, String> {
+    if let Ok(count) = s.parse::<usize>() {
+        return match count {
+            0 => Err("number of sites must be positive".to_string()),
+            count => Ok(Subsample::Count(count)),
+        };
+    }
+
+    match s.parse::<f64>() {
+        Ok(v) if v > 0.0 && v <= 1.0 => Ok(Subsample::Fraction(v)),
+        Ok(v) => Err(format!(
+            "expected number of sites or fraction between zero and one, found {v}"
+        )),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Reads regions from a file path in BED format.
+///
+/// See [`read_regions`].
+pub fn read_regions_from_path<P>(path: P) -> io::Result<Vec<Region>>
+where
+    P: AsRef<Path>,
+{
+    log::debug!(
+        target: "init",
+        "Reading regions from path:\n\t{}",
+        path.as_ref().display()
+    );
+
+    let regions = File::open(path)
+        .map(io::BufReader::new)
+        .and_then(read_regions)?;
+
+    log::debug!(target: "init", "Found {n} region(s)", n = regions.len());
+
+    Ok(regions)
+}
+
+/// Reads regions from a reader in BED format.
+///
+/// Each non-empty line not starting with '#' should contain at least three whitespace-separated
+/// fields giving contig name, zero-based start, and exclusive end. Any further fields are
+/// ignored.
+pub fn read_regions<R>(reader: R) -> io::Result<Vec<Region>>
+where
+    R: BufRead,
+{
+    let mut regions = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (contig, (start, end)) = parse_bed_line(line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to parse regions on line {}: {e}", i + 1),
+            )
+        })?;
+
+        // Empty regions are rejected when parsing
+        regions.push(Region::new(contig, start, end).unwrap());
+    }
+
+    Ok(regions)
+}
+
+/// Parses a single line of a BED file into a contig name and a region.
+pub fn parse_bed_line(line: &str) -> Result<(&str, (u32, u32)), String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+
+    let parse_position = |s: &str| {
+        s.parse::<u32>()
+            .map_err(|_| format!("invalid position '{s}'"))
+    };
+
+    match fields[..] {
+        [contig, start, end, ..] => {
+            let (start, end) = (parse_position(start)?, parse_position(end)?);
+
+            if start < end {
+                Ok((contig, (start, end)))
+            } else {
+                Err(format!("empty region {start}-{end}"))
+            }
+        }
+        _ => Err(format!(
+            "expected at least three fields, found {}",
+            fields.len()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(parse_region("chr1"), Ok(Region::whole_contig("chr1")));
+        assert_eq!(
+            parse_region("chr1:10"),
+            Ok(Region::new("chr1", 9, u32::MAX).unwrap())
+        );
+        assert_eq!(
+            parse_region("chr1:10-"),
+            Ok(Region::new("chr1", 9, u32::MAX).unwrap())
+        );
+        assert_eq!(
+            parse_region("chr1:10-20"),
+            Ok(Region::new("chr1", 9, 20).unwrap())
+        );
+        assert_eq!(
+            parse_region("chr1:10-10"),
+            Ok(Region::new("chr1", 9, 10).unwrap())
+        );
+        assert_eq!(
+            parse_region("HLA:A:1-2"),
+            Ok(Region::new("HLA:A", 0, 2).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_region_errors() {
+        for s in [
+            "",
+            ":1-10",
+            "chr1:0-10",
+            "chr1:20-10",
+            "chr1:x-10",
+            "chr1:1-y",
+            "chr1:",
+        ] {
+            assert!(parse_region(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_read_regions() {
+        let src = b"# comment\nchr1\t0\t10\n\nchr2 5 6 name\n";
+        let regions = read_regions(&src[..]).unwrap();
+
+        assert_eq!(
+            regions,
+            vec![
+                Region::new("chr1", 0, 10).unwrap(),
+                Region::new("chr2", 5, 6).unwrap(),
+            ]
+        );
+
+        assert!(read_regions(&b"chr1\t10\t5\n"[..]).is_err());
+    }
+}
@@ -1,23 +1,101 @@
-use std::{fs::File, io, num::NonZeroUsize, path::Path, thread};
+use std::{
+    fs::File,
+    io::{self, BufRead},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
 
 use angsd_saf as saf;
 use saf::version::Version;
 
 use winsfs_core::{
     em::likelihood::LogLikelihood,
-    io::{shuffle, Intersect, ReadSite},
-    saf::Saf,
+    io::{shuffle, Imputed, Intersect, Mask, MaskKind, ReadSite, Region, Subsample, Writer},
+    saf::{BandedSaf, Saf, Site},
     sfs::Sfs,
 };
 
 use crate::{estimate::Format, utils::join};
 
+use super::{
+    regions::{collect_regions, read_masks, SiteFilters},
+    weights::Weights,
+};
+
 /// A collection of SAF file readers from one of the supported SAF file formats.
-pub enum Readers<const D: usize, R> {
-    /// A collection of full SAF V3 readers.
-    Standard([saf::ReaderV3<R>; D]),
-    /// A collection of banded SAF V4 readers.
-    Banded([saf::ReaderV4<R>; D]),
+///
+/// Each population may be given by several sets of SAF files, which are read one after another as
+/// a single reader, see [`Readers::from_member_paths`]. The readers may optionally be restricted
+/// to regions, see [`Readers::with_regions`], and filtered by masks, see [`Readers::with_masks`].
+/// Rather than the intersection, the union of sites may be read, see [`Readers::with_union`], and
+/// a random subsample of sites may be read, see [`Readers::with_subsample`]. All of these may be
+/// set from command-line options at once, see [`Readers::with_site_filters`].
+pub struct Readers<const D: usize, R> {
+    inner: Inner<D, R>,
+    filters: Filters<D>,
+}
+
+/// The version-specific readers of a collection of SAF file readers.
+///
+/// Each inner collection contains one reader per population, and collections are read in order.
+enum Inner<const D: usize, R> {
+    /// Collections of full SAF V3 readers.
+    Standard(Parts<D, R, saf::version::V3>),
+    /// Collections of banded SAF V4 readers.
+    Banded(Parts<D, R, saf::version::V4>),
+}
+
+/// Collections of SAF file readers, each with one reader per population, to be read in order.
+type Parts<const D: usize, R, V> = Vec<[saf::Reader<R, V>; D]>;
+
+/// Restrictions on the sites read from a collection of SAF file readers.
+#[derive(Clone, Debug, Default)]
+struct Filters<const D: usize> {
+    regions: Option<Vec<Region>>,
+    // Masks are kept along with the path they were read from for logging
+    masks: Vec<(PathBuf, Mask)>,
+    // Set when reading the union of sites rather than the intersection
+    union: Option<Imputed<D>>,
+    // Subsample along with its seed
+    subsample: Option<(Subsample, u64)>,
+}
+
+impl<const D: usize> Filters<D> {
+    /// Returns `true` if no sites are filtered or subsampled, and only the intersection of sites
+    /// is read.
+    fn is_empty(&self) -> bool {
+        self.regions.is_none()
+            && self.masks.is_empty()
+            && self.union.is_none()
+            && self.subsample.is_none()
+    }
+
+    /// Logs the number of sites removed by each mask, and imputed for each population.
+    fn log_counts(&self) {
+        if let Some(imputed) = self.union.as_ref() {
+            for (i, imputed) in imputed.counts().iter().enumerate() {
+                log::info!(
+                    target: "init",
+                    "Imputed {imputed} sites missing from population {}",
+                    i + 1,
+                );
+            }
+        }
+
+        for (path, mask) in self.masks.iter() {
+            log::info!(
+                target: "init",
+                "Removed {removed} (intersecting) sites using {kind} mask:\n\t{path}",
+                removed = mask.removed(),
+                kind = match mask.kind() {
+                    MaskKind::Include => "include",
+                    MaskKind::Exclude => "exclude",
+                },
+                path = path.display(),
+            );
+        }
+    }
 }
 
 impl<const D: usize, R> Readers<D, R>
@@ -28,74 +106,324 @@ where
     ///
     /// Note that this requires taking a full pass through the readers to count, as the number of
     /// intersections cannot be known ahead of time. The exception is if there is only a single
-    /// reader, in which case the number of sites can be taken directly from the index.
+    /// reader with a single set of SAF files and no regions or masks, in which case the number of
+    /// sites can be taken directly from the index.
     pub fn count_sites(self) -> io::Result<usize> {
-        match self {
-            Self::Standard(readers) => readers.count_sites(),
-            Self::Banded(readers) => readers.count_sites(),
-        }
+        let sites = match self.inner {
+            Inner::Standard(readers) => readers.count_sites(&self.filters),
+            Inner::Banded(readers) => readers.count_sites(&self.filters),
+        }?;
+
+        self.filters.log_counts();
+
+        Ok(sites)
     }
 
     /// Returns the log-likelihood of an SFS given the data in readers, as well as the number of
     /// (intersecting) sites in the readers.
-    pub fn log_likelihood(self, sfs: Sfs<D>) -> io::Result<(LogLikelihood, usize)> {
-        match self {
-            Self::Standard(readers) => readers.log_likelihood(sfs),
-            Self::Banded(readers) => readers.log_likelihood(sfs),
-        }
+    ///
+    /// If `weights` are provided, the log-likelihood of each site is weighted accordingly.
+    pub fn log_likelihood(
+        self,
+        sfs: Sfs<D>,
+        weights: Option<&Weights>,
+    ) -> io::Result<(LogLikelihood, usize)> {
+        let result = match self.inner {
+            Inner::Standard(readers) => readers.log_likelihood(&self.filters, sfs, weights),
+            Inner::Banded(readers) => readers.log_likelihood(&self.filters, sfs, weights),
+        }?;
+
+        self.filters.log_counts();
+
+        Ok(result)
+    }
+
+    /// Calls a closure on each (intersecting) site in the readers, and returns the number of sites.
+    ///
+    /// The closure is given the contig name, the zero-based position, and the site itself.
+    pub fn for_each_site<F>(self, f: F) -> io::Result<usize>
+    where
+        F: FnMut(&str, u32, &Site<D>) -> io::Result<()>,
+    {
+        let sites = match self.inner {
+            Inner::Standard(readers) => readers.for_each_site(&self.filters, f),
+            Inner::Banded(readers) => readers.for_each_site(&self.filters, f),
+        }?;
+
+        self.filters.log_counts();
+
+        Ok(sites)
     }
 
     /// Returns the shape of the SAF to be read.
     pub fn shape(&self) -> [usize; D] {
-        match self {
-            Self::Standard(readers) => readers.shape(),
-            Self::Banded(readers) => readers.shape(),
+        match &self.inner {
+            Inner::Standard(readers) => readers.shape(),
+            Inner::Banded(readers) => readers.shape(),
         }
     }
 
     /// Pseudo-shuffles the sites in the readers into the provided shuffle writer.
     pub fn shuffle(self, writer: shuffle::Writer<io::BufWriter<File>>) -> io::Result<()> {
-        match self {
-            Self::Standard(readers) => {
-                let intersect = Intersect::new(readers);
-                writer.write_intersect(intersect)
+        match self.inner {
+            Inner::Standard(readers) => writer.write_intersect(readers.intersect(&self.filters)?),
+            Inner::Banded(readers) => writer.write_intersect(readers.intersect(&self.filters)?),
+        }?;
+
+        self.filters.log_counts();
+
+        Ok(())
+    }
+
+    /// Writes the sites in the readers to SAF files with the provided prefixes, one per population,
+    /// and returns the number of sites.
+    ///
+    /// The SAF files are written using the same version as the readers.
+    pub fn write_saf<P>(self, prefixes: &[P; D]) -> io::Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        let alleles = self.shape().map(|n| n - 1);
+
+        let sites = match self.inner {
+            Inner::Standard(readers) => {
+                let mut writer =
+                    Writer::<D, _, saf::version::V3>::from_prefixes(prefixes, alleles)?;
+                let sites = writer.write_intersect(readers.intersect(&self.filters)?)?;
+                writer.finish().map(|_| sites)
             }
-            Self::Banded(readers) => {
-                let intersect = Intersect::new(readers);
-                writer.write_intersect(intersect)
+            Inner::Banded(readers) => {
+                let mut writer =
+                    Writer::<D, _, saf::version::V4>::from_prefixes(prefixes, alleles)?;
+                let sites = writer.write_intersect(readers.intersect(&self.filters)?)?;
+                writer.finish().map(|_| sites)
             }
-        }
+        }?;
+
+        self.filters.log_counts();
+
+        Ok(sites)
+    }
+
+    /// Returns `true` if the readers are readers of banded SAF files.
+    pub fn is_banded(&self) -> bool {
+        matches!(self.inner, Inner::Banded(_))
     }
 
     /// Reads a SAF from the readers.
     ///
     /// Note that this will read a full SAF even if the version is V4. In other words, even if the
-    /// input is banded, a full SAF is read.
-    pub fn read_saf(self) -> io::Result<Saf<D>> {
+    /// input is banded, a full SAF is read. See [`Readers::read_banded_saf`] to keep banded input
+    /// banded in memory.
+    ///
+    /// If `weights` are provided, the returned SAF will be weighted accordingly.
+    pub fn read_saf(self, weights: Option<&Weights>) -> io::Result<Saf<D>> {
         log::info!(
             target: "init",
             "Reading (intersecting) sites in input SAF files into memory",
         );
 
-        let saf = match self {
-            Readers::Standard(readers) => Saf::read(readers),
-            Readers::Banded(readers) => Saf::read_from_banded(readers),
+        let filters = &self.filters;
+        let saf = match (self.inner, weights) {
+            (Inner::Standard(readers), None) => Saf::read(readers.intersect(filters)?),
+            (Inner::Banded(readers), None) => Saf::read_from_banded(readers.intersect(filters)?),
+            (Inner::Standard(readers), Some(weights)) => {
+                Saf::read_weighted(readers.intersect(filters)?, |contig, position| {
+                    weights.get(contig, position)
+                })
+            }
+            (Inner::Banded(readers), Some(weights)) => {
+                Saf::read_weighted_from_banded(readers.intersect(filters)?, |contig, position| {
+                    weights.get(contig, position)
+                })
+            }
         }?;
 
-        log::debug!(
+        filters.log_counts();
+        log_read(saf.sites(), saf.shape(), saf.weights());
+
+        Ok(saf)
+    }
+
+    /// Reads a banded SAF from the readers.
+    ///
+    /// Unlike [`Readers::read_saf`], only the values inside the bands are kept in memory.
+    /// An error is returned if the readers are not readers of banded SAF files.
+    ///
+    /// If `weights` are provided, the returned SAF will be weighted accordingly.
+    pub fn read_banded_saf(self, weights: Option<&Weights>) -> io::Result<BandedSaf<D>> {
+        log::info!(
             target: "init",
-            "Found {sites} (intersecting) sites in SAF files with shape {shape}",
-            sites = saf.sites(),
-            shape = join(saf.shape(), "/"),
+            "Reading (intersecting) sites in input SAF files into memory",
         );
 
+        let filters = &self.filters;
+        let readers = match self.inner {
+            Inner::Banded(readers) => readers,
+            Inner::Standard(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot read full SAF files as banded SAF",
+                ))
+            }
+        };
+        let saf = match weights {
+            None => BandedSaf::read(readers.intersect(filters)?),
+            Some(weights) => {
+                BandedSaf::read_weighted(readers.intersect(filters)?, |contig, position| {
+                    weights.get(contig, position)
+                })
+            }
+        }?;
+
+        filters.log_counts();
+        log_read(saf.sites(), saf.shape(), saf.weights());
+
         Ok(saf)
     }
+
+    /// Reads a separate SAF for each contig from the readers.
+    ///
+    /// The SAFs are returned along with their contig names in the order they occur in the
+    /// readers, and contigs without any (intersecting) sites are left out. Like
+    /// [`Readers::read_saf`], full SAFs are read even if the input is banded.
+    ///
+    /// If `weights` are provided, the returned SAFs will be weighted accordingly.
+    pub fn read_saf_by_contig(
+        self,
+        weights: Option<&Weights>,
+    ) -> io::Result<Vec<(String, Saf<D>)>> {
+        log::info!(
+            target: "init",
+            "Reading (intersecting) sites in input SAF files into memory by contig",
+        );
+
+        let shape = self.shape();
+
+        // Since sites are read in index order, all sites on a contig are read consecutively
+        let mut contigs: Vec<(String, Vec<f32>, Vec<f32>)> = Vec::new();
+        self.for_each_site(|contig, position, site| {
+            match contigs.last() {
+                Some((name, _, _)) if name == contig => (),
+                _ => contigs.push((contig.to_string(), Vec::new(), Vec::new())),
+            }
+
+            let (_, values, contig_weights) = contigs.last_mut().unwrap();
+            values.extend_from_slice(site.as_slice());
+            if let Some(weights) = weights {
+                contig_weights.push(weights.get(contig, position));
+            }
+
+            Ok(())
+        })?;
+
+        let safs = contigs
+            .into_iter()
+            .map(|(contig, values, contig_weights)| {
+                let mut saf = Saf::new(values, shape).expect("shape matches by construction");
+
+                if weights.is_some() {
+                    saf = saf
+                        .with_weights(contig_weights)
+                        .expect("one weight per site by construction");
+                }
+
+                log::debug!(
+                    target: "init",
+                    "Found {sites} (intersecting) sites on contig '{contig}'",
+                    sites = saf.sites(),
+                );
+
+                (contig, saf)
+            })
+            .collect();
+
+        Ok(safs)
+    }
+
+    /// Filters the sites read by the provided masks, each given along with its source path.
+    ///
+    /// See [`Intersect::with_mask`] for details.
+    pub fn with_masks(mut self, masks: Vec<(PathBuf, Mask)>) -> Self {
+        self.filters.masks = masks;
+        self
+    }
+
+    /// Reads only a random subsample of sites, if `subsample` is provided.
+    ///
+    /// If `seed` is `None`, a seed is chosen at random. See [`Intersect::with_subsample`] for
+    /// details.
+    pub fn with_subsample(mut self, subsample: Option<Subsample>, seed: Option<u64>) -> Self {
+        if let Some(subsample) = subsample {
+            let seed = seed.unwrap_or_else(rand::random);
+
+            log::info!(
+                target: "init",
+                "Reading random subsample of {subsample} using seed {seed}",
+            );
+
+            self.filters.subsample = Some((subsample, seed));
+        }
+
+        self
+    }
+
+    /// Reads the union of sites in the readers rather than the intersection, if `union` is set.
+    ///
+    /// See [`Intersect::with_union`] for details.
+    pub fn with_union(mut self, union: bool) -> Self {
+        if union {
+            log::info!(
+                target: "init",
+                "Reading union of sites, imputing missing sites with flat likelihoods",
+            );
+
+            self.filters.union = Some(Imputed::new());
+        }
+
+        self
+    }
+
+    /// Applies the provided site filters to the readers.
+    ///
+    /// This reads any regions file and masks, and restricts the readers accordingly. The `seed` is
+    /// used for any subsample, see [`Readers::with_subsample`].
+    pub fn with_site_filters(self, filters: &SiteFilters, seed: Option<u64>) -> io::Result<Self> {
+        Ok(self
+            .with_regions(collect_regions(
+                &filters.region,
+                filters.regions_file.as_ref(),
+            )?)
+            .with_masks(read_masks(&filters.include, &filters.exclude)?)
+            .with_union(filters.union)
+            .with_subsample(filters.subsample, seed))
+    }
+
+    /// Restricts the readers to the provided regions.
+    ///
+    /// If `regions` is `None`, all sites are read. See [`Intersect::with_regions`] for details.
+    pub fn with_regions(mut self, regions: Option<Vec<Region>>) -> Self {
+        if let Some(regions) = regions.as_ref() {
+            log::info!(
+                target: "init",
+                "Restricting input to {n} region(s)",
+                n = regions.len(),
+            );
+        }
+
+        self.filters.regions = regions;
+        self
+    }
 }
 
 impl<const D: usize> Readers<D, io::BufReader<File>> {
     /// Returns a new collection of SAF file readers from member file paths.
     ///
+    /// Each path may give several sets of SAF files for the population, see [`member_path_sets`].
+    /// The same number of sets must be given for each population, and sets are intersected in
+    /// order, so that e.g. the first set of each population is intersected, then the second, and
+    /// so on. See [`Intersect::chain`] for further requirements.
+    ///
     /// This will automatically attempt to infer the SAF file version based on the magic number of
     /// the first provided path. An error is thrown if the format cannot be inferred based on the
     /// magic number.
@@ -103,24 +431,61 @@ impl<const D: usize> Readers<D, io::BufReader<File>> {
     where
         P: AsRef<Path>,
     {
-        let mut file = File::open(&paths[0])?;
+        let sets = paths
+            .iter()
+            .map(member_path_sets)
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let n = sets[0].len();
+        if sets.iter().any(|population| population.len() != n) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the same number of SAF file sets must be given for each population \
+                    (found {})",
+                    join(sets.iter().map(Vec::len), ", "),
+                ),
+            ));
+        }
+
+        let mut file = File::open(&sets[0][0])?;
         let format = Format::infer_from_magic(&mut file)?;
 
         log::info!(
             target: "init",
             "Opening input {format} ({}) SAF files:\n\t{}",
             format.version_string(),
-            join(paths.iter().map(|p| p.as_ref().display()), "\n\t"),
+            join(sets.iter().flatten().map(|p| p.display()), "\n\t"),
         );
 
-        match format {
-            Format::Standard => create_readers(paths, threads).map(Self::Standard),
-            Format::Banded => create_readers(paths, threads).map(Self::Banded),
+        if n > 1 {
+            log::info!(target: "init", "Reading {n} sets of SAF files per population in order");
+        }
+
+        // Transpose from sets per population to populations per set
+        let parts = (0..n)
+            .map(|i| {
+                sets.iter()
+                    .map(|population| population[i].clone())
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .unwrap()
+            })
+            .collect::<Vec<[PathBuf; D]>>();
+
+        let inner = match format {
+            Format::Standard => create_parts(&parts, threads).map(Inner::Standard),
+            Format::Banded => create_parts(&parts, threads).map(Inner::Banded),
             Format::Shuffled => Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "cannot construct joint reader from shuffled format file",
             )),
-        }
+        }?;
+
+        Ok(Self {
+            inner,
+            filters: Filters::default(),
+        })
     }
 }
 
@@ -132,47 +497,114 @@ trait ReadersExt<const D: usize, R, V>
 where
     V: Version,
 {
-    fn count_sites(self) -> io::Result<usize>;
+    fn count_sites(self, filters: &Filters<D>) -> io::Result<usize>;
+
+    fn intersect(self, filters: &Filters<D>) -> io::Result<Intersect<D, R, V>>;
 
-    fn log_likelihood(self, sfs: Sfs<D>) -> io::Result<(LogLikelihood, usize)>
+    fn log_likelihood(
+        self,
+        filters: &Filters<D>,
+        sfs: Sfs<D>,
+        weights: Option<&Weights>,
+    ) -> io::Result<(LogLikelihood, usize)>
     where
         winsfs_core::io::Intersect<D, R, V>: ReadSite;
 
+    fn for_each_site<F>(self, filters: &Filters<D>, f: F) -> io::Result<usize>
+    where
+        winsfs_core::io::Intersect<D, R, V>: ReadSite,
+        F: FnMut(&str, u32, &Site<D>) -> io::Result<()>;
+
     fn shape(&self) -> [usize; D];
 }
 
-impl<const D: usize, R, V> ReadersExt<D, R, V> for [saf::Reader<R, V>; D]
+impl<const D: usize, R, V> ReadersExt<D, R, V> for Parts<D, R, V>
 where
     R: io::BufRead + io::Seek,
     V: Version,
 {
-    fn count_sites(self) -> io::Result<usize> {
-        if let [single_reader] = &self[..] {
-            return Ok(single_reader.index().total_sites());
+    fn count_sites(self, filters: &Filters<D>) -> io::Result<usize> {
+        if let ([readers], true) = (&self[..], filters.is_empty()) {
+            if let [single_reader] = &readers[..] {
+                return Ok(single_reader.index().total_sites());
+            }
         }
 
-        let mut intersect = saf::reader::Intersect::new(self.into());
-
-        let mut bufs = intersect.create_record_bufs();
+        let mut intersect = self.intersect(filters)?;
 
         let mut sites = 0;
-        while intersect.read_records(&mut bufs)?.is_not_done() {
+        while intersect.read_records()?.is_not_done() {
             sites += 1;
         }
         Ok(sites)
     }
 
-    fn log_likelihood(self, sfs: Sfs<D>) -> io::Result<(LogLikelihood, usize)>
+    fn intersect(self, filters: &Filters<D>) -> io::Result<Intersect<D, R, V>> {
+        let mut intersect = Intersect::chain(self)?;
+
+        if let Some(imputed) = filters.union.as_ref() {
+            intersect = intersect.with_union(imputed.clone());
+        }
+
+        if let Some(regions) = filters.regions.as_ref() {
+            intersect = intersect.with_regions(regions.clone());
+        }
+
+        if let Some((subsample, seed)) = filters.subsample {
+            intersect = intersect.with_subsample(subsample, seed);
+        }
+
+        Ok(filters
+            .masks
+            .iter()
+            .fold(intersect, |intersect, (_, mask)| {
+                intersect.with_mask(mask.clone())
+            }))
+    }
+
+    fn log_likelihood(
+        self,
+        filters: &Filters<D>,
+        sfs: Sfs<D>,
+        weights: Option<&Weights>,
+    ) -> io::Result<(LogLikelihood, usize)>
     where
         winsfs_core::io::Intersect<D, R, V>: ReadSite,
     {
-        let mut intersect = winsfs_core::io::Intersect::new(self);
-        sfs.stream_log_likelihood(&mut intersect)
-            .map(|sum_of| sum_of.into())
+        let mut intersect = self.intersect(filters)?;
+
+        match weights {
+            Some(weights) => sfs.stream_weighted_log_likelihood(&mut intersect, |reader| {
+                weights.get(reader.contig(), reader.position())
+            }),
+            None => sfs.stream_log_likelihood(&mut intersect),
+        }
+        .map(|sum_of| sum_of.into())
+    }
+
+    fn for_each_site<F>(self, filters: &Filters<D>, mut f: F) -> io::Result<usize>
+    where
+        winsfs_core::io::Intersect<D, R, V>: ReadSite,
+        F: FnMut(&str, u32, &Site<D>) -> io::Result<()>,
+    {
+        let shape = self.shape();
+        let mut site = Site::new(vec![0.0; shape.iter().sum()], shape).unwrap();
+
+        let mut intersect = self.intersect(filters)?;
+
+        let mut sites = 0;
+        while intersect.read_site(&mut site)?.is_not_done() {
+            f(intersect.contig(), intersect.position(), &site)?;
+
+            sites += 1;
+        }
+
+        Ok(sites)
     }
 
     fn shape(&self) -> [usize; D] {
-        self.iter()
+        self[0]
+            .iter()
             .map(|reader| reader.index().alleles() + 1)
             .collect::<Vec<_>>()
             .try_into()
@@ -180,6 +612,108 @@ where
     }
 }
 
+/// Logs the number of sites, shape, and total weight of a SAF read into memory.
+fn log_read<const D: usize>(sites: usize, shape: [usize; D], weights: Option<&[f32]>) {
+    log::debug!(
+        target: "init",
+        "Found {sites} (intersecting) sites in SAF files with shape {shape}",
+        shape = join(shape, "/"),
+    );
+
+    if let Some(weights) = weights {
+        log::debug!(
+            target: "init",
+            "Total weight of sites is {total:.6}",
+            total = weights.iter().map(|&x| f64::from(x)).sum::<f64>(),
+        );
+    }
+}
+
+/// Returns the paths of the sets of SAF files given by a single path.
+///
+/// The path may be either a comma-separated list of paths, or a text file with one path per line,
+/// each giving a set of SAF files. In a list file, empty lines and lines starting with '#' are
+/// ignored. A path is read as a list file if it is an existing file which is not recognised as a
+/// SAF file or shuffled SAF file. Otherwise, the path itself is the only set.
+pub fn member_path_sets<P>(path: P) -> io::Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+
+    let sets: Vec<PathBuf> = match path.to_str() {
+        Some(s) if s.contains(',') => s
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from)
+            .collect(),
+        _ if is_list_file(path) => {
+            log::debug!(
+                target: "init",
+                "Reading SAF file sets from path:\n\t{}",
+                path.display()
+            );
+
+            let mut sets = Vec::new();
+            for line in File::open(path).map(io::BufReader::new)?.lines() {
+                let line = line?;
+                let line = line.trim();
+
+                if !(line.is_empty() || line.starts_with('#')) {
+                    sets.push(PathBuf::from(line));
+                }
+            }
+            sets
+        }
+        _ => vec![path.to_path_buf()],
+    };
+
+    if sets.is_empty() {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no SAF file sets found in '{}'", path.display()),
+        ))
+    } else {
+        Ok(sets)
+    }
+}
+
+/// Returns `true` if the path is an existing file without a SAF or shuffled SAF magic number.
+///
+/// Paths with a conventional SAF member file extension are never list files, since the bgzipped
+/// SAF member files cannot be recognised by their magic number.
+fn is_list_file(path: &Path) -> bool {
+    let is_member_path = path.to_str().map_or(false, |s| {
+        [
+            saf::ext::INDEX_EXT,
+            saf::ext::POSITIONS_FILE_EXT,
+            saf::ext::ITEM_FILE_EXT,
+        ]
+        .iter()
+        .any(|ext| s.ends_with(ext))
+    });
+
+    !is_member_path
+        && path.is_file()
+        && File::open(path)
+            .and_then(|mut file| Format::infer_from_magic(&mut file))
+            .is_err()
+}
+
+/// Helper function to set up collections of readers with the provided number of threads.
+fn create_parts<const D: usize, V>(
+    parts: &[[PathBuf; D]],
+    threads: usize,
+) -> io::Result<Parts<D, io::BufReader<File>, V>>
+where
+    V: saf::version::Version,
+{
+    parts
+        .iter()
+        .map(|paths| create_readers(paths, threads))
+        .collect()
+}
+
 /// Helper function to set up a collection of readers with the provided number of threads.
 fn create_readers<const D: usize, P, V>(
     paths: &[P; D],
@@ -208,3 +742,47 @@ where
                 .unwrap()
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_member_path_sets_comma_separated() {
+        assert_eq!(
+            member_path_sets("chr1.saf.idx,chr2.saf.idx").unwrap(),
+            vec![PathBuf::from("chr1.saf.idx"), PathBuf::from("chr2.saf.idx")]
+        );
+        assert!(member_path_sets(",").is_err());
+    }
+
+    #[test]
+    fn test_member_path_sets_single() {
+        assert_eq!(
+            member_path_sets("/does/not/exist").unwrap(),
+            vec![PathBuf::from("/does/not/exist")]
+        );
+    }
+
+    #[test]
+    fn test_member_path_sets_member_file() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/D.banded.saf.gz");
+        assert_eq!(member_path_sets(path).unwrap(), vec![PathBuf::from(path)]);
+    }
+
+    #[test]
+    fn test_member_path_sets_list_file() -> io::Result<()> {
+        let path = std::env::temp_dir().join("winsfs_test_member_path_sets.txt");
+        std::fs::write(&path, "# sets\nchr1.saf.idx\n\nchr2.saf.idx\n")?;
+
+        let sets = member_path_sets(&path);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(
+            sets?,
+            vec![PathBuf::from("chr1.saf.idx"), PathBuf::from("chr2.saf.idx")]
+        );
+
+        Ok(())
+    }
+}
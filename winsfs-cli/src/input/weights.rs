@@ -0,0 +1,287 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgGroup, Args};
+
+/// Options giving per-site weights.
+///
+/// At most one weights file may be given, and its format is given by the option used.
+#[derive(Args, Debug)]
+#[clap(group(ArgGroup::new("weights")))]
+pub struct WeightsArgs {
+    /// Path to BED file with weights of regions.
+    ///
+    /// Each line should contain contig name, zero-based start, exclusive end, and weight of a
+    /// region. Any further fields are ignored, as are empty lines and lines starting with '#'.
+    /// The weight must be given in the fourth column, so that e.g. BED files with a name in the
+    /// fourth column are rejected. Sites not covered by the file have weight one.
+    ///
+    /// The contribution of each site is scaled by its weight, so that e.g. a site with weight zero
+    /// is ignored and a site with weight two counts twice. When estimating, the output SFS is
+    /// scaled by the total weight of all sites. Weights are only supported for SAF file input, not
+    /// for shuffled input.
+    #[clap(long, group = "weights", value_name = "PATH")]
+    pub weights_bed: Option<PathBuf>,
+
+    /// Path to file with weights of single sites.
+    ///
+    /// Each line should contain exactly three fields: contig name, one-based position (as used by
+    /// e.g. 'realSFS print'), and weight of a site. Empty lines and lines starting with '#' are
+    /// ignored. Sites not in the file have weight one. Otherwise, see '--weights-bed'.
+    #[clap(long, group = "weights", value_name = "PATH")]
+    pub weights_sites: Option<PathBuf>,
+}
+
+impl WeightsArgs {
+    /// Returns `true` if a weights file is given.
+    pub fn is_set(&self) -> bool {
+        self.weights_bed.is_some() || self.weights_sites.is_some()
+    }
+
+    /// Reads the weights from the weights file, if any.
+    pub fn read(&self) -> io::Result<Option<Weights>> {
+        match (&self.weights_bed, &self.weights_sites) {
+            (Some(path), _) => Weights::from_path(path, Format::Bed).map(Some),
+            (None, Some(path)) => Weights::from_path(path, Format::Sites).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// The format of a weights file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// Weights of single sites, given by contig name, one-based position, and weight.
+    Sites,
+    /// Weights of regions in BED format, given by contig name, zero-based start, exclusive end,
+    /// and weight.
+    Bed,
+}
+
+/// Per-site weights read from a weights file.
+///
+/// Weights are given for regions on named contigs. Sites not covered by any region have weight
+/// one. See [`Weights::read`] for the supported formats.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Weights {
+    regions: HashMap<String, Vec<Region>>,
+}
+
+/// A weighted region of a contig, given by a zero-based, half-open interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Region {
+    start: u32,
+    end: u32,
+    weight: f32,
+}
+
+impl Weights {
+    /// Creates new weights from a file path in the provided format.
+    pub fn from_path<P>(path: P, format: Format) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        log::debug!(
+            target: "init",
+            "Reading site weights from path:\n\t{}",
+            path.as_ref().display()
+        );
+
+        let weights = File::open(path)
+            .map(io::BufReader::new)
+            .and_then(|reader| Self::read(reader, format))?;
+
+        log::debug!(
+            target: "init",
+            "Found {n} weighted region(s) on {contigs} contig(s)",
+            n = weights.regions.values().map(Vec::len).sum::<usize>(),
+            contigs = weights.regions.len(),
+        );
+
+        Ok(weights)
+    }
+
+    /// Returns the weight of the site at the (zero-based) position on the contig.
+    pub fn get(&self, contig: &str, position: u32) -> f32 {
+        let regions = match self.regions.get(contig) {
+            Some(regions) => regions,
+            None => return 1.0,
+        };
+
+        // Regions are sorted and non-overlapping, so only the last region starting before or at
+        // the position can contain it
+        let i = regions.partition_point(|region| region.start <= position);
+
+        match i.checked_sub(1).map(|i| regions[i]) {
+            Some(region) if position < region.end => region.weight,
+            _ => 1.0,
+        }
+    }
+
+    /// Reads weights from a reader in the provided format.
+    ///
+    /// Each non-empty line not starting with '#' should contain whitespace-separated fields. For
+    /// [`Format::Sites`], lines should contain exactly three fields giving the weight of a single
+    /// site as contig name, one-based position (as used by e.g. `realSFS print`), and weight. For
+    /// [`Format::Bed`], lines should contain at least four fields giving the weight of a region as
+    /// contig name, zero-based start, exclusive end, and weight; any further fields are ignored.
+    ///
+    /// Weights must be finite and non-negative, and regions must not overlap.
+    pub fn read<R>(reader: R, format: Format) -> io::Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut regions: HashMap<String, Vec<Region>> = HashMap::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (contig, region) = parse_line(line, format).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("failed to parse weights on line {}: {e}", i + 1),
+                )
+            })?;
+
+            regions.entry(contig.to_string()).or_default().push(region);
+        }
+
+        for (contig, regions) in regions.iter_mut() {
+            regions.sort_by_key(|region| region.start);
+
+            if let Some(w) = regions.windows(2).find(|w| w[0].end > w[1].start) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "overlapping weight regions {}-{} and {}-{} on contig '{contig}'",
+                        w[0].start, w[0].end, w[1].start, w[1].end,
+                    ),
+                ));
+            }
+        }
+
+        Ok(Self { regions })
+    }
+}
+
+/// Parses a single line of a weights file in the provided format into a contig name and a region.
+fn parse_line(line: &str, format: Format) -> Result<(&str, Region), String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+
+    let parse_position = |s: &str| {
+        s.parse::<u32>()
+            .map_err(|_| format!("invalid position '{s}'"))
+    };
+
+    let (contig, start, end, weight) = match (format, &fields[..]) {
+        (Format::Sites, &[contig, position, weight]) => {
+            let position = parse_position(position)?;
+            let start = position
+                .checked_sub(1)
+                .ok_or_else(|| String::from("positions must be one-based"))?;
+
+            (contig, start, position, weight)
+        }
+        (Format::Sites, _) => {
+            return Err(format!(
+                "expected contig, position, and weight, found {} field(s)",
+                fields.len()
+            ))
+        }
+        (Format::Bed, &[contig, start, end, weight, ..]) => {
+            (contig, parse_position(start)?, parse_position(end)?, weight)
+        }
+        (Format::Bed, _) => {
+            return Err(format!(
+                "expected contig, start, end, and weight, found {} field(s)",
+                fields.len()
+            ))
+        }
+    };
+
+    if start >= end {
+        return Err(format!("empty region {start}-{end}"));
+    }
+
+    let weight = weight
+        .parse::<f32>()
+        .ok()
+        .filter(|weight| weight.is_finite() && *weight >= 0.0)
+        .ok_or_else(|| format!("invalid weight '{weight}', must be finite and non-negative"))?;
+
+    Ok((contig, Region { start, end, weight }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_sites() {
+        let src = b"# comment\nchr1\t5\t0.5\n\nchr1 7 2\nchr2\t1\t0\n";
+        let weights = Weights::read(&src[..], Format::Sites).unwrap();
+
+        assert_eq!(weights.get("chr1", 3), 1.0);
+        assert_eq!(weights.get("chr1", 4), 0.5);
+        assert_eq!(weights.get("chr1", 5), 1.0);
+        assert_eq!(weights.get("chr1", 6), 2.0);
+        assert_eq!(weights.get("chr2", 0), 0.0);
+        assert_eq!(weights.get("chr3", 0), 1.0);
+    }
+
+    #[test]
+    fn test_read_bed() {
+        let src = b"# comment\nchr1 10 20 2.0 name\n\nchr2\t0\t5\t0\n";
+        let weights = Weights::read(&src[..], Format::Bed).unwrap();
+
+        assert_eq!(weights.get("chr1", 9), 1.0);
+        assert_eq!(weights.get("chr1", 10), 2.0);
+        assert_eq!(weights.get("chr1", 19), 2.0);
+        assert_eq!(weights.get("chr1", 20), 1.0);
+        assert_eq!(weights.get("chr2", 0), 0.0);
+        assert_eq!(weights.get("chr3", 0), 1.0);
+    }
+
+    #[test]
+    fn test_read_bed3_errors() {
+        let src = b"chr1\t100\t200\n";
+        assert!(Weights::read(&src[..], Format::Bed).is_err());
+    }
+
+    #[test]
+    fn test_read_bed_with_name_errors() {
+        let src = b"chr1\t100\t200\tname\t0\t+\n";
+        assert!(Weights::read(&src[..], Format::Bed).is_err());
+    }
+
+    #[test]
+    fn test_read_errors() {
+        for src in [
+            &b"chr1\t1\n"[..],
+            b"chr1\t0\t1.0\n",
+            b"chr1\t1\t-1.0\n",
+            b"chr1\t1\tNaN\n",
+            b"chr1\tx\t1.0\n",
+            b"chr1\t0\t10\t1.0\n",
+            b"chr1\t1\t1.0\nchr1\t1\t1.0\n",
+        ] {
+            assert!(Weights::read(src, Format::Sites).is_err());
+        }
+
+        for src in [
+            &b"chr1\t5\t5\t1.0\n"[..],
+            b"chr1\t0\t10\t1.0\nchr1\t5\t6\t1.0\n",
+        ] {
+            assert!(Weights::read(src, Format::Bed).is_err());
+        }
+    }
+}
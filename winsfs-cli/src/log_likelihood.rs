@@ -1,13 +1,36 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fs::File,
+    io::{self, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
 
-use clap::{error::Result as ClapResult, Args};
+use clap::{
+    error::{ErrorKind, Result as ClapResult},
+    ArgAction, Args, CommandFactory,
+};
 
-use crate::{cli::MAX_PATHS, input};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use winsfs_core::{em::EmSite, sfs::Sfs};
+
+use crate::{
+    cli::{Cli, MAX_PATHS},
+    input::{self, regions::SiteFilters, weights::WeightsArgs},
+};
 
 /// Calculate log-likelihood of site frequency spectrum.
 ///
 /// The SAF files will be streamed, and therefore the calculation requires only constant memory
 /// usage.
+///
+/// If a single SFS is provided, the log-likelihood is written to stdout. If more than one SFS is
+/// provided, all are evaluated in a single pass over the data. In that case, a table is written
+/// with the log-likelihood and the per-site average log-likelihood of each SFS, followed by a table
+/// with the difference in log-likelihood between each pair of SFS and its standard error, as
+/// estimated by a bootstrap over blocks of consecutive sites.
+///
+/// Alternatively, the log-likelihood of each site can be written using '--per-site'.
 #[derive(Args, Debug)]
 pub struct LogLikelihood {
     /// Input SAF file paths.
@@ -16,22 +39,110 @@ pub struct LogLikelihood {
     /// specify either the shared prefix or the full path to any one member file.
     /// Up to three SAF files currently supported (six with the experimental '--features hd' compile
     /// flag).
+    ///
+    /// Each population may also be given by several sets of SAF files, e.g. one per chromosome,
+    /// either as a comma-separated list, or as a text file with one set per line. The sets for each
+    /// population are read one after another, and the same number of sets must be given for each
+    /// population. Sets are intersected in order, so a contig must not occur in more than one set.
     #[clap(value_parser, num_args = 1..=MAX_PATHS, required = true, value_name = "PATHS")]
     pub paths: Vec<PathBuf>,
 
+    /// Calculate log-likelihood of folded SFS.
+    ///
+    /// If set, the SFS is taken to be folded, and the likelihood of each site is computed by
+    /// averaging over the indistinguishable, unfolded combinations. This matches the likelihood
+    /// used when estimating with `--folded`. An unfolded input SFS will be folded before use.
+    #[clap(long)]
+    pub folded: bool,
+
     /// Input SFS to calculate log-likelihood from.
-    #[clap(short = 'i', long)]
-    pub sfs: PathBuf,
+    ///
+    /// Can be provided multiple times to compare the log-likelihoods of more than one SFS.
+    #[clap(short = 'i', long, required = true, action = ArgAction::Append)]
+    pub sfs: Vec<PathBuf>,
+
+    /// Number of consecutive sites in each block used for bootstrapping differences.
+    ///
+    /// Only used when more than one SFS is provided.
+    #[clap(
+        short = 'b',
+        long,
+        default_value_t = NonZeroUsize::new(10_000).unwrap(),
+        value_name = "INT"
+    )]
+    pub block_size: NonZeroUsize,
+
+    /// Number of bootstrap replicates used for standard errors of differences.
+    ///
+    /// Only used when more than one SFS is provided. At least two replicates are required.
+    #[clap(
+        short = 'B',
+        long,
+        value_parser = parse_replicates,
+        default_value_t = 1000,
+        value_name = "INT"
+    )]
+    pub bootstrap: usize,
+
+    /// Random seed used for bootstrapping and '--subsample'.
+    ///
+    /// If unset, a seed will be chosen at random.
+    #[clap(short = 's', long, value_name = "INT")]
+    pub seed: Option<u64>,
 
     /// Number of threads to use for reading.
     ///
     /// If set to 0, all available cores will be used.
     #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
     pub threads: usize,
+
+    #[clap(flatten)]
+    pub weights: WeightsArgs,
+
+    #[clap(flatten)]
+    pub site_filters: SiteFilters,
+
+    /// Write log-likelihood of each site.
+    ///
+    /// If set, a line is written to stdout for each site with the contig name, the one-based
+    /// position, and the log-likelihood of the site given the SFS, after a header line starting
+    /// with '#'. Sites with a very low log-likelihood may indicate e.g. paralogs or mapping
+    /// artefacts. Requires a single SFS.
+    #[clap(long, conflicts_with = "weights", help_heading = "Per-site")]
+    pub per_site: bool,
+
+    /// Only write sites with log-likelihood below threshold.
+    #[clap(
+        long,
+        requires = "per_site",
+        allow_negative_numbers = true,
+        help_heading = "Per-site",
+        value_name = "FLOAT"
+    )]
+    pub threshold: Option<f64>,
+
+    /// Output path for BED file of sites with log-likelihood below threshold.
+    ///
+    /// Consecutive flagged sites are merged into a single region. The file can be used to exclude
+    /// the flagged sites in subsequent analyses.
+    #[clap(
+        long,
+        requires = "threshold",
+        help_heading = "Per-site",
+        value_name = "PATH"
+    )]
+    pub flagged: Option<PathBuf>,
 }
 
 impl LogLikelihood {
     pub fn run(self) -> ClapResult<()> {
+        if self.per_site && self.sfs.len() > 1 {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "per-site log-likelihoods require a single SFS",
+            ));
+        }
+
         match &self.paths[..] {
             [p] => self.run_n([p]),
             [p1, p2] => self.run_n([p1, p2]),
@@ -50,34 +161,313 @@ impl LogLikelihood {
     where
         P: AsRef<Path>,
     {
-        let sfs = input::sfs::Reader::from_path(&self.sfs)?
-            .read::<D>()?
-            .normalise();
+        let sfs = self
+            .sfs
+            .iter()
+            .map(|path| {
+                let sfs = input::sfs::Reader::from_path(path)?
+                    .read::<D>()?
+                    .normalise();
+
+                Ok(if self.folded { sfs.symmetrise() } else { sfs })
+            })
+            .collect::<ClapResult<Vec<_>>>()?;
 
-        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?;
+        let weights = self.weights.read()?;
+
+        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?
+            .with_site_filters(&self.site_filters, self.seed)?;
 
         log::info!(
             target: "init",
             "Streaming (intersecting) sites in input SAF files",
         );
 
-        let (log_likelihood, sites) = readers.log_likelihood(sfs)?;
+        match &sfs[..] {
+            [sfs] if self.per_site => self.write_per_site(readers, sfs.clone()),
+            [sfs] => {
+                let (log_likelihood, sites) =
+                    readers.log_likelihood(sfs.clone(), weights.as_ref())?;
+
+                log::info!(target: "log-likelihood", "Processed {sites} sites");
+
+                println!("{}", f64::from(log_likelihood));
+
+                Ok(())
+            }
+            _ => self.compare(readers, sfs, weights.as_ref()),
+        }
+    }
+
+    /// Writes the log-likelihood of each site given the SFS.
+    fn write_per_site<const D: usize>(
+        &self,
+        readers: input::saf::Readers<D, io::BufReader<File>>,
+        sfs: Sfs<D>,
+    ) -> ClapResult<()> {
+        let sfs = sfs.restricted();
+
+        let mut writer = io::BufWriter::new(io::stdout().lock());
+        writeln!(writer, "#chrom\tpos\tlog_likelihood")?;
+
+        let mut flagged = self
+            .flagged
+            .as_ref()
+            .map(FlaggedWriter::create)
+            .transpose()?;
+        let mut flagged_sites = 0;
+
+        let sites = readers.for_each_site(|contig, position, site| {
+            let log_likelihood = f64::from(site.log_likelihood(&sfs));
+
+            match self.threshold {
+                Some(threshold) if log_likelihood >= threshold => return Ok(()),
+                Some(_) => flagged_sites += 1,
+                None => (),
+            }
+
+            if let Some(flagged) = flagged.as_mut() {
+                flagged.add(contig, position)?;
+            }
+
+            writeln!(
+                writer,
+                "{contig}\t{pos}\t{log_likelihood}",
+                pos = position + 1
+            )
+        })?;
+
+        writer.flush()?;
+        if let Some(flagged) = flagged {
+            flagged.finish()?;
+        }
 
         log::info!(target: "log-likelihood", "Processed {sites} sites");
 
-        println!("{}", f64::from(log_likelihood));
+        if let Some(threshold) = self.threshold {
+            log::info!(
+                target: "log-likelihood",
+                "Found {flagged_sites} sites with log-likelihood below {threshold}",
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Compares the log-likelihoods of multiple SFS.
+    fn compare<const D: usize>(
+        &self,
+        readers: input::saf::Readers<D, io::BufReader<File>>,
+        sfs: Vec<Sfs<D>>,
+        weights: Option<&input::weights::Weights>,
+    ) -> ClapResult<()> {
+        let sfs: Vec<Sfs<D>> = sfs.into_iter().map(Sfs::restricted).collect();
+
+        // Sums of log-likelihoods in each block, and the total weight of sites
+        let mut blocks: Vec<Vec<f64>> = Vec::new();
+        let mut block_sites = 0;
+        let mut total_weight = 0.0;
+
+        let sites = readers.for_each_site(|contig, position, site| {
+            let weight = weights
+                .map(|weights| f64::from(weights.get(contig, position)))
+                .unwrap_or(1.0);
+            total_weight += weight;
+
+            if block_sites == 0 {
+                blocks.push(vec![0.0; sfs.len()]);
+            }
+            block_sites = (block_sites + 1) % self.block_size.get();
+
+            // Just pushed if empty, so cannot fail
+            let block = blocks.last_mut().unwrap();
+            for (sum, sfs) in block.iter_mut().zip(sfs.iter()) {
+                *sum += weight * f64::from(site.log_likelihood(sfs));
+            }
+
+            Ok(())
+        })?;
+
+        log::info!(
+            target: "log-likelihood",
+            "Processed {sites} sites in {n} blocks",
+            n = blocks.len(),
+        );
+
+        let totals: Vec<f64> = (0..sfs.len())
+            .map(|i| blocks.iter().map(|block| block[i]).sum())
+            .collect();
+
+        println!("#sfs\tlog_likelihood\tper_site");
+        for (path, total) in self.sfs.iter().zip(totals.iter()) {
+            println!(
+                "{path}\t{total}\t{per_site}",
+                path = path.display(),
+                per_site = total / total_weight,
+            );
+        }
+
+        let standard_errors = self.bootstrap_standard_errors(&blocks);
+
+        println!("#first\tsecond\tdifference\tstandard_error");
+        for ((i, j), standard_error) in pairs(sfs.len()).zip(standard_errors) {
+            println!(
+                "{first}\t{second}\t{difference}\t{standard_error}",
+                first = self.sfs[i].display(),
+                second = self.sfs[j].display(),
+                difference = totals[i] - totals[j],
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the bootstrap standard errors of the differences in total log-likelihood between
+    /// all pairs of SFS, given the log-likelihood sums of each SFS in each block.
+    ///
+    /// Pairs are ordered as given by [`pairs`].
+    fn bootstrap_standard_errors(&self, blocks: &[Vec<f64>]) -> Vec<f64> {
+        let n = blocks.first().map(|block| block.len()).unwrap_or(0);
+
+        let mut rng = match self.seed {
+            Some(v) => StdRng::seed_from_u64(v),
+            None => StdRng::from_entropy(),
+        };
+
+        log::debug!(
+            target: "log-likelihood",
+            "Running {replicates} bootstrap replicates over {blocks} blocks",
+            replicates = self.bootstrap,
+            blocks = blocks.len(),
+        );
+
+        let replicates: Vec<Vec<f64>> = (0..self.bootstrap)
+            .map(|_| {
+                let mut totals = vec![0.0; n];
+                for _ in 0..blocks.len() {
+                    let block = &blocks[rng.gen_range(0..blocks.len())];
+                    totals.iter_mut().zip(block).for_each(|(x, y)| *x += y);
+                }
+
+                pairs(n).map(|(i, j)| totals[i] - totals[j]).collect()
+            })
+            .collect();
+
+        (0..pairs(n).count())
+            .map(|k| standard_deviation(replicates.iter().map(|replicate| replicate[k])))
+            .collect()
+    }
+}
+
+/// A writer for BED regions of consecutive sites.
+struct FlaggedWriter {
+    writer: io::BufWriter<File>,
+    // Contig name, zero-based start, and exclusive end of the current region
+    current: Option<(String, u32, u32)>,
+    regions: usize,
+}
+
+impl FlaggedWriter {
+    /// Creates a new writer to a file path.
+    fn create<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        log::debug!(
+            target: "init",
+            "Writing flagged sites to path:\n\t{}",
+            path.as_ref().display()
+        );
+
+        Ok(Self {
+            writer: File::create(path).map(io::BufWriter::new)?,
+            current: None,
+            regions: 0,
+        })
+    }
+
+    /// Adds a site at the zero-based position on the contig.
+    ///
+    /// Sites must be added in sorted order within each contig.
+    fn add(&mut self, contig: &str, position: u32) -> io::Result<()> {
+        match self.current.as_mut() {
+            Some((current, _, end)) if current == contig && *end == position => {
+                *end += 1;
+                return Ok(());
+            }
+            _ => self.write_current()?,
+        }
+
+        self.current = Some((contig.to_string(), position, position + 1));
 
         Ok(())
     }
+
+    /// Writes the remaining region and flushes the writer.
+    fn finish(mut self) -> io::Result<()> {
+        self.write_current()?;
+
+        log::debug!(
+            target: "log-likelihood",
+            "Wrote {regions} flagged regions",
+            regions = self.regions,
+        );
+
+        self.writer.flush()
+    }
+
+    /// Writes the current region, if any.
+    fn write_current(&mut self) -> io::Result<()> {
+        if let Some((contig, start, end)) = self.current.take() {
+            self.regions += 1;
+
+            writeln!(self.writer, "{contig}\t{start}\t{end}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a number of bootstrap replicates, of which there must be at least two.
+fn parse_replicates(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(v) if v >= 2 => Ok(v),
+        Ok(v) => Err(format!("at least two replicates are required, found {v}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Returns an iterator over all pairs of indices `(i, j)` with `i < j < n`.
+fn pairs(n: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..n).flat_map(move |i| (i + 1..n).map(move |j| (i, j)))
+}
+
+/// Returns the sample standard deviation of the values.
+///
+/// This is NaN if there are fewer than two values.
+fn standard_deviation<I>(values: I) -> f64
+where
+    I: Iterator<Item = f64> + Clone,
+{
+    let (n, sum) = values
+        .clone()
+        .fold((0., 0.), |(n, sum), x| (n + 1., sum + x));
+    let mean = sum / n;
+
+    let squares: f64 = values.map(|x| (x - mean).powi(2)).sum();
+
+    (squares / (n - 1.)).sqrt()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use clap::{error::ErrorKind, Parser};
+    use clap::Parser;
 
-    use crate::cli::{Cli, Command};
+    use winsfs_core::io::Region;
+
+    use crate::cli::Command;
 
     fn try_parse_args(cmd: &str) -> ClapResult<LogLikelihood> {
         Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
@@ -110,6 +500,118 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_multiple_sfs() {
+        let args = parse_args("winsfs log-likelihood -i sfs1 --sfs sfs2 -i sfs3 -B 200 -s 1 saf");
+        assert_eq!(
+            args.sfs,
+            vec![
+                PathBuf::from("sfs1"),
+                PathBuf::from("sfs2"),
+                PathBuf::from("sfs3")
+            ]
+        );
+        assert_eq!(args.paths, vec![PathBuf::from("saf")]);
+        assert_eq!(args.bootstrap, 200);
+        assert_eq!(args.seed, Some(1));
+    }
+
+    #[test]
+    fn test_zero_block_size() {
+        let result = try_parse_args("winsfs log-likelihood -i sfs1 -i sfs2 -b 0 saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_too_few_bootstrap_replicates() {
+        for replicates in [0, 1] {
+            let result = try_parse_args(&format!(
+                "winsfs log-likelihood -i sfs1 -i sfs2 -B {replicates} saf"
+            ));
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+        }
+    }
+
+    #[test]
+    fn test_per_site() {
+        let args = parse_args(
+            "winsfs log-likelihood -i sfs --per-site --threshold -10 --flagged out.bed saf",
+        );
+        assert!(args.per_site);
+        assert_eq!(args.threshold, Some(-10.));
+        assert_eq!(args.flagged, Some(PathBuf::from("out.bed")));
+    }
+
+    #[test]
+    fn test_per_site_requirements() {
+        let result = try_parse_args("winsfs log-likelihood -i sfs --threshold -10 saf");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+
+        let result = try_parse_args("winsfs log-likelihood -i sfs --per-site --flagged bed saf");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+
+        let result = try_parse_args("winsfs log-likelihood -i sfs --per-site --weights-bed w saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        // Checked before reading any input, so that the paths need not exist
+        let result = parse_args("winsfs log-likelihood -i sfs1 -i sfs2 --per-site saf").run();
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_regions() {
+        let args = parse_args("winsfs log-likelihood -i sfs -r chr1:1-100 -r chr2 saf");
+        assert_eq!(
+            args.site_filters.region,
+            vec![
+                Region::new("chr1", 0, 100).unwrap(),
+                Region::whole_contig("chr2")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_union() {
+        let args = parse_args("winsfs log-likelihood -i sfs --union saf1 saf2");
+        assert!(args.site_filters.union);
+    }
+
+    #[test]
+    fn test_pairs() {
+        assert_eq!(pairs(1).count(), 0);
+        assert_eq!(pairs(3).collect::<Vec<_>>(), vec![(0, 1), (0, 2), (1, 2)]);
+    }
+
+    #[test]
+    fn test_standard_deviation() {
+        let values = [2., 4., 4., 4., 5., 5., 7., 9.];
+        let expected = (32f64 / 7.).sqrt();
+        assert!((standard_deviation(values.into_iter()) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_weights() {
+        assert_eq!(
+            parse_args(
+                "winsfs log-likelihood --weights-bed /path/to/weights --sfs /path/to/sfs saf"
+            )
+            .weights
+            .weights_bed,
+            Some(PathBuf::from("/path/to/weights"))
+        );
+    }
+
+    #[test]
+    fn test_folded() {
+        assert!(parse_args("winsfs log-likelihood --folded --sfs /path/to/sfs saf").folded);
+    }
+
     #[test]
     fn test_missing_sfs() {
         let result = try_parse_args("winsfs log-likelihood --sfs /path/to/saf");
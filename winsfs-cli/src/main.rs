@@ -7,11 +7,32 @@ use cli::Cli;
 
 mod estimate;
 
+mod expected;
+pub use expected::Expected;
+
+mod fit;
+pub use fit::Fit;
+
+mod fst;
+pub use fst::Fst;
+
+mod info;
+pub use info::Info;
+
 mod input;
 
 mod log_likelihood;
 pub use log_likelihood::LogLikelihood;
 
+mod posterior;
+pub use posterior::Posterior;
+
+mod saf_convert;
+pub use saf_convert::SafConvert;
+
+mod saf_filter;
+pub use saf_filter::SafFilter;
+
 mod shuffle;
 pub use shuffle::Shuffle;
 
@@ -21,11 +42,16 @@ pub use split::Split;
 mod stat;
 pub use stat::Stat;
 
+mod thetas;
+pub use thetas::Thetas;
+
 pub mod utils;
 
 mod view;
 pub use view::View;
 
+mod windows;
+
 fn main() {
     let args = Cli::parse();
 
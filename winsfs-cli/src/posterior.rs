@@ -0,0 +1,171 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{error::Result as ClapResult, Args};
+
+use winsfs_core::{
+    em::EmSite,
+    sfs::{Sfs, USfs},
+};
+
+use crate::{input, utils::join};
+
+/// Header line of the output.
+const HEADER: &str = "#chrom\tpos\tmean\tp_variable\twatterson\tpi";
+
+/// Calculate per-site posterior statistics using SFS as prior.
+///
+/// For each site in the input SAF file, the SFS is used as a prior to calculate the posterior
+/// distribution of the derived allele count. From this, the posterior mean derived allele count,
+/// the posterior probability of the site being variable, and the expected contributions of the
+/// site to Watterson's and Tajima's estimators of theta are calculated. This corresponds to
+/// `realSFS saf2theta`.
+///
+/// Output is written to stdout as BGZF-compressed, tab-separated text with a header line starting
+/// with '#', followed by one line per site with the contig name, the one-based position, and the
+/// statistics above. The output can be indexed with `tabix -s1 -b2 -e2`. The SAF file will be
+/// streamed, and therefore the calculation requires only constant memory usage.
+#[derive(Args, Debug)]
+pub struct Posterior {
+    /// Input SAF file path.
+    ///
+    /// For the set of SAF files (conventially named 'prefix'.{saf.idx,saf.pos.gz,saf.gz}),
+    /// specify either the shared prefix or the full path to any one member file. Only a single
+    /// SAF file is currently supported.
+    #[clap(value_parser, value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Input SFS to use as prior.
+    ///
+    /// The SFS will be normalised, so the input SFS does not need to be normalised. The shape of
+    /// the SFS must match the shape of the SAF file.
+    #[clap(short = 'i', long)]
+    pub sfs: PathBuf,
+
+    /// Number of threads to use for reading.
+    ///
+    /// If set to 0, all available cores will be used.
+    #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
+    pub threads: usize,
+}
+
+impl Posterior {
+    pub fn run(self) -> ClapResult<()> {
+        let readers = input::saf::Readers::from_member_paths(&[&self.path], self.threads)?;
+        let prior = read_prior(&self.sfs, readers.shape())?;
+
+        let n = prior.shape()[0] - 1;
+        let mut posterior = USfs::zeros(*prior.shape());
+        let mut buf = USfs::zeros(*prior.shape());
+
+        let mut writer = bgzf::Writer::new(io::stdout().lock());
+        writeln!(writer, "{HEADER}")?;
+
+        log::info!(target: "init", "Streaming sites in input SAF file");
+
+        let sites = readers.for_each_site(|contig, position, site| {
+            posterior.iter_mut().for_each(|v| *v = 0.0);
+            site.posterior_into(&prior, &mut posterior, &mut buf);
+
+            let values = posterior.as_slice();
+            let mean: f64 = values.iter().enumerate().map(|(k, p)| k as f64 * p).sum();
+            let p_variable = 1.0 - values[0] - values[n];
+
+            // Prior is checked to have at least three values when read, so these cannot fail
+            let watterson = posterior.theta_watterson().unwrap();
+            let pi = posterior.theta_pi().unwrap();
+
+            writeln!(
+                writer,
+                "{contig}\t{pos}\t{mean:.6}\t{p_variable:.6e}\t{watterson:.6e}\t{pi:.6e}",
+                pos = position + 1,
+            )
+        })?;
+
+        writer.finish()?.flush()?;
+
+        log::info!(target: "posterior", "Processed {sites} sites");
+
+        Ok(())
+    }
+}
+
+/// Reads an SFS to use as prior for per-site posteriors.
+///
+/// The SFS is restricted to be strictly positive, since the posterior is otherwise not well-defined
+/// for sites with information only in the zero part of the SFS, and then normalised. An error is
+/// returned if the shape of the SFS does not match the provided shape, or if the SFS has fewer
+/// than three values in any dimension.
+pub fn read_prior<const D: usize, P>(path: P, shape: [usize; D]) -> io::Result<Sfs<D>>
+where
+    P: AsRef<Path>,
+{
+    let mut prior = input::sfs::Reader::from_path(path)?.read::<D>()?;
+
+    if *prior.shape() != shape {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "shape of SFS ({}) does not match shape of SAF files ({})",
+                join(prior.shape(), "/"),
+                join(shape, "/"),
+            ),
+        ));
+    }
+
+    if shape.iter().any(|&x| x < 3) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "posterior statistics require SFS with at least three values in each dimension",
+        ));
+    }
+
+    prior.iter_mut().for_each(|v| *v = v.max(f64::EPSILON));
+
+    Ok(prior.normalise())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{error::ErrorKind, Parser};
+
+    use crate::cli::{Cli, Command};
+
+    fn try_parse_args(cmd: &str) -> ClapResult<Posterior> {
+        Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
+            Some(Command::Posterior(posterior)) => posterior,
+            _ => panic!(),
+        })
+    }
+
+    fn parse_args(cmd: &str) -> Posterior {
+        try_parse_args(cmd).expect("failed to parse subcommand")
+    }
+
+    #[test]
+    fn test_basic() {
+        let args = parse_args("winsfs posterior --sfs /path/to/sfs -t 2 saf");
+        assert_eq!(args.path, PathBuf::from("saf"));
+        assert_eq!(args.sfs, PathBuf::from("/path/to/sfs"));
+        assert_eq!(args.threads, 2);
+    }
+
+    #[test]
+    fn test_multiple_safs() {
+        let result = try_parse_args("winsfs posterior --sfs /path/to/sfs saf1 saf2");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn test_missing_sfs() {
+        let result = try_parse_args("winsfs posterior saf");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument,
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    num::NonZeroUsize,
+    path::PathBuf,
+    thread,
+};
+
+use angsd_saf as saf;
+use saf::{
+    ext::INDEX_EXT,
+    version::{Version, V3, V4},
+};
+
+use clap::{error::Result as ClapResult, Args};
+
+use winsfs_core::io::{Banding, Intersect, Writer};
+
+use crate::{estimate::Format, info::prefix_from_member_path};
+
+/// Convert SAF files between the full (v3) and banded (v4) formats.
+///
+/// Full SAF files are converted to banded SAF files, and banded SAF files are converted to full
+/// SAF files. Contigs and positions are preserved.
+///
+/// When converting to banded SAF files, the band of each site is the smallest range of sample
+/// frequencies containing all likelihoods of at least '--threshold' relative to the largest
+/// likelihood of the site, and only likelihoods in the tails are dropped. The number of sites, the
+/// number of likelihood values before and after banding, their ratio, and the largest probability
+/// mass dropped from any site are written to stdout in tab-separated format. The dropped mass of
+/// a site is the sum of the dropped likelihoods relative to the sum of all likelihoods of the site.
+///
+/// When converting to full SAF files, likelihoods outside the band are written as zero.
+#[derive(Args, Debug)]
+pub struct SafConvert {
+    /// Input SAF file path.
+    ///
+    /// For a set of SAF files (conventially named 'prefix'.{saf.idx,saf.pos.gz,saf.gz}), specify
+    /// either the shared prefix or the full path to any one member file.
+    #[clap(value_parser, value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Output SAF file prefix.
+    ///
+    /// The output SAF files are written to '<prefix>.{saf.idx,saf.pos.gz,saf.gz}'. Existing files
+    /// are overwritten.
+    #[clap(short = 'o', long, value_name = "PREFIX")]
+    pub output: PathBuf,
+
+    /// Relative likelihood threshold for banding.
+    ///
+    /// Likelihoods in the tails of a site less than this fraction of the largest likelihood of the
+    /// site are dropped. Only used when converting full SAF files to banded SAF files.
+    #[clap(long, value_parser = parse_threshold, default_value_t = 1e-6, value_name = "FLOAT")]
+    pub threshold: f32,
+
+    /// Number of threads to use for reading.
+    ///
+    /// If set to 0, all available cores will be used.
+    #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
+    pub threads: usize,
+}
+
+impl SafConvert {
+    pub fn run(self) -> ClapResult<()> {
+        let prefix = prefix_from_member_path(&self.path)?;
+        let format = Format::infer_from_magic(&mut File::open(format!("{prefix}.{INDEX_EXT}"))?)?;
+
+        match format {
+            Format::Standard => {
+                log::info!(
+                    target: "init",
+                    "Converting full SAF files to banded SAF files using threshold {}",
+                    self.threshold,
+                );
+
+                let intersect = self.intersect::<V3>(&prefix)?;
+                let alleles = alleles(&intersect);
+
+                let mut writer = Writer::<1, _, V4>::from_prefixes(&[&self.output], alleles)?;
+                let banding = writer.write_intersect_banded(intersect, self.threshold)?;
+                writer.finish()?;
+
+                write_banding(&mut io::stdout().lock(), &banding)?;
+            }
+            Format::Banded => {
+                log::info!(target: "init", "Converting banded SAF files to full SAF files");
+
+                let intersect = self.intersect::<V4>(&prefix)?;
+                let alleles = alleles(&intersect);
+
+                let mut writer = Writer::<1, _, V3>::from_prefixes(&[&self.output], alleles)?;
+                let sites = writer.write_intersect_full(intersect)?;
+                writer.finish()?;
+
+                log::info!(target: "init", "Wrote {sites} sites");
+            }
+            Format::Shuffled => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot convert shuffled format file",
+                )
+                .into())
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a reader of the input SAF files with the provided shared prefix.
+    fn intersect<V>(&self, prefix: &str) -> io::Result<Intersect<1, io::BufReader<File>, V>>
+    where
+        V: Version,
+    {
+        let threads = NonZeroUsize::new(self.threads).unwrap_or(thread::available_parallelism()?);
+
+        log::info!(
+            target: "init",
+            "Opening input SAF files with prefix:\n\t{prefix}",
+        );
+
+        saf::reader::Builder::<V>::default()
+            .set_threads(threads)
+            .build_from_prefix(prefix)
+            .map(|reader| Intersect::new([reader]))
+    }
+}
+
+/// Returns the number of alleles of the reader.
+fn alleles<R, V>(intersect: &Intersect<1, R, V>) -> [usize; 1]
+where
+    R: io::BufRead + io::Seek,
+    V: Version,
+{
+    [intersect.get().get_readers()[0].index().alleles()]
+}
+
+/// Writes a summary of banding as a header line and a line of tab-separated values.
+fn write_banding<W>(writer: &mut W, banding: &Banding) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(
+        writer,
+        "#sites\tvalues\tband_values\tcompression\tmax_dropped"
+    )?;
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{:.6}\t{:.6e}",
+        banding.sites(),
+        banding.values(),
+        banding.band_values(),
+        banding.compression(),
+        banding.max_dropped(),
+    )
+}
+
+/// Parses a banding threshold between zero and one, inclusive.
+fn parse_threshold(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(v) if (0.0..=1.0).contains(&v) => Ok(v),
+        Ok(v) => Err(format!("threshold must be between zero and one, found {v}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::Parser;
+
+    use crate::cli::{Cli, Command};
+
+    fn try_parse_args(cmd: &str) -> ClapResult<SafConvert> {
+        Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
+            Some(Command::SafConvert(saf_convert)) => saf_convert,
+            _ => panic!(),
+        })
+    }
+
+    #[test]
+    fn test_threshold() {
+        let args = try_parse_args("winsfs saf-convert -o out in.saf.idx").unwrap();
+        assert_eq!(args.threshold, 1e-6);
+
+        let args = try_parse_args("winsfs saf-convert --threshold 0.01 -o out in.saf.idx").unwrap();
+        assert_eq!(args.threshold, 0.01);
+
+        assert!(try_parse_args("winsfs saf-convert --threshold 2 -o out in.saf.idx").is_err());
+        assert!(try_parse_args("winsfs saf-convert --threshold -1 -o out in.saf.idx").is_err());
+    }
+}
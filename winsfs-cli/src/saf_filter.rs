@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+
+use clap::{
+    error::{ErrorKind, Result as ClapResult},
+    ArgAction, Args, CommandFactory,
+};
+
+use crate::{
+    cli::{Cli, MAX_PATHS},
+    input::{self, regions::SiteFilters},
+    utils::join,
+};
+
+/// Write a subset of sites in SAF files to new SAF files.
+///
+/// The (intersecting) sites in the input SAF files are written to a new set of SAF files for each
+/// population, after applying any regions, masks, and subsampling. Contigs and positions are
+/// preserved, and the output uses the same SAF version as the input, so that banded (v4) SAF files
+/// are written as banded SAF files. The output can be read by winsfs as well as by realSFS.
+///
+/// When reading the union of sites, each site is written only for the populations in which it is
+/// present, and no likelihoods are imputed in the output.
+#[derive(Args, Debug)]
+pub struct SafFilter {
+    /// Input SAF file paths.
+    ///
+    /// For each set of SAF files (conventially named 'prefix'.{saf.idx,saf.pos.gz,saf.gz}),
+    /// specify either the shared prefix or the full path to any one member file.
+    /// Up to three SAF files currently supported (six with the experimental '--features hd' compile
+    /// flag).
+    ///
+    /// Each population may also be given by several sets of SAF files, e.g. one per chromosome,
+    /// either as a comma-separated list, or as a text file with one set per line. The sets for each
+    /// population are read one after another, and the same number of sets must be given for each
+    /// population. Sets are intersected in order, so a contig must not occur in more than one set.
+    #[clap(value_parser, num_args = 1..=MAX_PATHS, required = true, value_name = "PATHS")]
+    pub paths: Vec<PathBuf>,
+
+    /// Output SAF file prefix.
+    ///
+    /// The output SAF files are written to '<prefix>.{saf.idx,saf.pos.gz,saf.gz}'. The option must
+    /// be given once for each input population, in the same order as the input. Existing files
+    /// are overwritten.
+    #[clap(
+        short = 'o',
+        long,
+        action = ArgAction::Append,
+        required = true,
+        value_name = "PREFIX"
+    )]
+    pub output: Vec<PathBuf>,
+
+    /// Number of threads to use for reading.
+    ///
+    /// If set to 0, all available cores will be used.
+    #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
+    pub threads: usize,
+
+    #[clap(flatten)]
+    pub site_filters: SiteFilters,
+
+    /// Random seed used for '--subsample'.
+    ///
+    /// If unset, a seed will be chosen at random.
+    #[clap(short = 's', long, requires = "subsample", value_name = "INT")]
+    pub seed: Option<u64>,
+}
+
+impl SafFilter {
+    pub fn run(self) -> ClapResult<()> {
+        if self.output.len() != self.paths.len() {
+            return Err(Cli::command().error(
+                ErrorKind::WrongNumberOfValues,
+                format!(
+                    "expected one output prefix per input population \
+                    (found {} input(s) and {} output(s))",
+                    self.paths.len(),
+                    self.output.len(),
+                ),
+            ));
+        }
+
+        log::info!(
+            target: "init",
+            "Writing (intersecting) sites in input SAF files to SAF files with prefixes:\n\t{}",
+            join(self.output.iter().map(|p| p.display()), "\n\t"),
+        );
+
+        match &self.paths[..] {
+            [] => unreachable!("checked by clap"),
+            [p] => self.run_n([p]),
+            [p1, p2] => self.run_n([p1, p2]),
+            [p1, p2, p3] => self.run_n([p1, p2, p3]),
+            #[cfg(feature = "hd")]
+            [p1, p2, p3, p4] => self.run_n([p1, p2, p3, p4]),
+            #[cfg(feature = "hd")]
+            [p1, p2, p3, p4, p5] => self.run_n([p1, p2, p3, p4, p5]),
+            #[cfg(feature = "hd")]
+            [p1, p2, p3, p4, p5, p6] => self.run_n([p1, p2, p3, p4, p5, p6]),
+            _ => unreachable!(), // Checked by clap
+        }
+    }
+
+    fn run_n<const N: usize, P>(&self, paths: [P; N]) -> ClapResult<()>
+    where
+        P: AsRef<Path>,
+    {
+        let prefixes: [&PathBuf; N] = std::array::from_fn(|i| &self.output[i]);
+
+        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?
+            .with_site_filters(&self.site_filters, self.seed)?;
+
+        let sites = readers.write_saf(&prefixes)?;
+
+        log::info!(target: "init", "Wrote {sites} (intersecting) sites");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::Parser;
+
+    use winsfs_core::io::{Region, Subsample};
+
+    use crate::cli::Command;
+
+    fn try_parse_args(cmd: &str) -> ClapResult<SafFilter> {
+        Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
+            Some(Command::SafFilter(saf_filter)) => saf_filter,
+            _ => panic!(),
+        })
+    }
+
+    fn parse_args(cmd: &str) -> SafFilter {
+        try_parse_args(cmd).expect("failed to parse subcommand")
+    }
+
+    #[test]
+    fn test_output() {
+        let args = parse_args("winsfs saf-filter -o out1 --output out2 saf1 saf2");
+        assert_eq!(
+            args.paths,
+            vec![PathBuf::from("saf1"), PathBuf::from("saf2")]
+        );
+        assert_eq!(
+            args.output,
+            vec![PathBuf::from("out1"), PathBuf::from("out2")]
+        );
+    }
+
+    #[test]
+    fn test_output_required() {
+        let result = try_parse_args("winsfs saf-filter saf1");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument,
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let args = parse_args(
+            "winsfs saf-filter -o out -r chr1:1-10 --exclude bed --subsample 0.1 -s 1 saf",
+        );
+        assert_eq!(
+            args.site_filters.region,
+            vec![Region::new("chr1", 0, 10).unwrap()]
+        );
+        assert_eq!(args.site_filters.exclude, vec![PathBuf::from("bed")]);
+        assert_eq!(args.site_filters.subsample, Some(Subsample::Fraction(0.1)));
+        assert_eq!(args.seed, Some(1));
+    }
+}
@@ -4,7 +4,11 @@ use clap::{error::Result as ClapResult, Args};
 
 use winsfs_core::io::shuffle::{Header, Writer};
 
-use crate::{cli::MAX_PATHS, input, utils::join};
+use crate::{
+    cli::MAX_PATHS,
+    input::{self, regions::SiteFilters},
+    utils::join,
+};
 
 /// Jointly pseudo-shuffle SAF files blockwise on disk.
 ///
@@ -20,6 +24,11 @@ pub struct Shuffle {
     /// specify either the shared prefix or the full path to any one member file.
     /// Up to three SAF files currently supported (six with the experimental '--features hd' compile
     /// flag).
+    ///
+    /// Each population may also be given by several sets of SAF files, e.g. one per chromosome,
+    /// either as a comma-separated list, or as a text file with one set per line. The sets for each
+    /// population are read one after another, and the same number of sets must be given for each
+    /// population. Sets are intersected in order, so a contig must not occur in more than one set.
     #[clap(value_parser, num_args = 1..=MAX_PATHS, required = true, value_name = "PATHS")]
     pub paths: Vec<PathBuf>,
 
@@ -39,6 +48,15 @@ pub struct Shuffle {
     /// If set to 0, all available cores will be used.
     #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
     pub threads: usize,
+
+    #[clap(flatten)]
+    pub site_filters: SiteFilters,
+
+    /// Random seed used for '--subsample'.
+    ///
+    /// If unset, a seed will be chosen at random.
+    #[clap(short = 's', long, requires = "subsample", value_name = "INT")]
+    pub seed: Option<u64>,
 }
 
 impl Shuffle {
@@ -78,7 +96,11 @@ impl Shuffle {
     where
         P: AsRef<Path>,
     {
-        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?;
+        // The same subsample must be read when counting and when shuffling sites
+        let seed = Some(self.seed.unwrap_or_else(rand::random));
+
+        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?
+            .with_site_filters(&self.site_filters, seed)?;
         let shape = readers.shape();
 
         // In 2D we cannot know the number of intersecting sites ahead of time,
@@ -96,8 +118,10 @@ impl Shuffle {
             shape = join(header.shape(), "/")
         );
 
-        // Readers were consumed by counting sites above, so recreate.
-        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?;
+        // Readers were consumed by counting sites above, so recreate. Masks are also recreated,
+        // since they count the number of sites removed.
+        let readers = input::saf::Readers::from_member_paths(&paths, self.threads)?
+            .with_site_filters(&self.site_filters, seed)?;
 
         let writer = Writer::create(&self.output, header)?;
 
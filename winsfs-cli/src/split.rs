@@ -15,7 +15,7 @@ use winsfs_core::{
 use crate::{
     cli::MAX_PATHS,
     estimate::{get_block_spec, Checker, Logger},
-    input,
+    input::{self, regions::SiteFilters},
     utils::set_threads,
 };
 
@@ -31,6 +31,11 @@ pub struct Split {
     /// specify either the shared prefix or the full path to any one member file.
     /// Up to three SAF files currently supported (six with the experimental '--features hd' compile
     /// flag).
+    ///
+    /// Each population may also be given by several sets of SAF files, e.g. one per chromosome,
+    /// either as a comma-separated list, or as a text file with one set per line. The sets for each
+    /// population are read one after another, and the same number of sets must be given for each
+    /// population. Sets are intersected in order, so a contig must not occur in more than one set.
     #[clap(
         value_parser,
         num_args = 1..=MAX_PATHS,
@@ -71,6 +76,15 @@ pub struct Split {
     /// in the block.
     #[clap(short = 'l', long, default_value_t = 1e-8, value_name = "FLOAT")]
     pub tolerance: f64,
+
+    #[clap(flatten)]
+    pub site_filters: SiteFilters,
+
+    /// Random seed used for '--subsample'.
+    ///
+    /// If unset, a seed will be chosen at random.
+    #[clap(long, requires = "subsample", value_name = "INT")]
+    pub seed: Option<u64>,
 }
 
 impl Split {
@@ -103,7 +117,9 @@ impl Split {
         let initial_sfs = input::sfs::Reader::from_path(&self.sfs)?
             .read::<D>()?
             .normalise();
-        let saf = input::saf::Readers::from_member_paths(&paths, self.threads)?.read_saf()?;
+        let saf = input::saf::Readers::from_member_paths(&paths, self.threads)?
+            .with_site_filters(&self.site_filters, self.seed)?
+            .read_saf(None)?;
 
         let sites = saf.sites();
         let block_spec = get_block_spec(
@@ -0,0 +1,196 @@
+use std::{
+    io::{self, Write},
+    num::NonZeroU32,
+    path::PathBuf,
+};
+
+use clap::{error::Result as ClapResult, Args};
+
+use winsfs_core::{em::EmSite, sfs::USfs};
+
+use crate::{
+    input,
+    posterior::read_prior,
+    windows::{Scan, WindowStatistic, Windows},
+};
+
+/// Calculate window sums of theta estimators and neutrality tests using SFS as prior.
+///
+/// For each site in the input SAF file, the SFS is used as a prior to calculate the posterior
+/// distribution of the derived allele count, as in `winsfs posterior`. The per-site posteriors are
+/// summed in windows along the genome, and the window sums are used to calculate Watterson's,
+/// Tajima's, and Fay and Wu's estimators of theta, as well as Tajima's D. This corresponds to
+/// `thetaStat do_stat`.
+///
+/// Output is written to stdout as tab-separated text with a header line starting with '#',
+/// followed by one line per window with the contig name, the zero-based start and exclusive end of
+/// the window, the number of sites in the window, and the statistics above. The thetas are given as
+/// sums over the window, and may be divided by the number of sites to get per-site estimates.
+/// Windows without any sites are not written. The SAF file will be streamed, and therefore the
+/// calculation requires only constant memory usage.
+#[derive(Args, Debug)]
+pub struct Thetas {
+    /// Input SAF file path.
+    ///
+    /// For the set of SAF files (conventially named 'prefix'.{saf.idx,saf.pos.gz,saf.gz}),
+    /// specify either the shared prefix or the full path to any one member file. Only a single
+    /// SAF file is currently supported.
+    #[clap(value_parser, value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Input SFS to use as prior.
+    ///
+    /// The SFS will be normalised, so the input SFS does not need to be normalised. The shape of
+    /// the SFS must match the shape of the SAF file.
+    #[clap(short = 'i', long)]
+    pub sfs: PathBuf,
+
+    /// Number of threads to use for reading.
+    ///
+    /// If set to 0, all available cores will be used.
+    #[clap(short = 't', long, default_value_t = 4, value_name = "INT")]
+    pub threads: usize,
+
+    /// Window size in base pairs.
+    ///
+    /// Windows start at the beginning of each contig. Either this or '--windows' must be set.
+    #[clap(
+        short = 'w',
+        long,
+        required_unless_present = "windows",
+        conflicts_with = "windows",
+        value_name = "INT"
+    )]
+    pub window_size: Option<NonZeroU32>,
+
+    /// Step between window starts in base pairs.
+    ///
+    /// Defaults to the window size, so that windows are non-overlapping.
+    #[clap(short = 's', long, conflicts_with = "windows", value_name = "INT")]
+    pub step: Option<NonZeroU32>,
+
+    /// Path to BED file with windows.
+    ///
+    /// Each line should contain contig name, zero-based start, and exclusive end of a window. Any
+    /// further fields are ignored, as are empty lines and lines starting with '#'. Windows may
+    /// overlap.
+    #[clap(long, value_name = "PATH")]
+    pub windows: Option<PathBuf>,
+}
+
+impl Thetas {
+    pub fn run(self) -> ClapResult<()> {
+        // Either window size or BED windows are required by clap
+        let windows =
+            Windows::from_args(self.window_size, self.step, self.windows.as_deref())?.unwrap();
+
+        let readers = input::saf::Readers::from_member_paths(&[&self.path], self.threads)?;
+        let prior = read_prior(&self.sfs, readers.shape())?;
+
+        let mut posterior = USfs::zeros(*prior.shape());
+        let mut buf = USfs::zeros(*prior.shape());
+
+        let mut scan = Scan::new(windows, posterior.clone(), io::stdout().lock())?;
+
+        log::info!(target: "init", "Streaming sites in input SAF file");
+
+        let sites = readers.for_each_site(|contig, position, site| {
+            posterior.iter_mut().for_each(|v| *v = 0.0);
+            site.posterior_into(&prior, &mut posterior, &mut buf);
+
+            scan.add(contig, position, &posterior)
+        })?;
+
+        let (windows, _) = scan.finish()?;
+
+        log::info!(target: "thetas", "Processed {sites} sites in {windows} windows");
+
+        Ok(())
+    }
+}
+
+impl WindowStatistic for USfs<1> {
+    const HEADER: &'static str = "watterson\tpi\ttheta_h\ttajimas_d";
+
+    fn add(&mut self, other: &Self) {
+        *self += other;
+    }
+
+    fn write<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        // Prior is checked to have at least three values when read, so these cannot fail
+        write!(
+            writer,
+            "{watterson:.6e}\t{pi:.6e}\t{theta_h:.6e}\t{tajimas_d:.6}",
+            watterson = self.theta_watterson().unwrap(),
+            pi = self.theta_pi().unwrap(),
+            theta_h = self.theta_h().unwrap(),
+            tajimas_d = self.tajimas_d().unwrap(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{error::ErrorKind, Parser};
+
+    use crate::cli::{Cli, Command};
+
+    fn try_parse_args(cmd: &str) -> ClapResult<Thetas> {
+        Cli::try_parse_from(cmd.split_whitespace()).map(|cli| match cli.subcommand {
+            Some(Command::Thetas(thetas)) => thetas,
+            _ => panic!(),
+        })
+    }
+
+    fn parse_args(cmd: &str) -> Thetas {
+        try_parse_args(cmd).expect("failed to parse subcommand")
+    }
+
+    #[test]
+    fn test_sliding_windows() {
+        let args = parse_args("winsfs thetas --sfs /path/to/sfs -w 50000 -s 10000 saf");
+        assert_eq!(args.path, PathBuf::from("saf"));
+        assert_eq!(args.window_size, NonZeroU32::new(50000));
+        assert_eq!(args.step, NonZeroU32::new(10000));
+        assert_eq!(args.windows, None);
+    }
+
+    #[test]
+    fn test_bed_windows() {
+        let args = parse_args("winsfs thetas --sfs /path/to/sfs --windows /path/to/bed saf");
+        assert_eq!(args.windows, Some(PathBuf::from("/path/to/bed")));
+        assert_eq!(args.window_size, None);
+    }
+
+    #[test]
+    fn test_windows_required() {
+        let result = try_parse_args("winsfs thetas --sfs /path/to/sfs saf");
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument,
+        );
+    }
+
+    #[test]
+    fn test_conflicting_windows() {
+        let result = try_parse_args("winsfs thetas --sfs /path/to/sfs -w 100 --windows bed saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_step_conflicts_with_bed_windows() {
+        let result = try_parse_args("winsfs thetas --sfs /path/to/sfs -s 100 --windows bed saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_zero_window_size() {
+        let result = try_parse_args("winsfs thetas --sfs /path/to/sfs -w 0 saf");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+}
@@ -5,9 +5,7 @@ use clap::{
 
 use rand::{rngs::StdRng, SeedableRng};
 
-use winsfs_core::saf::Saf;
-
-use super::Cli;
+use super::{estimate::InMemorySaf, Cli};
 
 pub fn init_logger(verbosity_arg: u8) -> ClapResult<()> {
     let level = match verbosity_arg {
@@ -39,7 +37,10 @@ where
         .join(sep)
 }
 
-pub fn shuffle_saf<const N: usize>(saf: &mut Saf<N>, seed: Option<u64>) {
+pub fn shuffle_saf<const N: usize, S>(saf: &mut S, seed: Option<u64>)
+where
+    S: InMemorySaf<N>,
+{
     let mut rng = match seed {
         Some(v) => StdRng::seed_from_u64(v),
         None => StdRng::from_entropy(),
@@ -0,0 +1,388 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufRead, Write},
+    num::NonZeroU32,
+    path::Path,
+};
+
+use crate::input::regions::parse_bed_line;
+
+/// A per-site statistic that can be summed over sites in windows.
+pub trait WindowStatistic: Clone {
+    /// Tab-separated names of the output columns.
+    const HEADER: &'static str;
+
+    /// Adds the statistic of another site to `self`.
+    fn add(&mut self, other: &Self);
+
+    /// Writes the tab-separated output columns of the statistic, without a trailing newline.
+    fn write<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write;
+}
+
+/// A definition of windows along the genome.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Windows {
+    /// Windows of a fixed size in base pairs, with starts separated by a fixed step.
+    Sliding { size: u32, step: u32 },
+    /// Windows given by zero-based, half-open regions on named contigs, sorted by start.
+    Regions(HashMap<String, Vec<(u32, u32)>>),
+}
+
+impl Windows {
+    /// Creates new windows from the cli arguments, if any.
+    ///
+    /// If `bed` is provided, windows are read from the path. Otherwise, if `size` is provided,
+    /// sliding windows are used, where the step defaults to the window size.
+    pub fn from_args(
+        size: Option<NonZeroU32>,
+        step: Option<NonZeroU32>,
+        bed: Option<&Path>,
+    ) -> io::Result<Option<Self>> {
+        match (bed, size) {
+            (Some(path), _) => Self::from_bed_path(path).map(Some),
+            (None, Some(size)) => {
+                let step = step.unwrap_or(size);
+
+                log::debug!(
+                    target: "init",
+                    "Using windows of size {size} with step {step}",
+                );
+
+                Ok(Some(Self::Sliding {
+                    size: size.get(),
+                    step: step.get(),
+                }))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Creates new windows from a BED file path.
+    pub fn from_bed_path<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        log::debug!(
+            target: "init",
+            "Reading windows from path:\n\t{}",
+            path.as_ref().display()
+        );
+
+        File::open(path)
+            .map(io::BufReader::new)
+            .and_then(Self::read_bed)
+    }
+
+    /// Reads windows from a reader in BED format.
+    ///
+    /// Each non-empty line not starting with '#' should contain at least three whitespace-separated
+    /// fields giving contig name, zero-based start, and exclusive end. Any further fields are
+    /// ignored. Windows may overlap.
+    pub fn read_bed<R>(reader: R) -> io::Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut regions: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (contig, region) = parse_bed_line(line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("failed to parse windows on line {}: {e}", i + 1),
+                )
+            })?;
+
+            regions.entry(contig.to_string()).or_default().push(region);
+        }
+
+        regions.values_mut().for_each(|regions| regions.sort());
+
+        Ok(Self::Regions(regions))
+    }
+}
+
+/// A window with the sum of per-site statistics of the sites it contains.
+#[derive(Clone, Debug, PartialEq)]
+struct Window<T> {
+    start: u32,
+    end: u32,
+    sites: usize,
+    value: T,
+}
+
+/// A streaming scan of windows along the genome.
+///
+/// Sites must be added in sorted order within each contig, with all sites on a contig added
+/// consecutively, as is the case when reading SAF files. Windows are written as soon as no more
+/// sites can be added to them. Windows without any sites are not written.
+pub struct Scan<W, T> {
+    windows: Windows,
+    zero: T,
+    writer: W,
+    contig: Option<String>,
+    // Index of the next window to open on the current contig
+    next: usize,
+    active: VecDeque<Window<T>>,
+    written: usize,
+}
+
+impl<W, T> Scan<W, T>
+where
+    W: Write,
+    T: WindowStatistic,
+{
+    /// Creates a new scan, where `zero` is the statistic of an empty window.
+    ///
+    /// A header is immediately written to the writer.
+    pub fn new(windows: Windows, zero: T, mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "#chrom\tstart\tend\tsites\t{}", T::HEADER)?;
+
+        Ok(Self {
+            windows,
+            zero,
+            writer,
+            contig: None,
+            next: 0,
+            active: VecDeque::new(),
+            written: 0,
+        })
+    }
+
+    /// Adds the statistic of a site at the zero-based position on the contig to all windows
+    /// containing the site.
+    pub fn add(&mut self, contig: &str, position: u32, value: &T) -> io::Result<()> {
+        if self.contig.as_deref() != Some(contig) {
+            self.flush()?;
+            self.contig = Some(contig.to_string());
+            self.next = 0;
+        }
+
+        self.open(contig, position);
+
+        while let Some(window) = self.active.front() {
+            if window.end > position {
+                break;
+            }
+
+            let window = self.active.pop_front().unwrap();
+            self.write(&window)?;
+        }
+
+        self.active
+            .iter_mut()
+            .filter(|window| window.start <= position && position < window.end)
+            .for_each(|window| {
+                window.sites += 1;
+                window.value.add(value);
+            });
+
+        Ok(())
+    }
+
+    /// Writes all remaining windows, and returns the number of windows written and the writer.
+    pub fn finish(mut self) -> io::Result<(usize, W)> {
+        self.flush()?;
+
+        Ok((self.written, self.writer))
+    }
+
+    /// Writes all active windows.
+    fn flush(&mut self) -> io::Result<()> {
+        while let Some(window) = self.active.pop_front() {
+            self.write(&window)?;
+        }
+
+        Ok(())
+    }
+
+    /// Opens all windows containing the position not previously opened on the contig.
+    fn open(&mut self, contig: &str, position: u32) {
+        match &self.windows {
+            Windows::Sliding { size, step } => {
+                let (position, size, step) =
+                    (u64::from(position), u64::from(*size), u64::from(*step));
+
+                // Window i covers [i * step, i * step + size)
+                let first = match position.checked_sub(size) {
+                    Some(v) => v / step + 1,
+                    None => 0,
+                };
+                let last = position / step;
+
+                for i in (first as usize).max(self.next)..=last as usize {
+                    let start = i as u64 * step;
+                    let end = (start + size).min(u64::from(u32::MAX));
+
+                    self.active.push_back(Window {
+                        start: start as u32,
+                        end: end as u32,
+                        sites: 0,
+                        value: self.zero.clone(),
+                    });
+                }
+
+                self.next = self.next.max(last as usize + 1);
+            }
+            Windows::Regions(regions) => {
+                let regions = regions.get(contig).map(Vec::as_slice).unwrap_or(&[]);
+
+                while let Some(&(start, end)) = regions.get(self.next) {
+                    if start > position {
+                        break;
+                    }
+
+                    // Windows ending before the position contain no sites, and are skipped
+                    if end > position {
+                        self.active.push_back(Window {
+                            start,
+                            end,
+                            sites: 0,
+                            value: self.zero.clone(),
+                        });
+                    }
+
+                    self.next += 1;
+                }
+            }
+        }
+    }
+
+    /// Writes a single window.
+    fn write(&mut self, window: &Window<T>) -> io::Result<()> {
+        self.written += 1;
+
+        write!(
+            self.writer,
+            "{contig}\t{start}\t{end}\t{sites}\t",
+            contig = self.contig.as_deref().unwrap_or_default(),
+            start = window.start,
+            end = window.end,
+            sites = window.sites,
+        )?;
+        window.value.write(&mut self.writer)?;
+        writeln!(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl WindowStatistic for f64 {
+        const HEADER: &'static str = "sum";
+
+        fn add(&mut self, other: &Self) {
+            *self += other;
+        }
+
+        fn write<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            write!(writer, "{self}")
+        }
+    }
+
+    fn scan(windows: Windows, sites: &[(&str, u32)]) -> Vec<(String, u32, u32, usize)> {
+        let mut scan = Scan::new(windows, 0.0, Vec::new()).unwrap();
+
+        for &(contig, position) in sites {
+            scan.add(contig, position, &1.0).unwrap();
+        }
+
+        let (written, bytes) = scan.finish().unwrap();
+
+        let lines = String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                assert_eq!(fields[3], fields[4]);
+                (
+                    fields[0].to_string(),
+                    fields[1].parse().unwrap(),
+                    fields[2].parse().unwrap(),
+                    fields[3].parse().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), written);
+        lines
+    }
+
+    fn window(contig: &str, start: u32, end: u32, sites: usize) -> (String, u32, u32, usize) {
+        (contig.to_string(), start, end, sites)
+    }
+
+    #[test]
+    fn test_sliding_windows() {
+        let windows = Windows::Sliding { size: 10, step: 5 };
+        let sites = [
+            ("chr1", 0),
+            ("chr1", 7),
+            ("chr1", 12),
+            ("chr1", 40),
+            ("chr2", 3),
+        ];
+
+        assert_eq!(
+            scan(windows, &sites),
+            vec![
+                window("chr1", 0, 10, 2),
+                window("chr1", 5, 15, 2),
+                window("chr1", 10, 20, 1),
+                window("chr1", 35, 45, 1),
+                window("chr1", 40, 50, 1),
+                window("chr2", 0, 10, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sliding_windows_with_gaps() {
+        let windows = Windows::Sliding { size: 2, step: 5 };
+        let sites = [("chr1", 0), ("chr1", 1), ("chr1", 3), ("chr1", 6)];
+
+        assert_eq!(
+            scan(windows, &sites),
+            vec![window("chr1", 0, 2, 2), window("chr1", 5, 7, 1)]
+        );
+    }
+
+    #[test]
+    fn test_region_windows() {
+        let src = b"# comment\nchr1\t0\t10\nchr1 5 8 name\nchr1\t20\t30\nchr2\t0\t5\n";
+        let windows = Windows::read_bed(&src[..]).unwrap();
+        let sites = [
+            ("chr1", 3),
+            ("chr1", 6),
+            ("chr1", 9),
+            ("chr1", 35),
+            ("chr3", 0),
+        ];
+
+        assert_eq!(
+            scan(windows, &sites),
+            vec![window("chr1", 0, 10, 3), window("chr1", 5, 8, 1)]
+        );
+    }
+
+    #[test]
+    fn test_read_bed_errors() {
+        for src in [&b"chr1\t0\n"[..], b"chr1\t5\t5\n", b"chr1\tx\t5\n"] {
+            assert!(Windows::read_bed(src).is_err());
+        }
+    }
+}
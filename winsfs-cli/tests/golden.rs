@@ -29,6 +29,10 @@ use std::{
     thread,
 };
 
+use angsd_saf::{
+    version::{Version, V3, V4},
+    Record,
+};
 use pretty_assertions::assert_eq;
 
 const WINSFS: &str = env!("CARGO_BIN_EXE_winsfs");
@@ -280,6 +284,29 @@ fn test_1d_banded_estimate_default() -> DynResult {
     impl_test_estimate([], [BANDED_SAF_D])
 }
 
+#[test]
+fn test_1d_banded_estimate_by_contig_skips_too_few_held_out_sites() -> DynResult {
+    impl_test_estimate(
+        [
+            "--by-contig",
+            "--region",
+            "1:1-3",
+            "--region",
+            "2",
+            "--block-size",
+            "1",
+            "--holdout",
+            "0.1",
+            "--max-epochs",
+            "1",
+            // Contigs are estimated in parallel, so use a single thread for deterministic logs
+            "--threads",
+            "1",
+        ],
+        [BANDED_SAF_D],
+    )
+}
+
 #[test]
 fn test_2d_estimate_default() -> DynResult {
     impl_test_estimate([], [SAF_A, SAF_B])
@@ -410,3 +437,252 @@ fn test_3d_log_likelihood() -> DynResult {
 fn test_3d_banded_log_likelihood() -> DynResult {
     impl_test_log_likelihood([], SFS_D_E_F, [BANDED_SAF_D, BANDED_SAF_E, BANDED_SAF_F])
 }
+
+/// Writes the sites in a region of the provided SAF files to new SAF files, and returns the
+/// paths of the new SAF index files.
+///
+/// Returns an error including the stderr of `saf-filter` if filtering fails, so that tests do not
+/// run on stale files from earlier runs.
+///
+/// The new SAF files are created in the CARGO_TARGET_TMPDIR with prefixes based on the test name
+/// and the population index. They are overwritten by later runs of the same test.
+fn filter_safs<'a, I>(region: &str, safs: I) -> io::Result<Vec<String>>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let prefixes: Vec<String> = safs
+        .into_iter()
+        .enumerate()
+        .map(|(i, saf)| {
+            let prefix = format!("{TMP_DIR}/{test_name}.{i}", test_name = get_test_name());
+
+            let output = winsfs_cmd(["saf-filter", "--region", region, "--output", &prefix, saf])
+                .output()?;
+
+            if output.status.success() {
+                Ok(prefix)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "failed to filter SAF file '{saf}':\n{stderr}",
+                        stderr = String::from_utf8_lossy(&output.stderr),
+                    ),
+                ))
+            }
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(prefixes
+        .into_iter()
+        .map(|prefix| format!("{prefix}.saf.idx"))
+        .collect())
+}
+
+#[test]
+fn test_1d_posterior() -> DynResult {
+    let safs = filter_safs("chr1:1001-1100", [SAF_A])?;
+
+    winsfs(["posterior", "-vv", "--sfs", SFS_A, &safs[0]]).map(test_output)?
+}
+
+#[test]
+fn test_1d_thetas_windows() -> DynResult {
+    winsfs([
+        "thetas",
+        "-vv",
+        "--sfs",
+        SFS_A,
+        "--window-size",
+        "20000",
+        "--step",
+        "10000",
+        SAF_A,
+    ])
+    .map(test_output)?
+}
+
+#[test]
+fn test_2d_banded_fst_windows() -> DynResult {
+    winsfs([
+        "fst",
+        "-vv",
+        "--sfs",
+        SFS_D_E,
+        "--window-size",
+        "2000",
+        BANDED_SAF_D,
+        BANDED_SAF_E,
+    ])
+    .map(test_output)?
+}
+
+#[test]
+fn test_1d_fit_default() -> DynResult {
+    winsfs(["fit", "-vv", SFS_A]).map(test_output)?
+}
+
+#[test]
+fn test_1d_fit_two_epoch_interior() -> DynResult {
+    let expected = format!("{TMP_DIR}/{test_name}.sfs", test_name = get_test_name());
+    let output = winsfs([
+        "expected",
+        "--shape",
+        "21",
+        "--epochs",
+        "0:1,0.2:0.1",
+        "--sites",
+        "1000000",
+    ])?;
+    write(&expected, output.stdout)?;
+
+    winsfs(["fit", "-vv", "-m", "two-epoch", &expected]).map(test_output)?
+}
+
+/// Removes any instance of the CARGO_MANIFEST_DIR and CARGO_TARGET_TMPDIR from stdout.
+///
+/// This is required for outputs listing input file paths in stdout, see [`remove_dirs`].
+fn remove_stdout_dirs(mut output: Output) -> Result<Output, Box<dyn std::error::Error>> {
+    output.stdout = remove_dirs(from_utf8(&output.stdout)?).into_bytes();
+
+    Ok(output)
+}
+
+#[test]
+fn test_1d_log_likelihood_compare() -> DynResult {
+    let expected = format!("{TMP_DIR}/{test_name}.sfs", test_name = get_test_name());
+    let output = winsfs(["expected", "--shape", "11", "--sites", "220000"])?;
+    write(&expected, output.stdout)?;
+
+    let output = winsfs([
+        "log-likelihood",
+        "-vv",
+        "--sfs",
+        SFS_A,
+        "--sfs",
+        &expected,
+        "--bootstrap",
+        "10",
+        "--seed",
+        "1",
+        SAF_A,
+    ])?;
+
+    test_output(remove_stdout_dirs(output)?)
+}
+
+#[test]
+fn test_1d_info() -> DynResult {
+    let output = winsfs(["info", "-vv", SAF_A])?;
+
+    test_output(remove_stdout_dirs(output)?)
+}
+
+#[test]
+fn test_3d_banded_info() -> DynResult {
+    let output = winsfs(["info", "-vv", BANDED_SAF_D, BANDED_SAF_E, BANDED_SAF_F])?;
+
+    test_output(remove_stdout_dirs(output)?)
+}
+
+/// Reads all records of the SAF files with the provided prefix, using contig names as IDs.
+fn read_saf_records<V>(prefix: &str) -> io::Result<Vec<Record<String, V::Item>>>
+where
+    V: Version,
+    V::Item: Clone,
+{
+    let mut reader = angsd_saf::reader::Builder::<V>::default().build_from_prefix(prefix)?;
+    let mut record = reader.create_record_buf();
+
+    let mut records = Vec::new();
+    while !reader.read_record(&mut record)?.is_done() {
+        let name = reader.index().records()[*record.contig_id()].name();
+        records.push(Record::new(
+            name.to_string(),
+            record.position(),
+            record.item().clone(),
+        ));
+    }
+
+    Ok(records)
+}
+
+#[test]
+fn test_2d_banded_saf_filter_region() -> DynResult {
+    let test_name = get_test_name();
+    let outputs = [
+        format!("{TMP_DIR}/{test_name}.D"),
+        format!("{TMP_DIR}/{test_name}.E"),
+    ];
+
+    winsfs([
+        "saf-filter",
+        "-vv",
+        "--region",
+        "2:1001-1500",
+        "--output",
+        &outputs[0],
+        "--output",
+        &outputs[1],
+        BANDED_SAF_D,
+        BANDED_SAF_E,
+    ])
+    .map(test_output)??;
+
+    // Region is one-based and inclusive, positions in SAF files are zero-based
+    for (input, output) in [BANDED_SAF_D, BANDED_SAF_E].into_iter().zip(&outputs) {
+        let expected: Vec<_> = read_saf_records::<V4>(input.trim_end_matches(".saf.idx"))?
+            .into_iter()
+            .filter(|record| record.contig_id() == "2" && (1000..1500).contains(&record.position()))
+            .collect();
+
+        assert_eq!(expected.len(), 500);
+        assert_eq!(read_saf_records::<V4>(output)?, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_1d_saf_convert_to_banded() -> DynResult {
+    let output = format!("{TMP_DIR}/{test_name}", test_name = get_test_name());
+
+    winsfs(["saf-convert", "-vv", "--output", &output, SAF_A]).map(test_output)??;
+
+    let full = read_saf_records::<V3>(SAF_A.trim_end_matches(".saf.idx"))?;
+    let banded = read_saf_records::<V4>(&output)?;
+
+    assert_eq!(full.len(), banded.len());
+    for (full, banded) in full.iter().zip(banded.iter()) {
+        assert_eq!(full.contig_id(), banded.contig_id());
+        assert_eq!(full.position(), banded.position());
+
+        let band = banded.item();
+        assert_eq!(
+            band.likelihoods(),
+            &full.item()[band.start()..band.start() + band.len()]
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_1d_banded_saf_convert_to_full() -> DynResult {
+    let output = format!("{TMP_DIR}/{test_name}", test_name = get_test_name());
+
+    winsfs(["saf-convert", "-vv", "--output", &output, BANDED_SAF_D]).map(test_output)??;
+
+    let banded = read_saf_records::<V4>(BANDED_SAF_D.trim_end_matches(".saf.idx"))?;
+    let full = read_saf_records::<V3>(&output)?;
+
+    assert_eq!(banded.len(), full.len());
+    for (banded, full) in banded.into_iter().zip(full.iter()) {
+        assert_eq!(
+            &banded.into_full(full.item().len() - 1, f32::NEG_INFINITY),
+            full
+        );
+    }
+
+    Ok(())
+}
@@ -9,7 +9,7 @@ use std::io;
 use likelihood::{LogLikelihood, SumOf};
 
 mod site;
-pub use site::{EmSite, StreamEmSite};
+pub use site::{EmSaf, EmSite, StreamEmSite};
 
 mod standard_em;
 pub use standard_em::{ParallelEm, StandardEm, StreamingEm};
@@ -22,7 +22,7 @@ pub use window_em::{StreamingWindowEm, WindowEm};
 
 use crate::{
     io::Rewind,
-    saf::SafView,
+    saf::{BandedSafView, PatternsView, SafView},
     sfs::{Sfs, USfs},
 };
 
@@ -132,6 +132,55 @@ where
     }
 }
 
+impl<'a, const N: usize, T> Em<N, BandedSafView<'a, N>> for T
+where
+    T: EmStep<N, BandedSafView<'a, N>>,
+{
+    fn em<S>(
+        &mut self,
+        mut sfs: Sfs<N>,
+        saf: BandedSafView<'a, N>,
+        mut stopping_rule: S,
+    ) -> Result<(Self::Status, Sfs<N>), Self::Error>
+    where
+        S: Stop<Self>,
+    {
+        loop {
+            let (status, new_sfs) = self.em_step(sfs, saf)?;
+            sfs = new_sfs;
+
+            if stopping_rule.stop(self, &status, &sfs) {
+                break Ok((status, sfs));
+            }
+        }
+    }
+}
+
+impl<'a, const N: usize, V, T> Em<N, PatternsView<'a, V>> for T
+where
+    T: EmStep<N, PatternsView<'a, V>>,
+    V: Copy,
+{
+    fn em<S>(
+        &mut self,
+        mut sfs: Sfs<N>,
+        saf: PatternsView<'a, V>,
+        mut stopping_rule: S,
+    ) -> Result<(Self::Status, Sfs<N>), Self::Error>
+    where
+        S: Stop<Self>,
+    {
+        loop {
+            let (status, new_sfs) = self.em_step(sfs, saf)?;
+            sfs = new_sfs;
+
+            if stopping_rule.stop(self, &status, &sfs) {
+                break Ok((status, sfs));
+            }
+        }
+    }
+}
+
 impl<'a, const N: usize, R, T> Em<N, &'a mut R> for T
 where
     for<'b> T: EmStep<N, &'b mut R, Error = io::Error>,
@@ -171,7 +220,10 @@ pub(self) fn to_f64(x: usize) -> f64 {
 mod tests {
     use super::*;
 
+    use tempfile::NamedTempFile;
+
     use crate::{
+        io::shuffle,
         saf::{Blocks, SafView},
         saf1d, sfs1d,
     };
@@ -191,6 +243,118 @@ mod tests {
         assert!(!has_nan);
     }
 
+    #[test]
+    fn test_weighted_e_step_matches_repeated_sites() {
+        let sfs = sfs1d![1., 2., 3.].normalise();
+
+        let weighted = saf1d![[1., 2., 0.], [0., 1., 1.]]
+            .with_weights(vec![2., 0.5])
+            .unwrap();
+        let repeated = saf1d![[1., 2., 0.], [1., 2., 0.], [0., 1., 1.]];
+        let single = saf1d![[0., 1., 1.]];
+
+        let (weighted_log_likelihood, weighted_posterior) = sfs.clone().e_step(weighted.view());
+        let (repeated_log_likelihood, repeated_posterior) = sfs.clone().e_step(repeated.view());
+        let (single_log_likelihood, single_posterior) = sfs.clone().e_step(single.view());
+
+        let expected_log_likelihood = f64::from(repeated_log_likelihood.into_sum())
+            - 0.5 * f64::from(single_log_likelihood.into_sum());
+        assert!(
+            (f64::from(*weighted_log_likelihood.sum()) - expected_log_likelihood).abs() < 1e-12
+        );
+        assert_eq!(weighted_log_likelihood.n(), 2);
+        assert_eq!(weighted_log_likelihood.weight(), 2.5);
+        assert_eq!(
+            weighted_log_likelihood.normalise(),
+            f64::from(*weighted_log_likelihood.sum()) / 2.5
+        );
+
+        let expected_posterior = repeated_posterior - single_posterior.scale(0.5);
+        for (x, y) in weighted_posterior.iter().zip(expected_posterior.iter()) {
+            assert!((x - y).abs() < 1e-12);
+        }
+
+        let (par_log_likelihood, par_posterior) = sfs.clone().par_e_step(weighted.view());
+        assert_eq!(par_log_likelihood, weighted_log_likelihood);
+        for (x, y) in weighted_posterior.iter().zip(par_posterior.iter()) {
+            assert!((x - y).abs() < 1e-12);
+        }
+
+        assert_eq!(
+            sfs.clone().log_likelihood(weighted.view()),
+            weighted_log_likelihood
+        );
+    }
+
+    #[test]
+    fn test_zero_weight_sites_are_skipped() -> io::Result<()> {
+        let sfs = sfs1d![1., 2., 3.].normalise();
+
+        let weighted = saf1d![[1., 2., 0.], [0., 0., 0.]]
+            .with_weights(vec![1., 0.])
+            .unwrap();
+        let single = saf1d![[1., 2., 0.]];
+
+        let (expected_log_likelihood, expected_posterior) = sfs.clone().e_step(single.view());
+        assert!(f64::from(*expected_log_likelihood.sum()).is_finite());
+
+        let (log_likelihood, posterior) = sfs.clone().e_step(weighted.view());
+        assert_eq!(log_likelihood.sum(), expected_log_likelihood.sum());
+        assert_eq!(
+            log_likelihood.normalise(),
+            expected_log_likelihood.normalise()
+        );
+        assert_eq!(posterior, expected_posterior);
+
+        let (log_likelihood, posterior) = sfs.clone().par_e_step(weighted.view());
+        assert_eq!(log_likelihood.sum(), expected_log_likelihood.sum());
+        assert_eq!(posterior, expected_posterior);
+
+        let log_likelihood = sfs.clone().log_likelihood(weighted.view());
+        assert_eq!(log_likelihood.sum(), expected_log_likelihood.sum());
+        let log_likelihood = sfs.clone().par_log_likelihood(weighted.view());
+        assert_eq!(log_likelihood.sum(), expected_log_likelihood.sum());
+        assert_eq!(log_likelihood.weight(), expected_log_likelihood.weight());
+
+        let file = NamedTempFile::new()?;
+        let mut writer = shuffle::Writer::create(file.path(), shuffle::Header::new(2, vec![3], 1))?;
+        writer.write_site(&[0., 2f32.ln(), f32::NEG_INFINITY])?;
+        writer.write_site(&[f32::NEG_INFINITY; 3])?;
+        writer.try_finish().unwrap();
+
+        // The sites are read in the order written, since there is a single block
+        let weights = [1., 0.];
+        let mut reader = shuffle::Reader::try_from_path(file.path())?;
+        let mut i = 0;
+        let (log_likelihood, posterior) =
+            sfs.clone().stream_weighted_e_step(&mut reader, |_| {
+                i += 1;
+                weights[i - 1]
+            })?;
+        assert!(
+            (f64::from(*log_likelihood.sum()) - f64::from(*expected_log_likelihood.sum())).abs()
+                < 1e-6
+        );
+        assert_eq!(log_likelihood.n(), 2);
+        assert_eq!(log_likelihood.weight(), 1.);
+        for (x, y) in posterior.iter().zip(expected_posterior.iter()) {
+            assert!((x - y).abs() < 1e-6);
+        }
+
+        reader.rewind()?;
+        let mut i = 0;
+        let log_likelihood = sfs.stream_weighted_log_likelihood(&mut reader, |_| {
+            i += 1;
+            weights[i - 1]
+        })?;
+        assert!(
+            (f64::from(*log_likelihood.sum()) - f64::from(*expected_log_likelihood.sum())).abs()
+                < 1e-6
+        );
+
+        file.close()
+    }
+
     #[test]
     fn test_em_zero_sfs_not_nan() {
         impl_test_em_zero_not_nan(StandardEm::<false>::new())
@@ -209,4 +373,27 @@ mod tests {
             Blocks::Size(1),
         ))
     }
+
+    #[test]
+    fn test_folded_em_step() {
+        let saf = saf1d![[1., 0., 0.5, 0.], [0., 0.2, 0., 1.], [0., 0., 0., 1.]];
+        let sfs = sfs1d![0.6, 0.3, 0.1, 0.].into_normalised().unwrap();
+
+        let mut folded_runner = StandardEm::<false>::new().with_folding(true);
+        let (folded_status, folded_posterior) =
+            folded_runner.e_step(sfs.clone(), saf.view()).unwrap();
+
+        let (status, posterior) = StandardEm::<false>::new()
+            .e_step(sfs.symmetrise(), saf.view())
+            .unwrap();
+
+        assert_eq!(folded_status, status);
+        assert_eq!(folded_posterior, posterior.fold());
+        assert_eq!(folded_posterior.as_slice()[2..], [0., 0.]);
+
+        let (_, estimate) = folded_runner
+            .em(sfs, saf.view(), stopping::Steps::new(10))
+            .unwrap();
+        assert_eq!(estimate, estimate.fold());
+    }
 }
@@ -6,7 +6,7 @@
 
 use std::{
     iter::Sum,
-    ops::{Add, AddAssign},
+    ops::{Add, AddAssign, Mul},
 };
 
 use super::to_f64;
@@ -81,11 +81,24 @@ impl AddAssign for LogLikelihood {
     }
 }
 
+impl Mul<f64> for LogLikelihood {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, rhs: f64) -> Self::Output {
+        Self(self.0 * rhs)
+    }
+}
+
 /// A sum of items, and the number of items summed.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+///
+/// The items may be weighted, in which case the total weight of the items is tracked alongside
+/// their number. For unweighted items, the total weight is equal to the number of items.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct SumOf<T> {
     sum: T,
     n: usize,
+    weight: f64,
 }
 
 impl<T> SumOf<T> {
@@ -99,9 +112,19 @@ impl<T> SumOf<T> {
         self.n
     }
 
-    /// Creates a new sum.
+    /// Returns the total weight of items summed.
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    /// Creates a new sum of unweighted items.
     pub fn new(sum: T, n: usize) -> Self {
-        Self { sum, n }
+        Self::with_weight(sum, n, to_f64(n))
+    }
+
+    /// Creates a new sum of weighted items with the provided total weight.
+    pub fn with_weight(sum: T, n: usize, weight: f64) -> Self {
+        Self { sum, n, weight }
     }
 
     /// Returns the sum of items.
@@ -111,9 +134,16 @@ impl<T> SumOf<T> {
 }
 
 impl SumOf<LogLikelihood> {
-    /// Returns the log-likelihood normalised by the input size.
+    /// Returns the log-likelihood normalised by the total weight of the input.
+    ///
+    /// For unweighted input, this is the per-site log-likelihood. If the total weight is zero,
+    /// nothing contributes to the sum, and zero is returned.
     pub(super) fn normalise(&self) -> f64 {
-        f64::from(self.sum) / to_f64(self.n)
+        if self.weight == 0.0 {
+            0.0
+        } else {
+            f64::from(self.sum) / self.weight
+        }
     }
 }
 
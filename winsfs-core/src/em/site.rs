@@ -1,9 +1,44 @@
 use crate::{
-    saf::{AsSiteView, Site},
+    saf::{AsSiteView, BandedSiteView, Site},
     sfs::{Sfs, USfs},
 };
 
-use super::likelihood::{Likelihood, LogLikelihood};
+use super::{
+    likelihood::{Likelihood, LogLikelihood},
+    Sites,
+};
+
+/// A type of in-memory SAF that can be used as input for EM.
+///
+/// Like [`EmSite`], this trait should not typically be used in user code, except as a trait bound
+/// where code has to be written that is generic over different EM input types.
+pub trait EmSaf<const D: usize>: Copy + Sites + Sync {
+    /// The type of a single site in the SAF.
+    type Site: EmSite<D> + Send;
+
+    /// Returns the number of items to iterate over in the SAF.
+    ///
+    /// This is the number of sites, unless sites have been collapsed into weighted site patterns
+    /// (see [`Patterns`](crate::saf::Patterns)).
+    fn patterns(&self) -> usize {
+        self.sites()
+    }
+
+    /// Returns a single item in the SAF, where `index` is less than [`EmSaf::patterns`].
+    fn get_site(&self, index: usize) -> Self::Site;
+
+    /// Returns the weight of a single item in the SAF.
+    ///
+    /// If the SAF is unweighted, all sites have weight one.
+    fn get_weight(&self, index: usize) -> f64;
+
+    /// Returns a block of `size` sites starting from site `start`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block is out of bounds.
+    fn block(&self, start: usize, size: usize) -> Self;
+}
 
 /// A type of SAF site that can be used as input for EM.
 ///
@@ -12,6 +47,9 @@ use super::likelihood::{Likelihood, LogLikelihood};
 pub trait EmSite<const D: usize> {
     /// Returns the likelihood of a single site given the SFS.
     ///
+    /// For sites with very small SAF values, e.g. in high dimensions, the likelihood may underflow
+    /// to zero. In that case, see [`EmSite::log_likelihood`].
+    ///
     /// # Panics
     ///
     /// Panics if the shape of the SFS does not fit the shape of `self`.
@@ -28,6 +66,10 @@ pub trait EmSite<const D: usize> {
 
     /// Returns the log-likelihood of a single site given the SFS.
     ///
+    /// If the likelihood of the site underflows, the SAF values of each population are scaled by
+    /// their maximum and the likelihood is recomputed, tracking the scaling factor in log space, so
+    /// that the log-likelihood remains accurate.
+    ///
     /// # Panics
     ///
     /// Panics if the shape of the SFS does not fit the shape of `self`.
@@ -51,7 +93,9 @@ pub trait EmSite<const D: usize> {
     /// will be matched against the shape of the SFS, and a panic will be thrown if they do not
     /// match. The shapes of `posterior` and `buf` are unchecked, but must match the shape of self.
     ///
-    /// The likelihood of the site given the SFS is returned.
+    /// The log-likelihood of the site given the SFS is returned. As for
+    /// [`EmSite::log_likelihood`], SAF values are scaled if the likelihood underflows, so that the
+    /// posterior and the log-likelihood remain well-defined.
     ///
     /// # Panics
     ///
@@ -61,7 +105,26 @@ pub trait EmSite<const D: usize> {
         sfs: &Sfs<D>,
         posterior: &mut USfs<D>,
         buf: &mut USfs<D>,
-    ) -> Likelihood;
+    ) -> LogLikelihood {
+        self.weighted_posterior_into(sfs, posterior, buf, 1.0)
+    }
+
+    /// Adds the posterior counts for the site scaled by `weight` into the provided `posterior`
+    /// buffer, using the extra `buf` to avoid extraneous allocations.
+    ///
+    /// This is the weighted version of [`EmSite::posterior_into`], see also its documentation for
+    /// more. Note that the returned log-likelihood of the site is not weighted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the shape of the SFS does not fit the shape of `self`.
+    fn weighted_posterior_into(
+        &self,
+        sfs: &Sfs<D>,
+        posterior: &mut USfs<D>,
+        buf: &mut USfs<D>,
+        weight: f64,
+    ) -> LogLikelihood;
 }
 
 impl<const D: usize, T> EmSite<D> for T
@@ -78,6 +141,7 @@ where
             sfs.as_slice(),
             sfs.strides.as_slice(),
             site.split().as_slice(),
+            &[1.; D],
             &mut sum,
             1.,
         );
@@ -85,25 +149,57 @@ where
         sum.into()
     }
 
-    fn posterior_into(
+    fn log_likelihood(&self, sfs: &Sfs<D>) -> LogLikelihood {
+        let site = self.as_site_view();
+        assert_eq!(sfs.shape, site.shape());
+
+        let split = site.split();
+
+        let (sum, log_scale) = scaled(split, |scales| {
+            let mut sum = 0.;
+
+            likelihood_inner(
+                sfs.as_slice(),
+                sfs.strides.as_slice(),
+                split.as_slice(),
+                scales,
+                &mut sum,
+                1.,
+            );
+
+            sum
+        });
+
+        LogLikelihood::from(sum.ln() + log_scale)
+    }
+
+    fn weighted_posterior_into(
         &self,
         sfs: &Sfs<D>,
         posterior: &mut USfs<D>,
         buf: &mut USfs<D>,
-    ) -> Likelihood {
+        weight: f64,
+    ) -> LogLikelihood {
         let site = self.as_site_view();
         assert_eq!(sfs.shape, site.shape());
 
-        let mut sum = 0.;
+        let split = site.split();
 
-        posterior_inner(
-            sfs.as_slice(),
-            sfs.strides.as_slice(),
-            site.split().as_slice(),
-            buf.as_mut_slice(),
-            &mut sum,
-            1.,
-        );
+        let (sum, log_scale) = scaled(split, |scales| {
+            let mut sum = 0.;
+
+            posterior_inner(
+                sfs.as_slice(),
+                sfs.strides.as_slice(),
+                split.as_slice(),
+                scales,
+                buf.as_mut_slice(),
+                &mut sum,
+                1.,
+            );
+
+            sum
+        });
 
         // Normalising and adding to the posterior in a single iterator has slightly better perf
         // than normalising and then adding to posterior.
@@ -111,11 +207,92 @@ where
             .zip(posterior.iter_mut())
             .for_each(|(buf, posterior)| {
                 *buf /= sum;
-                *posterior += *buf;
+                *posterior += weight * *buf;
             });
 
+        LogLikelihood::from(sum.ln() + log_scale)
+    }
+}
+
+impl<'a, const D: usize> EmSite<D> for BandedSiteView<'a, D> {
+    fn likelihood(&self, sfs: &Sfs<D>) -> Likelihood {
+        assert_eq!(sfs.shape, self.shape());
+
+        let mut sum = 0.;
+
+        banded_likelihood_inner(
+            sfs.as_slice(),
+            sfs.strides.as_slice(),
+            self.bands().as_slice(),
+            &[1.; D],
+            &mut sum,
+            1.,
+        );
+
         sum.into()
     }
+
+    fn log_likelihood(&self, sfs: &Sfs<D>) -> LogLikelihood {
+        assert_eq!(sfs.shape, self.shape());
+
+        let bands = self.bands();
+
+        let (sum, log_scale) = scaled(bands.map(|(_, band)| band), |scales| {
+            let mut sum = 0.;
+
+            banded_likelihood_inner(
+                sfs.as_slice(),
+                sfs.strides.as_slice(),
+                bands.as_slice(),
+                scales,
+                &mut sum,
+                1.,
+            );
+
+            sum
+        });
+
+        LogLikelihood::from(sum.ln() + log_scale)
+    }
+
+    fn weighted_posterior_into(
+        &self,
+        sfs: &Sfs<D>,
+        posterior: &mut USfs<D>,
+        buf: &mut USfs<D>,
+        weight: f64,
+    ) -> LogLikelihood {
+        assert_eq!(sfs.shape, self.shape());
+
+        let bands = self.bands();
+
+        let (sum, log_scale) = scaled(bands.map(|(_, band)| band), |scales| {
+            let mut sum = 0.;
+
+            banded_posterior_inner(
+                sfs.as_slice(),
+                sfs.strides.as_slice(),
+                bands.as_slice(),
+                scales,
+                buf.as_mut_slice(),
+                &mut sum,
+                1.,
+            );
+
+            sum
+        });
+
+        banded_add_posterior_inner(
+            sfs.strides.as_slice(),
+            bands.as_slice(),
+            buf.as_mut_slice(),
+            posterior.as_mut_slice(),
+            sum,
+            weight,
+        );
+
+        LogLikelihood::from(sum.ln() + log_scale)
+    }
 }
 
 /// A type of SAF site that can be used as input for streaming EM.
@@ -136,24 +313,70 @@ impl<const D: usize> StreamEmSite<D> for Site<D> {
     }
 }
 
+/// Runs a likelihood kernel for a site, rerunning it with scaled SAF values if the likelihood
+/// underflows.
+///
+/// The `kernel` takes the scale of the SAF values of each population and returns the likelihood,
+/// and is first run without scaling. If the likelihood underflows, the SAF values of each population
+/// are scaled by the reciprocal of their maximum, and the kernel is run again. Returns the
+/// (possibly scaled) likelihood, and the log of the factor by which it was scaled down, which must
+/// be added to the log of the likelihood to get the log-likelihood. Since a common scale cancels
+/// when normalising, the (possibly scaled) likelihood can be used to normalise the posterior.
+fn scaled<const D: usize, F>(site: [&[f32]; D], mut kernel: F) -> (f64, f64)
+where
+    F: FnMut(&[f64; D]) -> f64,
+{
+    let sum = kernel(&[1.; D]);
+
+    if sum >= f64::MIN_POSITIVE || sum.is_nan() {
+        return (sum, 0.);
+    }
+
+    let maxima = site.map(|values| values.iter().copied().fold(0., f32::max));
+
+    // If any population has no positive values, the likelihood is exactly zero
+    if maxima.contains(&0.) {
+        return (sum, 0.);
+    }
+
+    let scales = maxima.map(|max| 1. / f64::from(max));
+    let log_scale = maxima.iter().map(|&max| f64::from(max).ln()).sum();
+
+    (kernel(&scales), log_scale)
+}
+
 /// Calculate the likelihood for a site any dimension recursively.
 ///
 /// The logic here is a simplified version of `posterior_inner`: see the comments there for more.
-fn likelihood_inner(sfs: &[f64], strides: &[usize], site: &[&[f32]], sum: &mut f64, acc: f64) {
-    match site {
-        &[hd] => sfs.iter().zip(hd).for_each(|(sfs, &saf)| {
-            *sum += sfs * saf as f64 * acc;
+fn likelihood_inner(
+    sfs: &[f64],
+    strides: &[usize],
+    site: &[&[f32]],
+    scales: &[f64],
+    sum: &mut f64,
+    acc: f64,
+) {
+    match (site, scales) {
+        (&[hd], &[scale]) => sfs.iter().zip(hd).for_each(|(sfs, &saf)| {
+            *sum += sfs * (saf as f64 * scale) * acc;
         }),
-        [hd, cons @ ..] => {
+        ([hd, cons @ ..], [scale, scales @ ..]) => {
             let (stride, strides) = strides.split_first().expect("invalid strides");
 
             for (i, &saf) in hd.iter().enumerate() {
                 let offset = i * stride;
 
-                likelihood_inner(&sfs[offset..], strides, cons, sum, saf as f64 * acc);
+                likelihood_inner(
+                    &sfs[offset..],
+                    strides,
+                    cons,
+                    scales,
+                    sum,
+                    saf as f64 * scale * acc,
+                );
             }
         }
-        [] => (),
+        _ => (),
     }
 }
 
@@ -161,7 +384,9 @@ fn likelihood_inner(sfs: &[f64], strides: &[usize], site: &[&[f32]], sum: &mut f
 ///
 /// The posterior is written into the `buf`, which is not normalised. The `sum` will contain
 /// the likelihood, which can be used to normalise. The passed-in `sum` should typically be zero,
-/// whereas the passed-in `acc` should typically be one.
+/// whereas the passed-in `acc` should typically be one. The SAF values of each population are
+/// multiplied by the corresponding value in `scales`, which should typically be one, in which case
+/// the result is exactly the unscaled result.
 ///
 /// It is  assumed that `sfs` and `buf` have the same length, which should correspond to the product
 /// of the length of the sites in `site`.
@@ -169,12 +394,13 @@ fn posterior_inner(
     sfs: &[f64],
     strides: &[usize],
     site: &[&[f32]],
+    scales: &[f64],
     buf: &mut [f64],
     sum: &mut f64,
     acc: f64,
 ) {
-    match site {
-        &[hd] => {
+    match (site, scales) {
+        (&[hd], &[scale]) => {
             // Base case: we have a single site, which signifies that the SFS slice
             // now corresponds to a single slice along its last dimension, e.g. a row in 2D.
             debug_assert_eq!(sfs.len(), hd.len());
@@ -183,12 +409,12 @@ fn posterior_inner(
                 .zip(sfs)
                 .zip(hd)
                 .for_each(|((buf, sfs), &saf)| {
-                    let v = sfs * saf as f64 * acc;
+                    let v = sfs * (saf as f64 * scale) * acc;
                     *sum += v;
                     *buf = v
                 })
         }
-        [hd, cons @ ..] => {
+        ([hd, cons @ ..], [scale, scales @ ..]) => {
             // Recursive case: we have multiple sites. For each value in the first site,
             // we add the value to the accumulant, "peel" the corresponding slice of the SFS,
             // and recurse to a lower dimension.
@@ -201,9 +427,135 @@ fn posterior_inner(
                     &sfs[offset..][..*stride],
                     strides,
                     cons,
+                    scales,
                     &mut buf[offset..][..*stride],
                     sum,
-                    saf as f64 * acc,
+                    saf as f64 * scale * acc,
+                );
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Calculate the likelihood for a banded site any dimension recursively.
+///
+/// This is the banded version of `likelihood_inner`, where each population is given by the start
+/// of its band and the values in the band. Only values of the SFS inside the bands are visited.
+/// Since values outside the bands are zero, and terms are added in the same order, the result is
+/// identical to that of `likelihood_inner` on the corresponding full site.
+fn banded_likelihood_inner(
+    sfs: &[f64],
+    strides: &[usize],
+    site: &[(usize, &[f32])],
+    scales: &[f64],
+    sum: &mut f64,
+    acc: f64,
+) {
+    match (site, scales) {
+        (&[(start, hd)], &[scale]) => sfs[start..].iter().zip(hd).for_each(|(sfs, &saf)| {
+            *sum += sfs * (saf as f64 * scale) * acc;
+        }),
+        ([(start, hd), cons @ ..], [scale, scales @ ..]) => {
+            let (stride, strides) = strides.split_first().expect("invalid strides");
+
+            for (i, &saf) in hd.iter().enumerate() {
+                let offset = (start + i) * stride;
+
+                banded_likelihood_inner(
+                    &sfs[offset..],
+                    strides,
+                    cons,
+                    scales,
+                    sum,
+                    saf as f64 * scale * acc,
+                );
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Calculate the unnormalised posterior for a banded site any dimension recursively.
+///
+/// This is the banded version of `posterior_inner`. Only the values of `buf` inside the bands are
+/// written, and the remaining values of `buf` should be ignored.
+fn banded_posterior_inner(
+    sfs: &[f64],
+    strides: &[usize],
+    site: &[(usize, &[f32])],
+    scales: &[f64],
+    buf: &mut [f64],
+    sum: &mut f64,
+    acc: f64,
+) {
+    match (site, scales) {
+        (&[(start, hd)], &[scale]) => {
+            buf[start..]
+                .iter_mut()
+                .zip(&sfs[start..])
+                .zip(hd)
+                .for_each(|((buf, sfs), &saf)| {
+                    let v = sfs * (saf as f64 * scale) * acc;
+                    *sum += v;
+                    *buf = v
+                })
+        }
+        ([(start, hd), cons @ ..], [scale, scales @ ..]) => {
+            let (stride, strides) = strides.split_first().expect("invalid strides");
+
+            for (i, &saf) in hd.iter().enumerate() {
+                let offset = (start + i) * stride;
+
+                banded_posterior_inner(
+                    &sfs[offset..][..*stride],
+                    strides,
+                    cons,
+                    scales,
+                    &mut buf[offset..][..*stride],
+                    sum,
+                    saf as f64 * scale * acc,
+                );
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Normalise the posterior for a banded site in `buf` and add it to `posterior` recursively.
+///
+/// Only values inside the bands are visited, and so `buf` must be as written by
+/// `banded_posterior_inner`. Outside the bands, the normalised posterior is zero, and so
+/// `posterior` is left unchanged.
+fn banded_add_posterior_inner(
+    strides: &[usize],
+    site: &[(usize, &[f32])],
+    buf: &mut [f64],
+    posterior: &mut [f64],
+    sum: f64,
+    weight: f64,
+) {
+    match site {
+        &[(start, hd)] => buf[start..][..hd.len()]
+            .iter_mut()
+            .zip(&mut posterior[start..])
+            .for_each(|(buf, posterior)| {
+                *buf /= sum;
+                *posterior += weight * *buf;
+            }),
+        [(start, hd), cons @ ..] => {
+            let (stride, strides) = strides.split_first().expect("invalid strides");
+
+            for i in 0..hd.len() {
+                let offset = (start + i) * stride;
+
+                banded_add_posterior_inner(
+                    strides,
+                    cons,
+                    &mut buf[offset..][..*stride],
+                    &mut posterior[offset..][..*stride],
+                    sum,
+                    weight,
                 );
             }
         }
@@ -244,7 +596,26 @@ mod tests {
 
         let likelihood = site.likelihood(&sfs);
         test_f64_equal(likelihood.into(), 2., f64::EPSILON);
-        test_f64_equal(likelihood.into(), posterior_likelihood.into(), f64::EPSILON);
+        test_f64_equal(
+            likelihood.ln().into(),
+            posterior_likelihood.into(),
+            f64::EPSILON,
+        );
+    }
+
+    #[test]
+    fn test_1d_weighted() {
+        let sfs = sfs1d![1., 2., 3.].normalise();
+
+        let site = Site::new(vec![2., 2., 2.], [3]).unwrap();
+        let mut posterior = sfs1d![10., 20., 30.];
+        let mut buf = USfs::zeros(sfs.shape);
+
+        let likelihood = site.weighted_posterior_into(&sfs, &mut posterior, &mut buf, 3.);
+
+        let expected = vec![10. + 1. / 2., 20. + 1., 30. + 3. / 2.];
+        test_f64_slice_equal(posterior.as_slice(), expected.as_slice(), 1e-12);
+        test_f64_equal(likelihood.into(), 2f64.ln(), f64::EPSILON);
     }
 
     #[test]
@@ -272,7 +643,11 @@ mod tests {
 
         let likelihood = site.likelihood(&sfs);
         test_f64_equal(likelihood.into(), 13., f64::EPSILON);
-        test_f64_equal(likelihood.into(), posterior_likelihood.into(), f64::EPSILON);
+        test_f64_equal(
+            likelihood.ln().into(),
+            posterior_likelihood.into(),
+            f64::EPSILON,
+        );
     }
 
     #[test]
@@ -300,6 +675,61 @@ mod tests {
 
         let likelihood = site.likelihood(&sfs);
         test_f64_equal(likelihood.into(), 139.8418, 1e-4);
-        test_f64_equal(likelihood.into(), posterior_likelihood.into(), f64::EPSILON);
+        test_f64_equal(
+            likelihood.ln().into(),
+            posterior_likelihood.into(),
+            f64::EPSILON,
+        );
+    }
+
+    #[test]
+    fn test_1d_underflow() {
+        let sfs = sfs1d![1., 1e-300, 1e-300].normalise();
+
+        let site = Site::new(vec![0., 1e-30, 1e-30], [3]).unwrap();
+        let mut posterior = USfs::zeros(sfs.shape);
+        let mut buf = USfs::zeros(sfs.shape);
+
+        assert_eq!(f64::from(site.likelihood(&sfs)), 0.);
+
+        let expected = (2e-300f64).ln() + f64::from(1e-30f32).ln();
+        let log_likelihood = site.log_likelihood(&sfs);
+        test_f64_equal(log_likelihood.into(), expected, 1e-12);
+
+        let posterior_log_likelihood = site.posterior_into(&sfs, &mut posterior, &mut buf);
+        test_f64_equal(posterior_log_likelihood.into(), expected, 1e-12);
+        test_f64_slice_equal(posterior.as_slice(), &[0., 0.5, 0.5], f64::EPSILON);
+    }
+
+    #[test]
+    fn test_3d_underflow() {
+        let mut values = vec![1.; 27];
+        values[26] = 1e-200;
+        let sfs = USfs::from_vec_shape(values, [3, 3, 3]).unwrap().normalise();
+        let sfs_value = sfs.as_slice()[26];
+
+        let site = Site::new(vec![0., 0., 1e-40, 0., 0., 1e-38, 0., 0., 1e-35], [3, 3, 3]).unwrap();
+        let mut posterior = USfs::zeros(sfs.shape);
+        let mut buf = USfs::zeros(sfs.shape);
+
+        let log_likelihood = site.posterior_into(&sfs, &mut posterior, &mut buf);
+
+        let expected = sfs_value.ln()
+            + [1e-40f32, 1e-38, 1e-35]
+                .iter()
+                .map(|&x| f64::from(x).ln())
+                .sum::<f64>();
+        test_f64_equal(log_likelihood.into(), expected, 1e-9);
+        test_f64_equal(site.log_likelihood(&sfs).into(), expected, 1e-9);
+        assert_eq!(posterior.as_slice()[26], 1.);
+        assert_eq!(posterior.iter().sum::<f64>(), 1.);
+    }
+
+    #[test]
+    fn test_zero_likelihood_not_scaled() {
+        let sfs = sfs1d![1., 2., 3.].normalise();
+        let site = Site::new(vec![0., 0., 0.], [3]).unwrap();
+
+        assert_eq!(f64::from(site.log_likelihood(&sfs)), f64::NEG_INFINITY);
     }
 }
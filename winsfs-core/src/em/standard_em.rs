@@ -2,13 +2,12 @@ use std::{convert::Infallible, io};
 
 use crate::{
     io::ReadSite,
-    saf::SafView,
     sfs::{Sfs, USfs},
 };
 
 use super::{
     likelihood::{LogLikelihood, SumOf},
-    EmStep, WithStatus,
+    EmSaf, EmStep, WithStatus,
 };
 
 /// A parallel runner of the standard EM algorithm.
@@ -21,15 +20,55 @@ pub type StreamingEm = StandardEm<false, true>;
 ///
 /// Whether to parallelise over the input in the E-step is controlled by the `PAR` parameter,
 /// whether to stream through data on disk is controlled by the `STREAM` parameter.
+///
+/// The runner can optionally estimate a folded SFS, see [`StandardEm::with_folding`].
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 #[non_exhaustive]
 // TODO: Use const enum here when stable, see github.com/rust-lang/rust/issues/95174
-pub struct StandardEm<const PAR: bool = false, const STREAM: bool = false>;
+pub struct StandardEm<const PAR: bool = false, const STREAM: bool = false> {
+    folded: bool,
+}
 
 impl<const PAR: bool, const STREAM: bool> StandardEm<PAR, STREAM> {
+    /// Returns `true` if the runner estimates a folded SFS, `false` otherwise.
+    pub fn is_folded(&self) -> bool {
+        self.folded
+    }
+
     /// Returns a new instance of the runner.
     pub fn new() -> Self {
-        Self {}
+        Self { folded: false }
+    }
+
+    /// Sets whether the runner should estimate a folded SFS.
+    ///
+    /// When folded, the parameter space of the algorithm is the folded SFS, using the
+    /// representation from [`SfsBase::fold`](crate::sfs::SfsBase::fold). The input SFS is
+    /// symmetrised over the folding map before each step, so that the likelihood of each site is
+    /// the average over the indistinguishable, unfolded combinations. The returned posterior counts
+    /// are folded correspondingly. The input SFS to each step is assumed to be folded, though
+    /// passing an unfolded SFS is equivalent to passing its folded counterpart.
+    pub fn with_folding(mut self, folded: bool) -> Self {
+        self.folded = folded;
+        self
+    }
+
+    /// Returns the SFS used for computing site likelihoods.
+    fn unfold<const D: usize>(&self, sfs: Sfs<D>) -> Sfs<D> {
+        if self.folded {
+            sfs.symmetrise()
+        } else {
+            sfs
+        }
+    }
+
+    /// Returns the posterior in the parameter space of the runner.
+    fn fold<const D: usize>(&self, posterior: USfs<D>) -> USfs<D> {
+        if self.folded {
+            posterior.fold()
+        } else {
+            posterior
+        }
     }
 }
 
@@ -37,14 +76,15 @@ impl<const PAR: bool, const STREAM: bool> WithStatus for StandardEm<PAR, STREAM>
     type Status = SumOf<LogLikelihood>;
 }
 
-impl<'a, const D: usize, const PAR: bool> EmStep<D, SafView<'a, D>> for StandardEm<PAR, false> {
+impl<const D: usize, const PAR: bool, I> EmStep<D, I> for StandardEm<PAR, false>
+where
+    I: EmSaf<D>,
+{
     type Error = Infallible;
 
-    fn log_likelihood(
-        &mut self,
-        sfs: Sfs<D>,
-        saf: SafView<'a, D>,
-    ) -> Result<SumOf<LogLikelihood>, Self::Error> {
+    fn log_likelihood(&mut self, sfs: Sfs<D>, saf: I) -> Result<SumOf<LogLikelihood>, Self::Error> {
+        let sfs = self.unfold(sfs);
+
         if PAR {
             Ok(sfs.par_log_likelihood(saf))
         } else {
@@ -52,16 +92,16 @@ impl<'a, const D: usize, const PAR: bool> EmStep<D, SafView<'a, D>> for Standard
         }
     }
 
-    fn e_step(
-        &mut self,
-        sfs: Sfs<D>,
-        saf: SafView<D>,
-    ) -> Result<(Self::Status, USfs<D>), Self::Error> {
-        if PAR {
-            Ok(sfs.par_e_step(saf))
+    fn e_step(&mut self, sfs: Sfs<D>, saf: I) -> Result<(Self::Status, USfs<D>), Self::Error> {
+        let sfs = self.unfold(sfs);
+
+        let (status, posterior) = if PAR {
+            sfs.par_e_step(saf)
         } else {
-            Ok(sfs.e_step(saf))
-        }
+            sfs.e_step(saf)
+        };
+
+        Ok((status, self.fold(posterior)))
     }
 }
 
@@ -76,7 +116,7 @@ where
         sfs: Sfs<D>,
         reader: &'a mut R,
     ) -> Result<SumOf<LogLikelihood>, Self::Error> {
-        sfs.stream_log_likelihood(reader)
+        self.unfold(sfs).stream_log_likelihood(reader)
     }
 
     fn e_step(
@@ -84,6 +124,8 @@ where
         sfs: Sfs<D>,
         reader: &'a mut R,
     ) -> Result<(Self::Status, USfs<D>), Self::Error> {
-        sfs.stream_e_step(reader)
+        self.unfold(sfs)
+            .stream_e_step(reader)
+            .map(|(status, posterior)| (status, self.fold(posterior)))
     }
 }
@@ -1,6 +1,11 @@
 //! Stopping rules used for deciding convergence for EM algorithms.
 
-use crate::sfs::Sfs;
+use std::time::{Duration, Instant};
+
+use crate::{
+    saf::Saf,
+    sfs::{DynSfs, Sfs},
+};
 
 use super::{
     likelihood::{LogLikelihood, SumOf},
@@ -40,6 +45,18 @@ where
     fn stop<const N: usize>(&mut self, em: &T, status: &T::Status, sfs: &Sfs<N>) -> bool;
 }
 
+impl<S> StoppingRule for &mut S where S: StoppingRule {}
+
+impl<T, S> Stop<T> for &mut S
+where
+    T: WithStatus,
+    S: Stop<T>,
+{
+    fn stop<const N: usize>(&mut self, em: &T, status: &T::Status, sfs: &Sfs<N>) -> bool {
+        <S as Stop<T>>::stop(self, em, status, sfs)
+    }
+}
+
 /// A stopping rule that lets the EM algorithm run for a specific number of EM-steps.
 pub struct Steps {
     current_step: usize,
@@ -82,7 +99,8 @@ where
 /// normalised log-likelihood values falls below some tolerance.
 ///
 /// The log-likelihood will be normalised by the number of sites, so that it becomes a per-site
-/// measure. This makes it easier to find a reasonable tolerance for a range of input sizes.
+/// measure. This makes it easier to find a reasonable tolerance for a range of input sizes. For
+/// weighted input, the log-likelihood is normalised by the total weight of the sites instead.
 pub struct LogLikelihoodTolerance {
     abs_diff: f64,
     log_likelihood: f64,
@@ -188,6 +206,250 @@ where
     }
 }
 
+/// A stopping rule that lets the EM algorithm run until the log-likelihood of a held-out set of
+/// sites stops improving.
+///
+/// After each EM-step, the normalised log-likelihood of the held-out sites is evaluated given the
+/// current SFS estimate. If it has not improved on the best value seen for `patience` consecutive
+/// steps, the algorithm stops. Since the estimate at that point will typically have started
+/// overfitting the training data, the best SFS seen is kept and can be retrieved using
+/// [`HeldOutLogLikelihood::best_sfs`]. To get access to the rule after running EM, pass it by
+/// mutable reference.
+///
+/// The held-out sites should not be part of the input used for training.
+///
+/// # Panics
+///
+/// Checking the rule panics if the SFS does not have dimension `D`, or if the held-out sites do
+/// not fit the shape of the SFS. The SFS is only known when the rule is checked, so this cannot be
+/// caught when the rule is created.
+pub struct HeldOutLogLikelihood<const D: usize> {
+    saf: Saf<D>,
+    patience: usize,
+    folded: bool,
+    log_likelihood: f64,
+    best_log_likelihood: f64,
+    best_sfs: Option<Sfs<D>>,
+    steps_since_best: usize,
+}
+
+impl<const D: usize> HeldOutLogLikelihood<D> {
+    /// Returns the best SFS seen so far, if any steps have been taken.
+    pub fn best_sfs(&self) -> Option<&Sfs<D>> {
+        self.best_sfs.as_ref()
+    }
+
+    /// Returns the best normalised held-out log-likelihood seen so far.
+    pub fn best_log_likelihood(&self) -> LogLikelihood {
+        self.best_log_likelihood.into()
+    }
+
+    /// Returns the best SFS seen so far, consuming `self`.
+    pub fn into_best_sfs(self) -> Option<Sfs<D>> {
+        self.best_sfs
+    }
+
+    /// Returns the current, normalised held-out log-likelihood value.
+    pub fn log_likelihood(&self) -> LogLikelihood {
+        self.log_likelihood.into()
+    }
+
+    /// Creates a new stopping rule that allows EM steps until the normalised log-likelihood of
+    /// the sites in `saf` has not improved for `patience` steps.
+    ///
+    /// # Panics
+    ///
+    /// The rule panics when checked against an SFS that does not match the shape of the sites in
+    /// `saf`, see [`HeldOutLogLikelihood`].
+    pub fn new(saf: Saf<D>, patience: usize) -> Self {
+        Self {
+            saf,
+            patience,
+            folded: false,
+            log_likelihood: f64::NEG_INFINITY,
+            best_log_likelihood: f64::NEG_INFINITY,
+            best_sfs: None,
+            steps_since_best: 0,
+        }
+    }
+
+    /// Returns the number of steps allowed without improvement before stopping.
+    pub fn patience(&self) -> usize {
+        self.patience
+    }
+
+    /// Returns the held-out sites.
+    pub fn saf(&self) -> &Saf<D> {
+        &self.saf
+    }
+
+    /// Returns the number of steps since the best SFS was seen.
+    pub fn steps_since_best(&self) -> usize {
+        self.steps_since_best
+    }
+
+    /// Sets whether the SFS estimates are folded.
+    ///
+    /// If so, the held-out log-likelihood is evaluated using the SFS symmetrised over the folding
+    /// map, matching [`StandardEm::with_folding`](super::StandardEm::with_folding).
+    pub fn with_folding(mut self, folded: bool) -> Self {
+        self.folded = folded;
+        self
+    }
+}
+
+impl<const D: usize> StoppingRule for HeldOutLogLikelihood<D> {}
+
+impl<const D: usize, T> Stop<T> for HeldOutLogLikelihood<D>
+where
+    T: WithStatus,
+{
+    fn stop<const N: usize>(&mut self, _em: &T, _status: &T::Status, sfs: &Sfs<N>) -> bool {
+        // The const bound is on the method rather than the trait, so we have to go through
+        // the dynamic shape to get an SFS of the matching dimension.
+        let sfs = Sfs::<D>::try_from(DynSfs::from(sfs.clone()))
+            .expect("held-out SAF dimension does not match SFS dimension");
+
+        let likelihood_sfs = if self.folded {
+            sfs.symmetrise()
+        } else {
+            sfs.clone()
+        };
+        self.log_likelihood = likelihood_sfs
+            .par_log_likelihood(self.saf.view())
+            .normalise();
+
+        if self.log_likelihood > self.best_log_likelihood {
+            self.best_log_likelihood = self.log_likelihood;
+            self.best_sfs = Some(sfs);
+            self.steps_since_best = 0;
+        } else {
+            self.steps_since_best += 1;
+        }
+
+        self.steps_since_best >= self.patience
+    }
+}
+
+/// A measure of distance between successive SFS estimates.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SfsDistance {
+    /// The sum of absolute differences between corresponding values.
+    L1,
+    /// The maximum absolute difference between corresponding values.
+    MaxAbs,
+}
+
+impl SfsDistance {
+    /// Returns the distance between two collections of SFS values.
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let abs_diffs = a.iter().zip(b).map(|(a, b)| (a - b).abs());
+
+        match self {
+            Self::L1 => abs_diffs.sum(),
+            Self::MaxAbs => abs_diffs.fold(0.0, f64::max),
+        }
+    }
+}
+
+/// A stopping rule that lets the EM algorithm run until the distance between successive,
+/// normalised SFS estimates falls below some tolerance.
+pub struct SfsTolerance {
+    distance: SfsDistance,
+    diff: f64,
+    // We go through a bit of effort to not keep an `Sfs<D>` here to avoid the const bound
+    // propagating to the rule.
+    values: Option<Vec<f64>>,
+    tolerance: f64,
+}
+
+impl SfsTolerance {
+    /// Returns the distance between the two most recent SFS estimates.
+    pub fn difference(&self) -> f64 {
+        self.diff
+    }
+
+    /// Returns the measure of distance used between SFS estimates.
+    pub fn distance(&self) -> SfsDistance {
+        self.distance
+    }
+
+    /// Creates a new stopping rule that allows EM steps until the `distance` between successive,
+    /// normalised SFS estimates falls below `tolerance`.
+    pub fn new(distance: SfsDistance, tolerance: f64) -> Self {
+        Self {
+            distance,
+            diff: f64::INFINITY,
+            values: None,
+            tolerance,
+        }
+    }
+
+    /// Returns the tolerance defining convergence.
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+}
+
+impl StoppingRule for SfsTolerance {}
+
+impl<T> Stop<T> for SfsTolerance
+where
+    T: WithStatus,
+{
+    fn stop<const N: usize>(&mut self, _em: &T, _status: &T::Status, sfs: &Sfs<N>) -> bool {
+        match self.values.as_mut() {
+            Some(values) => {
+                self.diff = self.distance.distance(values, sfs.as_slice());
+                values.copy_from_slice(sfs.as_slice());
+            }
+            None => self.values = Some(sfs.as_slice().to_vec()),
+        }
+
+        self.diff <= self.tolerance
+    }
+}
+
+/// A stopping rule that lets the EM algorithm run until a wall-clock time limit is exceeded.
+///
+/// The clock starts when the rule is created. Note that the limit is only checked after each
+/// EM-step, so that the total running time may exceed the limit by up to the duration of a step.
+pub struct TimeLimit {
+    limit: Duration,
+    start: Instant,
+}
+
+impl TimeLimit {
+    /// Returns the time elapsed since the rule was created.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Returns the time limit.
+    pub fn limit(&self) -> Duration {
+        self.limit
+    }
+
+    /// Creates a new stopping rule that allows EM steps until `limit` has passed.
+    pub fn new(limit: Duration) -> Self {
+        Self {
+            limit,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl StoppingRule for TimeLimit {}
+
+impl<T> Stop<T> for TimeLimit
+where
+    T: WithStatus,
+{
+    fn stop<const N: usize>(&mut self, _em: &T, _status: &T::Status, _sfs: &Sfs<N>) -> bool {
+        self.elapsed() >= self.limit
+    }
+}
+
 /// A stopping rule that lets the EM algorithm run until *both* the contained stopping rules
 /// indicate convergence.
 ///
@@ -275,3 +537,78 @@ where
         self.left.stop(em, status, sfs) || self.right.stop(em, status, sfs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{em::StandardEm, saf1d, sfs1d};
+
+    #[test]
+    fn test_held_out_keeps_best_and_stops_after_patience() {
+        let saf = saf1d![[1., 0., 0.], [1., 0., 0.], [0., 1., 0.]];
+        let mut rule = HeldOutLogLikelihood::new(saf, 2);
+
+        let em = StandardEm::<false, false>::new();
+        let status = SumOf::new(LogLikelihood::from(0.), 0);
+
+        let good = sfs1d![2., 1., 0.].normalise();
+        let bad = sfs1d![1., 1., 1.].normalise();
+        let worse = sfs1d![0., 1., 2.].normalise();
+
+        assert!(!rule.stop(&em, &status, &good));
+        assert_eq!(rule.steps_since_best(), 0);
+        assert!(!rule.stop(&em, &status, &bad));
+        assert!(rule.stop(&em, &status, &worse));
+
+        assert_eq!(rule.best_sfs(), Some(&good));
+        assert!(rule.best_log_likelihood() > rule.log_likelihood());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_held_out_panics_dimension_mismatch() {
+        let saf = saf1d![[1., 0., 0.]];
+        let mut rule = HeldOutLogLikelihood::new(saf, 2);
+
+        let em = StandardEm::<false, false>::new();
+        let status = SumOf::new(LogLikelihood::from(0.), 0);
+
+        rule.stop(&em, &status, &Sfs::uniform([3, 3]));
+    }
+
+    #[test]
+    fn test_sfs_distance() {
+        let a = [0.5, 0.25, 0.25];
+        let b = [0.4, 0.4, 0.2];
+
+        assert!((SfsDistance::L1.distance(&a, &b) - 0.3).abs() < 1e-12);
+        assert!((SfsDistance::MaxAbs.distance(&a, &b) - 0.15).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sfs_tolerance() {
+        let mut rule = SfsTolerance::new(SfsDistance::MaxAbs, 0.1);
+
+        let em = StandardEm::<false, false>::new();
+        let status = SumOf::new(LogLikelihood::from(0.), 0);
+
+        assert!(!rule.stop(&em, &status, &sfs1d![0.5, 0.5].normalise()));
+        assert!(!rule.stop(&em, &status, &sfs1d![0.7, 0.3].normalise()));
+        assert!(rule.stop(&em, &status, &sfs1d![0.75, 0.25].normalise()));
+        assert!((rule.difference() - 0.05).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_time_limit_composes() {
+        let em = StandardEm::<false, false>::new();
+        let status = SumOf::new(LogLikelihood::from(0.), 0);
+        let sfs = sfs1d![1., 1.].normalise();
+
+        let mut rule = TimeLimit::new(Duration::ZERO).or(Steps::new(100));
+        assert!(rule.stop(&em, &status, &sfs));
+
+        let mut rule = TimeLimit::new(Duration::from_secs(3600)).and(Steps::new(1));
+        assert!(!rule.stop(&em, &status, &sfs));
+    }
+}
@@ -2,7 +2,7 @@ use std::{collections::VecDeque, io, iter::repeat};
 
 use crate::{
     io::{Enumerate, ReadSite, Take},
-    saf::{AsSafView, Blocks, SafView},
+    saf::Blocks,
     sfs::{
         generics::{Shape, Unnorm},
         Sfs, SfsBase, USfs,
@@ -11,7 +11,7 @@ use crate::{
 
 use super::{
     likelihood::{LogLikelihood, SumOf},
-    to_f64, EmStep, Sites, WithStatus,
+    to_f64, EmSaf, EmStep, Sites, WithStatus,
 };
 
 /// A streaming runner of the window EM algorithm.
@@ -74,25 +74,18 @@ where
     type Status = Vec<T::Status>;
 }
 
-impl<'a, const D: usize, T> EmStep<D, SafView<'a, D>> for WindowEm<T, false>
+impl<const D: usize, T, I> EmStep<D, I> for WindowEm<T, false>
 where
-    T: EmStep<D, SafView<'a, D>>,
+    I: EmSaf<D>,
+    T: EmStep<D, I>,
 {
     type Error = T::Error;
 
-    fn log_likelihood(
-        &mut self,
-        sfs: Sfs<D>,
-        saf: SafView<'a, D>,
-    ) -> Result<SumOf<LogLikelihood>, Self::Error> {
+    fn log_likelihood(&mut self, sfs: Sfs<D>, saf: I) -> Result<SumOf<LogLikelihood>, Self::Error> {
         self.em.log_likelihood(sfs, saf)
     }
 
-    fn e_step(
-        &mut self,
-        mut sfs: Sfs<D>,
-        saf: SafView<'a, D>,
-    ) -> Result<(Self::Status, USfs<D>), Self::Error> {
+    fn e_step(&mut self, mut sfs: Sfs<D>, saf: I) -> Result<(Self::Status, USfs<D>), Self::Error> {
         let window = self
             .window
             .get_or_insert_with(|| Window::from_zeros(*sfs.shape(), self.window_size));
@@ -102,8 +95,9 @@ where
 
         let mut sites = 0;
 
-        for block in saf.iter_blocks(self.blocks) {
-            sites += block.as_saf_view().sites();
+        for size in blocks_inner.iter_block_sizes() {
+            let block = saf.block(sites, size);
+            sites += size;
 
             let (log_likelihood, posterior) = self.em.e_step(sfs, block)?;
 
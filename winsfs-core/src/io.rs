@@ -1,9 +1,15 @@
 //! Utilities for working with SAF files stored on disk.
 //!
 //! To read and write standard SAF files, see the [`angsd_saf`] crate. This module contains
-//! utilities based on that for doing SFS estimation from files kept on disk.
-
-use std::{fs::File, io, path::Path};
+//! utilities based on that for doing SFS estimation from files kept on disk, as well as for
+//! writing subsets of SAF files, see [`Writer`].
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io,
+    path::Path,
+};
 
 pub use angsd_saf::{
     record::Id,
@@ -16,6 +22,24 @@ use crate::{em::Sites, saf::Site};
 mod adaptors;
 pub use adaptors::{Enumerate, Take};
 
+mod mask;
+pub use mask::{Mask, MaskKind};
+
+mod region;
+pub use region::Region;
+use region::Regions;
+
+mod subsample;
+use subsample::Sampler;
+pub use subsample::Subsample;
+
+mod union;
+pub use union::Imputed;
+use union::Union;
+
+mod writer;
+pub use writer::{Banding, Writer};
+
 pub mod shuffle;
 
 /// A type that can read SAF sites from a source.
@@ -107,7 +131,12 @@ where
 /// An intersecting SAF reader.
 ///
 /// This a wrapper around the [`Intersect`](angsd_saf::Intersect) with a static number of readers
-/// and holds its read buffers internally.
+/// and holds its read buffers internally. The reader can optionally be restricted to regions,
+/// see [`Intersect::with_regions`], and filtered by masks, see [`Intersect::with_mask`]. Several
+/// collections of readers can be read one after another as a single reader, see
+/// [`Intersect::chain`]. Rather than the intersection, the reader may also read the union of sites
+/// across readers, see [`Intersect::with_union`]. Finally, the reader may read only a random
+/// subsample of sites, see [`Intersect::with_subsample`].
 pub struct Intersect<const D: usize, R, V>
 where
     V: Version,
@@ -115,6 +144,14 @@ where
     // D readers in inner intersect is maintained as invariant
     inner: angsd_saf::Intersect<R, V>,
     bufs: [angsd_saf::Record<Id, V::Item>; D],
+    regions: Option<Regions>,
+    masks: Vec<Mask>,
+    // Remaining collections of readers to be read after the inner reader
+    parts: VecDeque<[angsd_saf::Reader<R, V>; D]>,
+    union: Option<Union<D, V>>,
+    // Populations missing from the most recently read site, only used when reading the union
+    missing: [bool; D],
+    sampler: Option<Sampler<D, V>>,
 }
 
 impl<const D: usize, R, V> Intersect<D, R, V>
@@ -122,6 +159,28 @@ where
     R: io::BufRead + io::Seek,
     V: Version,
 {
+    /// Creates a new reader from several collections of readers, to be read one after another.
+    ///
+    /// Each collection should contain one reader for each population, and the readers for each
+    /// population across collections are treated as a single reader. For instance, when SAF files
+    /// have been created separately for each chromosome, each collection would contain the readers
+    /// for a chromosome. Since collections are intersected separately, a contig must not occur in
+    /// different collections. Also, the number of alleles for each population must be the same
+    /// across collections. An error is returned if either of these conditions are not met, or if
+    /// no collections are provided.
+    ///
+    /// Note that [`Intersect::get`] and related methods refer to the collection currently being
+    /// read.
+    pub fn chain(parts: Vec<[angsd_saf::Reader<R, V>; D]>) -> io::Result<Self> {
+        check_parts(&parts)?;
+
+        let mut parts = VecDeque::from(parts);
+        let mut intersect = Self::new(parts.pop_front().expect("checked non-empty"));
+        intersect.parts = parts;
+
+        Ok(intersect)
+    }
+
     /// Returns the inner reader.
     pub fn get(&self) -> &angsd_saf::Intersect<R, V> {
         &self.inner
@@ -132,11 +191,51 @@ where
         &mut self.inner
     }
 
+    /// Returns the contig name of the most recently read site.
+    ///
+    /// The name is taken from the index of the first reader. If no site has been read, the
+    /// returned name is unspecified.
+    pub fn contig(&self) -> &str {
+        if let Some(contig) = self.sampler.as_ref().and_then(Sampler::contig) {
+            return contig;
+        }
+
+        if let Some(union) = self.union.as_ref() {
+            return union.contig();
+        }
+
+        let id = *self.bufs[0].contig_id();
+
+        self.inner.get_readers()[0].index().records()[id].name()
+    }
+
     /// Returns the inner reader, consuming `self`.
     pub fn into_inner(self) -> angsd_saf::Intersect<R, V> {
         self.inner
     }
 
+    /// Returns for each population whether the most recently read site was missing.
+    ///
+    /// Sites can only be missing when reading the union of sites, see [`Intersect::with_union`].
+    /// The record of a missing population is unspecified, and should not be used.
+    pub fn missing(&self) -> &[bool; D] {
+        &self.missing
+    }
+
+    /// Returns the (zero-based) position of the most recently read site.
+    ///
+    /// If no site has been read, the returned position is unspecified.
+    pub fn position(&self) -> u32 {
+        if let Some(position) = self.sampler.as_ref().and_then(Sampler::position) {
+            return position;
+        }
+
+        match self.union.as_ref() {
+            Some(union) => union.position(),
+            None => self.bufs[0].position(),
+        }
+    }
+
     /// Creates a new reader.
     pub fn new(readers: [angsd_saf::Reader<R, V>; D]) -> Self {
         let inner = angsd_saf::Intersect::new(readers.into());
@@ -146,7 +245,243 @@ where
             .map_err(|_| ())
             .unwrap();
 
-        Self { inner, bufs }
+        Self {
+            inner,
+            bufs,
+            regions: None,
+            masks: Vec::new(),
+            parts: VecDeque::new(),
+            union: None,
+            missing: [false; D],
+            sampler: None,
+        }
+    }
+
+    /// Returns an upper bound on the number of sites left to read.
+    ///
+    /// This is the sum over remaining collections of readers of the smallest total number of
+    /// sites in the index of any reader in the collection, or the sum of sites in all readers when
+    /// reading the union of sites. When reading a subsample of sites, the bound is reduced
+    /// accordingly, though it may be exceeded when sampling a fraction of sites.
+    pub(crate) fn max_sites(&self) -> usize {
+        let union = self.union.is_some();
+        let min_sites = |readers: &[angsd_saf::Reader<R, V>]| {
+            let sites = readers.iter().map(|reader| reader.index().total_sites());
+
+            if union {
+                sites.sum()
+            } else {
+                sites.min().unwrap_or(0)
+            }
+        };
+
+        let max_sites = min_sites(self.inner.get_readers())
+            + self
+                .parts
+                .iter()
+                .map(|readers| min_sites(readers))
+                .sum::<usize>();
+
+        match self.sampler.as_ref() {
+            Some(sampler) => sampler.subsample().sites(max_sites),
+            None => max_sites,
+        }
+    }
+
+    /// Replaces the inner reader with the next collection of readers, if any.
+    ///
+    /// Returns `false` if no collections remain.
+    fn next_part(&mut self) -> bool {
+        match self.parts.pop_front() {
+            Some(readers) => {
+                self.inner = angsd_saf::Intersect::new(readers.into());
+
+                if let Some(regions) = self.regions.as_mut() {
+                    regions.reset(self.inner.get_readers());
+                }
+
+                if let Some(union) = self.union.as_mut() {
+                    *union = Union::new(self.inner.get_readers(), union.imputed.clone());
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads a set of intersecting records, one from each reader, into the internal buffers.
+    ///
+    /// If the reader is restricted to regions, only records in the regions are read. If the reader
+    /// has masks, only records kept by all masks are read. When reading the union of sites, the
+    /// records of populations missing from the site are unspecified, see [`Intersect::missing`].
+    /// When reading a subsample of sites, only sampled sites are read.
+    pub fn read_records(&mut self) -> io::Result<ReadStatus> {
+        let status = match self.sampler.take() {
+            Some(mut sampler) => {
+                let status = sampler.read_records(self);
+                self.sampler = Some(sampler);
+                status?
+            }
+            None => self.read_filtered_records()?,
+        };
+
+        if status.is_not_done() {
+            if let Some(union) = self.union.as_ref() {
+                union.imputed.add(&self.missing);
+            }
+        }
+
+        Ok(status)
+    }
+
+    /// Reads the next set of records kept by regions and masks, before any subsampling.
+    fn read_filtered_records(&mut self) -> io::Result<ReadStatus> {
+        loop {
+            let status = match (self.union.as_mut(), self.regions.as_mut()) {
+                (Some(union), _) => union.read_records(
+                    self.inner.get_readers_mut(),
+                    &mut self.bufs,
+                    &mut self.missing,
+                )?,
+                (None, Some(regions)) => regions.read_records(&mut self.inner, &mut self.bufs)?,
+                (None, None) => self.inner.read_records(&mut self.bufs)?,
+            };
+
+            if status.is_done() && self.next_part() {
+                continue;
+            }
+
+            if status.is_done() {
+                return Ok(status);
+            }
+
+            let (contig, position) = (self.contig(), self.position());
+
+            if self.masks.iter().all(|mask| mask.filter(contig, position)) {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Returns the most recently read records, one from each reader.
+    ///
+    /// If no records have been read, the returned records are unspecified.
+    pub fn records(&self) -> &[angsd_saf::Record<Id, V::Item>; D] {
+        &self.bufs
+    }
+
+    /// Adds a mask to filter sites read by the reader.
+    ///
+    /// Only intersecting sites kept by all masks will be read. Masks are applied in the order
+    /// they were added, and a removed site is only counted as removed by the first mask removing
+    /// it, see [`Mask::removed`]. Unlike [`Intersect::with_regions`], masks do not use the index,
+    /// and all sites are read before filtering.
+    pub fn with_mask(mut self, mask: Mask) -> Self {
+        self.masks.push(mask);
+        self
+    }
+
+    /// Restricts the reader to the provided regions.
+    ///
+    /// Only intersecting sites lying in one of the regions will be read. The index of each reader
+    /// is used to seek directly to the start of the contig of each region, so that data on other
+    /// contigs is never read. Since the index contains no information about positions within
+    /// contigs, sites on the contig before the start of a region are read and skipped.
+    ///
+    /// The order of the regions does not matter, and overlapping regions are merged. Regions on
+    /// contigs that are not present in all readers are ignored.
+    ///
+    /// This should be called before reading any sites. When reading several collections of readers,
+    /// see [`Intersect::chain`], the regions apply to each collection. When reading the union of
+    /// sites, see [`Intersect::with_union`], the index is not used, and regions act as a mask.
+    pub fn with_regions(mut self, regions: Vec<Region>) -> Self {
+        if self.union.is_some() {
+            self.masks.insert(0, Mask::include(regions));
+        } else {
+            self.regions = Some(Regions::new(regions, self.inner.get_readers()));
+        }
+        self
+    }
+
+    /// Reads only a random subsample of sites.
+    ///
+    /// The subsample is taken among the sites kept by any regions and masks, and is determined by
+    /// the provided seed. When sampling a fraction of sites, each site is kept independently as it
+    /// is read. When sampling a number of sites, all sites are read on the first read using
+    /// reservoir sampling, so that only the sampled sites are kept in memory, and the sampled
+    /// sites are then read in their original order.
+    ///
+    /// When reading the union of sites, only sampled sites are counted as imputed.
+    ///
+    /// This should be called before reading any sites.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a fraction is not in the interval [0, 1].
+    pub fn with_subsample(mut self, subsample: Subsample, seed: u64) -> Self {
+        if let Subsample::Fraction(fraction) = subsample {
+            assert!(
+                (0.0..=1.0).contains(&fraction),
+                "invalid subsample fraction"
+            );
+        }
+
+        self.sampler = Some(Sampler::new(subsample, seed));
+        self
+    }
+
+    /// Reads the union of sites across readers rather than the intersection.
+    ///
+    /// Sites present in at least one reader will be read, and [`Intersect::missing`] gives the
+    /// populations missing from each site. When reading sites using [`ReadSite`], missing
+    /// populations get flat likelihoods, that is, all values are zero in log-space. The sites
+    /// imputed for each population in this way are counted by the provided counter, where sites
+    /// removed by any masks are not counted.
+    ///
+    /// Contigs are assumed to occur in the same order in the index of each reader, though not all
+    /// contigs must occur in all readers.
+    ///
+    /// This should be called before reading any sites.
+    pub fn with_union(mut self, imputed: Imputed<D>) -> Self {
+        if let Some(regions) = self.regions.take() {
+            self.masks.insert(0, Mask::include(regions.into_regions()));
+        }
+
+        self.union = Some(Union::new(self.inner.get_readers(), imputed));
+        self
+    }
+}
+
+impl<const N: usize, R, V> Intersect<N, R, V>
+where
+    V: Version,
+{
+    /// Sets the values of populations missing from the most recently read site to flat
+    /// likelihoods in log-space.
+    fn fill_missing<const D: usize>(&self, buf: &mut Site<D>) {
+        if !self.missing.contains(&true) {
+            return;
+        }
+
+        let shape = buf.shape();
+        let mut offset = 0;
+        for (&n, &missing) in shape.iter().zip(self.missing.iter()) {
+            if missing {
+                buf.as_mut_slice()[offset..][..n].fill(0.0);
+            }
+            offset += n;
+        }
+    }
+}
+
+impl<const D: usize, R, V> From<[angsd_saf::Reader<R, V>; D]> for Intersect<D, R, V>
+where
+    R: io::BufRead + io::Seek,
+    V: Version,
+{
+    fn from(readers: [angsd_saf::Reader<R, V>; D]) -> Self {
+        Self::new(readers)
     }
 }
 
@@ -188,10 +523,11 @@ where
         // TODO: This should really be type-enforced somehow, but requires a bit more work.
         assert_eq!(N, D);
 
-        let status = self.inner.read_records(&mut self.bufs)?;
+        let status = self.read_records()?;
 
         let src = self.bufs.iter().map(|record| record.item());
         copy_from_slices(src, buf.as_mut_slice());
+        self.fill_missing(buf);
 
         Ok(status)
     }
@@ -216,7 +552,7 @@ where
         // TODO: This should really be type-enforced somehow, but requires a bit more work.
         assert_eq!(N, D);
 
-        let status = self.inner.read_records(&mut self.bufs)?;
+        let status = self.read_records()?;
 
         let alleles_iter = self
             .inner
@@ -229,11 +565,56 @@ where
             .zip(alleles_iter)
             .map(|(record, alleles)| record.item().clone().into_full(alleles, f32::NEG_INFINITY));
         copy_from_slices(src, buf.as_mut_slice());
+        self.fill_missing(buf);
 
         Ok(status)
     }
 }
 
+/// Checks that collections of readers can be chained, see [`Intersect::chain`].
+fn check_parts<const D: usize, R, V>(parts: &[[angsd_saf::Reader<R, V>; D]]) -> io::Result<()>
+where
+    R: io::BufRead,
+    V: Version,
+{
+    let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+
+    let first = match parts.first() {
+        Some(first) => first,
+        None => return invalid("cannot construct intersection without readers".to_string()),
+    };
+
+    // Maps each contig name to the collection in which it occurs
+    let mut contigs: HashMap<&str, usize> = HashMap::new();
+
+    for (i, readers) in parts.iter().enumerate() {
+        for (j, (reader, first)) in readers.iter().zip(first.iter()).enumerate() {
+            let (alleles, expected) = (reader.index().alleles(), first.index().alleles());
+            if alleles != expected {
+                return invalid(format!(
+                    "number of alleles for population {} differs between SAF files \
+                    ({expected} and {alleles})",
+                    j + 1,
+                ));
+            }
+
+            for record in reader.index().records() {
+                match contigs.insert(record.name(), i) {
+                    Some(other) if other != i => {
+                        return invalid(format!(
+                            "contig '{}' occurs in more than one set of SAF files",
+                            record.name()
+                        ))
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Copy multiple slices into successive subslices of a new slice.
 ///
 /// `dest` is assumed to have length equal to the sum of the lengths of slice sin `src`.
@@ -255,6 +636,112 @@ where
 mod tests {
     use super::*;
 
+    use std::io::Cursor;
+
+    use angsd_saf::{ReaderV3, WriterV3};
+
+    /// Returns an in-memory reader with one allele containing the provided sites.
+    pub(super) fn reader(sites: &[(&str, u32)]) -> ReaderV3<Cursor<Vec<u8>>> {
+        reader_with_alleles(1, sites)
+    }
+
+    fn reader_with_alleles(alleles: usize, sites: &[(&str, u32)]) -> ReaderV3<Cursor<Vec<u8>>> {
+        let mut writer = WriterV3::new(Vec::new(), Vec::new(), Vec::new());
+        writer.write_magic().unwrap();
+        writer.write_alleles(alleles).unwrap();
+
+        for &(contig, position) in sites {
+            let item = vec![position as f32; alleles + 1].into();
+            let record = angsd_saf::Record::new(contig, position, item);
+            writer.write_record(&record).unwrap();
+        }
+
+        let (index, positions, items) = writer.finish().unwrap();
+        let index = angsd_saf::Index::<V3>::read(&mut &index[..]).unwrap();
+
+        let mut reader = angsd_saf::reader::Builder::<V3>::default()
+            .build(index, Cursor::new(positions), Cursor::new(items))
+            .unwrap();
+        reader.read_magic().unwrap();
+        reader
+    }
+
+    /// Reads all sites in the reader, returning their contigs and positions.
+    pub(super) fn read_all<const D: usize>(
+        mut intersect: Intersect<D, Cursor<Vec<u8>>, V3>,
+    ) -> Vec<(String, u32)> {
+        let mut sites = Vec::new();
+        while intersect.read_records().unwrap().is_not_done() {
+            sites.push((intersect.contig().to_string(), intersect.position()));
+        }
+        sites
+    }
+
+    pub(super) fn sites(sites: &[(&str, u32)]) -> Vec<(String, u32)> {
+        sites
+            .iter()
+            .map(|&(contig, position)| (contig.to_string(), position))
+            .collect()
+    }
+
+    #[test]
+    fn test_chain() {
+        let intersect = Intersect::chain(vec![
+            [
+                reader(&[("chr1", 0), ("chr1", 5)]),
+                reader(&[("chr1", 5), ("chr1", 6)]),
+            ],
+            [
+                reader(&[("chr2", 1), ("chr3", 2)]),
+                reader(&[("chr2", 1), ("chr2", 2), ("chr3", 2)]),
+            ],
+        ])
+        .unwrap();
+
+        assert_eq!(intersect.max_sites(), 4);
+        assert_eq!(
+            read_all(intersect),
+            sites(&[("chr1", 5), ("chr2", 1), ("chr3", 2)])
+        );
+    }
+
+    #[test]
+    fn test_chain_with_regions_and_masks() {
+        let mask = Mask::exclude(vec![Region::new("chr3", 0, 3).unwrap()]);
+
+        let intersect = Intersect::chain(vec![
+            [reader(&[("chr1", 0), ("chr1", 5)])],
+            [reader(&[("chr2", 1)])],
+            [reader(&[("chr3", 2), ("chr3", 4), ("chr4", 0)])],
+        ])
+        .unwrap()
+        .with_regions(vec![
+            Region::new("chr1", 1, 10).unwrap(),
+            Region::whole_contig("chr3"),
+        ])
+        .with_mask(mask.clone());
+
+        assert_eq!(read_all(intersect), sites(&[("chr1", 5), ("chr3", 4)]));
+        assert_eq!(mask.removed(), 1);
+    }
+
+    #[test]
+    fn test_chain_errors() {
+        assert!(Intersect::<1, Cursor<Vec<u8>>, V3>::chain(Vec::new()).is_err());
+
+        let result = Intersect::chain(vec![
+            [reader(&[("chr1", 0)])],
+            [reader_with_alleles(2, &[("chr2", 0)])],
+        ]);
+        assert!(result.is_err());
+
+        let result = Intersect::chain(vec![
+            [reader(&[("chr1", 0)]), reader(&[("chr1", 0)])],
+            [reader(&[("chr2", 0)]), reader(&[("chr1", 1), ("chr2", 0)])],
+        ]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_copy_from_slices() {
         let src = vec![&[0, 1][..], &[2, 3, 4, 5]];
@@ -45,7 +45,9 @@ where
     }
 
     fn rewind(&mut self) -> io::Result<()> {
-        self.inner.rewind()
+        self.inner.rewind()?;
+        self.sites_read = 0;
+        Ok(())
     }
 }
 
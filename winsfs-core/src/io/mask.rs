@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{self, AtomicUsize},
+        Arc,
+    },
+};
+
+use super::Region;
+
+/// Whether a [`Mask`] includes or excludes sites in its regions.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MaskKind {
+    /// Only sites inside the regions are kept.
+    Include,
+    /// Only sites outside the regions are kept.
+    Exclude,
+}
+
+/// A mask of sites given by a collection of regions.
+///
+/// Used to filter sites read by an [`Intersect`](super::Intersect) reader, see
+/// [`Intersect::with_mask`](super::Intersect::with_mask).
+///
+/// The mask counts the number of sites it removes. Clones of a mask share both regions and count,
+/// so that a clone may be kept to inspect the count after the mask has been given to a reader.
+#[derive(Clone, Debug)]
+pub struct Mask {
+    kind: MaskKind,
+    // Sorted and non-overlapping intervals for each contig
+    regions: Arc<HashMap<String, Vec<(u32, u32)>>>,
+    removed: Arc<AtomicUsize>,
+}
+
+impl Mask {
+    /// Returns `true` if the site at the (zero-based) position on the contig is in the regions of
+    /// the mask.
+    pub fn contains(&self, contig: &str, position: u32) -> bool {
+        let intervals = match self.regions.get(contig) {
+            Some(intervals) => intervals,
+            None => return false,
+        };
+
+        // Intervals are sorted and non-overlapping, so only the last interval starting before or
+        // at the position can contain it
+        let i = intervals.partition_point(|&(start, _)| start <= position);
+
+        matches!(i.checked_sub(1).map(|i| intervals[i]), Some((_, end)) if position < end)
+    }
+
+    /// Creates a new mask that excludes sites in the provided regions.
+    pub fn exclude(regions: Vec<Region>) -> Self {
+        Self::new(MaskKind::Exclude, regions)
+    }
+
+    /// Creates a new mask that includes only sites in the provided regions.
+    pub fn include(regions: Vec<Region>) -> Self {
+        Self::new(MaskKind::Include, regions)
+    }
+
+    /// Returns `true` if the site at the (zero-based) position on the contig is kept by the mask.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::io::{Mask, Region};
+    /// let regions = vec![Region::new("chr1", 10, 20).unwrap()];
+    /// let include = Mask::include(regions.clone());
+    /// assert!(include.keeps("chr1", 10));
+    /// assert!(!include.keeps("chr1", 20));
+    /// assert!(!include.keeps("chr2", 10));
+    /// let exclude = Mask::exclude(regions);
+    /// assert!(!exclude.keeps("chr1", 10));
+    /// assert!(exclude.keeps("chr1", 20));
+    /// assert!(exclude.keeps("chr2", 10));
+    /// ```
+    pub fn keeps(&self, contig: &str, position: u32) -> bool {
+        match self.kind {
+            MaskKind::Include => self.contains(contig, position),
+            MaskKind::Exclude => !self.contains(contig, position),
+        }
+    }
+
+    /// Returns the kind of mask.
+    pub fn kind(&self) -> MaskKind {
+        self.kind
+    }
+
+    /// Creates a new mask from regions.
+    ///
+    /// Regions may be given in any order, and may overlap.
+    pub fn new(kind: MaskKind, regions: Vec<Region>) -> Self {
+        let mut map: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+        for region in regions {
+            map.entry(region.contig().to_string())
+                .or_default()
+                .push((region.start(), region.end()));
+        }
+
+        map.values_mut().for_each(|intervals| {
+            intervals.sort_unstable();
+
+            let mut merged: Vec<(u32, u32)> = Vec::with_capacity(intervals.len());
+            for &(start, end) in intervals.iter() {
+                match merged.last_mut() {
+                    Some((_, last_end)) if start <= *last_end => *last_end = end.max(*last_end),
+                    _ => merged.push((start, end)),
+                }
+            }
+
+            *intervals = merged;
+        });
+
+        Self {
+            kind,
+            regions: Arc::new(map),
+            removed: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns the number of sites removed by the mask so far.
+    pub fn removed(&self) -> usize {
+        self.removed.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Returns `true` if the site is kept by the mask, and counts the site as removed otherwise.
+    pub(super) fn filter(&self, contig: &str, position: u32) -> bool {
+        let keep = self.keeps(contig, position);
+
+        if !keep {
+            self.removed.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+
+        keep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_merges_overlapping_regions() {
+        let mask = Mask::include(vec![
+            Region::new("chr1", 15, 30).unwrap(),
+            Region::new("chr1", 0, 5).unwrap(),
+            Region::new("chr1", 10, 20).unwrap(),
+            Region::new("chr2", 0, 1).unwrap(),
+        ]);
+
+        assert_eq!(mask.regions.get("chr1"), Some(&vec![(0, 5), (10, 30)]));
+
+        for (position, expected) in [
+            (0, true),
+            (5, false),
+            (9, false),
+            (10, true),
+            (29, true),
+            (30, false),
+        ] {
+            assert_eq!(mask.contains("chr1", position), expected, "{position}");
+        }
+        assert!(mask.contains("chr2", 0));
+        assert!(!mask.contains("chr3", 0));
+    }
+
+    #[test]
+    fn test_mask_counts_removed_in_clones() {
+        let mask = Mask::exclude(vec![Region::new("chr1", 0, 10).unwrap()]);
+        let clone = mask.clone();
+
+        assert!(!mask.filter("chr1", 0));
+        assert!(mask.filter("chr1", 10));
+        assert!(!mask.filter("chr1", 9));
+
+        assert_eq!(clone.removed(), 2);
+    }
+}
@@ -0,0 +1,366 @@
+use std::{fmt, io};
+
+use angsd_saf::{record::Id, version::Version, ReadStatus};
+
+/// A region of a contig, given by a zero-based, half-open interval.
+///
+/// Used to restrict an [`Intersect`](super::Intersect) reader to certain regions, see
+/// [`Intersect::with_regions`](super::Intersect::with_regions).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Region {
+    contig: String,
+    start: u32,
+    end: u32,
+}
+
+impl Region {
+    /// Returns the contig name of the region.
+    pub fn contig(&self) -> &str {
+        &self.contig
+    }
+
+    /// Returns the exclusive end position of the region.
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    /// Creates a new region covering positions in `start..end` on the contig.
+    ///
+    /// Returns `None` if `start` is not less than `end`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::io::Region;
+    /// let region = Region::new("chr1", 10, 20).unwrap();
+    /// assert_eq!(region.start(), 10);
+    /// assert_eq!(region.end(), 20);
+    /// assert!(Region::new("chr1", 20, 20).is_none());
+    /// ```
+    pub fn new<S>(contig: S, start: u32, end: u32) -> Option<Self>
+    where
+        S: ToString,
+    {
+        (start < end).then(|| Self {
+            contig: contig.to_string(),
+            start,
+            end,
+        })
+    }
+
+    /// Returns the zero-based start position of the region.
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// Creates a new region covering an entire contig.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::io::Region;
+    /// let region = Region::whole_contig("chr1");
+    /// assert_eq!(region.start(), 0);
+    /// assert_eq!(region.end(), u32::MAX);
+    /// ```
+    pub fn whole_contig<S>(contig: S) -> Self
+    where
+        S: ToString,
+    {
+        Self {
+            contig: contig.to_string(),
+            start: 0,
+            end: u32::MAX,
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.start, self.end) {
+            (0, u32::MAX) => write!(f, "{}", self.contig),
+            (start, u32::MAX) => write!(f, "{}:{}-", self.contig, start + 1),
+            (start, end) => write!(f, "{}:{}-{end}", self.contig, start + 1),
+        }
+    }
+}
+
+/// A region resolved against the indexes of a collection of readers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ResolvedRegion {
+    // Contig ID of the region in the index of each reader
+    ids: Vec<usize>,
+    start: u32,
+    end: u32,
+}
+
+/// The state of an intersecting reader restricted to regions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) struct Regions {
+    // Regions as provided, kept for resolving against new readers
+    source: Vec<Region>,
+    // Sorted by contig ID and start in the first reader, and non-overlapping
+    regions: Vec<ResolvedRegion>,
+    current: usize,
+    seek: bool,
+}
+
+impl Regions {
+    /// Creates new regions resolved against the indexes of the readers.
+    ///
+    /// Regions on contigs that do not occur in all indexes cannot contain any intersecting sites,
+    /// and are dropped. Overlapping regions are merged.
+    pub fn new<R, V>(regions: Vec<Region>, readers: &[angsd_saf::Reader<R, V>]) -> Self
+    where
+        R: io::BufRead,
+        V: Version,
+    {
+        let mut resolved: Vec<ResolvedRegion> = regions
+            .iter()
+            .filter_map(|region| {
+                let ids = readers
+                    .iter()
+                    .map(|reader| {
+                        reader
+                            .index()
+                            .records()
+                            .iter()
+                            .position(|record| record.name() == region.contig)
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+
+                Some(ResolvedRegion {
+                    ids,
+                    start: region.start,
+                    end: region.end,
+                })
+            })
+            .collect();
+
+        resolved.sort_by_key(|region| (region.ids[0], region.start));
+
+        let mut merged: Vec<ResolvedRegion> = Vec::with_capacity(resolved.len());
+        for region in resolved {
+            match merged.last_mut() {
+                Some(last) if last.ids == region.ids && region.start <= last.end => {
+                    last.end = last.end.max(region.end);
+                }
+                _ => merged.push(region),
+            }
+        }
+
+        Self {
+            source: regions,
+            regions: merged,
+            current: 0,
+            seek: true,
+        }
+    }
+
+    /// Returns the regions as provided, consuming `self`.
+    pub fn into_regions(self) -> Vec<Region> {
+        self.source
+    }
+
+    /// Resolves the regions against the indexes of new readers, and starts over from the first
+    /// region.
+    pub fn reset<R, V>(&mut self, readers: &[angsd_saf::Reader<R, V>])
+    where
+        R: io::BufRead,
+        V: Version,
+    {
+        *self = Self::new(std::mem::take(&mut self.source), readers);
+    }
+
+    /// Reads a set of intersecting records lying in the regions.
+    ///
+    /// Readers are positioned at the start of the contig of each region using the index, after
+    /// which records before the start of the region are skipped.
+    pub fn read_records<R, V>(
+        &mut self,
+        intersect: &mut angsd_saf::Intersect<R, V>,
+        bufs: &mut [angsd_saf::Record<Id, V::Item>],
+    ) -> io::Result<ReadStatus>
+    where
+        R: io::BufRead + io::Seek,
+        V: Version,
+    {
+        loop {
+            if self.seek {
+                let region = match self.regions.get(self.current) {
+                    Some(region) => region,
+                    None => return Ok(ReadStatus::Done),
+                };
+
+                for (reader, &id) in intersect
+                    .get_readers_mut()
+                    .iter_mut()
+                    .zip(region.ids.iter())
+                {
+                    reader.seek(id)?;
+                }
+
+                self.seek = false;
+            }
+
+            if intersect.read_records(bufs)?.is_done() {
+                return Ok(ReadStatus::Done);
+            }
+
+            // Since a record past the end of the current region may lie in the next region,
+            // we keep checking the record against regions until it can be placed
+            loop {
+                let region = match self.regions.get(self.current) {
+                    Some(region) => region,
+                    None => return Ok(ReadStatus::Done),
+                };
+
+                let (id, position) = (*bufs[0].contig_id(), bufs[0].position());
+
+                if id == region.ids[0] {
+                    if position < region.start {
+                        break;
+                    } else if position < region.end {
+                        return Ok(ReadStatus::NotDone);
+                    }
+                }
+
+                // Record lies past the current region, either on the same or a later contig
+                self.current += 1;
+
+                match self.regions.get(self.current) {
+                    Some(next) if next.ids == region.ids => (),
+                    _ => {
+                        self.seek = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::io::{
+        tests::{read_all, reader, sites},
+        Intersect,
+    };
+
+    const SITES: &[(&str, u32)] = &[
+        ("chr1", 0),
+        ("chr1", 5),
+        ("chr1", 10),
+        ("chr2", 1),
+        ("chr2", 2),
+        ("chr3", 3),
+        ("chr3", 7),
+        ("chr3", 9),
+    ];
+
+    #[test]
+    fn test_intersect_with_regions() {
+        let regions = vec![
+            Region::new("chr3", 5, 9).unwrap(),
+            Region::new("chr1", 3, 11).unwrap(),
+            Region::new("chr1", 0, 1).unwrap(),
+            Region::whole_contig("chr4"),
+        ];
+
+        let intersect = Intersect::new([reader(SITES)]).with_regions(regions);
+
+        assert_eq!(
+            read_all(intersect),
+            sites(&[("chr1", 0), ("chr1", 5), ("chr1", 10), ("chr3", 7)])
+        );
+    }
+
+    #[test]
+    fn test_intersect_with_adjacent_and_overlapping_regions() {
+        let regions = vec![
+            Region::new("chr1", 0, 5).unwrap(),
+            Region::new("chr1", 5, 6).unwrap(),
+            Region::new("chr2", 0, 2).unwrap(),
+            Region::new("chr2", 1, 3).unwrap(),
+            Region::whole_contig("chr3"),
+        ];
+
+        let intersect = Intersect::new([reader(SITES)]).with_regions(regions);
+
+        assert_eq!(
+            read_all(intersect),
+            sites(&[
+                ("chr1", 0),
+                ("chr1", 5),
+                ("chr2", 1),
+                ("chr2", 2),
+                ("chr3", 3),
+                ("chr3", 7),
+                ("chr3", 9)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_intersect_with_regions_multiple_readers() {
+        let other = reader(&[("chr1", 5), ("chr1", 10), ("chr3", 3), ("chr3", 9)]);
+
+        let regions = vec![
+            Region::new("chr1", 6, 20).unwrap(),
+            Region::whole_contig("chr2"),
+            Region::new("chr3", 0, 5).unwrap(),
+        ];
+
+        let intersect = Intersect::new([reader(SITES), other]).with_regions(regions);
+
+        assert_eq!(read_all(intersect), sites(&[("chr1", 10), ("chr3", 3)]));
+    }
+
+    #[test]
+    fn test_intersect_with_regions_and_masks() {
+        use crate::io::Mask;
+
+        let include = Mask::include(vec![
+            Region::whole_contig("chr1"),
+            Region::new("chr3", 0, 8).unwrap(),
+        ]);
+        let exclude = Mask::exclude(vec![Region::new("chr1", 5, 6).unwrap()]);
+
+        let intersect = Intersect::new([reader(SITES)])
+            .with_regions(vec![
+                Region::new("chr1", 1, 20).unwrap(),
+                Region::whole_contig("chr3"),
+            ])
+            .with_mask(include.clone())
+            .with_mask(exclude.clone());
+
+        assert_eq!(
+            read_all(intersect),
+            sites(&[("chr1", 10), ("chr3", 3), ("chr3", 7)])
+        );
+        assert_eq!(include.removed(), 1);
+        assert_eq!(exclude.removed(), 1);
+    }
+
+    #[test]
+    fn test_intersect_with_empty_regions() {
+        let intersect = Intersect::new([reader(SITES)]).with_regions(Vec::new());
+
+        assert!(read_all(intersect).is_empty());
+    }
+
+    #[test]
+    fn test_region_display() {
+        assert_eq!(Region::whole_contig("chr1").to_string(), "chr1");
+        assert_eq!(
+            Region::new("chr1", 9, u32::MAX).unwrap().to_string(),
+            "chr1:10-"
+        );
+        assert_eq!(
+            Region::new("chr1", 9, 20).unwrap().to_string(),
+            "chr1:10-20"
+        );
+    }
+}
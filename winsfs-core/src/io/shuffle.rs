@@ -85,7 +85,10 @@ mod tests {
 
     use tempfile::NamedTempFile;
 
-    use crate::{io::ReadSite, saf::Site};
+    use crate::{
+        io::{ReadSite, Rewind},
+        saf::Site,
+    };
 
     #[test]
     fn test_write_then_read() -> io::Result<()> {
@@ -131,4 +134,38 @@ mod tests {
 
         file.close()
     }
+
+    #[test]
+    fn test_read_tail() -> io::Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+
+        let header = Header::new(5, vec![2], 2);
+        let mut writer = Writer::create(path, header)?;
+
+        for i in 0..5 {
+            writer.write_site(&[i as f32, i as f32])?;
+        }
+        writer.try_finish().unwrap();
+
+        let mut reader = Reader::try_from_path(path)?;
+        let tail = reader.read_tail::<1>(2)?;
+
+        let expected = [1f32, 1., 3., 3.].map(f32::exp);
+        assert_eq!(tail.as_slice(), expected.as_slice());
+
+        // Reader should be rewound and limited to the remaining sites
+        let mut reader = reader.take(3);
+        let mut site = Site::new(vec![0.; 2], [2]).unwrap();
+        for _ in 0..2 {
+            for i in [0., 2., 4.] {
+                reader.read_site(&mut site)?;
+                assert_eq!(site.as_slice(), &[f32::exp(i), f32::exp(i)]);
+            }
+            assert!(reader.read_site(&mut site)?.is_done());
+            reader.rewind()?;
+        }
+
+        file.close()
+    }
 }
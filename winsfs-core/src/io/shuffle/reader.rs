@@ -9,7 +9,7 @@ use byteorder::{ReadBytesExt, LE};
 use crate::{
     em::Sites,
     io::{ReadSite, ReadStatus, Rewind},
-    saf::Site,
+    saf::{Saf, Site},
 };
 
 use super::{to_u64, Header};
@@ -72,6 +72,50 @@ where
     }
 }
 
+impl<R> Reader<R>
+where
+    R: io::BufRead + io::Seek,
+{
+    /// Reads the last `sites` sites of the file into memory.
+    ///
+    /// Due to the pseudo-shuffled layout, the sites at the end of the file are spread across
+    /// the input rather than being contiguous. This makes them suitable for holding out from
+    /// estimation, e.g. by limiting the reader to the remaining sites using [`ReadSite::take`].
+    ///
+    /// The stream will be positioned at the first site afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sites` is greater than the number of sites in the file, or if the
+    /// dimensionality defined in the header is not `D`.
+    pub fn read_tail<const D: usize>(&mut self, sites: usize) -> io::Result<Saf<D>> {
+        let total = self.header.sites();
+        assert!(
+            sites <= total,
+            "cannot read more sites than contained in file"
+        );
+
+        let shape: [usize; D] = self
+            .header
+            .shape()
+            .try_into()
+            .expect("shuffled SAF dimension does not match");
+        let width = self.header.width();
+
+        let offset =
+            self.header.header_size() + (total - sites) * width * std::mem::size_of::<f32>();
+        self.seek(io::SeekFrom::Start(to_u64(offset)))?;
+
+        let mut values = vec![0.0; sites * width];
+        self.inner.read_f32_into::<LE>(&mut values)?;
+        values.iter_mut().for_each(|x| *x = x.exp());
+
+        Rewind::rewind(self)?;
+
+        Ok(Saf::new(values, shape).expect("shape matches by construction"))
+    }
+}
+
 impl Reader<io::BufReader<File>> {
     /// Creates a new reader from a path, and read its header.
     ///
@@ -0,0 +1,340 @@
+use std::{fmt, io};
+
+use angsd_saf::{record::Id, version::Version, ReadStatus};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::Intersect;
+
+/// A random subsample of sites.
+///
+/// Used to read a random subsample of the sites read by an [`Intersect`] reader, see
+/// [`Intersect::with_subsample`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subsample {
+    /// Each site is kept independently with the provided probability.
+    Fraction(f64),
+    /// The provided number of sites are kept, or all sites if fewer are available.
+    Count(usize),
+}
+
+impl Subsample {
+    /// Returns an upper bound on the number of sites kept from the provided number of sites.
+    ///
+    /// For a fraction, this is the expected number of sites kept, which may be exceeded.
+    pub(super) fn sites(&self, sites: usize) -> usize {
+        match *self {
+            Subsample::Fraction(fraction) => (fraction * sites as f64).ceil() as usize,
+            Subsample::Count(count) => count.min(sites),
+        }
+    }
+}
+
+impl fmt::Display for Subsample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subsample::Fraction(fraction) => write!(f, "fraction {fraction} of sites"),
+            Subsample::Count(count) => write!(f, "{count} sites"),
+        }
+    }
+}
+
+/// The state of an intersecting reader reading a subsample of sites.
+pub(super) enum Sampler<const D: usize, V>
+where
+    V: Version,
+{
+    /// Each site is kept as it is read.
+    Bernoulli { fraction: f64, rng: StdRng },
+    /// Sites are collected up front and replayed.
+    Reservoir(Reservoir<D, V>),
+}
+
+impl<const D: usize, V> Sampler<D, V>
+where
+    V: Version,
+{
+    /// Returns the contig name of the most recently read site, if known by the sampler.
+    pub fn contig(&self) -> Option<&str> {
+        match self {
+            Sampler::Bernoulli { .. } => None,
+            Sampler::Reservoir(reservoir) => reservoir.contig(),
+        }
+    }
+
+    /// Creates a new sampler with the provided seed.
+    pub fn new(subsample: Subsample, seed: u64) -> Self {
+        let rng = StdRng::seed_from_u64(seed);
+
+        match subsample {
+            Subsample::Fraction(fraction) => Sampler::Bernoulli { fraction, rng },
+            Subsample::Count(count) => Sampler::Reservoir(Reservoir::new(count, rng)),
+        }
+    }
+
+    /// Returns the position of the most recently read site, if known by the sampler.
+    pub fn position(&self) -> Option<u32> {
+        match self {
+            Sampler::Bernoulli { .. } => None,
+            Sampler::Reservoir(reservoir) => reservoir.position(),
+        }
+    }
+
+    /// Returns the subsample taken by the sampler.
+    pub fn subsample(&self) -> Subsample {
+        match self {
+            Sampler::Bernoulli { fraction, .. } => Subsample::Fraction(*fraction),
+            Sampler::Reservoir(reservoir) => Subsample::Count(reservoir.count),
+        }
+    }
+
+    /// Reads the next sampled site from the reader.
+    ///
+    /// The reader should not hold the sampler while reading.
+    pub fn read_records<R>(&mut self, intersect: &mut Intersect<D, R, V>) -> io::Result<ReadStatus>
+    where
+        R: io::BufRead + io::Seek,
+    {
+        match self {
+            Sampler::Bernoulli { fraction, rng } => loop {
+                let status = intersect.read_filtered_records()?;
+
+                if status.is_done() || rng.gen_bool(*fraction) {
+                    return Ok(status);
+                }
+            },
+            Sampler::Reservoir(reservoir) => reservoir.read_records(intersect),
+        }
+    }
+}
+
+/// A sampled site stored by a [`Reservoir`].
+struct Stored<const D: usize, V>
+where
+    V: Version,
+{
+    // Position of the site among all sites read
+    index: usize,
+    // Position of the contig name in the names of the reservoir
+    contig: usize,
+    position: u32,
+    missing: [bool; D],
+    records: [angsd_saf::Record<Id, V::Item>; D],
+}
+
+/// A fixed-size uniform sample of sites, using reservoir sampling.
+///
+/// All sites are read on the first read, keeping only the sampled sites in memory. The sampled
+/// sites are then replayed in the order they were read.
+pub(super) struct Reservoir<const D: usize, V>
+where
+    V: Version,
+{
+    count: usize,
+    rng: StdRng,
+    names: Vec<String>,
+    sites: Vec<Stored<D, V>>,
+    filled: bool,
+    // Contig name and position of the most recently replayed site
+    current: Option<(usize, u32)>,
+}
+
+impl<const D: usize, V> Reservoir<D, V>
+where
+    V: Version,
+{
+    fn contig(&self) -> Option<&str> {
+        self.current.map(|(contig, _)| self.names[contig].as_str())
+    }
+
+    /// Reads all sites from the reader, keeping a uniform sample.
+    fn fill<R>(&mut self, intersect: &mut Intersect<D, R, V>) -> io::Result<()>
+    where
+        R: io::BufRead + io::Seek,
+    {
+        let mut index = 0;
+        while intersect.read_filtered_records()?.is_not_done() {
+            let slot = if index < self.count {
+                Some(self.sites.len())
+            } else {
+                Some(self.rng.gen_range(0..=index)).filter(|&j| j < self.count)
+            };
+
+            if let Some(slot) = slot {
+                if self.names.last().map(String::as_str) != Some(intersect.contig()) {
+                    self.names.push(intersect.contig().to_string());
+                }
+                let position = intersect.position();
+
+                // Take the records from the reader, leaving fresh buffers behind
+                let readers = intersect.inner.get_readers();
+                let records = std::array::from_fn(|i| {
+                    std::mem::replace(&mut intersect.bufs[i], readers[i].create_record_buf())
+                });
+
+                let stored = Stored {
+                    index,
+                    contig: self.names.len() - 1,
+                    position,
+                    missing: intersect.missing,
+                    records,
+                };
+
+                if slot < self.sites.len() {
+                    self.sites[slot] = stored;
+                } else {
+                    self.sites.push(stored);
+                }
+            }
+
+            index += 1;
+        }
+
+        // Replay in reverse order, so that sites can be popped in the order they were read
+        self.sites
+            .sort_unstable_by_key(|stored| std::cmp::Reverse(stored.index));
+
+        Ok(())
+    }
+
+    fn new(count: usize, rng: StdRng) -> Self {
+        Self {
+            count,
+            rng,
+            names: Vec::new(),
+            sites: Vec::new(),
+            filled: false,
+            current: None,
+        }
+    }
+
+    fn position(&self) -> Option<u32> {
+        self.current.map(|(_, position)| position)
+    }
+
+    fn read_records<R>(&mut self, intersect: &mut Intersect<D, R, V>) -> io::Result<ReadStatus>
+    where
+        R: io::BufRead + io::Seek,
+    {
+        if !self.filled {
+            self.fill(intersect)?;
+            self.filled = true;
+        }
+
+        match self.sites.pop() {
+            Some(stored) => {
+                intersect.bufs = stored.records;
+                intersect.missing = stored.missing;
+                self.current = Some((stored.contig, stored.position));
+
+                Ok(ReadStatus::NotDone)
+            }
+            None => Ok(ReadStatus::Done),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::io::{
+        tests::{read_all, reader, sites},
+        Imputed, Mask, Region,
+    };
+
+    fn positions(n: u32) -> Vec<(&'static str, u32)> {
+        (0..n)
+            .map(|i| if i < n / 2 { ("chr1", i) } else { ("chr2", i) })
+            .collect()
+    }
+
+    #[test]
+    fn test_subsample_sites() {
+        assert_eq!(Subsample::Fraction(0.25).sites(10), 3);
+        assert_eq!(Subsample::Count(4).sites(10), 4);
+        assert_eq!(Subsample::Count(40).sites(10), 10);
+    }
+
+    #[test]
+    fn test_subsample_count() {
+        let all = positions(100);
+
+        let intersect =
+            Intersect::new([reader(&all), reader(&all)]).with_subsample(Subsample::Count(10), 1);
+        let result = read_all(intersect);
+
+        assert_eq!(result.len(), 10);
+        // Sites are read in the original order
+        let mut sorted = result.clone();
+        sorted.sort_by_key(|(_, position)| *position);
+        assert_eq!(result, sorted);
+        assert!(result.iter().all(|site| sites(&all).contains(site)));
+
+        // The same seed gives the same sample
+        let intersect =
+            Intersect::new([reader(&all), reader(&all)]).with_subsample(Subsample::Count(10), 1);
+        assert_eq!(read_all(intersect), result);
+    }
+
+    #[test]
+    fn test_subsample_count_exceeding_sites() {
+        let all = positions(5);
+
+        let intersect = Intersect::new([reader(&all)]).with_subsample(Subsample::Count(10), 1);
+
+        assert_eq!(read_all(intersect), sites(&all));
+    }
+
+    #[test]
+    fn test_subsample_fraction() {
+        let all = positions(1000);
+
+        let intersect = Intersect::new([reader(&all)]).with_subsample(Subsample::Fraction(0.1), 2);
+        let result = read_all(intersect);
+
+        assert!((50..150).contains(&result.len()));
+        assert!(result.iter().all(|site| sites(&all).contains(site)));
+
+        let intersect = Intersect::new([reader(&all)]).with_subsample(Subsample::Fraction(0.1), 2);
+        assert_eq!(read_all(intersect), result);
+
+        let intersect = Intersect::new([reader(&all)]).with_subsample(Subsample::Fraction(1.0), 2);
+        assert_eq!(read_all(intersect), sites(&all));
+    }
+
+    #[test]
+    fn test_read_saf_with_subsample_count() {
+        let all = positions(20);
+
+        let intersect = Intersect::new([reader(&all)]).with_subsample(Subsample::Count(3), 3);
+        let expected = read_all(intersect)
+            .into_iter()
+            .flat_map(|(_, position)| [position as f32; 2])
+            .map(f32::exp)
+            .collect::<Vec<_>>();
+
+        let intersect = Intersect::new([reader(&all)]).with_subsample(Subsample::Count(3), 3);
+        let saf = crate::saf::Saf::read(intersect).unwrap();
+
+        assert_eq!(saf.sites(), 3);
+        assert_eq!(saf.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_subsample_after_masks_and_union() {
+        let imputed = Imputed::new();
+        let mask = Mask::exclude(vec![Region::whole_contig("chr2")]);
+
+        let intersect = Intersect::new([
+            reader(&[("chr1", 0), ("chr1", 1), ("chr2", 2)]),
+            reader(&[("chr1", 1), ("chr2", 2), ("chr2", 3)]),
+        ])
+        .with_union(imputed.clone())
+        .with_mask(mask.clone())
+        .with_subsample(Subsample::Count(5), 4);
+
+        assert_eq!(read_all(intersect), sites(&[("chr1", 0), ("chr1", 1)]));
+        assert_eq!(mask.removed(), 2);
+        assert_eq!(imputed.counts(), [0, 1]);
+    }
+}
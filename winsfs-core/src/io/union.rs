@@ -0,0 +1,314 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{self, AtomicUsize},
+        Arc,
+    },
+};
+
+use angsd_saf::{record::Id, version::Version, ReadStatus};
+
+/// Counts of sites imputed for each population when reading the union of sites.
+///
+/// Used to count the sites missing from each population when an [`Intersect`](super::Intersect)
+/// reader reads the union of sites, see [`Intersect::with_union`](super::Intersect::with_union).
+///
+/// Clones share counts, so that a clone may be kept to inspect the counts after the counter has
+/// been given to a reader.
+#[derive(Clone, Debug)]
+pub struct Imputed<const D: usize>(Arc<[AtomicUsize; D]>);
+
+impl<const D: usize> Imputed<D> {
+    /// Returns the number of imputed sites for each population so far.
+    pub fn counts(&self) -> [usize; D] {
+        std::array::from_fn(|i| self.0[i].load(atomic::Ordering::Relaxed))
+    }
+
+    /// Creates a new counter with all counts zero.
+    pub fn new() -> Self {
+        Self(Arc::new(std::array::from_fn(|_| AtomicUsize::new(0))))
+    }
+
+    /// Counts a site as imputed for each population marked as missing.
+    pub(super) fn add(&self, missing: &[bool; D]) {
+        for (count, _) in self.0.iter().zip(missing).filter(|(_, &missing)| missing) {
+            count.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+    }
+}
+
+impl<const D: usize> Default for Imputed<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The state of an intersecting reader reading the union of sites.
+pub(super) struct Union<const D: usize, V>
+where
+    V: Version,
+{
+    // All contig names across readers, in order
+    names: Vec<String>,
+    // For each reader, the position in `names` of each contig ID in the index of the reader
+    ranks: [Vec<usize>; D],
+    // Next record for each reader, read ahead of the current site
+    next: [angsd_saf::Record<Id, V::Item>; D],
+    done: [bool; D],
+    started: bool,
+    // Contig (as position in `names`) and position of the most recently read site
+    current: (usize, u32),
+    pub(super) imputed: Imputed<D>,
+}
+
+impl<const D: usize, V> Union<D, V>
+where
+    V: Version,
+{
+    /// Returns the contig name of the most recently read site.
+    pub fn contig(&self) -> &str {
+        &self.names[self.current.0]
+    }
+
+    /// Creates a new union state for the readers.
+    ///
+    /// Contigs are assumed to occur in the same order in each index, though not every contig
+    /// needs to occur in every index.
+    pub fn new<R>(readers: &[angsd_saf::Reader<R, V>], imputed: Imputed<D>) -> Self
+    where
+        R: io::BufRead,
+    {
+        let names = merge_contig_names(readers.iter().map(|reader| {
+            reader
+                .index()
+                .records()
+                .iter()
+                .map(|record| record.name())
+                .collect()
+        }));
+
+        let map: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+
+        let ranks = std::array::from_fn(|i| {
+            readers[i]
+                .index()
+                .records()
+                .iter()
+                .map(|record| map[record.name()])
+                .collect()
+        });
+
+        let next = std::array::from_fn(|i| readers[i].create_record_buf());
+
+        Self {
+            names,
+            ranks,
+            next,
+            done: [false; D],
+            started: false,
+            current: (0, 0),
+            imputed,
+        }
+    }
+
+    /// Returns the position of the most recently read site.
+    pub fn position(&self) -> u32 {
+        self.current.1
+    }
+
+    /// Reads the next site present in any of the readers.
+    ///
+    /// Records are read into the buffers for readers containing the site, and the corresponding
+    /// entries in `missing` are set to `false`. For readers not containing the site, the buffers
+    /// are left as is, and `missing` is set to `true`.
+    pub fn read_records<R>(
+        &mut self,
+        readers: &mut [angsd_saf::Reader<R, V>],
+        bufs: &mut [angsd_saf::Record<Id, V::Item>; D],
+        missing: &mut [bool; D],
+    ) -> io::Result<ReadStatus>
+    where
+        R: io::BufRead + io::Seek,
+    {
+        if !self.started {
+            for ((reader, next), done) in readers
+                .iter_mut()
+                .zip(self.next.iter_mut())
+                .zip(self.done.iter_mut())
+            {
+                *done = reader.read_record(next)?.is_done();
+            }
+
+            self.started = true;
+        }
+
+        let key = |i: usize, next: &angsd_saf::Record<Id, V::Item>| {
+            (self.ranks[i][*next.contig_id()], next.position())
+        };
+
+        let current = match (0..D)
+            .filter(|&i| !self.done[i])
+            .map(|i| key(i, &self.next[i]))
+            .min()
+        {
+            Some(current) => current,
+            None => return Ok(ReadStatus::Done),
+        };
+
+        for i in 0..D {
+            missing[i] = self.done[i] || key(i, &self.next[i]) != current;
+
+            if !missing[i] {
+                std::mem::swap(&mut bufs[i], &mut self.next[i]);
+                self.done[i] = readers[i].read_record(&mut self.next[i])?.is_done();
+            }
+        }
+
+        self.current = current;
+
+        Ok(ReadStatus::NotDone)
+    }
+}
+
+/// Merges lists of contig names into a single list containing all names.
+///
+/// Names shared between lists are assumed to occur in the same order in each list, and the
+/// relative order of names in each list is preserved.
+fn merge_contig_names<'a, I>(lists: I) -> Vec<String>
+where
+    I: IntoIterator<Item = Vec<&'a str>>,
+{
+    let mut names: Vec<String> = Vec::new();
+
+    for list in lists {
+        let map: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+
+        // New names are placed as late as possible, i.e. immediately before the next shared name
+        let mut merged = Vec::with_capacity(names.len() + list.len());
+        let mut pending = Vec::new();
+        let mut cursor = 0;
+        for name in list {
+            match map.get(name) {
+                Some(&i) if i >= cursor => {
+                    merged.extend(names[cursor..i].iter().cloned());
+                    merged.append(&mut pending);
+                    merged.push(names[i].clone());
+                    cursor = i + 1;
+                }
+                Some(_) => (),
+                None => pending.push(name.to_string()),
+            }
+        }
+        merged.extend(names[cursor..].iter().cloned());
+        merged.append(&mut pending);
+
+        names = merged;
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::io::{
+        tests::{read_all, reader, sites},
+        Intersect, Mask, Region,
+    };
+
+    #[test]
+    fn test_merge_contig_names() {
+        assert_eq!(
+            merge_contig_names([
+                vec!["chr1", "chr3", "chr5"],
+                vec!["chr2", "chr3", "chr4", "chr5", "chr6"],
+                vec!["chr1", "chr7"],
+            ]),
+            vec!["chr1", "chr2", "chr3", "chr4", "chr5", "chr6", "chr7"]
+        );
+    }
+
+    #[test]
+    fn test_intersect_with_union() {
+        let imputed = Imputed::new();
+
+        let mut intersect = Intersect::new([
+            reader(&[("chr1", 0), ("chr1", 5), ("chr3", 1)]),
+            reader(&[("chr1", 5), ("chr2", 2), ("chr3", 1), ("chr3", 4)]),
+        ])
+        .with_union(imputed.clone());
+
+        let mut result = Vec::new();
+        while intersect.read_records().unwrap().is_not_done() {
+            result.push((
+                intersect.contig().to_string(),
+                intersect.position(),
+                *intersect.missing(),
+            ));
+        }
+
+        assert_eq!(
+            result,
+            vec![
+                ("chr1".to_string(), 0, [false, true]),
+                ("chr1".to_string(), 5, [false, false]),
+                ("chr2".to_string(), 2, [true, false]),
+                ("chr3".to_string(), 1, [false, false]),
+                ("chr3".to_string(), 4, [true, false]),
+            ]
+        );
+        assert_eq!(imputed.counts(), [2, 1]);
+    }
+
+    #[test]
+    fn test_read_saf_with_union() {
+        let intersect = Intersect::new([
+            reader(&[("chr1", 1), ("chr1", 2)]),
+            reader(&[("chr1", 2), ("chr1", 3)]),
+        ])
+        .with_union(Imputed::new());
+
+        let saf = crate::saf::Saf::read(intersect).unwrap();
+
+        let expected = [
+            [1.0, 1.0, 0.0, 0.0],
+            [2.0, 2.0, 2.0, 2.0],
+            [0.0, 0.0, 3.0, 3.0],
+        ]
+        .iter()
+        .flatten()
+        .map(|&x: &f32| x.exp())
+        .collect::<Vec<_>>();
+        assert_eq!(saf.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_intersect_with_union_regions_and_masks() {
+        let imputed = Imputed::new();
+        let mask = Mask::exclude(vec![Region::new("chr1", 0, 1).unwrap()]);
+
+        let intersect = Intersect::new([
+            reader(&[("chr1", 0), ("chr1", 5), ("chr3", 1)]),
+            reader(&[("chr1", 5), ("chr2", 2), ("chr3", 1), ("chr3", 4)]),
+        ])
+        .with_regions(vec![
+            Region::whole_contig("chr1"),
+            Region::whole_contig("chr2"),
+        ])
+        .with_union(imputed.clone())
+        .with_mask(mask);
+
+        assert_eq!(read_all(intersect), sites(&[("chr1", 5), ("chr2", 2)]));
+        assert_eq!(imputed.counts(), [1, 0]);
+    }
+}
@@ -0,0 +1,620 @@
+use std::{borrow::Cow, fs::File, io, path::Path};
+
+use angsd_saf::{
+    index,
+    record::{Band, Likelihoods},
+    version::{Version, V3, V4},
+};
+
+use super::Intersect;
+
+/// A SAF file writer for a number of populations.
+///
+/// The writer holds one SAF writer for each population, and is used to write the sites read by an
+/// [`Intersect`] reader back to disk, see [`Writer::write_intersect`]. Since the reader may be
+/// restricted to regions, filtered by masks, or subsampled, this allows writing subsets of SAF
+/// files. Contigs and positions of sites are preserved, and the SAF version of the writer matches
+/// that of the reader, so that banded SAF files are written as banded SAF files. To convert
+/// between versions, see [`Writer::write_intersect_banded`] and [`Writer::write_intersect_full`].
+///
+/// Note that the writer must be finished after writing to write the final index records, see
+/// [`Writer::finish`].
+pub struct Writer<const D: usize, W, V>
+where
+    W: io::Write,
+{
+    inner: [angsd_saf::Writer<W, V>; D],
+    // Index record of the contig currently being written for each population; these are kept here
+    // rather than by the inner writers to avoid copying each item into a record for writing
+    index_records: [Option<index::Record<V>>; D],
+}
+
+impl<const D: usize, W, V> Writer<D, W, V>
+where
+    W: io::Write,
+    V: Version,
+{
+    /// Finishes writing, returning the inner index, position, and item writers of each population.
+    pub fn finish(mut self) -> io::Result<[(W, W, W); D]> {
+        for (writer, record) in self.inner.iter_mut().zip(self.index_records.iter_mut()) {
+            if let Some(record) = record.take() {
+                record.write(writer.index_writer_mut())?;
+            }
+        }
+
+        // TODO: Use array::try_map when stable here
+        self.inner
+            .into_iter()
+            .map(angsd_saf::Writer::finish)
+            .collect::<io::Result<Vec<_>>>()
+            .map(|vec| vec.try_into().map_err(|_| ()).unwrap())
+    }
+
+    /// Returns the inner writers.
+    pub fn get(&self) -> &[angsd_saf::Writer<W, V>; D] {
+        &self.inner
+    }
+
+    /// Creates a new writer from SAF writers, one for each population.
+    ///
+    /// The magic numbers and the number of alleles should already have been written to the SAF
+    /// writers, as is done by e.g. [`angsd_saf::Writer::from_prefix`].
+    pub fn new(writers: [angsd_saf::Writer<W, V>; D]) -> Self {
+        Self {
+            inner: writers,
+            index_records: std::array::from_fn(|_| None),
+        }
+    }
+
+    /// Writes all sites read by a reader, returning the number of sites read.
+    ///
+    /// Each item is passed through `convert` along with the index of its population before
+    /// writing.
+    fn write_intersect_with<R, U, F>(
+        &mut self,
+        mut intersect: Intersect<D, R, U>,
+        mut convert: F,
+    ) -> io::Result<usize>
+    where
+        R: io::BufRead + io::Seek,
+        U: Version,
+        V: IndexRecordExt,
+        F: for<'a> FnMut(usize, &'a U::Item) -> Cow<'a, V::Item>,
+        V::Item: Clone,
+    {
+        let mut sites = 0;
+
+        while intersect.read_records()?.is_not_done() {
+            let contig = intersect.contig();
+            let position = intersect.position();
+
+            let records = intersect.records().iter().zip(intersect.missing());
+            let writers = self.inner.iter_mut().zip(self.index_records.iter_mut());
+            for (i, ((record, &missing), (writer, index_record))) in
+                records.zip(writers).enumerate()
+            {
+                if missing {
+                    continue;
+                }
+
+                if index_record.as_ref().map(index::Record::name) != Some(contig) {
+                    let position_offset = u64::from(writer.position_writer().virtual_position());
+                    let item_offset = u64::from(writer.item_writer().virtual_position());
+                    let new = V::new_index_record(contig.to_string(), position_offset, item_offset);
+
+                    if let Some(old) = index_record.replace(new) {
+                        old.write(writer.index_writer_mut())?;
+                    }
+                }
+
+                let item = convert(i, record.item());
+
+                let index_record = index_record.as_mut().expect("index record set above");
+                V::update_index_record(index_record, &item);
+
+                io::Write::write_all(writer.position_writer_mut(), &position.to_le_bytes())?;
+                V::write_item(writer.item_writer_mut(), &item)?;
+            }
+
+            sites += 1;
+        }
+
+        Ok(sites)
+    }
+}
+
+impl<const D: usize, V> Writer<D, io::BufWriter<File>, V>
+where
+    V: Version,
+{
+    /// Creates a new writer from the shared prefixes of the SAF files for each population.
+    ///
+    /// See [`angsd_saf::Writer::from_prefix`] for details on file naming. If the paths already
+    /// exist, they will be overwritten.
+    pub fn from_prefixes<P>(prefixes: &[P; D], alleles: [usize; D]) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        prefixes
+            .iter()
+            .zip(alleles)
+            .map(|(prefix, alleles)| angsd_saf::Writer::from_prefix(alleles, prefix))
+            .collect::<io::Result<Vec<_>>>()
+            .map(|vec| Self::new(vec.try_into().map_err(|_| ()).unwrap()))
+    }
+}
+
+impl<const D: usize, W> Writer<D, W, V3>
+where
+    W: io::Write,
+{
+    /// Writes all sites read by a reader, returning the number of sites read.
+    ///
+    /// When reading the union of sites, see [`Intersect::with_union`], each site is only written
+    /// for the populations in which it is not missing.
+    pub fn write_intersect<R>(&mut self, intersect: Intersect<D, R, V3>) -> io::Result<usize>
+    where
+        R: io::BufRead + io::Seek,
+    {
+        self.write_intersect_with(intersect, |_, item| Cow::Borrowed(item))
+    }
+
+    /// Writes all sites read by a reader of banded SAF files as full SAF files, returning the
+    /// number of sites read.
+    ///
+    /// Likelihoods outside the band are written as zero, that is, as negative infinity in
+    /// log-space. See [`Writer::write_intersect`](Writer#method.write_intersect) for details.
+    pub fn write_intersect_full<R>(&mut self, intersect: Intersect<D, R, V4>) -> io::Result<usize>
+    where
+        R: io::BufRead + io::Seek,
+    {
+        let alleles: Vec<usize> = intersect
+            .get()
+            .get_readers()
+            .iter()
+            .map(|reader| reader.index().alleles())
+            .collect();
+
+        self.write_intersect_with(intersect, |i, band| {
+            Cow::Owned(band.clone().into_full(alleles[i], f32::NEG_INFINITY))
+        })
+    }
+}
+
+impl<const D: usize, W> Writer<D, W, V4>
+where
+    W: io::Write,
+{
+    /// Writes all sites read by a reader of banded SAF files, returning the number of sites read.
+    ///
+    /// See [`Writer::write_intersect`](Writer#method.write_intersect) for details.
+    pub fn write_intersect<R>(&mut self, intersect: Intersect<D, R, V4>) -> io::Result<usize>
+    where
+        R: io::BufRead + io::Seek,
+    {
+        self.write_intersect_with(intersect, |_, item| Cow::Borrowed(item))
+    }
+
+    /// Writes all sites read by a reader of full SAF files as banded SAF files.
+    ///
+    /// For each site, the band is the smallest range of sample frequencies containing all
+    /// likelihoods of at least `threshold` relative to the largest likelihood of the site, so that
+    /// only the likelihoods in the tails of the site are dropped. Zero likelihoods in the tails are
+    /// always dropped. A summary of the banding is returned, including the number of sites read.
+    /// See [`Writer::write_intersect`](Writer#method.write_intersect) for further details.
+    pub fn write_intersect_banded<R>(
+        &mut self,
+        intersect: Intersect<D, R, V3>,
+        threshold: f32,
+    ) -> io::Result<Banding>
+    where
+        R: io::BufRead + io::Seek,
+    {
+        let mut banding = Banding::default();
+
+        let sites = self.write_intersect_with(intersect, |_, likelihoods| {
+            let (band, dropped) = to_band(likelihoods, threshold);
+
+            banding.values += likelihoods.len();
+            banding.band_values += band.len();
+            banding.max_dropped = banding.max_dropped.max(dropped);
+
+            Cow::Owned(band)
+        })?;
+        banding.sites = sites;
+
+        Ok(banding)
+    }
+}
+
+/// A summary of writing full SAF files as banded SAF files.
+///
+/// See [`Writer::write_intersect_banded`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Banding {
+    sites: usize,
+    values: usize,
+    band_values: usize,
+    max_dropped: f64,
+}
+
+impl Banding {
+    /// Returns the number of likelihood values written in bands.
+    pub fn band_values(&self) -> usize {
+        self.band_values
+    }
+
+    /// Returns the ratio of the number of likelihood values written in bands to the number of
+    /// likelihood values read.
+    pub fn compression(&self) -> f64 {
+        self.band_values as f64 / self.values as f64
+    }
+
+    /// Returns the largest dropped probability mass of any site in any population.
+    ///
+    /// The dropped probability mass of a site is the sum of the likelihoods outside the band
+    /// divided by the sum of all likelihoods of the site.
+    pub fn max_dropped(&self) -> f64 {
+        self.max_dropped
+    }
+
+    /// Returns the number of sites read.
+    pub fn sites(&self) -> usize {
+        self.sites
+    }
+
+    /// Returns the number of likelihood values read.
+    pub fn values(&self) -> usize {
+        self.values
+    }
+}
+
+/// Version-specific construction and updating of SAF index records.
+trait IndexRecordExt: Version {
+    /// Creates a new, empty index record from the contig name and the position and item offsets
+    /// at the start of the contig.
+    fn new_index_record(
+        name: String,
+        position_offset: u64,
+        item_offset: u64,
+    ) -> index::Record<Self>;
+
+    /// Updates an index record for a single item written to the contig.
+    fn update_index_record(record: &mut index::Record<Self>, item: &Self::Item);
+}
+
+impl IndexRecordExt for V3 {
+    fn new_index_record(
+        name: String,
+        position_offset: u64,
+        item_offset: u64,
+    ) -> index::Record<Self> {
+        index::Record::new(name, 0, position_offset, item_offset)
+    }
+
+    fn update_index_record(record: &mut index::Record<Self>, _: &Likelihoods) {
+        *record.sites_mut() += 1;
+    }
+}
+
+impl IndexRecordExt for V4 {
+    fn new_index_record(
+        name: String,
+        position_offset: u64,
+        item_offset: u64,
+    ) -> index::Record<Self> {
+        index::Record::new_with_sum_band(name, 0, 0, position_offset, item_offset)
+    }
+
+    fn update_index_record(record: &mut index::Record<Self>, band: &Band) {
+        *record.sites_mut() += 1;
+        *record.sum_band_mut() += band.len();
+    }
+}
+
+/// Returns the band of log-likelihoods relative to a threshold, and the dropped probability mass.
+///
+/// See [`Writer::write_intersect_banded`] for details. If all likelihoods are zero, the full set
+/// of likelihoods is kept.
+fn to_band(likelihoods: &[f32], threshold: f32) -> (Band, f64) {
+    let max = likelihoods
+        .iter()
+        .copied()
+        .fold(f32::NEG_INFINITY, f32::max);
+
+    if max == f32::NEG_INFINITY {
+        return (Band::new(0, likelihoods.to_vec()), 0.0);
+    }
+
+    // Zero likelihoods are never kept in the tails, even when the threshold is zero
+    let cutoff = max + threshold.ln();
+    let keep = |&v: &f32| v >= cutoff && v > f32::NEG_INFINITY;
+    let start = likelihoods.iter().position(keep).expect("maximum is kept");
+    let end = likelihoods.iter().rposition(keep).expect("maximum is kept") + 1;
+
+    let relative = |v: &f32| f64::from(v - max).exp();
+    let total: f64 = likelihoods.iter().map(relative).sum();
+    let dropped: f64 = likelihoods[..start]
+        .iter()
+        .chain(&likelihoods[end..])
+        .map(relative)
+        .sum();
+
+    let band = Band::new(start, likelihoods[start..end].to_vec());
+
+    (band, dropped / total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use angsd_saf::{record::Id, ReaderV3, ReaderV4};
+
+    use crate::io::{
+        tests::{read_all, reader, sites},
+        Imputed, Mask, Region,
+    };
+
+    type Buf = Cursor<Vec<u8>>;
+
+    /// Returns an in-memory SAF writer with the magic number and alleles written.
+    fn writer<V>(alleles: usize) -> angsd_saf::Writer<Vec<u8>, V>
+    where
+        V: Version,
+    {
+        let mut writer = angsd_saf::Writer::new(Vec::new(), Vec::new(), Vec::new());
+        writer.write_magic().unwrap();
+        writer.write_alleles(alleles).unwrap();
+        writer
+    }
+
+    /// Returns an in-memory reader of the data written by an in-memory SAF writer.
+    fn reread<V>(
+        (index, positions, items): (Vec<u8>, Vec<u8>, Vec<u8>),
+    ) -> angsd_saf::Reader<Buf, V>
+    where
+        V: Version,
+    {
+        let index = angsd_saf::Index::<V>::read(&mut &index[..]).unwrap();
+
+        let mut reader = angsd_saf::reader::Builder::<V>::default()
+            .build(index, Cursor::new(positions), Cursor::new(items))
+            .unwrap();
+        reader.read_magic().unwrap();
+        reader
+    }
+
+    /// Returns the contig names and number of sites in the index of a reader.
+    fn index_sites<V>(reader: &angsd_saf::Reader<Buf, V>) -> Vec<(String, usize)>
+    where
+        V: Version,
+    {
+        reader
+            .index()
+            .records()
+            .iter()
+            .map(|record| (record.name().to_string(), record.sites()))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_intersect() -> io::Result<()> {
+        let intersect = Intersect::new([
+            reader(&[
+                ("chr1", 1),
+                ("chr1", 3),
+                ("chr2", 1),
+                ("chr2", 5),
+                ("chr3", 7),
+            ]),
+            reader(&[
+                ("chr1", 3),
+                ("chr2", 1),
+                ("chr2", 5),
+                ("chr3", 7),
+                ("chr3", 8),
+            ]),
+        ])
+        .with_mask(Mask::exclude(vec![Region::new("chr2", 0, 2).unwrap()]));
+
+        let mut writer = Writer::new([writer::<V3>(1), writer(1)]);
+        assert_eq!(writer.write_intersect(intersect)?, 3);
+        let [first, second] = writer.finish()?.map(reread::<V3>);
+
+        let expected_index = vec![
+            ("chr1".to_string(), 1),
+            ("chr2".to_string(), 1),
+            ("chr3".to_string(), 1),
+        ];
+        assert_eq!(index_sites(&first), expected_index);
+        assert_eq!(index_sites(&second), expected_index);
+
+        let mut intersect = Intersect::new([first, second]);
+        let mut values = Vec::new();
+        while intersect.read_records()?.is_not_done() {
+            values.push(intersect.records()[1].item().to_vec());
+        }
+        assert_eq!(values, vec![vec![3.0; 2], vec![5.0; 2], vec![7.0; 2]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_intersect_union() -> io::Result<()> {
+        let intersect = Intersect::new([
+            reader(&[("chr1", 1), ("chr1", 3), ("chr2", 1)]),
+            reader(&[("chr1", 3), ("chr2", 0)]),
+        ])
+        .with_union(Imputed::new());
+
+        let mut writer = Writer::new([writer::<V3>(1), writer(1)]);
+        assert_eq!(writer.write_intersect(intersect)?, 4);
+        let [first, second] = writer.finish()?.map(reread::<V3>);
+
+        assert_eq!(
+            read_all(Intersect::new([first])),
+            sites(&[("chr1", 1), ("chr1", 3), ("chr2", 1)])
+        );
+        assert_eq!(
+            read_all(Intersect::new([second])),
+            sites(&[("chr1", 3), ("chr2", 0)])
+        );
+
+        Ok(())
+    }
+
+    /// Returns an in-memory reader of banded SAF data with the provided records.
+    fn banded_reader(alleles: usize, records: Vec<(&str, u32, Band)>) -> ReaderV4<Buf> {
+        let mut writer = writer::<V4>(alleles);
+        for (contig, position, band) in records {
+            writer
+                .write_record(&angsd_saf::Record::new(contig, position, band))
+                .unwrap();
+        }
+        reread(writer.finish().unwrap())
+    }
+
+    /// Returns an in-memory reader of full SAF data with the provided records.
+    fn full_reader(alleles: usize, records: Vec<(&str, u32, Vec<f32>)>) -> ReaderV3<Buf> {
+        let mut writer = writer::<V3>(alleles);
+        for (contig, position, likelihoods) in records {
+            writer
+                .write_record(&angsd_saf::Record::new(
+                    contig,
+                    position,
+                    likelihoods.into(),
+                ))
+                .unwrap();
+        }
+        reread(writer.finish().unwrap())
+    }
+
+    #[test]
+    fn test_write_intersect_banded() -> io::Result<()> {
+        let reader = banded_reader(
+            4,
+            vec![
+                ("chr1", 0, Band::new(0, vec![0.0, -1.0])),
+                ("chr1", 2, Band::new(1, vec![-2.0, 0.0, -1.0])),
+                ("chr2", 4, Band::new(3, vec![0.0])),
+            ],
+        );
+
+        let intersect =
+            Intersect::new([reader]).with_regions(vec![Region::new("chr1", 1, 10).unwrap()]);
+
+        let mut writer = Writer::new([writer::<V4>(4)]);
+        assert_eq!(writer.write_intersect(intersect)?, 1);
+        let [reader] = writer.finish()?.map(reread::<V4>);
+
+        let index = reader.index().records();
+        assert_eq!(index.len(), 1);
+        assert_eq!((index[0].name(), index[0].sites()), ("chr1", 1));
+        assert_eq!(index[0].sum_band(), 3);
+
+        let mut intersect = Intersect::new([reader]);
+        assert!(intersect.read_records()?.is_not_done());
+        let record: &angsd_saf::Record<Id, Band> = &intersect.records()[0];
+        assert_eq!(record.position(), 2);
+        assert_eq!(record.item(), &Band::new(1, vec![-2.0, 0.0, -1.0]));
+        assert!(intersect.read_records()?.is_done());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_intersect_full() -> io::Result<()> {
+        let reader = banded_reader(
+            2,
+            vec![
+                ("chr1", 0, Band::new(0, vec![0.0, -1.0])),
+                ("chr2", 4, Band::new(2, vec![0.0])),
+            ],
+        );
+
+        let mut writer = Writer::new([writer::<V3>(2)]);
+        assert_eq!(writer.write_intersect_full(Intersect::new([reader]))?, 2);
+        let [reader] = writer.finish()?.map(reread::<V3>);
+
+        assert_eq!(
+            index_sites(&reader),
+            vec![("chr1".to_string(), 1), ("chr2".to_string(), 1)]
+        );
+
+        let mut intersect = Intersect::new([reader]);
+        let mut items = Vec::new();
+        while intersect.read_records()?.is_not_done() {
+            items.push(intersect.records()[0].item().to_vec());
+        }
+        let inf = f32::NEG_INFINITY;
+        assert_eq!(items, vec![vec![0.0, -1.0, inf], vec![inf, inf, 0.0]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_intersect_banded_from_full() -> io::Result<()> {
+        let reader = full_reader(
+            3,
+            vec![
+                ("chr1", 0, vec![-20.0, 0.0, -1.0, -20.0]),
+                ("chr1", 1, vec![-1.0, -20.0, 0.0, -30.0]),
+            ],
+        );
+
+        let mut writer = Writer::new([writer::<V4>(3)]);
+        let banding = writer.write_intersect_banded(Intersect::new([reader]), 1e-3)?;
+        let [reader] = writer.finish()?.map(reread::<V4>);
+
+        assert_eq!(banding.sites(), 2);
+        assert_eq!(banding.values(), 8);
+        assert_eq!(banding.band_values(), 5);
+        assert!((banding.compression() - 5.0 / 8.0).abs() < 1e-12);
+
+        let dropped = |kept: f64, all: &[f64]| {
+            let total: f64 = all.iter().map(|v| v.exp()).sum();
+            (total - kept) / total
+        };
+        let expected = dropped(1.0 + (-1f64).exp(), &[-20.0, 0.0, -1.0, -20.0]);
+        assert!((banding.max_dropped() - expected).abs() < 1e-12);
+
+        assert_eq!(reader.index().records()[0].sum_band(), 5);
+
+        let mut intersect = Intersect::new([reader]);
+        let mut items = Vec::new();
+        while intersect.read_records()?.is_not_done() {
+            items.push(intersect.records()[0].item().clone());
+        }
+        assert_eq!(
+            items,
+            vec![
+                Band::new(1, vec![0.0, -1.0]),
+                Band::new(0, vec![-1.0, -20.0, 0.0]),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_band() {
+        assert_eq!(
+            to_band(&[-10.0, 0.0, -10.0], 1e-3).0,
+            Band::new(1, vec![0.0])
+        );
+        assert_eq!(to_band(&[-1.0, 0.0, -10.0], 0.0).0.len(), 3);
+
+        let inf = f32::NEG_INFINITY;
+        assert_eq!(
+            to_band(&[inf, 0.0, inf, -1.0, inf], 0.0),
+            (Band::new(1, vec![0.0, inf, -1.0]), 0.0)
+        );
+        assert_eq!(
+            to_band(&[inf, inf], 0.5),
+            (Band::new(0, vec![inf, inf]), 0.0)
+        );
+    }
+}
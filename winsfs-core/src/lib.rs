@@ -37,10 +37,6 @@ macro_rules! matrix {
 }
 
 pub(crate) trait ArrayExt<const N: usize, T> {
-    // TODO: Use each_ref when stable,
-    // see github.com/rust-lang/rust/issues/76118
-    fn by_ref(&self) -> [&T; N];
-
     // TODO: Use each_mut when stable,
     // see github.com/rust-lang/rust/issues/76118
     fn by_mut(&mut self) -> [&mut T; N];
@@ -51,20 +47,6 @@ pub(crate) trait ArrayExt<const N: usize, T> {
 }
 
 impl<const N: usize, T> ArrayExt<N, T> for [T; N] {
-    fn by_ref(&self) -> [&T; N] {
-        // Adapted from code in tracking issue, see above.
-        let mut out: MaybeUninit<[&T; N]> = MaybeUninit::uninit();
-
-        let buf = out.as_mut_ptr() as *mut &T;
-        let mut refs = self.iter();
-
-        for i in 0..N {
-            unsafe { buf.add(i).write(refs.next().unwrap()) }
-        }
-
-        unsafe { out.assume_init() }
-    }
-
     fn by_mut(&mut self) -> [&mut T; N] {
         // Adapted from code in tracking issue, see above.
         let mut out: MaybeUninit<[&mut T; N]> = MaybeUninit::uninit();
@@ -97,11 +79,6 @@ impl<const N: usize, T> ArrayExt<N, T> for [T; N] {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_by_ref() {
-        assert_eq!([1, 2, 3].by_ref(), [&1, &2, &3]);
-    }
-
     #[test]
     fn test_by_mut() {
         assert_eq!([1, 2, 3].by_mut(), [&mut 1, &mut 2, &mut 3]);
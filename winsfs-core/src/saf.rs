@@ -3,7 +3,9 @@
 //! SAF likelihoods represent a generalisation of genotype likelihoods from individuals to
 //! populations. To estimate the N-dimensional SFS, we need the SAF likelihoods from intersecting
 //! sites for those N populations. We represent those by the owned type [`Saf`] or the borrowed type
-//! [`SafView`], which may represent the full data or only a smaller block of sites.
+//! [`SafView`], which may represent the full data or only a smaller block of sites. SAF likelihoods
+//! read from banded SAF files may also be kept banded in memory, see [`BandedSaf`]. Identical sites
+//! may be collapsed into weighted site patterns to speed up EM, see [`Patterns`].
 
 use std::{cmp::Ordering, error::Error, fmt, io};
 
@@ -16,11 +18,20 @@ use rayon::{
     slice::ParallelSlice,
 };
 
-use crate::{em::Sites, ArrayExt};
+use crate::{
+    em::{EmSaf, Sites},
+    io::Intersect,
+};
+
+mod banded;
+pub use banded::{BandedSaf, BandedSafView, BandedSiteView};
 
 mod blocks;
 pub use blocks::{BlockIter, Blocks, ParBlockIter};
 
+mod patterns;
+pub use patterns::{Patterns, PatternsView};
+
 mod site;
 pub use site::{AsSiteView, Site, SiteView};
 
@@ -116,6 +127,19 @@ macro_rules! impl_shared_saf_methods {
             SiteView::new_unchecked(&self.values[index * width..][..width], self.shape)
         }
 
+        /// Returns the weight of a single site in the SAF.
+        ///
+        /// If the SAF is unweighted, all sites have weight one.
+        #[inline]
+        pub fn get_weight(&self, index: usize) -> f32 {
+            self.weights().map_or(1.0, |weights| weights[index])
+        }
+
+        /// Returns the per-site weights of the SAF, if any.
+        pub fn weights(&self) -> Option<&[f32]> {
+            self.weights.as_deref()
+        }
+
         /// Returns the number of sites in the SAF.
         #[inline]
         pub fn sites(&self) -> usize {
@@ -143,10 +167,13 @@ macro_rules! impl_shared_saf_methods {
 /// then comes the next site, and so on. [`Saf::shape`] gives the number of values
 /// per site per population. This should only be important when operating directly
 /// on the underlying storage, e.g. using [`Saf::as_slice`] or [`Saf::as_mut_slice`].
+///
+/// Optionally, each site may carry a weight, see [`Saf::with_weights`].
 #[derive(Clone, Debug, PartialEq)]
 pub struct Saf<const N: usize> {
     values: Vec<f32>,
     shape: [usize; N],
+    weights: Option<Vec<f32>>,
 }
 
 impl<const N: usize> Saf<N> {
@@ -204,7 +231,11 @@ impl<const N: usize> Saf<N> {
 
     /// Returns a new SAF without checking that the shape fits the number of values.
     pub(crate) fn new_unchecked(values: Vec<f32>, shape: [usize; N]) -> Self {
-        Self { values, shape }
+        Self {
+            values,
+            shape,
+            weights: None,
+        }
     }
 
     /// Creates a new SAF by reading intersecting sites among SAF readers.
@@ -212,16 +243,24 @@ impl<const N: usize> Saf<N> {
     /// SAF files contain values in log-space. The returned values will be exponentiated
     /// to get out of log-space.
     ///
+    /// The readers may be given either as an array of readers, or as an [`Intersect`] reader,
+    /// which allows e.g. restricting the sites read to certain regions.
+    ///
     /// # Panics
     ///
     /// Panics if `N == 0`.
-    pub fn read<R>(readers: [saf::ReaderV3<R>; N]) -> io::Result<Self>
+    pub fn read<I, R>(readers: I) -> io::Result<Self>
     where
+        I: Into<Intersect<N, R, saf::version::V3>>,
         R: io::BufRead + io::Seek,
     {
-        Self::read_inner_impl(readers, |values, item, _| {
-            values.extend_from_slice(item);
-        })
+        Self::read_inner_impl(
+            readers.into(),
+            |values, item, _| {
+                values.extend_from_slice(item);
+            },
+            None::<fn(&str, u32) -> f32>,
+        )
     }
 
     /// Creates a new SAF by reading intersecting sites among banded SAF readers.
@@ -236,53 +275,129 @@ impl<const N: usize> Saf<N> {
     /// # Panics
     ///
     /// Panics if `N == 0`.
-    pub fn read_from_banded<R>(readers: [saf::ReaderV4<R>; N]) -> io::Result<Self>
+    pub fn read_from_banded<I, R>(readers: I) -> io::Result<Self>
     where
+        I: Into<Intersect<N, R, saf::version::V4>>,
         R: io::BufRead + io::Seek,
     {
-        Self::read_inner_impl(readers, |values, item, alleles| {
-            let full_likelihoods = &item.clone().into_full(alleles, f32::NEG_INFINITY);
-            values.extend_from_slice(full_likelihoods);
-        })
+        Self::read_inner_impl(
+            readers.into(),
+            |values, item, alleles| {
+                let full_likelihoods = &item.clone().into_full(alleles, f32::NEG_INFINITY);
+                values.extend_from_slice(full_likelihoods);
+            },
+            None::<fn(&str, u32) -> f32>,
+        )
+    }
+
+    /// Creates a new weighted SAF by reading intersecting sites among SAF readers.
+    ///
+    /// The weight of each site is given by calling `weight` with the contig name and (zero-based)
+    /// position of the site, as given by the first reader. Otherwise, see [`Saf::read`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N == 0`.
+    pub fn read_weighted<I, R, F>(readers: I, weight: F) -> io::Result<Self>
+    where
+        I: Into<Intersect<N, R, saf::version::V3>>,
+        R: io::BufRead + io::Seek,
+        F: FnMut(&str, u32) -> f32,
+    {
+        Self::read_inner_impl(
+            readers.into(),
+            |values, item, _| {
+                values.extend_from_slice(item);
+            },
+            Some(weight),
+        )
+    }
+
+    /// Creates a new weighted SAF by reading intersecting sites among banded SAF readers.
+    ///
+    /// See [`Saf::read_weighted`] and [`Saf::read_from_banded`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N == 0`.
+    pub fn read_weighted_from_banded<I, R, F>(readers: I, weight: F) -> io::Result<Self>
+    where
+        I: Into<Intersect<N, R, saf::version::V4>>,
+        R: io::BufRead + io::Seek,
+        F: FnMut(&str, u32) -> f32,
+    {
+        Self::read_inner_impl(
+            readers.into(),
+            |values, item, alleles| {
+                let full_likelihoods = &item.clone().into_full(alleles, f32::NEG_INFINITY);
+                values.extend_from_slice(full_likelihoods);
+            },
+            Some(weight),
+        )
     }
 
     /// The inner implementor of readers from full SAF and banded SAF.
-    fn read_inner_impl<R, V, F>(readers: [saf::Reader<R, V>; N], f: F) -> io::Result<Self>
+    ///
+    /// If `weight` is provided, it is called for each intersecting site to get its weight.
+    fn read_inner_impl<R, V, F, W>(
+        mut intersect: Intersect<N, R, V>,
+        f: F,
+        mut weight: Option<W>,
+    ) -> io::Result<Self>
     where
         R: io::BufRead + io::Seek,
         V: saf::version::Version,
         F: Fn(&mut Vec<f32>, &V::Item, usize),
+        W: FnMut(&str, u32) -> f32,
     {
         assert!(N > 0);
 
-        let max_sites = readers
+        let readers = intersect.get().get_readers();
+
+        let max_sites = intersect.max_sites();
+
+        let shape: [usize; N] = readers
             .iter()
-            .map(|reader| reader.index().total_sites())
-            .min()
+            .map(|reader| reader.index().alleles() + 1)
+            .collect::<Vec<_>>()
+            .try_into()
             .unwrap();
 
-        let shape = readers.by_ref().map(|reader| reader.index().alleles() + 1);
-
         // The number of intersecting sites is as most the smallest number of sites,
         // so we preallocate this number and free excess capacity at the end.
         let capacity = shape.iter().map(|shape| shape * max_sites).sum();
         let mut values = Vec::with_capacity(capacity);
 
-        let mut intersect = saf::Intersect::new(Vec::from(readers));
-        let mut bufs = intersect.create_record_bufs();
+        let mut weights = weight.as_ref().map(|_| Vec::with_capacity(max_sites));
+
+        while intersect.read_records()?.is_not_done() {
+            let records = intersect.records().iter().zip(intersect.missing());
+            for ((buf, &missing), alleles) in records.zip(shape.iter().map(|x| x - 1)) {
+                if missing {
+                    // Missing populations have flat likelihoods
+                    values.extend(std::iter::repeat(0.0).take(alleles + 1));
+                } else {
+                    f(&mut values, buf.item(), alleles)
+                }
+            }
 
-        while intersect.read_records(&mut bufs)?.is_not_done() {
-            for (buf, alleles) in bufs.iter().zip(shape.iter().map(|x| x - 1)) {
-                f(&mut values, buf.item(), alleles)
+            if let (Some(weights), Some(weight)) = (weights.as_mut(), weight.as_mut()) {
+                weights.push(weight(intersect.contig(), intersect.position()));
             }
         }
         // The allocated capacity is an overestimate unless all sites in smallest file intersected.
         values.shrink_to_fit();
+        if let Some(weights) = weights.as_mut() {
+            weights.shrink_to_fit();
+        }
 
         // Representation in SAF file is in log-space.
         values.iter_mut().for_each(|x| *x = x.exp());
 
-        Ok(Self::new_unchecked(values, shape))
+        Ok(Self {
+            weights,
+            ..Self::new_unchecked(values, shape)
+        })
     }
 
     /// Shuffles the SAF sitewise according to a random permutation.
@@ -297,6 +412,49 @@ impl<const N: usize> Saf<N> {
             let j = rng.gen_range(0..i + 1);
 
             self.swap_sites(i, j, width);
+
+            if let Some(weights) = self.weights.as_mut() {
+                weights.swap(i, j);
+            }
+        }
+    }
+
+    /// Splits the SAF into two at the given site index.
+    ///
+    /// Returns a newly allocated SAF containing the sites from `site` and onwards, and leaves the
+    /// sites before `site` in `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `site` is greater than the number of sites.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::saf1d;
+    /// let mut saf = saf1d![
+    ///     [0.,  0.,  0.],
+    ///     [1.,  1.,  1.],
+    ///     [2.,  2.,  2.],
+    /// ];
+    /// let tl = saf.split_off(2);
+    /// assert_eq!(saf.as_slice(), &[0., 0., 0., 1., 1., 1.]);
+    /// assert_eq!(tl.as_slice(), &[2., 2., 2.]);
+    /// ```
+    pub fn split_off(&mut self, site: usize) -> Self {
+        let width = self.width();
+        let tl = self.values.split_off(site * width);
+        self.values.shrink_to_fit();
+
+        let weights = self.weights.as_mut().map(|weights| {
+            let tl = weights.split_off(site);
+            weights.shrink_to_fit();
+            tl
+        });
+
+        Self {
+            weights,
+            ..Self::new_unchecked(tl, self.shape)
         }
     }
 
@@ -335,6 +493,41 @@ impl<const N: usize> Saf<N> {
         SafView {
             values: self.values.as_slice(),
             shape: self.shape,
+            weights: self.weights.as_deref(),
+        }
+    }
+
+    /// Sets the per-site weights of the SAF.
+    ///
+    /// Weights are used to scale the contribution of each site to the posterior and the
+    /// log-likelihood during EM. A site with weight two is equivalent to including the site twice,
+    /// and a site with weight zero is equivalent to excluding it. An unweighted SAF corresponds to
+    /// all sites having weight one.
+    ///
+    /// A [`WeightsError`] is thrown if the number of weights does not match the number of sites.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::saf1d;
+    /// let saf = saf1d![
+    ///     [0.,  0.,  0.],
+    ///     [1.,  1.,  1.],
+    /// ];
+    /// assert_eq!(saf.get_weight(1), 1.0);
+    /// let weighted = saf.clone().with_weights(vec![0.5, 2.0]).unwrap();
+    /// assert_eq!(weighted.get_weight(1), 2.0);
+    /// assert!(saf.with_weights(vec![1.0]).is_err());
+    /// ```
+    pub fn with_weights(mut self, weights: Vec<f32>) -> Result<Self, WeightsError> {
+        if weights.len() == self.sites() {
+            self.weights = Some(weights);
+            Ok(self)
+        } else {
+            Err(WeightsError {
+                sites: self.sites(),
+                weights: weights.len(),
+            })
         }
     }
 
@@ -365,6 +558,7 @@ where
 pub struct SafView<'a, const N: usize> {
     values: &'a [f32],
     shape: [usize; N],
+    weights: Option<&'a [f32]>,
 }
 
 impl<'a, const N: usize> SafView<'a, N> {
@@ -372,7 +566,12 @@ impl<'a, const N: usize> SafView<'a, N> {
     pub(crate) fn block(&self, start: usize, size: usize) -> Self {
         let width = self.width();
         let block = &self.values[width * start..][..width * size];
-        Self::new_unchecked(block, self.shape)
+        let weights = self.weights.map(|weights| &weights[start..][..size]);
+
+        Self {
+            weights,
+            ..Self::new_unchecked(block, self.shape)
+        }
     }
 
     /// Returns an iterator over blocks of sites in the SAF.
@@ -474,7 +673,11 @@ impl<'a, const N: usize> SafView<'a, N> {
 
     /// Returns a new SAF view without checking that the shape fits the number of values.
     pub(crate) fn new_unchecked(values: &'a [f32], shape: [usize; N]) -> Self {
-        Self { values, shape }
+        Self {
+            values,
+            shape,
+            weights: None,
+        }
     }
 
     /// Returns a parallel iterator over the blocks in the SAF.
@@ -554,9 +757,20 @@ impl<'a, const N: usize> SafView<'a, N> {
     pub fn split(&self, site: usize) -> (Self, Self) {
         let width = self.width();
         let (hd, tl) = self.values.split_at(site * width);
+        let (hd_weights, tl_weights) = match self.weights.map(|weights| weights.split_at(site)) {
+            Some((hd, tl)) => (Some(hd), Some(tl)),
+            None => (None, None),
+        };
+
         (
-            Self::new_unchecked(hd, self.shape),
-            Self::new_unchecked(tl, self.shape),
+            Self {
+                weights: hd_weights,
+                ..Self::new_unchecked(hd, self.shape)
+            },
+            Self {
+                weights: tl_weights,
+                ..Self::new_unchecked(tl, self.shape)
+            },
         )
     }
 
@@ -588,6 +802,26 @@ impl<'a, const N: usize> Sites for SafView<'a, N> {
     }
 }
 
+impl<'a, const N: usize> EmSaf<N> for SafView<'a, N> {
+    type Site = SiteView<'a, N>;
+
+    #[inline]
+    fn get_site(&self, index: usize) -> Self::Site {
+        let width = self.width();
+
+        SiteView::new_unchecked(&self.values[index * width..][..width], self.shape)
+    }
+
+    #[inline]
+    fn get_weight(&self, index: usize) -> f64 {
+        f64::from(SafView::get_weight(self, index))
+    }
+
+    fn block(&self, start: usize, size: usize) -> Self {
+        SafView::block(self, start, size)
+    }
+}
+
 /// An error associated with SAF or SAF site construction using invalid shape.
 #[derive(Clone, Debug)]
 pub struct ShapeError<const N: usize> {
@@ -608,6 +842,25 @@ impl<const N: usize> fmt::Display for ShapeError<N> {
 
 impl<const N: usize> Error for ShapeError<N> {}
 
+/// An error associated with setting SAF weights of the wrong length.
+#[derive(Clone, Debug)]
+pub struct WeightsError {
+    sites: usize,
+    weights: usize,
+}
+
+impl fmt::Display for WeightsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot apply {} weights to SAF with {} sites",
+            self.weights, self.sites,
+        )
+    }
+}
+
+impl Error for WeightsError {}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -657,6 +910,33 @@ mod tests {
         assert_eq!(saf.get_site(0).as_slice(), &[0., 0., 0., 10., 10.,]);
     }
 
+    #[test]
+    fn test_weights_follow_sites() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut saf = saf1d![[0., 0.], [1., 1.], [2., 2.], [3., 3.], [4., 4.]]
+            .with_weights(vec![0., 1., 2., 3., 4.])
+            .unwrap();
+
+        saf.shuffle(&mut StdRng::seed_from_u64(0));
+        for i in 0..saf.sites() {
+            assert_eq!(saf.get_site(i).as_slice()[0], saf.get_weight(i));
+        }
+
+        let (hd, tl) = saf.view().split(2);
+        assert_eq!(hd.weights(), Some(&saf.weights().unwrap()[..2]));
+        assert_eq!(tl.weights(), Some(&saf.weights().unwrap()[2..]));
+
+        let block = saf.view().block(1, 3);
+        assert_eq!(block.weights(), Some(&saf.weights().unwrap()[1..4]));
+
+        let (expected_hd, expected_tl) = (hd.weights().unwrap(), tl.weights().unwrap());
+        let (expected_hd, expected_tl) = (expected_hd.to_vec(), expected_tl.to_vec());
+        let split = saf.split_off(2);
+        assert_eq!(saf.weights(), Some(expected_hd.as_slice()));
+        assert_eq!(split.weights(), Some(expected_tl.as_slice()));
+    }
+
     #[test]
     #[should_panic]
     fn test_swap_panics_out_of_bounds() {
@@ -0,0 +1,799 @@
+use std::io;
+
+use angsd_saf as saf;
+
+use rand::Rng;
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    em::{EmSaf, Sites},
+    io::Intersect,
+};
+
+use super::{Saf, Site, WeightsError};
+
+/// Joint banded SAF likelihood matrix for `N` populations.
+///
+/// Banded SAF files only store the likelihoods inside a band of sample frequencies for each site,
+/// with all likelihoods outside the band being (implicitly) zero. Unlike [`Saf::read_from_banded`],
+/// which fills in these zeros, the banded SAF keeps only the values inside the bands in memory,
+/// along with the start and length of the band for each site and population. The values of each
+/// site are stored contiguously, with the bands of the populations in order.
+///
+/// The EM-related methods on the SFS take banded SAF input using [`BandedSaf::view`], in which
+/// case only values inside the bands are visited. The results are identical to those using the
+/// corresponding full SAF, see [`BandedSaf::to_full`].
+///
+/// Optionally, each site may carry a weight, see [`BandedSaf::with_weights`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct BandedSaf<const N: usize> {
+    values: Vec<f32>,
+    // The offset of the values of each site, followed by the total number of values
+    offsets: Vec<usize>,
+    // The band start and length of each site for each population, one site after another
+    starts: Vec<u32>,
+    lens: Vec<u32>,
+    shape: [usize; N],
+    weights: Option<Vec<f32>>,
+}
+
+impl<const N: usize> BandedSaf<N> {
+    /// Returns the values inside the bands of the SAF as a flat slice.
+    ///
+    /// See the [`BandedSaf`] documentation for details on the storage order.
+    pub fn as_slice(&self) -> &[f32] {
+        &self.values
+    }
+
+    /// Returns a single site in the SAF.
+    pub fn get_site(&self, index: usize) -> BandedSiteView<'_, N> {
+        self.view().get_site(index)
+    }
+
+    /// Returns the weight of a single site in the SAF.
+    ///
+    /// If the SAF is unweighted, all sites have weight one.
+    #[inline]
+    pub fn get_weight(&self, index: usize) -> f32 {
+        self.weights().map_or(1.0, |weights| weights[index])
+    }
+
+    /// Creates a new banded SAF by reading intersecting sites among banded SAF readers.
+    ///
+    /// SAF files contain values in log-space. The returned values will be exponentiated
+    /// to get out of log-space.
+    ///
+    /// The readers may be given either as an array of readers, or as an [`Intersect`] reader,
+    /// which allows e.g. restricting the sites read to certain regions. When reading the union of
+    /// sites, missing populations have flat likelihoods covering all sample frequencies.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N == 0`.
+    pub fn read<I, R>(readers: I) -> io::Result<Self>
+    where
+        I: Into<Intersect<N, R, saf::version::V4>>,
+        R: io::BufRead + io::Seek,
+    {
+        Self::read_inner_impl(readers.into(), None::<fn(&str, u32) -> f32>)
+    }
+
+    /// Creates a new weighted banded SAF by reading intersecting sites among banded SAF readers.
+    ///
+    /// See [`Saf::read_weighted`] and [`BandedSaf::read`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N == 0`.
+    pub fn read_weighted<I, R, F>(readers: I, weight: F) -> io::Result<Self>
+    where
+        I: Into<Intersect<N, R, saf::version::V4>>,
+        R: io::BufRead + io::Seek,
+        F: FnMut(&str, u32) -> f32,
+    {
+        Self::read_inner_impl(readers.into(), Some(weight))
+    }
+
+    /// The inner implementor of readers from banded SAF.
+    ///
+    /// If `weight` is provided, it is called for each intersecting site to get its weight.
+    fn read_inner_impl<R, W>(
+        mut intersect: Intersect<N, R, saf::version::V4>,
+        mut weight: Option<W>,
+    ) -> io::Result<Self>
+    where
+        R: io::BufRead + io::Seek,
+        W: FnMut(&str, u32) -> f32,
+    {
+        assert!(N > 0);
+
+        let readers = intersect.get().get_readers();
+
+        let max_sites = intersect.max_sites();
+
+        let shape: [usize; N] = readers
+            .iter()
+            .map(|reader| reader.index().alleles() + 1)
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+
+        // Unlike for full SAF, the number of values is unknown up front, but the number of sites
+        // is at most the smallest number of sites
+        let mut values = Vec::new();
+        let mut offsets = Vec::with_capacity(max_sites + 1);
+        offsets.push(0);
+        let mut starts = Vec::with_capacity(N * max_sites);
+        let mut lens = Vec::with_capacity(N * max_sites);
+
+        let mut weights = weight.as_ref().map(|_| Vec::with_capacity(max_sites));
+
+        while intersect.read_records()?.is_not_done() {
+            let records = intersect.records().iter().zip(intersect.missing());
+            for ((buf, &missing), &width) in records.zip(shape.iter()) {
+                if missing {
+                    // Missing populations have flat likelihoods
+                    starts.push(0);
+                    lens.push(width as u32);
+                    values.extend(std::iter::repeat(0.0).take(width));
+                } else {
+                    let band = buf.item();
+
+                    if band.start() + band.len() > width {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "band from {} with {} values exceeds {width} sample frequencies",
+                                band.start(),
+                                band.len(),
+                            ),
+                        ));
+                    }
+
+                    starts.push(band.start() as u32);
+                    lens.push(band.len() as u32);
+                    values.extend_from_slice(band.likelihoods());
+                }
+            }
+            offsets.push(values.len());
+
+            if let (Some(weights), Some(weight)) = (weights.as_mut(), weight.as_mut()) {
+                weights.push(weight(intersect.contig(), intersect.position()));
+            }
+        }
+        // The allocated capacity is an overestimate unless all sites in smallest file intersected.
+        values.shrink_to_fit();
+        offsets.shrink_to_fit();
+        starts.shrink_to_fit();
+        lens.shrink_to_fit();
+        if let Some(weights) = weights.as_mut() {
+            weights.shrink_to_fit();
+        }
+
+        // Representation in SAF file is in log-space.
+        values.iter_mut().for_each(|x| *x = x.exp());
+
+        Ok(Self {
+            values,
+            offsets,
+            starts,
+            lens,
+            shape,
+            weights,
+        })
+    }
+
+    /// Returns the number of sites in the SAF.
+    #[inline]
+    pub fn sites(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Returns the shape of the SAF.
+    ///
+    /// This is the number of sample frequencies for each population, i.e. the shape of the
+    /// corresponding full SAF.
+    #[inline]
+    pub fn shape(&self) -> [usize; N] {
+        self.shape
+    }
+
+    /// Shuffles the SAF sitewise according to a random permutation.
+    ///
+    /// The permutation is the same as the one used by [`Saf::shuffle`] given the same random
+    /// state, so that a banded SAF and the corresponding full SAF are shuffled identically.
+    pub fn shuffle<R>(&mut self, rng: &mut R)
+    where
+        R: Rng,
+    {
+        let mut order = (0..self.sites()).collect::<Vec<_>>();
+
+        // Modified from rand::seq::SliceRandom::shuffle
+        for i in (1..self.sites()).rev() {
+            let j = rng.gen_range(0..i + 1);
+
+            order.swap(i, j);
+        }
+
+        // Since sites have different numbers of values, they cannot be swapped in place
+        let weights = self
+            .weights
+            .as_ref()
+            .map(|weights| order.iter().map(|&i| weights[i]).collect());
+
+        *self = Self {
+            weights,
+            ..self.select_sites(&order)
+        };
+    }
+
+    /// Returns a new, unweighted SAF containing the sites with the provided indices, in order.
+    pub(super) fn select_sites(&self, indices: &[usize]) -> Self {
+        let mut values = Vec::new();
+        let mut offsets = Vec::with_capacity(indices.len() + 1);
+        offsets.push(0);
+        let mut starts = Vec::with_capacity(N * indices.len());
+        let mut lens = Vec::with_capacity(N * indices.len());
+
+        for &i in indices.iter() {
+            values.extend_from_slice(&self.values[self.offsets[i]..self.offsets[i + 1]]);
+            offsets.push(values.len());
+            starts.extend_from_slice(&self.starts[i * N..][..N]);
+            lens.extend_from_slice(&self.lens[i * N..][..N]);
+        }
+        values.shrink_to_fit();
+
+        Self {
+            values,
+            offsets,
+            starts,
+            lens,
+            shape: self.shape,
+            weights: None,
+        }
+    }
+
+    /// Splits the SAF into two at the given site index.
+    ///
+    /// Returns a newly allocated SAF containing the sites from `site` and onwards, and leaves the
+    /// sites before `site` in `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `site` is greater than the number of sites.
+    pub fn split_off(&mut self, site: usize) -> Self {
+        let offset = self.offsets[site];
+
+        let values = self.values.split_off(offset);
+        self.values.shrink_to_fit();
+
+        let offsets = self.offsets[site..].iter().map(|x| x - offset).collect();
+        self.offsets.truncate(site + 1);
+        self.offsets.shrink_to_fit();
+
+        let starts = self.starts.split_off(site * N);
+        self.starts.shrink_to_fit();
+        let lens = self.lens.split_off(site * N);
+        self.lens.shrink_to_fit();
+
+        let weights = self.weights.as_mut().map(|weights| {
+            let tl = weights.split_off(site);
+            weights.shrink_to_fit();
+            tl
+        });
+
+        Self {
+            values,
+            offsets,
+            starts,
+            lens,
+            shape: self.shape,
+            weights,
+        }
+    }
+
+    /// Returns the corresponding full SAF.
+    ///
+    /// Values outside the bands are filled with zeros, so the result is the same as reading the
+    /// banded SAF files using [`Saf::read_from_banded`].
+    pub fn to_full(&self) -> Saf<N> {
+        let width: usize = self.shape.iter().sum();
+
+        let mut values = Vec::with_capacity(width * self.sites());
+        for site in self.view().iter_sites() {
+            values.extend_from_slice(site.to_full().as_slice());
+        }
+
+        Saf {
+            weights: self.weights.clone(),
+            ..Saf::new_unchecked(values, self.shape)
+        }
+    }
+
+    /// Returns a view of the entire SAF.
+    pub fn view(&self) -> BandedSafView<'_, N> {
+        BandedSafView {
+            values: self.values.as_slice(),
+            offsets: self.offsets.as_slice(),
+            starts: self.starts.as_slice(),
+            lens: self.lens.as_slice(),
+            shape: self.shape,
+            weights: self.weights.as_deref(),
+        }
+    }
+
+    /// Returns the per-site weights of the SAF, if any.
+    pub fn weights(&self) -> Option<&[f32]> {
+        self.weights.as_deref()
+    }
+
+    /// Sets the per-site weights of the SAF.
+    ///
+    /// See [`Saf::with_weights`] for details.
+    pub fn with_weights(mut self, weights: Vec<f32>) -> Result<Self, WeightsError> {
+        if weights.len() == self.sites() {
+            self.weights = Some(weights);
+            Ok(self)
+        } else {
+            Err(WeightsError {
+                sites: self.sites(),
+                weights: weights.len(),
+            })
+        }
+    }
+}
+
+/// A view of a joint banded SAF likelihood matrix for `N` populations.
+///
+/// This may or may not be the entire matrix, but it always represents a contiguous block of sites.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BandedSafView<'a, const N: usize> {
+    // All values of the underlying SAF, since offsets are not relative to the view
+    values: &'a [f32],
+    offsets: &'a [usize],
+    starts: &'a [u32],
+    lens: &'a [u32],
+    shape: [usize; N],
+    weights: Option<&'a [f32]>,
+}
+
+impl<'a, const N: usize> BandedSafView<'a, N> {
+    /// Returns a single block of sites.
+    pub(crate) fn block(&self, start: usize, size: usize) -> Self {
+        Self {
+            values: self.values,
+            offsets: &self.offsets[start..][..size + 1],
+            starts: &self.starts[N * start..][..N * size],
+            lens: &self.lens[N * start..][..N * size],
+            shape: self.shape,
+            weights: self.weights.map(|weights| &weights[start..][..size]),
+        }
+    }
+
+    /// Returns a single site in the SAF.
+    pub fn get_site(&self, index: usize) -> BandedSiteView<'a, N> {
+        BandedSiteView {
+            values: &self.values[self.offsets[index]..self.offsets[index + 1]],
+            starts: &self.starts[N * index..][..N],
+            lens: &self.lens[N * index..][..N],
+            shape: self.shape,
+        }
+    }
+
+    /// Returns the weight of a single site in the SAF.
+    ///
+    /// If the SAF is unweighted, all sites have weight one.
+    #[inline]
+    pub fn get_weight(&self, index: usize) -> f32 {
+        self.weights.map_or(1.0, |weights| weights[index])
+    }
+
+    /// Returns an iterator over the sites in the SAF.
+    pub fn iter_sites(
+        &self,
+    ) -> impl ExactSizeIterator<Item = BandedSiteView<'a, N>> + DoubleEndedIterator {
+        let view = *self;
+
+        (0..self.sites()).map(move |i| view.get_site(i))
+    }
+
+    /// Returns a parallel iterator over the sites in the SAF.
+    ///
+    /// This is the parallel version of [`BandedSafView::iter_sites`].
+    pub fn par_iter_sites(&self) -> impl IndexedParallelIterator<Item = BandedSiteView<'a, N>> {
+        let view = *self;
+
+        (0..self.sites())
+            .into_par_iter()
+            .map(move |i| view.get_site(i))
+    }
+
+    /// Returns the number of sites in the SAF.
+    #[inline]
+    pub fn sites(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Returns the shape of the SAF.
+    #[inline]
+    pub fn shape(&self) -> [usize; N] {
+        self.shape
+    }
+
+    /// Returns the per-site weights of the SAF, if any.
+    pub fn weights(&self) -> Option<&[f32]> {
+        self.weights
+    }
+}
+
+/// A view of a single site in a banded SAF.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BandedSiteView<'a, const N: usize> {
+    values: &'a [f32],
+    starts: &'a [u32],
+    lens: &'a [u32],
+    shape: [usize; N],
+}
+
+impl<'a, const N: usize> BandedSiteView<'a, N> {
+    /// Returns the band of each population as the start of the band and the values in the band.
+    #[inline]
+    pub fn bands(&self) -> [(usize, &'a [f32]); N] {
+        let mut buf = self.values;
+        let mut i = 0;
+
+        self.shape.map(|_| {
+            let (hd, tl) = buf.split_at(self.lens[i] as usize);
+            buf = tl;
+            let start = self.starts[i] as usize;
+            i += 1;
+            (start, hd)
+        })
+    }
+
+    /// Returns the shape of the site.
+    #[inline]
+    pub fn shape(&self) -> [usize; N] {
+        self.shape
+    }
+
+    /// Returns the corresponding full site, with values outside the bands filled with zeros.
+    pub fn to_full(&self) -> Site<N> {
+        let mut site = Site::zeros(self.shape);
+
+        let mut offset = 0;
+        for (&width, (start, band)) in self.shape.iter().zip(self.bands()) {
+            site.as_mut_slice()[offset + start..][..band.len()].copy_from_slice(band);
+            offset += width;
+        }
+
+        site
+    }
+}
+
+impl<const N: usize> From<BandedSaf<N>> for Saf<N> {
+    fn from(saf: BandedSaf<N>) -> Self {
+        saf.to_full()
+    }
+}
+
+impl<const N: usize> Sites for BandedSaf<N> {
+    fn sites(&self) -> usize {
+        BandedSaf::sites(self)
+    }
+}
+
+impl<const N: usize> Sites for &BandedSaf<N> {
+    fn sites(&self) -> usize {
+        BandedSaf::sites(self)
+    }
+}
+
+impl<'a, const N: usize> Sites for BandedSafView<'a, N> {
+    fn sites(&self) -> usize {
+        BandedSafView::sites(self)
+    }
+}
+
+impl<'a, const N: usize> EmSaf<N> for BandedSafView<'a, N> {
+    type Site = BandedSiteView<'a, N>;
+
+    #[inline]
+    fn get_site(&self, index: usize) -> Self::Site {
+        BandedSafView::get_site(self, index)
+    }
+
+    #[inline]
+    fn get_weight(&self, index: usize) -> f64 {
+        f64::from(BandedSafView::get_weight(self, index))
+    }
+
+    fn block(&self, start: usize, size: usize) -> Self {
+        BandedSafView::block(self, start, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use angsd_saf::{record::Band, version::V4, ReaderV4, WriterV4};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        em::EmSite,
+        saf::{Blocks, Patterns},
+        sfs::{Sfs, USfs},
+    };
+
+    /// Returns an in-memory reader of banded SAF data with one site per band.
+    fn reader(alleles: usize, bands: &[(usize, &[f32])]) -> ReaderV4<Cursor<Vec<u8>>> {
+        let mut writer = WriterV4::new(Vec::new(), Vec::new(), Vec::new());
+        writer.write_magic().unwrap();
+        writer.write_alleles(alleles).unwrap();
+
+        for (position, &(start, likelihoods)) in bands.iter().enumerate() {
+            let band = Band::new(start, likelihoods.to_vec());
+            let record = angsd_saf::Record::new("chr1", position as u32, band);
+            writer.write_record(&record).unwrap();
+        }
+
+        let (index, positions, items) = writer.finish().unwrap();
+        let index = angsd_saf::Index::<V4>::read(&mut &index[..]).unwrap();
+
+        let mut reader = angsd_saf::reader::Builder::<V4>::default()
+            .build(index, Cursor::new(positions), Cursor::new(items))
+            .unwrap();
+        reader.read_magic().unwrap();
+        reader
+    }
+
+    /// Returns readers of two populations with four and three alleles.
+    fn readers() -> [ReaderV4<Cursor<Vec<u8>>>; 2] {
+        [
+            reader(
+                4,
+                &[
+                    (0, &[0.0, -1.0]),
+                    (1, &[-2.0, 0.0, -1.0]),
+                    (4, &[0.0]),
+                    (0, &[-3.0, -1.0, 0.0, -0.5, -4.0]),
+                    (2, &[-1.0, f32::NEG_INFINITY, 0.0]),
+                ],
+            ),
+            reader(
+                3,
+                &[
+                    (3, &[0.0]),
+                    (0, &[0.0, -0.5, -1.0, -5.0]),
+                    (1, &[-1.0, 0.0]),
+                    (2, &[0.0, -2.0]),
+                    (0, &[0.0]),
+                ],
+            ),
+        ]
+    }
+
+    fn weights() -> Vec<f32> {
+        vec![1.0, 0.5, 2.0, 0.0, 1.5]
+    }
+
+    fn sfs() -> Sfs<2> {
+        let values = (1..=20)
+            .map(|x| f64::from(x) * f64::from(x % 3 + 1))
+            .collect();
+        USfs::from_vec_shape(values, [5, 4]).unwrap().normalise()
+    }
+
+    #[test]
+    fn test_read_matches_full() -> io::Result<()> {
+        let banded = BandedSaf::read(readers())?;
+        let full = Saf::read_from_banded(readers())?;
+
+        assert_eq!(banded.sites(), 5);
+        assert_eq!(banded.shape(), [5, 4]);
+        assert_eq!(banded.as_slice().len(), 24);
+        assert_eq!(
+            banded.get_site(2).bands(),
+            [(4, &[1.0][..]), (1, &[(-1f32).exp(), 1.0][..])]
+        );
+        assert_eq!(banded.to_full(), full);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_weighted_matches_full() -> io::Result<()> {
+        let weight = |_: &str, position: u32| weights()[position as usize];
+        let banded = BandedSaf::read_weighted(readers(), weight)?;
+        let full = Saf::read_weighted_from_banded(readers(), weight)?;
+
+        assert_eq!(banded.weights(), Some(weights().as_slice()));
+        assert_eq!(banded.to_full(), full);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_invalid_band() {
+        let result = BandedSaf::read([reader(2, &[(2, &[0.0, 0.0])])]);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_em_matches_full() -> io::Result<()> {
+        let banded = BandedSaf::read(readers())?.with_weights(weights()).unwrap();
+        let full = banded.to_full();
+
+        assert_eq!(sfs().e_step(banded.view()), sfs().e_step(full.view()));
+        assert_eq!(
+            sfs().log_likelihood(banded.view()),
+            sfs().log_likelihood(full.view()),
+        );
+        assert_eq!(
+            sfs().observed_information(banded.view()).unwrap(),
+            sfs().observed_information(full.view()).unwrap(),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_underflow_matches_full() -> io::Result<()> {
+        let banded = BandedSaf::read([reader(2, &[(1, &[-80.0, -80.0])])])?;
+        let full = banded.to_full();
+        let sfs = USfs::from_vec_shape(vec![1.0, 1e-300, 1e-300], [3])
+            .unwrap()
+            .normalise();
+
+        let mut posterior = USfs::zeros([3]);
+        let mut buf = USfs::zeros([3]);
+
+        let log_likelihood = banded.get_site(0).log_likelihood(&sfs);
+        assert!(f64::from(log_likelihood).is_finite());
+        assert_eq!(log_likelihood, full.get_site(0).log_likelihood(&sfs));
+        assert_eq!(
+            banded
+                .get_site(0)
+                .posterior_into(&sfs, &mut posterior, &mut buf),
+            log_likelihood,
+        );
+        assert_eq!(posterior.as_slice(), &[0.0, 0.5, 0.5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_em_matches_full_3d() -> io::Result<()> {
+        let [first, second] = readers();
+        let third = reader(
+            2,
+            &[
+                (1, &[0.0, -1.0]),
+                (0, &[-1.0, 0.0, -2.0]),
+                (2, &[0.0]),
+                (0, &[0.0]),
+                (1, &[-0.5, 0.0]),
+            ],
+        );
+        let banded = BandedSaf::read([first, second, third])?;
+        let full = banded.to_full();
+
+        let values = (1..=60).map(|x| f64::from(x % 7 + 1)).collect();
+        let sfs = USfs::from_vec_shape(values, [5, 4, 3]).unwrap().normalise();
+
+        assert_eq!(
+            sfs.clone().e_step(banded.view()),
+            sfs.clone().e_step(full.view())
+        );
+        assert_eq!(
+            sfs.clone().log_likelihood(banded.view()),
+            sfs.log_likelihood(full.view()),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shuffle_and_split_match_full() -> io::Result<()> {
+        let mut banded = BandedSaf::read(readers())?.with_weights(weights()).unwrap();
+        let mut full = banded.to_full();
+
+        banded.shuffle(&mut StdRng::seed_from_u64(1));
+        full.shuffle(&mut StdRng::seed_from_u64(1));
+        assert_eq!(banded.to_full(), full);
+
+        let banded_tl = banded.split_off(2);
+        let full_tl = full.split_off(2);
+        assert_eq!(banded.to_full(), full);
+        assert_eq!(banded_tl.to_full(), full_tl);
+        assert_eq!(banded.sites() + banded_tl.sites(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block() -> io::Result<()> {
+        let banded = BandedSaf::read(readers())?.with_weights(weights()).unwrap();
+        let block = banded.view().block(1, 3);
+
+        assert_eq!(block.sites(), 3);
+        assert_eq!(block.weights(), Some(&weights()[1..4]));
+        for (i, site) in block.iter_sites().enumerate() {
+            assert_eq!(site, banded.get_site(i + 1));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_patterns_match_full() -> io::Result<()> {
+        let bands: &[(usize, &[f32])] = &[
+            (0, &[0.0, -1.0]),
+            (1, &[-1.0, 0.0]),
+            (0, &[0.0, -1.0]),
+            (0, &[0.0, -1.0, -2.0]),
+            (1, &[-1.0, 0.0]),
+        ];
+        let banded = BandedSaf::read([reader(3, bands)])?;
+        let patterns = Patterns::from_banded(&banded, Blocks::Number(1));
+        let full = Patterns::from_saf(&banded.to_full(), Blocks::Number(1));
+
+        assert_eq!(patterns.sites(), 5);
+        assert_eq!(patterns.patterns(), 3);
+        assert_eq!(patterns.get_weight(1), 2.0);
+        assert_eq!(patterns.get_pattern(2).bands()[0].1.len(), 3);
+
+        assert_eq!(patterns.sites(), full.sites());
+        assert_eq!(patterns.patterns(), full.patterns());
+        for i in 0..patterns.patterns() {
+            assert_eq!(patterns.get_weight(i), full.get_weight(i));
+            assert_eq!(
+                patterns.get_pattern(i).to_full().as_slice(),
+                full.get_pattern(i).as_slice()
+            );
+        }
+
+        let sfs = Sfs::uniform([4]);
+        assert_eq!(
+            sfs.clone().e_step(patterns.view()),
+            sfs.clone().e_step(full.view())
+        );
+        assert_eq!(
+            sfs.clone().observed_information(patterns.view()).unwrap(),
+            sfs.observed_information(full.view()).unwrap(),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantised_patterns_match_full() -> io::Result<()> {
+        let bands: &[(usize, &[f32])] = &[
+            (0, &[0.0, -1.0]),
+            (0, &[0.0, -1.001]),
+            (1, &[-1.001, 0.0]),
+            (1, &[-1.0, 0.0]),
+            (0, &[0.0, -1.0, -2.0]),
+        ];
+        let banded = BandedSaf::read([reader(3, bands)])?;
+        let patterns = Patterns::from_banded_quantised(&banded, Blocks::Number(1), 0.01);
+        let full = Patterns::from_saf_quantised(&banded.to_full(), Blocks::Number(1), 0.01);
+
+        assert_eq!(patterns.patterns(), 3);
+        assert_eq!(patterns.patterns(), full.patterns());
+        for i in 0..patterns.patterns() {
+            assert_eq!(patterns.get_weight(i), full.get_weight(i));
+            assert_eq!(
+                patterns.get_pattern(i).to_full().as_slice(),
+                full.get_pattern(i).as_slice()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,518 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use crate::em::{EmSaf, Sites};
+
+use super::{BandedSaf, BandedSafView, BandedSiteView, Blocks, Saf, SafView, SiteView};
+
+/// Unique site patterns of a SAF.
+///
+/// Many sites in a SAF may have identical likelihoods, in particular the monomorphic sites that
+/// make up most of the genome in low-depth data. For EM, such sites can be collapsed into a single
+/// site pattern weighted by the total weight of the sites, so that each E-step only visits unique
+/// patterns. Sites are deduplicated within blocks, so that each pattern belongs to a single block,
+/// and the blocks of the original SAF can be recovered when running window EM with the same
+/// [`Blocks`], see [`Patterns::from_saf`]. Otherwise, blocks must align with the deduplication
+/// blocks.
+///
+/// By default, only sites with bit-identical values are collapsed. Sites with near-identical values
+/// may also be collapsed by quantising the values first, see [`Patterns::from_saf_quantised`].
+///
+/// The patterns are stored as the same kind of SAF as the input, i.e. as a [`Saf`] for patterns
+/// created by [`Patterns::from_saf`], and as a [`BandedSaf`] for patterns created by
+/// [`Patterns::from_banded`].
+///
+/// The EM-related methods on the SFS take patterns as input using [`Patterns::view`]. Without
+/// quantisation, the results are the same as for the original SAF, up to floating point error from
+/// summation order. Sites are counted as in the original SAF, so that e.g. the number of sites
+/// reported along with log-likelihoods is unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Patterns<T> {
+    saf: T,
+    // The total weight of the sites of each pattern
+    weights: Vec<f64>,
+    // The offsets of the blocks in sites and in patterns, each followed by the total number
+    site_offsets: Vec<usize>,
+    pattern_offsets: Vec<usize>,
+}
+
+impl<T> Patterns<T> {
+    /// Returns the compression ratio, i.e. the number of sites per site pattern.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::{saf::{Blocks, Patterns}, saf1d};
+    /// let saf = saf1d![[0., 1.], [0., 1.], [1., 0.], [0., 1.]];
+    /// let patterns = Patterns::from_saf(&saf, Blocks::Number(1));
+    /// assert_eq!(patterns.compression_ratio(), 2.0);
+    /// ```
+    pub fn compression_ratio(&self) -> f64 {
+        self.sites() as f64 / self.patterns().max(1) as f64
+    }
+
+    /// Returns the total weight of the sites of a single site pattern.
+    pub fn get_weight(&self, index: usize) -> f64 {
+        self.weights[index]
+    }
+
+    /// Returns the number of site patterns.
+    pub fn patterns(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Returns the number of sites in the original SAF.
+    pub fn sites(&self) -> usize {
+        self.site_offsets[self.site_offsets.len() - 1]
+    }
+
+    /// The inner implementor of site pattern constructors.
+    ///
+    /// The `key` identifies identical sites, and `collect` creates the storage of the patterns
+    /// from the indices of the first site of each pattern, in order.
+    fn from_sites<K, F, W, C>(sites: usize, blocks: Blocks, key: F, weight: W, collect: C) -> Self
+    where
+        K: Hash + Eq,
+        F: Fn(usize) -> K,
+        W: Fn(usize) -> f64,
+        C: FnOnce(&[usize]) -> T,
+    {
+        let spec = blocks.to_spec(sites);
+
+        let mut firsts = Vec::new();
+        let mut weights = Vec::new();
+        let mut site_offsets = vec![0];
+        let mut pattern_offsets = vec![0];
+
+        let mut start = 0;
+        let mut indices = HashMap::new();
+        for size in spec.iter_block_sizes() {
+            indices.clear();
+
+            for i in start..start + size {
+                match indices.entry(key(i)) {
+                    Entry::Occupied(entry) => weights[*entry.get()] += weight(i),
+                    Entry::Vacant(entry) => {
+                        entry.insert(weights.len());
+                        weights.push(weight(i));
+                        firsts.push(i);
+                    }
+                }
+            }
+
+            start += size;
+            site_offsets.push(start);
+            pattern_offsets.push(weights.len());
+        }
+        debug_assert_eq!(start, sites);
+
+        weights.shrink_to_fit();
+
+        Self {
+            saf: collect(&firsts),
+            weights,
+            site_offsets,
+            pattern_offsets,
+        }
+    }
+
+    /// Returns a view of all site patterns given a view of the pattern storage.
+    fn view_with<V>(&self, saf: V) -> PatternsView<'_, V> {
+        PatternsView {
+            saf,
+            weights: self.weights.as_slice(),
+            site_offsets: self.site_offsets.as_slice(),
+            pattern_offsets: self.pattern_offsets.as_slice(),
+        }
+    }
+}
+
+impl<const N: usize> Patterns<Saf<N>> {
+    /// Creates the site patterns of a SAF.
+    ///
+    /// The SAF is split into blocks according to `blocks`, and sites with bit-identical values are
+    /// collapsed within each block. The weight of each pattern is the total weight of its sites,
+    /// see [`Saf::with_weights`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::{saf::{Blocks, Patterns}, saf1d};
+    /// let saf = saf1d![[0., 1.], [0., 1.], [1., 0.], [0., 1.]];
+    /// let patterns = Patterns::from_saf(&saf, Blocks::Number(2));
+    /// assert_eq!(patterns.sites(), 4);
+    /// assert_eq!(patterns.patterns(), 3);
+    /// assert_eq!(patterns.get_weight(0), 2.0);
+    /// ```
+    pub fn from_saf(saf: &Saf<N>, blocks: Blocks) -> Self {
+        Self::from_saf_with(saf, blocks, None)
+    }
+
+    /// Creates the site patterns of a SAF, collapsing sites with near-identical values.
+    ///
+    /// Sites are collapsed if the natural logarithms of their values round to the same multiples
+    /// of `precision`, and each pattern takes the values of its first site. The values of a
+    /// collapsed site may therefore differ from those of its pattern by up to a factor of
+    /// `exp(precision)`. Zero values are only collapsed with zero values. Otherwise, see
+    /// [`Patterns::from_saf`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `precision` is not positive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::{saf::{Blocks, Patterns}, saf1d};
+    /// let saf = saf1d![[1., 0.5], [1., 0.501], [1., 0.], [1., 0.25]];
+    /// assert_eq!(Patterns::from_saf(&saf, Blocks::Number(1)).patterns(), 4);
+    /// let patterns = Patterns::from_saf_quantised(&saf, Blocks::Number(1), 0.01);
+    /// assert_eq!(patterns.patterns(), 3);
+    /// assert_eq!(patterns.get_weight(0), 2.0);
+    /// assert_eq!(patterns.get_pattern(0).as_slice(), &[1., 0.5]);
+    /// ```
+    pub fn from_saf_quantised(saf: &Saf<N>, blocks: Blocks, precision: f32) -> Self {
+        Self::from_saf_with(saf, blocks, Some(check_precision(precision)))
+    }
+
+    /// Creates the site patterns of a SAF, quantising values if `precision` is set.
+    fn from_saf_with(saf: &Saf<N>, blocks: Blocks, precision: Option<f32>) -> Self {
+        let width = saf.shape().iter().sum::<usize>();
+        let site = |i: usize| &saf.as_slice()[i * width..][..width];
+
+        Self::from_sites(
+            saf.sites(),
+            blocks,
+            |i| Key::new(site(i), precision),
+            |i| f64::from(saf.get_weight(i)),
+            |firsts| {
+                let mut values = Vec::with_capacity(firsts.len() * width);
+                firsts
+                    .iter()
+                    .for_each(|&i| values.extend_from_slice(site(i)));
+
+                Saf::new_unchecked(values, saf.shape())
+            },
+        )
+    }
+
+    /// Returns a single site pattern.
+    pub fn get_pattern(&self, index: usize) -> SiteView<'_, N> {
+        self.saf.get_site(index)
+    }
+
+    /// Returns the shape of the site patterns.
+    pub fn shape(&self) -> [usize; N] {
+        self.saf.shape()
+    }
+
+    /// Returns a view of all site patterns.
+    pub fn view(&self) -> PatternsView<'_, SafView<'_, N>> {
+        self.view_with(self.saf.view())
+    }
+}
+
+impl<const N: usize> Patterns<BandedSaf<N>> {
+    /// Creates the site patterns of a banded SAF.
+    ///
+    /// Sites with bit-identical bands are collapsed within blocks, and the patterns are kept
+    /// banded, so that EM only visits values inside the bands. Otherwise, see
+    /// [`Patterns::from_saf`].
+    pub fn from_banded(saf: &BandedSaf<N>, blocks: Blocks) -> Self {
+        Self::from_banded_with(saf, blocks, None)
+    }
+
+    /// Creates the site patterns of a banded SAF, collapsing sites with near-identical bands.
+    ///
+    /// Sites are only collapsed if their bands start at the same positions, and the values inside
+    /// the bands are quantised as described for [`Patterns::from_saf_quantised`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `precision` is not positive.
+    pub fn from_banded_quantised(saf: &BandedSaf<N>, blocks: Blocks, precision: f32) -> Self {
+        Self::from_banded_with(saf, blocks, Some(check_precision(precision)))
+    }
+
+    /// Creates the site patterns of a banded SAF, quantising values if `precision` is set.
+    fn from_banded_with(saf: &BandedSaf<N>, blocks: Blocks, precision: Option<f32>) -> Self {
+        let view = saf.view();
+
+        Self::from_sites(
+            saf.sites(),
+            blocks,
+            |i| {
+                let site = view.get_site(i);
+                site.bands()
+                    .map(|(start, band)| (start, Key::new(band, precision)))
+            },
+            |i| f64::from(saf.get_weight(i)),
+            |firsts| saf.select_sites(firsts),
+        )
+    }
+
+    /// Returns a single site pattern.
+    pub fn get_pattern(&self, index: usize) -> BandedSiteView<'_, N> {
+        self.saf.get_site(index)
+    }
+
+    /// Returns the shape of the site patterns.
+    pub fn shape(&self) -> [usize; N] {
+        self.saf.shape()
+    }
+
+    /// Returns a view of all site patterns.
+    pub fn view(&self) -> PatternsView<'_, BandedSafView<'_, N>> {
+        self.view_with(self.saf.view())
+    }
+}
+
+/// A view of unique site patterns of a SAF.
+///
+/// This may or may not be all patterns, but it always represents a contiguous range of blocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PatternsView<'a, V> {
+    // The patterns of the blocks in the view only
+    saf: V,
+    weights: &'a [f64],
+    // Offsets are not relative to the view
+    site_offsets: &'a [usize],
+    pattern_offsets: &'a [usize],
+}
+
+impl<'a, V> PatternsView<'a, V> {
+    /// Returns the number of site patterns.
+    pub fn patterns(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Returns the number of sites in the original SAF.
+    pub fn sites(&self) -> usize {
+        self.site_offsets[self.site_offsets.len() - 1] - self.site_offsets[0]
+    }
+}
+
+impl<T> Sites for Patterns<T> {
+    fn sites(&self) -> usize {
+        Patterns::sites(self)
+    }
+}
+
+impl<'a, V> Sites for PatternsView<'a, V> {
+    fn sites(&self) -> usize {
+        PatternsView::sites(self)
+    }
+}
+
+impl<'a, const N: usize, V> EmSaf<N> for PatternsView<'a, V>
+where
+    V: EmSaf<N>,
+{
+    type Site = V::Site;
+
+    #[inline]
+    fn patterns(&self) -> usize {
+        PatternsView::patterns(self)
+    }
+
+    #[inline]
+    fn get_site(&self, index: usize) -> Self::Site {
+        self.saf.get_site(index)
+    }
+
+    #[inline]
+    fn get_weight(&self, index: usize) -> f64 {
+        self.weights[index]
+    }
+
+    /// Returns the blocks covering sites from `start` and `size` sites on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sites do not start and end at block boundaries.
+    fn block(&self, start: usize, size: usize) -> Self {
+        let offset = |site| {
+            self.site_offsets
+                .binary_search(&(self.site_offsets[0] + site))
+                .expect("block does not align with site pattern blocks")
+        };
+        let (first, last) = (offset(start), offset(start + size));
+
+        let pattern_start = self.pattern_offsets[first] - self.pattern_offsets[0];
+        let patterns = self.pattern_offsets[last] - self.pattern_offsets[first];
+
+        Self {
+            saf: self.saf.block(pattern_start, patterns),
+            weights: &self.weights[pattern_start..][..patterns],
+            site_offsets: &self.site_offsets[first..=last],
+            pattern_offsets: &self.pattern_offsets[first..=last],
+        }
+    }
+}
+
+/// Returns the precision if it is positive.
+///
+/// # Panics
+///
+/// Panics if `precision` is not positive.
+fn check_precision(precision: f32) -> f32 {
+    assert!(
+        precision > 0.0,
+        "quantisation precision must be positive, found {precision}"
+    );
+
+    precision
+}
+
+/// A key for hashing and comparing SAF values.
+///
+/// Values are compared by their bits, or after quantising their logarithms if a precision is set.
+#[derive(Clone, Copy, Debug)]
+struct Key<'a> {
+    values: &'a [f32],
+    precision: Option<f32>,
+}
+
+impl<'a> Key<'a> {
+    fn new(values: &'a [f32], precision: Option<f32>) -> Self {
+        Self { values, precision }
+    }
+
+    /// Returns the bits used for hashing and comparing each value.
+    ///
+    /// Zero values have no logarithm, and are represented by `None` when quantising.
+    fn bits(&self) -> impl Iterator<Item = Option<i64>> + '_ {
+        self.values.iter().map(|&x| match self.precision {
+            Some(_) if x == 0.0 => None,
+            Some(precision) => Some((x.ln() / precision).round() as i64),
+            None => Some(i64::from(x.to_bits())),
+        })
+    }
+}
+
+impl<'a> PartialEq for Key<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.values.len() == other.values.len() && self.bits().eq(other.bits())
+    }
+}
+
+impl<'a> Eq for Key<'a> {}
+
+impl<'a> Hash for Key<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.values.len().hash(state);
+        self.bits().for_each(|x| x.hash(state));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{saf1d, sfs1d};
+
+    fn saf() -> Saf<1> {
+        saf1d![
+            [1., 0., 0.],
+            [0., 1., 0.],
+            [1., 0., 0.],
+            [1., 0., 0.],
+            [0., 0.5, 0.5],
+            [0., 1., 0.],
+            [0., 1., 0.],
+        ]
+        .with_weights(vec![1., 2., 0.5, 1., 1., 0., 3.])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_saf() {
+        let patterns = Patterns::from_saf(&saf(), Blocks::Size(3));
+
+        assert_eq!(patterns.sites(), 7);
+        assert_eq!(patterns.patterns(), 6);
+        assert_eq!(patterns.site_offsets, vec![0, 3, 6, 7]);
+        assert_eq!(patterns.pattern_offsets, vec![0, 2, 5, 6]);
+        assert_eq!(patterns.weights, vec![1.5, 2., 1., 1., 0., 3.]);
+        assert_eq!(patterns.get_pattern(3).as_slice(), &[0., 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_from_saf_quantised() {
+        let saf = saf1d![
+            [1., 0.5, 0.],
+            [1., 0.501, 0.],
+            [1., 0.5, 1e-30],
+            [1., 0.6, 0.],
+            [1., 0.501, 0.],
+        ];
+
+        let patterns = Patterns::from_saf_quantised(&saf, Blocks::Size(3), 0.01);
+        assert_eq!(patterns.sites(), 5);
+        assert_eq!(patterns.pattern_offsets, vec![0, 2, 4]);
+        assert_eq!(patterns.weights, vec![2., 1., 1., 1.]);
+        assert_eq!(patterns.get_pattern(0).as_slice(), &[1., 0.5, 0.]);
+
+        let patterns = Patterns::from_saf_quantised(&saf, Blocks::Size(3), 1.);
+        assert_eq!(patterns.weights, vec![2., 1., 2.]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_saf_quantised_panics_non_positive_precision() {
+        Patterns::from_saf_quantised(&saf(), Blocks::Number(1), 0.);
+    }
+
+    #[test]
+    fn test_block() {
+        let patterns = Patterns::from_saf(&saf(), Blocks::Size(3));
+        let view = patterns.view();
+
+        let block = view.block(3, 4);
+        assert_eq!(block.sites(), 4);
+        assert_eq!(block.patterns(), 4);
+        assert_eq!(block.weights, &[1., 1., 0., 3.]);
+
+        let inner = block.block(3, 1);
+        assert_eq!(inner.sites(), 1);
+        assert_eq!(inner.weights, &[3.]);
+        assert_eq!(inner.saf.as_slice(), &[0., 1., 0.]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_block_panics_unaligned() {
+        Patterns::from_saf(&saf(), Blocks::Size(3))
+            .view()
+            .block(1, 3);
+    }
+
+    #[test]
+    fn test_em_matches_saf() {
+        let saf = saf();
+        let patterns = Patterns::from_saf(&saf, Blocks::Number(2));
+        let sfs = sfs1d![1., 2., 3.].normalise();
+
+        let (log_likelihood, posterior) = sfs.clone().e_step(patterns.view());
+        let (expected_log_likelihood, expected_posterior) = sfs.clone().e_step(saf.view());
+
+        assert_eq!(log_likelihood.n(), expected_log_likelihood.n());
+        let (x, y) = (*log_likelihood.sum(), *expected_log_likelihood.sum());
+        assert!((f64::from(x) - f64::from(y)).abs() < 1e-12);
+        for (x, y) in posterior.iter().zip(expected_posterior.iter()) {
+            assert!((x - y).abs() < 1e-12);
+        }
+
+        let information = sfs.clone().observed_information(patterns.view()).unwrap();
+        let expected_information = sfs.observed_information(saf.view()).unwrap();
+        for (x, y) in information
+            .as_slice()
+            .iter()
+            .zip(expected_information.as_slice())
+        {
+            assert!((x - y).abs() < 1e-12);
+        }
+    }
+}
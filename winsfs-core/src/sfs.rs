@@ -26,6 +26,10 @@ use iter::Indices;
 
 mod em;
 
+pub mod expected;
+
+pub mod information;
+
 const NORMALISATION_TOLERANCE: f64 = 10. * f64::EPSILON;
 
 /// Creates an unnormalised 1D SFS.
@@ -243,6 +247,45 @@ impl<S: Shape, N: Normalisation> SfsBase<S, N> {
         folded
     }
 
+    /// Returns the SFS symmetrised over the folding map.
+    ///
+    /// Each value is replaced by the average of itself and the value it would be folded onto, or
+    /// from. For a folded SFS (see [`SfsBase::fold`]), this gives an unfolded SFS in which the mass
+    /// of each folded value is split equally between the two indistinguishable combinations.
+    /// Conversely, folding a symmetrised SFS gives the same result as folding the original.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs1d;
+    /// let sfs = sfs1d![6., 12., 3., 0., 0.];
+    /// assert_eq!(sfs.symmetrise(), sfs1d![3., 6., 3., 6., 3.]);
+    /// assert_eq!(sfs.symmetrise().fold(), sfs);
+    /// ```
+    ///
+    /// ```
+    /// use winsfs_core::sfs2d;
+    /// let sfs = sfs2d![
+    ///     [8., 5., 0.],
+    ///     [10., 0., 0.],
+    /// ];
+    /// let expected = sfs2d![
+    ///     [4., 2.5, 5.],
+    ///     [5., 2.5, 4.],
+    /// ];
+    /// assert_eq!(sfs.symmetrise(), expected);
+    /// ```
+    pub fn symmetrise(&self) -> Self {
+        let values = self
+            .values
+            .iter()
+            .zip(self.values.iter().rev())
+            .map(|(x, rev_x)| 0.5 * (x + rev_x))
+            .collect();
+
+        Self::new_unchecked(values, self.shape.clone())
+    }
+
     /// Returns a string containing a flat, row-major represention of the SFS.
     ///
     /// # Examples
@@ -673,6 +716,138 @@ impl SfsBase<ConstShape<1>, Unnorm> {
     }
 }
 
+impl<N: Normalisation> SfsBase<ConstShape<1>, N> {
+    /// Returns the number of haplotypes represented by the SFS, if the SFS has at least three values.
+    fn haplotypes(&self) -> Option<usize> {
+        let n = self.shape[0] - 1;
+
+        (n >= 2).then_some(n)
+    }
+
+    /// Returns the sum of the SFS over the segregating (i.e. non-fixed) bins.
+    fn segregating(&self) -> f64 {
+        let n = self.shape[0] - 1;
+
+        self.values[1..n].iter().sum()
+    }
+
+    /// Returns Tajima's D.
+    ///
+    /// The SFS should be unnormalised, giving the (expected) number of sites in each bin, since the
+    /// variance of Tajima's D depends on the number of segregating sites. The SFS is assumed to be
+    /// unfolded, and `None` is returned if the SFS has fewer than three values. If the SFS has no
+    /// segregating sites, the result is NaN.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs1d;
+    /// // The expected neutral SFS has D = 0
+    /// let neutral = sfs1d![0., 1., 1. / 2., 1. / 3., 0.];
+    /// assert!(neutral.tajimas_d().unwrap().abs() < 1e-12);
+    /// // Excess of rare variants gives negative D
+    /// let rare = sfs1d![0., 10., 1., 1., 0.];
+    /// assert!(rare.tajimas_d().unwrap() < 0.);
+    /// assert_eq!(sfs1d![1., 1.].tajimas_d(), None);
+    /// ```
+    pub fn tajimas_d(&self) -> Option<f64> {
+        let n = self.haplotypes()?;
+        let nf = n as f64;
+
+        let a1 = harmonic(n - 1, 1);
+        let a2 = harmonic(n - 1, 2);
+        let b1 = (nf + 1.) / (3. * (nf - 1.));
+        let b2 = 2. * (nf * nf + nf + 3.) / (9. * nf * (nf - 1.));
+        let c1 = b1 - 1. / a1;
+        let c2 = b2 - (nf + 2.) / (a1 * nf) + a2 / (a1 * a1);
+        let e1 = c1 / a1;
+        let e2 = c2 / (a1 * a1 + a2);
+
+        let segregating = self.segregating();
+        let difference = self.theta_pi()? - segregating / a1;
+        let variance = e1 * segregating + e2 * segregating * (segregating - 1.);
+
+        Some(difference / variance.sqrt())
+    }
+
+    /// Returns Fay and Wu's estimator of theta, based on the squared derived allele counts.
+    ///
+    /// If the SFS is normalised, this is the estimate per site. The SFS is assumed to be
+    /// unfolded, and `None` is returned if the SFS has fewer than three values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs1d;
+    /// let sfs = sfs1d![0., 0., 0., 3., 0.];
+    /// assert_eq!(sfs.theta_h(), Some(4.5));
+    /// assert_eq!(sfs1d![1., 1.].theta_h(), None);
+    /// ```
+    pub fn theta_h(&self) -> Option<f64> {
+        let n = self.haplotypes()?;
+        let pairs = (n * (n - 1)) as f64;
+
+        let theta = self.values[1..n]
+            .iter()
+            .zip(1..)
+            .map(|(v, k)| v * (2 * k * k) as f64 / pairs)
+            .sum();
+
+        Some(theta)
+    }
+
+    /// Returns Tajima's estimator of theta, i.e. the average number of pairwise differences.
+    ///
+    /// If the SFS is normalised, this is the estimate per site. The SFS is assumed to be
+    /// unfolded, and `None` is returned if the SFS has fewer than three values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs1d;
+    /// let sfs = sfs1d![0., 1., 0., 0., 0.];
+    /// assert_eq!(sfs.theta_pi(), Some(0.5));
+    /// assert_eq!(sfs1d![1., 1.].theta_pi(), None);
+    /// ```
+    pub fn theta_pi(&self) -> Option<f64> {
+        let n = self.haplotypes()?;
+        let pairs = (n * (n - 1)) as f64;
+
+        let theta = self
+            .iter()
+            .enumerate()
+            .map(|(k, v)| v * (2 * k * (n - k)) as f64 / pairs)
+            .sum();
+
+        Some(theta)
+    }
+
+    /// Returns Watterson's estimator of theta, i.e. the number of segregating sites divided by
+    /// the harmonic number of one less than the number of haplotypes.
+    ///
+    /// If the SFS is normalised, this is the estimate per site. The SFS is assumed to be
+    /// unfolded, and `None` is returned if the SFS has fewer than three values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs1d;
+    /// let sfs = sfs1d![0., 3., 0., 0.];
+    /// assert_eq!(sfs.theta_watterson(), Some(2.));
+    /// assert_eq!(sfs1d![1., 1.].theta_watterson(), None);
+    /// ```
+    pub fn theta_watterson(&self) -> Option<f64> {
+        let n = self.haplotypes()?;
+        Some(self.segregating() / harmonic(n - 1, 1))
+    }
+}
+
+/// Returns the generalised harmonic number of order `n` and power `m`, i.e. the sum of `1 / i^m`
+/// for `i` in `1..=n`.
+fn harmonic(n: usize, m: i32) -> f64 {
+    (1..=n).map(|i| (i as f64).powi(m).recip()).sum()
+}
+
 impl SfsBase<ConstShape<2>, Norm> {
     /// Returns the f2-statistic.
     ///
@@ -702,6 +877,32 @@ impl SfsBase<ConstShape<2>, Norm> {
     ///
     /// [bhatia]: https://www.ncbi.nlm.nih.gov/pmc/articles/PMC3759727/
     pub fn fst(&self) -> f64 {
+        let (num, denom) = self.fst_components();
+
+        num / denom
+    }
+}
+
+impl<N: Normalisation> SfsBase<ConstShape<2>, N> {
+    /// Returns the numerator and denominator of the Fst statistic.
+    ///
+    /// See [`Sfs::fst`] for the definition. Both numerator and denominator are linear in the SFS,
+    /// so they can be summed across spectra. In particular, summing the components of per-site
+    /// posteriors across sites gives the components of the ratio of averages across the sites.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs2d;
+    /// let sfs = sfs2d![
+    ///     [0., 0., 1.],
+    ///     [0., 0., 0.],
+    ///     [1., 0., 0.],
+    /// ];
+    /// assert_eq!(sfs.fst_components(), (2., 2.));
+    /// assert_eq!(sfs.normalise().fst(), 1.);
+    /// ```
+    pub fn fst_components(&self) -> (f64, f64) {
         let [n_i_sub, n_j_sub] = self.shape().map(|x| (x - 2) as f64);
 
         // We only want the polymorphic parts of the spectrum and corresponding frequencies,
@@ -713,20 +914,16 @@ impl SfsBase<ConstShape<2>, Norm> {
             .take(self.values.len() - 1)
             .skip(1);
 
-        let (num, denom) = polymorphic_iter
+        polymorphic_iter
             .map(|(v, f)| (v, f, f.map(|f| 1. - f)))
             .map(|(v, [f_i, f_j], [g_i, g_j])| {
                 let num = (f_i - f_j).powi(2) - f_i * g_i / n_i_sub - f_j * g_j / n_j_sub;
                 let denom = f_i * g_j + f_j * g_i;
                 (v * num, v * denom)
             })
-            .fold((0., 0.), |(n_sum, d_sum), (n, d)| (n_sum + n, d_sum + d));
-
-        num / denom
+            .fold((0., 0.), |(n_sum, d_sum), (n, d)| (n_sum + n, d_sum + d))
     }
-}
 
-impl<N: Normalisation> SfsBase<ConstShape<2>, N> {
     /// Returns the King kinship statistic.
     ///
     /// If the SFS does not have shape 3x3, `None` is returned. If all heterozygote bins are zero,
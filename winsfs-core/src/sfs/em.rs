@@ -1,20 +1,20 @@
 use std::io;
 
-use rayon::iter::ParallelIterator;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 use crate::{
     em::{
         likelihood::{LogLikelihood, SumOf},
-        EmSite,
+        EmSaf, EmSite,
     },
     io::ReadSite,
-    saf::{SafView, Site},
+    saf::Site,
 };
 
 use super::{Sfs, USfs};
 
 /// The minimum allowable SFS value during EM.
-const RESTRICT_MIN: f64 = f64::EPSILON;
+pub(super) const RESTRICT_MIN: f64 = f64::EPSILON;
 
 impl<const D: usize> Sfs<D> {
     /// Returns the log-likelihood of the data given the SFS, and the expected number of sites
@@ -24,6 +24,15 @@ impl<const D: usize> Sfs<D> {
     /// expected number of sites in each bin given `self` and the `input`.
     /// The sum of the returned SFS will be equal to the number of sites in the input.
     ///
+    /// If the input is weighted (see [`Saf::with_weights`](crate::saf::Saf::with_weights)), the
+    /// contribution of each site to the posterior and the log-likelihood is scaled by its weight.
+    /// In that case, the sum of the returned SFS will be equal to the sum of the weights. Sites with
+    /// weight zero are skipped, so that their likelihoods do not affect the result.
+    ///
+    /// The input may be a view of either a full SAF or a banded SAF (see
+    /// [`BandedSaf`](crate::saf::BandedSaf)), and the result is the same for a banded SAF and the
+    /// corresponding full SAF.
+    ///
     /// # Panics
     ///
     /// Panics if any of the sites in the input does not fit the shape of `self`.
@@ -43,23 +52,39 @@ impl<const D: usize> Sfs<D> {
     /// assert_eq!(posterior, sfs1d![2., 1., 0., 1., 0.]);
     /// assert_eq!(log_likelihood, sfs.log_likelihood(saf.view()));
     /// ```
-    pub fn e_step(mut self, saf: SafView<D>) -> (SumOf<LogLikelihood>, USfs<D>) {
+    pub fn e_step<I>(mut self, saf: I) -> (SumOf<LogLikelihood>, USfs<D>)
+    where
+        I: EmSaf<D>,
+    {
         self = restrict(self, RESTRICT_MIN);
 
-        let (log_likelihood, posterior, _) = saf.iter_sites().fold(
+        let (log_likelihood, total_weight, posterior, _) = (0..saf.patterns()).fold(
             (
                 LogLikelihood::from(0.0),
+                0.0,
                 USfs::zeros(self.shape),
                 USfs::zeros(self.shape),
             ),
-            |(mut log_likelihood, mut posterior, mut buf), site| {
-                log_likelihood += site.posterior_into(&self, &mut posterior, &mut buf).ln();
+            |(mut log_likelihood, mut total_weight, mut posterior, mut buf), i| {
+                let weight = saf.get_weight(i);
+                if weight == 0.0 {
+                    return (log_likelihood, total_weight, posterior, buf);
+                }
 
-                (log_likelihood, posterior, buf)
+                log_likelihood += saf.get_site(i).weighted_posterior_into(
+                    &self,
+                    &mut posterior,
+                    &mut buf,
+                    weight,
+                ) * weight;
+                total_weight += weight;
+
+                (log_likelihood, total_weight, posterior, buf)
             },
         );
 
-        (SumOf::new(log_likelihood, saf.sites()), posterior)
+        let log_likelihood = SumOf::with_weight(log_likelihood, saf.sites(), total_weight);
+        (log_likelihood, posterior)
     }
 
     /// Returns the log-likelihood of the data given the SFS, and the expected number of sites
@@ -86,36 +111,57 @@ impl<const D: usize> Sfs<D> {
     /// assert_eq!(posterior, sfs1d![2., 1., 0., 1., 0.]);
     /// assert_eq!(log_likelihood, sfs.log_likelihood(saf.view()));
     /// ```
-    pub fn par_e_step(mut self, saf: SafView<D>) -> (SumOf<LogLikelihood>, USfs<D>) {
+    pub fn par_e_step<I>(mut self, saf: I) -> (SumOf<LogLikelihood>, USfs<D>)
+    where
+        I: EmSaf<D>,
+    {
         self = restrict(self, RESTRICT_MIN);
 
-        let (log_likelihood, posterior) = saf
-            .par_iter_sites()
+        let (log_likelihood, total_weight, posterior) = (0..saf.patterns())
+            .into_par_iter()
             .fold(
                 || {
                     (
                         LogLikelihood::from(0.0),
+                        0.0,
                         USfs::zeros(self.shape),
                         USfs::zeros(self.shape),
                     )
                 },
-                |(mut log_likelihood, mut posterior, mut buf), site| {
-                    log_likelihood += site.posterior_into(&self, &mut posterior, &mut buf).ln();
+                |(mut log_likelihood, mut total_weight, mut posterior, mut buf), i| {
+                    let weight = saf.get_weight(i);
+                    if weight == 0.0 {
+                        return (log_likelihood, total_weight, posterior, buf);
+                    }
 
-                    (log_likelihood, posterior, buf)
+                    log_likelihood += saf.get_site(i).weighted_posterior_into(
+                        &self,
+                        &mut posterior,
+                        &mut buf,
+                        weight,
+                    ) * weight;
+                    total_weight += weight;
+
+                    (log_likelihood, total_weight, posterior, buf)
                 },
             )
-            .map(|(log_likelihood, posterior, _buf)| (log_likelihood, posterior))
+            .map(|(log_likelihood, total_weight, posterior, _buf)| {
+                (log_likelihood, total_weight, posterior)
+            })
             .reduce(
-                || (LogLikelihood::from(0.0), USfs::zeros(self.shape)),
-                |a, b| (a.0 + b.0, a.1 + b.1),
+                || (LogLikelihood::from(0.0), 0.0, USfs::zeros(self.shape)),
+                |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2),
             );
 
-        (SumOf::new(log_likelihood, saf.sites()), posterior)
+        let log_likelihood = SumOf::with_weight(log_likelihood, saf.sites(), total_weight);
+        (log_likelihood, posterior)
     }
 
     /// Returns the log-likelihood of the data given the SFS.
     ///
+    /// If the input is weighted, the log-likelihood of each site is scaled by its weight, and sites
+    /// with weight zero are skipped.
+    ///
     /// # Panics
     ///
     /// Panics if any of the sites in the input does not fit the shape of `self`.
@@ -134,16 +180,28 @@ impl<const D: usize> Sfs<D> {
     /// let expected = SumOf::new(Likelihood::from(0.2f64.powi(4)).ln(), saf.sites());
     /// assert_eq!(sfs.log_likelihood(saf.view()), expected);
     /// ```
-    pub fn log_likelihood(mut self, saf: SafView<D>) -> SumOf<LogLikelihood> {
+    pub fn log_likelihood<I>(mut self, saf: I) -> SumOf<LogLikelihood>
+    where
+        I: EmSaf<D>,
+    {
         self = restrict(self, RESTRICT_MIN);
 
-        let log_likelihood = saf
-            .iter_sites()
-            .fold(LogLikelihood::from(0.0), |log_likelihood, site| {
-                log_likelihood + site.log_likelihood(&self)
-            });
+        let (log_likelihood, total_weight) = (0..saf.patterns()).fold(
+            (LogLikelihood::from(0.0), 0.0),
+            |(log_likelihood, total_weight), i| {
+                let weight = saf.get_weight(i);
+                if weight == 0.0 {
+                    (log_likelihood, total_weight)
+                } else {
+                    (
+                        log_likelihood + saf.get_site(i).log_likelihood(&self) * weight,
+                        total_weight + weight,
+                    )
+                }
+            },
+        );
 
-        SumOf::new(log_likelihood, saf.sites())
+        SumOf::with_weight(log_likelihood, saf.sites(), total_weight)
     }
 
     /// Returns the log-likelihood of the data given the SFS.
@@ -168,18 +226,34 @@ impl<const D: usize> Sfs<D> {
     /// let expected = SumOf::new(Likelihood::from(0.2f64.powi(4)).ln(), saf.sites());
     /// assert_eq!(sfs.par_log_likelihood(saf.view()), expected);
     /// ```
-    pub fn par_log_likelihood(mut self, saf: SafView<D>) -> SumOf<LogLikelihood> {
+    pub fn par_log_likelihood<I>(mut self, saf: I) -> SumOf<LogLikelihood>
+    where
+        I: EmSaf<D>,
+    {
         self = restrict(self, RESTRICT_MIN);
 
-        let log_likelihood = saf
-            .par_iter_sites()
+        let (log_likelihood, total_weight) = (0..saf.patterns())
+            .into_par_iter()
             .fold(
-                || LogLikelihood::from(0.0),
-                |log_likelihood, site| log_likelihood + site.log_likelihood(&self),
+                || (LogLikelihood::from(0.0), 0.0),
+                |(log_likelihood, total_weight), i| {
+                    let weight = saf.get_weight(i);
+                    if weight == 0.0 {
+                        (log_likelihood, total_weight)
+                    } else {
+                        (
+                            log_likelihood + saf.get_site(i).log_likelihood(&self) * weight,
+                            total_weight + weight,
+                        )
+                    }
+                },
             )
-            .sum();
+            .reduce(
+                || (LogLikelihood::from(0.0), 0.0),
+                |a, b| (a.0 + b.0, a.1 + b.1),
+            );
 
-        SumOf::new(log_likelihood, saf.sites())
+        SumOf::with_weight(log_likelihood, saf.sites(), total_weight)
     }
 
     /// Returns the log-likelihood of the data given the SFS, and the expected number of sites
@@ -190,9 +264,31 @@ impl<const D: usize> Sfs<D> {
     /// # Panics
     ///
     /// Panics if any of the sites in the input does not fit the shape of `self`.
-    pub fn stream_e_step<R>(mut self, reader: &mut R) -> io::Result<(SumOf<LogLikelihood>, USfs<D>)>
+    pub fn stream_e_step<R>(self, reader: &mut R) -> io::Result<(SumOf<LogLikelihood>, USfs<D>)>
+    where
+        R: ReadSite,
+    {
+        self.stream_weighted_e_step(reader, |_| 1.0)
+    }
+
+    /// Returns the weighted log-likelihood of the data given the SFS, and the weighted expected
+    /// number of sites in each frequency bin given the SFS and the input.
+    ///
+    /// This is the weighted version of [`Sfs::stream_e_step`]. After each site is read, its
+    /// weight is given by calling `weight` with the reader, so that the weight may depend on the
+    /// state of the reader, e.g. the position of the site. See [`Sfs::e_step`] for more on weights.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the sites in the input does not fit the shape of `self`.
+    pub fn stream_weighted_e_step<R, F>(
+        mut self,
+        reader: &mut R,
+        mut weight: F,
+    ) -> io::Result<(SumOf<LogLikelihood>, USfs<D>)>
     where
         R: ReadSite,
+        F: FnMut(&R) -> f32,
     {
         self = restrict(self, RESTRICT_MIN);
         let mut post = USfs::zeros(self.shape);
@@ -201,14 +297,25 @@ impl<const D: usize> Sfs<D> {
         let mut site = Site::zeros(*self.shape());
 
         let mut sites = 0;
+        let mut total_weight = 0.0;
         let mut log_likelihood = LogLikelihood::from(0.0);
         while reader.read_site(&mut site)?.is_not_done() {
-            log_likelihood += site.posterior_into(&self, &mut post, &mut buf).ln();
-
             sites += 1;
+
+            let weight = f64::from(weight(reader));
+            if weight == 0.0 {
+                continue;
+            }
+            total_weight += weight;
+
+            log_likelihood +=
+                site.weighted_posterior_into(&self, &mut post, &mut buf, weight) * weight;
         }
 
-        Ok((SumOf::new(log_likelihood, sites), post))
+        Ok((
+            SumOf::with_weight(log_likelihood, sites, total_weight),
+            post,
+        ))
     }
 
     /// Returns the log-likelihood of the data given the SFS.
@@ -218,22 +325,70 @@ impl<const D: usize> Sfs<D> {
     /// # Panics
     ///
     /// Panics if any of the sites in the input does not fit the shape of `self`.
-    pub fn stream_log_likelihood<R>(mut self, reader: &mut R) -> io::Result<SumOf<LogLikelihood>>
+    pub fn stream_log_likelihood<R>(self, reader: &mut R) -> io::Result<SumOf<LogLikelihood>>
+    where
+        R: ReadSite,
+    {
+        self.stream_weighted_log_likelihood(reader, |_| 1.0)
+    }
+
+    /// Returns the weighted log-likelihood of the data given the SFS.
+    ///
+    /// This is the weighted version of [`Sfs::stream_log_likelihood`], see
+    /// [`Sfs::stream_weighted_e_step`] for details on the weights.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the sites in the input does not fit the shape of `self`.
+    pub fn stream_weighted_log_likelihood<R, F>(
+        mut self,
+        reader: &mut R,
+        mut weight: F,
+    ) -> io::Result<SumOf<LogLikelihood>>
     where
         R: ReadSite,
+        F: FnMut(&R) -> f32,
     {
         self = restrict(self, RESTRICT_MIN);
         let mut site = Site::zeros(*self.shape());
 
         let mut sites = 0;
+        let mut total_weight = 0.0;
         let mut log_likelihood = LogLikelihood::from(0.0);
         while reader.read_site(&mut site)?.is_not_done() {
-            log_likelihood += site.log_likelihood(&self);
-
             sites += 1;
+
+            let weight = f64::from(weight(reader));
+            if weight == 0.0 {
+                continue;
+            }
+            total_weight += weight;
+
+            log_likelihood += site.log_likelihood(&self) * weight;
         }
 
-        Ok(SumOf::new(log_likelihood, sites))
+        Ok(SumOf::with_weight(log_likelihood, sites, total_weight))
+    }
+
+    /// Returns the SFS restricted as in likelihood calculations.
+    ///
+    /// The likelihood methods on the SFS ensure that no value in the SFS is below a small minimum
+    /// value before calculating any likelihoods, since a site with information only in a zero part
+    /// of the SFS would otherwise have zero likelihood. The SFS is not renormalised afterwards.
+    /// This method applies the same restriction, and can be used to get consistent results when
+    /// calculating likelihoods of single sites, e.g. using [`EmSite::log_likelihood`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::{em::EmSite, saf::Site, sfs1d};
+    /// let sfs = sfs1d![1., 0., 0.].normalise();
+    /// let site = Site::new(vec![0., 1., 0.], [3]).unwrap();
+    /// assert!(f64::from(site.log_likelihood(&sfs)).is_infinite());
+    /// assert!(f64::from(site.log_likelihood(&sfs.restricted())).is_finite());
+    /// ```
+    pub fn restricted(self) -> Self {
+        restrict(self, RESTRICT_MIN)
     }
 }
 
@@ -242,7 +397,7 @@ impl<const D: usize> Sfs<D> {
 /// We have to ensure that that no value in the SFS is zero. If that happens, a situation can
 /// arise in which a site arrives with information only in the part of the SFS that is zero: this
 /// will lead to a zero posterior that cannot be normalised.
-fn restrict<const D: usize>(mut sfs: Sfs<D>, min: f64) -> Sfs<D> {
+pub(super) fn restrict<const D: usize>(mut sfs: Sfs<D>, min: f64) -> Sfs<D> {
     sfs.values.iter_mut().for_each(|v| {
         if *v < min {
             *v = min;
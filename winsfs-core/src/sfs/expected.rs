@@ -0,0 +1,557 @@
+//! Expected SFS under demographic histories.
+//!
+//! The expected 1D SFS is calculated using the coalescent with variable population size, following
+//! [Polanski and Kimmel (2003)][pk]. In this approach, the expected SFS is a linear combination
+//! of the expected times to the first coalescence in samples of decreasing size, where the weights
+//! depend only on the sample size. For a piecewise-constant history, these expected times are
+//! available in closed form.
+//!
+//! Time is measured in units of 2N₀ generations and population sizes relative to N₀, where N₀ is
+//! the (diploid) size of the population at present. The population mutation rate is θ = 4N₀μ
+//! per site. Under these conventions, a constant size history has expected SFS θ/k for k derived
+//! alleles.
+//!
+//! Note that the weights have alternating signs, and the calculation may therefore lose precision
+//! for very large samples.
+//!
+//! [pk]: https://doi.org/10.1534/genetics.103.015164
+
+use std::{error::Error, fmt};
+
+use super::{Sfs, USfs};
+
+/// A piecewise population size history.
+///
+/// The history consists of one or more epochs going backwards in time, each given by its start
+/// time and relative population size. The first epoch starts at time zero, and the last epoch
+/// extends infinitely far back in time. Within each epoch, the population size is either constant
+/// or changes exponentially, see [`Epoch::with_growth`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct History {
+    epochs: Vec<Epoch>,
+}
+
+/// A single epoch of a piecewise-constant population size history.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Epoch {
+    start: f64,
+    size: f64,
+    growth: f64,
+}
+
+impl Epoch {
+    /// Returns the exponential growth rate of the epoch.
+    pub fn growth(&self) -> f64 {
+        self.growth
+    }
+
+    /// Creates a new epoch with constant size from its start time and relative population size.
+    pub fn new(start: f64, size: f64) -> Self {
+        Self {
+            start,
+            size,
+            growth: 0.0,
+        }
+    }
+
+    /// Returns the relative population size at the start of the epoch.
+    pub fn size(&self) -> f64 {
+        self.size
+    }
+
+    /// Returns the start time of the epoch.
+    pub fn start(&self) -> f64 {
+        self.start
+    }
+
+    /// Returns the epoch with exponential growth.
+    ///
+    /// The size of the population `t` time units after the start of the epoch, going backwards in
+    /// time, is then `size * exp(-growth * t)`. In other words, a positive growth rate means that
+    /// the population has been growing towards the present during the epoch. Only non-negative
+    /// growth rates are supported.
+    pub fn with_growth(mut self, growth: f64) -> Self {
+        self.growth = growth;
+        self
+    }
+}
+
+impl History {
+    /// Returns a history with constant population size.
+    pub fn constant() -> Self {
+        Self {
+            epochs: vec![Epoch::new(0.0, 1.0)],
+        }
+    }
+
+    /// Returns the epochs of the history.
+    pub fn epochs(&self) -> &[Epoch] {
+        &self.epochs
+    }
+
+    /// Returns the expected SFS of the history.
+    ///
+    /// The returned SFS is normalised, and gives the expected proportion of sites in each bin
+    /// given the per-site population mutation rate `theta`. The expected proportion of sites with
+    /// all alleles ancestral is whatever remains after the polymorphic bins, and the expected
+    /// proportion of sites with all alleles derived is zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the shape is less than three, or if the expected proportion of
+    /// polymorphic sites exceeds one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs::expected::History;
+    /// let sfs = History::constant().expected_sfs([5], 0.01).unwrap();
+    /// let expected = [1. - 0.01 * (1. + 1. / 2. + 1. / 3.), 0.01, 0.01 / 2., 0.01 / 3., 0.];
+    /// for (v, x) in sfs.iter().zip(expected) {
+    ///     assert!((v - x).abs() < 1e-12);
+    /// }
+    /// ```
+    pub fn expected_sfs(&self, shape: [usize; 1], theta: f64) -> Result<Sfs<1>, ExpectedSfsError> {
+        let mut sfs = self.expected_mutations(shape)?.scale(theta);
+
+        let polymorphic = sfs.sum();
+        if polymorphic > 1. {
+            return Err(ExpectedSfsError::Theta { theta, polymorphic });
+        }
+        sfs[[0]] = 1. - polymorphic;
+
+        Ok(sfs.normalise())
+    }
+
+    /// Returns the expected number of mutations with each derived allele count per unit of theta.
+    ///
+    /// In other words, this is the expected polymorphic part of the SFS at `theta = 1`, and may be
+    /// scaled by any theta. The first and last values of the returned SFS are zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the shape is less than three.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs::expected::History;
+    /// let sfs = History::constant().expected_mutations([4]).unwrap();
+    /// let expected = [0., 1., 1. / 2., 0.];
+    /// for (v, x) in sfs.iter().zip(expected) {
+    ///     assert!((v - x).abs() < 1e-12);
+    /// }
+    /// ```
+    pub fn expected_mutations(&self, shape: [usize; 1]) -> Result<USfs<1>, ExpectedSfsError> {
+        let n = shape[0]
+            .checked_sub(1)
+            .filter(|&n| n >= 2)
+            .ok_or(ExpectedSfsError::Shape { shape: shape[0] })?;
+
+        let times: Vec<f64> = (2..=n).map(|k| self.first_coalescence_time(k)).collect();
+
+        let mut values = vec![0.0; n + 1];
+        for (b, value) in values.iter_mut().enumerate().take(n).skip(1) {
+            let length: f64 = weights(n, b).iter().zip(&times).map(|(w, t)| w * t).sum();
+
+            *value = length / 2.;
+        }
+
+        Ok(USfs::from_vec(values))
+    }
+
+    /// Creates a new history from its epochs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are no epochs, if the first epoch does not start at time zero, if
+    /// the start times are not strictly increasing and finite, if any size is not positive and
+    /// finite, or if any growth rate is not non-negative and finite.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::sfs::expected::{Epoch, History};
+    /// assert!(History::new(vec![Epoch::new(0., 1.), Epoch::new(0.5, 0.1)]).is_ok());
+    /// assert!(History::new(vec![Epoch::new(0.5, 1.)]).is_err());
+    /// assert!(History::new(vec![Epoch::new(0., 1.), Epoch::new(0., 2.)]).is_err());
+    /// assert!(History::new(vec![Epoch::new(0., 0.)]).is_err());
+    /// ```
+    pub fn new(epochs: Vec<Epoch>) -> Result<Self, HistoryError> {
+        match epochs.first() {
+            Some(epoch) if epoch.start == 0. => (),
+            _ => return Err(HistoryError::Start),
+        }
+
+        if let Some(epoch) = epochs
+            .iter()
+            .find(|epoch| !(epoch.size.is_finite() && epoch.size > 0.))
+        {
+            return Err(HistoryError::Size { size: epoch.size });
+        }
+
+        if let Some(epoch) = epochs
+            .iter()
+            .find(|epoch| !(epoch.growth.is_finite() && epoch.growth >= 0.))
+        {
+            return Err(HistoryError::Growth {
+                growth: epoch.growth,
+            });
+        }
+
+        if let Some(w) = epochs
+            .windows(2)
+            .find(|w| !(w[1].start.is_finite() && w[1].start > w[0].start))
+        {
+            return Err(HistoryError::Times {
+                first: w[0].start,
+                second: w[1].start,
+            });
+        }
+
+        Ok(Self { epochs })
+    }
+
+    /// Returns the expected time to the first coalescence in a sample of size `k`.
+    fn first_coalescence_time(&self, k: usize) -> f64 {
+        let rate = (k * (k - 1)) as f64 / 2.;
+
+        // Integrated coalescence intensity up to the start of the current epoch
+        let mut intensity = 0.;
+        let mut time = 0.;
+
+        for (i, epoch) in self.epochs.iter().enumerate() {
+            let duration = self
+                .epochs
+                .get(i + 1)
+                .map(|next| next.start - epoch.start)
+                .unwrap_or(f64::INFINITY);
+
+            time += (-rate * intensity).exp() * epoch.first_coalescence_time(rate, duration);
+            intensity += epoch.intensity(duration);
+        }
+
+        time
+    }
+}
+
+impl Epoch {
+    /// Returns the expected time spent in the first `duration` time units of the epoch before the
+    /// first coalescence, given that no coalescence has happened before the epoch and given the
+    /// coalescence rate at unit size.
+    fn first_coalescence_time(&self, rate: f64, duration: f64) -> f64 {
+        if self.growth == 0. {
+            return self.size / rate * -(-rate * duration / self.size).exp_m1();
+        }
+
+        // With exponential growth, the integral can be written using the exponential integral
+        let a = rate / (self.growth * self.size);
+        let head = scaled_exp_integral(a);
+
+        let tail = if duration.is_finite() {
+            let increase = a * (self.growth * duration).exp_m1();
+            (-increase).exp() * scaled_exp_integral(a + increase)
+        } else {
+            0.
+        };
+
+        (head - tail) / self.growth
+    }
+
+    /// Returns the integrated coalescence intensity over the first `duration` time units of the
+    /// epoch at unit coalescence rate.
+    fn intensity(&self, duration: f64) -> f64 {
+        if self.growth == 0. {
+            duration / self.size
+        } else {
+            (self.growth * duration).exp_m1() / (self.growth * self.size)
+        }
+    }
+}
+
+/// Returns the exponential integral E₁ at `x > 0` scaled by `exp(x)`.
+fn scaled_exp_integral(x: f64) -> f64 {
+    if x.is_infinite() {
+        0.
+    } else if x <= 1. {
+        // Power series
+        const EULER_GAMMA: f64 = 0.577_215_664_901_532_9;
+
+        let mut term = 1.;
+        let mut sum = 0.;
+        for k in 1..60 {
+            let k = k as f64;
+            term *= -x / k;
+            sum += term / k;
+        }
+
+        x.exp() * (-EULER_GAMMA - x.ln() - sum)
+    } else {
+        // Continued fraction using the modified Lentz method
+        let mut b = x + 1.;
+        let mut c = f64::MAX;
+        let mut d = 1. / b;
+        let mut h = d;
+
+        for i in 1..300 {
+            let a = -((i * i) as f64);
+            b += 2.;
+            d = 1. / (a * d + b);
+            c = b + a / c;
+            let delta = c * d;
+            h *= delta;
+
+            if (delta - 1.).abs() < f64::EPSILON {
+                break;
+            }
+        }
+
+        h
+    }
+}
+
+/// Returns the weights of the expected times to first coalescence in samples of size 2 to `n`
+/// for the SFS bin `b`, as given by Polanski and Kimmel (2003).
+fn weights(n: usize, b: usize) -> Vec<f64> {
+    let (nf, bf) = (n as f64, b as f64);
+
+    let mut weights = Vec::with_capacity(n - 1);
+    weights.push(6. / (nf + 1.));
+
+    if n >= 3 {
+        weights.push(30. * (nf - 2. * bf) / ((nf + 1.) * (nf + 2.)));
+    }
+
+    for k in 2..n - 1 {
+        let kf = k as f64;
+
+        let a = -(1. + kf) * (3. + 2. * kf) * (nf - kf) / (kf * (2. * kf - 1.) * (nf + kf + 1.));
+        let c = (3. + 2. * kf) * (nf - 2. * bf) / (kf * (nf + kf + 1.));
+
+        weights.push(a * weights[k - 2] + c * weights[k - 1]);
+    }
+
+    weights
+}
+
+/// An error associated with an invalid population size history.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HistoryError {
+    /// The history has no epochs, or the first epoch does not start at time zero.
+    Start,
+    /// An epoch has a growth rate that is not non-negative and finite.
+    Growth {
+        /// The invalid growth rate.
+        growth: f64,
+    },
+    /// An epoch has a size that is not positive and finite.
+    Size {
+        /// The invalid size.
+        size: f64,
+    },
+    /// Consecutive epochs have start times that are not strictly increasing and finite.
+    Times {
+        /// The start time of the first epoch.
+        first: f64,
+        /// The start time of the second epoch.
+        second: f64,
+    },
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::Start => write!(f, "first epoch of history must start at time zero"),
+            HistoryError::Growth { growth } => write!(
+                f,
+                "epoch growth rates must be non-negative and finite, found growth rate {growth}"
+            ),
+            HistoryError::Size { size } => write!(
+                f,
+                "epoch sizes must be positive and finite, found size {size}"
+            ),
+            HistoryError::Times { first, second } => write!(
+                f,
+                "epoch start times must be strictly increasing and finite, \
+                found time {second} after time {first}"
+            ),
+        }
+    }
+}
+
+impl Error for HistoryError {}
+
+/// An error associated with calculating an expected SFS.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpectedSfsError {
+    /// The requested shape is too small.
+    Shape {
+        /// The requested shape.
+        shape: usize,
+    },
+    /// The expected proportion of polymorphic sites exceeds one.
+    Theta {
+        /// The population mutation rate.
+        theta: f64,
+        /// The expected proportion of polymorphic sites.
+        polymorphic: f64,
+    },
+}
+
+impl fmt::Display for ExpectedSfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpectedSfsError::Shape { shape } => write!(
+                f,
+                "expected SFS requires shape at least three, found shape {shape}"
+            ),
+            ExpectedSfsError::Theta { theta, polymorphic } => write!(
+                f,
+                "expected proportion of polymorphic sites {polymorphic} exceeds one \
+                with theta {theta}"
+            ),
+        }
+    }
+}
+
+impl Error for ExpectedSfsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sfs1d;
+
+    #[test]
+    fn test_constant_matches_theta_over_k() {
+        let theta = 1e-3;
+
+        for shape in [3, 4, 11, 41, 201] {
+            let sfs = History::constant().expected_sfs([shape], theta).unwrap();
+
+            for k in 1..shape - 1 {
+                let expected = theta / k as f64;
+                assert!((sfs[[k]] - expected).abs() / expected < 1e-10);
+            }
+            assert_eq!(sfs[[shape - 1]], 0.);
+        }
+    }
+
+    #[test]
+    fn test_constant_size_scales_theta() {
+        // Doubling the size throughout is equivalent to doubling theta
+        let history = History::new(vec![Epoch::new(0., 2.)]).unwrap();
+
+        let sfs = history.expected_sfs([11], 1e-3).unwrap();
+        let scaled = History::constant().expected_sfs([11], 2e-3).unwrap();
+
+        sfs.iter()
+            .zip(scaled.iter())
+            .for_each(|(x, y)| assert!((x - y).abs() < 1e-12));
+    }
+
+    #[test]
+    fn test_piecewise_matches_direct() {
+        // Calculated directly from the expected times with k lineages, as given by Tavaré (1984)
+        let history = History::new(vec![
+            Epoch::new(0., 1.),
+            Epoch::new(0.1, 0.2),
+            Epoch::new(0.5, 3.),
+        ])
+        .unwrap();
+        let sfs = history.expected_sfs([9], 2e-3).unwrap();
+
+        let expected = sfs1d![
+            1.1045263796756248,
+            0.4948980700089367,
+            0.37348974177714134,
+            0.33026448420317756,
+            0.3076864336184835,
+            0.29299718873490505,
+            0.28230706874512446,
+        ];
+
+        sfs.iter()
+            .skip(1)
+            .zip(expected.iter())
+            .for_each(|(x, y)| assert!((x / 1e-3 - y).abs() < 1e-10));
+    }
+
+    #[test]
+    fn test_exponential_growth_matches_piecewise_constant() {
+        // Approximate exponential growth over one time unit by many short constant epochs
+        let (growth, duration, m) = (3., 1., 4000);
+
+        let exponential = History::new(vec![
+            Epoch::new(0., 1.).with_growth(growth),
+            Epoch::new(duration, 0.5),
+        ])
+        .unwrap();
+
+        let mut epochs: Vec<Epoch> = (0..m)
+            .map(|i| {
+                let start = duration * i as f64 / m as f64;
+                let midpoint = start + duration / (2 * m) as f64;
+                Epoch::new(start, (-growth * midpoint).exp())
+            })
+            .collect();
+        epochs.push(Epoch::new(duration, 0.5));
+        let piecewise = History::new(epochs).unwrap();
+
+        let x = exponential.expected_sfs([11], 1e-3).unwrap();
+        let y = piecewise.expected_sfs([11], 1e-3).unwrap();
+
+        x.iter()
+            .zip(y.iter())
+            .skip(1)
+            .take(9)
+            .for_each(|(x, y)| assert!((x - y).abs() / y < 1e-6));
+    }
+
+    #[test]
+    fn test_scaled_exp_integral() {
+        // Reference values of exp(x) * E1(x)
+        for (x, expected) in [
+            (0.1, 2.0146425447),
+            (1., 0.5963473623),
+            (1.5, 0.4482566693),
+            (10., 0.0915633339),
+        ] {
+            assert!((scaled_exp_integral(x) - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_recent_expansion_has_excess_singletons() {
+        let history = History::new(vec![Epoch::new(0., 10.), Epoch::new(0.05, 1.)]).unwrap();
+
+        let sfs = history.expected_sfs([21], 1e-3).unwrap();
+        let constant = History::constant().expected_sfs([21], 1e-3).unwrap();
+
+        let relative_singletons = |sfs: &Sfs<1>| sfs[[1]] / sfs[[2]];
+        assert!(relative_singletons(&sfs) > relative_singletons(&constant));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            History::constant().expected_sfs([2], 1e-3),
+            Err(ExpectedSfsError::Shape { shape: 2 })
+        );
+        assert!(matches!(
+            History::constant().expected_sfs([11], 1.),
+            Err(ExpectedSfsError::Theta { .. })
+        ));
+        assert_eq!(History::new(vec![]), Err(HistoryError::Start));
+        assert_eq!(
+            History::new(vec![Epoch::new(0., 1.).with_growth(-1.)]),
+            Err(HistoryError::Growth { growth: -1. })
+        );
+        assert_eq!(
+            History::new(vec![Epoch::new(0., f64::INFINITY)]),
+            Err(HistoryError::Size {
+                size: f64::INFINITY
+            })
+        );
+    }
+}
@@ -0,0 +1,748 @@
+//! Observed information and covariance of SFS estimates.
+//!
+//! The SFS is the parameter of a multinomial mixture model for the SAF likelihoods, and its
+//! maximum likelihood estimate can be found using EM. The observed information of the SFS at the
+//! estimate can then be found using the site posteriors (or, equivalently, Louis' method). Since
+//! the SFS is constrained to sum to one, the covariance of the estimate is found by inverting the
+//! information with respect to all but one free bin, see [`ObservedInformation::covariance`].
+//!
+//! Note that the observed information has size quadratic in the number of bins in the SFS, and
+//! its calculation takes time proportional to the number of sites times the squared number of
+//! bins. It is therefore only available for spectra with at most [`MAX_INFORMATION_VALUES`]
+//! values. For larger spectra, standard errors can be found from the [`MarginalInformation`],
+//! which has size and cost linear in the number of bins.
+
+use std::{error::Error, fmt, ops::Add};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::em::{EmSaf, EmSite};
+
+use super::{
+    em::{restrict, RESTRICT_MIN},
+    Sfs, USfs,
+};
+
+/// The maximum number of values in an SFS for calculating the full observed information.
+///
+/// The observed information has a row and a column for each value in the SFS, and so this
+/// corresponds to an information matrix with a million entries.
+pub const MAX_INFORMATION_VALUES: usize = 1000;
+
+impl<const D: usize> Sfs<D> {
+    /// Returns the observed information of the SFS given the data.
+    ///
+    /// The observed information is the negative Hessian of the log-likelihood with respect to the
+    /// (unconstrained) values in the SFS. If the input is weighted, the contribution of each site is
+    /// scaled by its weight.
+    ///
+    /// A [`TooLargeError`] is returned if the SFS has more than [`MAX_INFORMATION_VALUES`] values,
+    /// in which case see [`Sfs::marginal_information`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the sites in the input does not fit the shape of `self`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::{saf1d, sfs1d};
+    /// let saf = saf1d![
+    ///     [1., 0., 0.],
+    ///     [1., 0., 0.],
+    ///     [0., 1., 0.],
+    ///     [0., 0., 1.],
+    /// ];
+    /// let sfs = sfs1d![2., 1., 1.].normalise();
+    /// let information = sfs.observed_information(saf.view()).unwrap();
+    /// assert_eq!(information.get(0, 0), 2. / 0.5f64.powi(2));
+    /// assert_eq!(information.get(0, 1), 0.);
+    /// ```
+    pub fn observed_information<I>(
+        mut self,
+        saf: I,
+    ) -> Result<ObservedInformation<D>, TooLargeError<D>>
+    where
+        I: EmSaf<D>,
+    {
+        check_information_size(self.shape)?;
+        self = restrict(self, RESTRICT_MIN);
+
+        let information = (0..saf.patterns())
+            .fold(Accumulator::new(&self), |mut acc, i| {
+                acc.add_site(&self, saf.get_site(i), saf.get_weight(i));
+                acc
+            })
+            .information;
+
+        Ok(information)
+    }
+
+    /// Returns the observed information of the SFS given the data.
+    ///
+    /// This is the parallel version of [`Sfs::observed_information`]. Note that each thread holds
+    /// its own copy of the information.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the sites in the input does not fit the shape of `self`.
+    pub fn par_observed_information<I>(
+        mut self,
+        saf: I,
+    ) -> Result<ObservedInformation<D>, TooLargeError<D>>
+    where
+        I: EmSaf<D>,
+    {
+        check_information_size(self.shape)?;
+        self = restrict(self, RESTRICT_MIN);
+
+        let information = (0..saf.patterns())
+            .into_par_iter()
+            .fold(
+                || Accumulator::new(&self),
+                |mut acc, i| {
+                    acc.add_site(&self, saf.get_site(i), saf.get_weight(i));
+                    acc
+                },
+            )
+            .map(|acc| acc.information)
+            .reduce(|| ObservedInformation::zeros(self.shape), |a, b| a + b);
+
+        Ok(information)
+    }
+
+    /// Returns the marginal information of each value in the SFS given the data.
+    ///
+    /// Unlike the [`ObservedInformation`], this only requires memory and time linear in the
+    /// number of values in the SFS, and so is suitable for large spectra. See
+    /// [`MarginalInformation`] for details. If the input is weighted, the contribution of each site
+    /// is scaled by its weight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the sites in the input does not fit the shape of `self`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::{saf1d, sfs1d};
+    /// let saf = saf1d![
+    ///     [1., 0., 0.],
+    ///     [1., 0., 0.],
+    ///     [0., 1., 0.],
+    ///     [0., 0., 1.],
+    /// ];
+    /// let sfs = sfs1d![2., 1., 1.].normalise();
+    /// let standard_errors = sfs.marginal_information(saf.view()).standard_errors();
+    /// // Equal to the multinomial standard errors when sites are fully observed
+    /// assert!((standard_errors[[0]] - (0.5f64 * 0.5 / 4.).sqrt()).abs() < 1e-12);
+    /// ```
+    pub fn marginal_information<I>(mut self, saf: I) -> MarginalInformation<D>
+    where
+        I: EmSaf<D>,
+    {
+        self = restrict(self, RESTRICT_MIN);
+
+        (0..saf.patterns())
+            .fold(MarginalAccumulator::new(&self), |mut acc, i| {
+                acc.add_site(&self, saf.get_site(i), saf.get_weight(i));
+                acc
+            })
+            .information
+    }
+
+    /// Returns the marginal information of each value in the SFS given the data.
+    ///
+    /// This is the parallel version of [`Sfs::marginal_information`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the sites in the input does not fit the shape of `self`.
+    pub fn par_marginal_information<I>(mut self, saf: I) -> MarginalInformation<D>
+    where
+        I: EmSaf<D>,
+    {
+        self = restrict(self, RESTRICT_MIN);
+
+        (0..saf.patterns())
+            .into_par_iter()
+            .fold(
+                || MarginalAccumulator::new(&self),
+                |mut acc, i| {
+                    acc.add_site(&self, saf.get_site(i), saf.get_weight(i));
+                    acc
+                },
+            )
+            .map(|acc| acc.information)
+            .reduce(|| MarginalInformation::zeros(self.shape), |a, b| a + b)
+    }
+}
+
+/// Returns an error if the observed information of an SFS with the provided shape is too large.
+fn check_information_size<const D: usize>(shape: [usize; D]) -> Result<(), TooLargeError<D>> {
+    if shape.iter().product::<usize>() > MAX_INFORMATION_VALUES {
+        Err(TooLargeError { shape })
+    } else {
+        Ok(())
+    }
+}
+
+/// The observed information of an SFS.
+///
+/// The information is a symmetric matrix with a row and a column for each value in the SFS,
+/// in the same (row-major) order as the SFS values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObservedInformation<const D: usize> {
+    values: Vec<f64>,
+    shape: [usize; D],
+}
+
+impl<const D: usize> ObservedInformation<D> {
+    /// Returns the values of the information matrix as a flat, row-major slice.
+    pub fn as_slice(&self) -> &[f64] {
+        &self.values
+    }
+
+    /// Returns the covariance of the SFS estimate.
+    ///
+    /// The covariance is found by inverting the information with respect to the free parameters
+    /// of the SFS, i.e. all values but one, which is given by the constraint that the SFS sums to
+    /// one. This assumes that the SFS is a maximum likelihood estimate in the interior of the
+    /// parameter space. In particular, values estimated to be zero may give misleading results.
+    ///
+    /// A [`SingularError`] is returned if the information with respect to the free parameters is
+    /// not positive definite. This may happen if, for instance, the data has no information about
+    /// some value in the SFS.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winsfs_core::{saf1d, sfs1d};
+    /// let saf = saf1d![
+    ///     [1., 0., 0.],
+    ///     [1., 0., 0.],
+    ///     [0., 1., 0.],
+    ///     [0., 0., 1.],
+    /// ];
+    /// let sfs = sfs1d![2., 1., 1.].normalise();
+    /// let information = sfs.observed_information(saf.view()).unwrap();
+    /// let covariance = information.covariance().unwrap();
+    /// // Equal to the multinomial covariance when sites are fully observed
+    /// assert!((covariance.get(0, 0) - 0.5 * 0.5 / 4.).abs() < 1e-12);
+    /// assert!((covariance.get(0, 1) + 0.5 * 0.25 / 4.).abs() < 1e-12);
+    /// ```
+    pub fn covariance(&self) -> Result<Covariance<D>, SingularError<D>> {
+        let n = self.len();
+
+        // The choice of reference value is arbitrary in exact arithmetic, so we pick the one with
+        // the most information for numerical stability
+        let reference = (0..n)
+            .max_by(|&i, &j| self.get(i, i).total_cmp(&self.get(j, j)))
+            .expect("empty information");
+        let free = (0..n).filter(|&i| i != reference).collect::<Vec<_>>();
+
+        let r = reference;
+        let mut free_information = Vec::with_capacity(free.len() * free.len());
+        for &i in free.iter() {
+            for &j in free.iter() {
+                free_information
+                    .push(self.get(i, j) - self.get(i, r) - self.get(r, j) + self.get(r, r));
+            }
+        }
+
+        let free_covariance = invert_positive_definite(free_information, free.len())
+            .ok_or(SingularError { shape: self.shape })?;
+
+        let mut values = vec![0.0; n * n];
+        for (a, &i) in free.iter().enumerate() {
+            let mut row_sum = 0.0;
+
+            for (b, &j) in free.iter().enumerate() {
+                let v = free_covariance[a * free.len() + b];
+                values[i * n + j] = v;
+                row_sum += v;
+            }
+
+            values[i * n + r] = -row_sum;
+            values[r * n + i] = -row_sum;
+            values[r * n + r] += row_sum;
+        }
+
+        Ok(Covariance {
+            values,
+            shape: self.shape,
+        })
+    }
+
+    /// Returns the value of the information matrix for the values with flat indices `i` and `j`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the indices are out of bounds.
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        let n = self.len();
+        assert!(i < n && j < n, "index out of bounds");
+
+        self.values[i * n + j]
+    }
+
+    /// Returns the number of values in the SFS.
+    fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    /// Returns the shape of the SFS.
+    pub fn shape(&self) -> [usize; D] {
+        self.shape
+    }
+
+    /// Returns a new information matrix filled with zeros.
+    fn zeros(shape: [usize; D]) -> Self {
+        let n: usize = shape.iter().product();
+
+        Self {
+            values: vec![0.0; n * n],
+            shape,
+        }
+    }
+}
+
+impl<const D: usize> Add for ObservedInformation<D> {
+    type Output = Self;
+
+    /// Adds the information of two disjoint sets of sites.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the shapes of the SFS do not match.
+    fn add(mut self, rhs: Self) -> Self::Output {
+        assert_eq!(self.shape, rhs.shape);
+
+        self.values
+            .iter_mut()
+            .zip(rhs.values)
+            .for_each(|(x, y)| *x += y);
+
+        self
+    }
+}
+
+/// The marginal information of each value in an SFS.
+///
+/// For each value in the SFS, this is the information about that value when the remaining values
+/// are rescaled proportionally to keep the SFS summing to one. Unlike the full
+/// [`ObservedInformation`], this ignores how uncertainty about the remaining values is traded off
+/// against each other, and so the resulting standard errors are approximate. When all sites are
+/// fully observed, they equal the standard errors from the full covariance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarginalInformation<const D: usize> {
+    values: USfs<D>,
+}
+
+impl<const D: usize> MarginalInformation<D> {
+    /// Returns the values of the marginal information as a flat, row-major slice.
+    pub fn as_slice(&self) -> &[f64] {
+        self.values.as_slice()
+    }
+
+    /// Returns the shape of the SFS.
+    pub fn shape(&self) -> [usize; D] {
+        self.values.shape
+    }
+
+    /// Returns the approximate standard errors of the values in the SFS.
+    ///
+    /// The standard errors are on probability scale, and can be scaled by the number of sites
+    /// to match an SFS on count scale. Values without information have infinite standard errors.
+    pub fn standard_errors(&self) -> USfs<D> {
+        let values = self.values.iter().map(|&x| x.recip().sqrt());
+
+        USfs::from_iter_shape(values, self.shape()).expect("shape matches by construction")
+    }
+
+    /// Returns a new marginal information filled with zeros.
+    fn zeros(shape: [usize; D]) -> Self {
+        Self {
+            values: USfs::zeros(shape),
+        }
+    }
+}
+
+impl<const D: usize> Add for MarginalInformation<D> {
+    type Output = Self;
+
+    /// Adds the marginal information of two disjoint sets of sites.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the shapes of the SFS do not match.
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            values: self.values + rhs.values,
+        }
+    }
+}
+
+/// The covariance of an SFS estimate.
+///
+/// The covariance is a symmetric matrix with a row and a column for each value in the SFS,
+/// in the same (row-major) order as the SFS values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Covariance<const D: usize> {
+    values: Vec<f64>,
+    shape: [usize; D],
+}
+
+impl<const D: usize> Covariance<D> {
+    /// Returns the values of the covariance matrix as a flat, row-major slice.
+    pub fn as_slice(&self) -> &[f64] {
+        &self.values
+    }
+
+    /// Returns the covariance of the values with flat indices `i` and `j`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the indices are out of bounds.
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        let n: usize = self.shape.iter().product();
+        assert!(i < n && j < n, "index out of bounds");
+
+        self.values[i * n + j]
+    }
+
+    /// Returns the shape of the SFS.
+    pub fn shape(&self) -> [usize; D] {
+        self.shape
+    }
+
+    /// Returns the standard errors of the values in the SFS.
+    ///
+    /// The standard errors are on probability scale, and can be scaled by the number of sites
+    /// to match an SFS on count scale.
+    pub fn standard_errors(&self) -> USfs<D> {
+        let n: usize = self.shape.iter().product();
+
+        let values = (0..n).map(|i| self.get(i, i).max(0.0).sqrt());
+
+        USfs::from_iter_shape(values, self.shape).expect("shape matches by construction")
+    }
+}
+
+/// An error associated with a singular observed information matrix.
+#[derive(Clone, Debug)]
+pub struct SingularError<const D: usize> {
+    shape: [usize; D],
+}
+
+impl<const D: usize> fmt::Display for SingularError<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "observed information for SFS with shape {} is singular",
+            self.shape.map(|x| x.to_string()).join("/"),
+        )
+    }
+}
+
+impl<const D: usize> Error for SingularError<D> {}
+
+/// An error associated with an SFS too large for calculating the observed information.
+#[derive(Clone, Debug)]
+pub struct TooLargeError<const D: usize> {
+    shape: [usize; D],
+}
+
+impl<const D: usize> fmt::Display for TooLargeError<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "observed information for SFS with shape {} has {} values, \
+            more than the maximum of {MAX_INFORMATION_VALUES} values",
+            self.shape.map(|x| x.to_string()).join("/"),
+            self.shape.iter().product::<usize>(),
+        )
+    }
+}
+
+impl<const D: usize> Error for TooLargeError<D> {}
+
+/// The running state for calculating observed information.
+///
+/// Holds the information along with buffers to avoid allocating for each site.
+struct Accumulator<const D: usize> {
+    information: ObservedInformation<D>,
+    posterior: USfs<D>,
+    buf: USfs<D>,
+    scores: Vec<f64>,
+}
+
+impl<const D: usize> Accumulator<D> {
+    fn new(sfs: &Sfs<D>) -> Self {
+        Self {
+            information: ObservedInformation::zeros(sfs.shape),
+            posterior: USfs::zeros(sfs.shape),
+            buf: USfs::zeros(sfs.shape),
+            scores: vec![0.0; sfs.as_slice().len()],
+        }
+    }
+
+    fn add_site<T>(&mut self, sfs: &Sfs<D>, site: T, weight: f64)
+    where
+        T: EmSite<D>,
+    {
+        // Sites with weight zero are skipped, since the posterior of a site with zero likelihood
+        // is undefined
+        if weight == 0.0 {
+            return;
+        }
+
+        let n = self.scores.len();
+
+        self.posterior.iter_mut().for_each(|x| *x = 0.0);
+        site.posterior_into(sfs, &mut self.posterior, &mut self.buf);
+
+        // The derivative of the site log-likelihood with respect to each value in the SFS is the
+        // posterior divided by the value
+        self.scores
+            .iter_mut()
+            .zip(self.posterior.iter().zip(sfs.iter()))
+            .for_each(|(score, (posterior, sfs))| *score = posterior / sfs);
+
+        for (i, &score) in self.scores.iter().enumerate() {
+            let v = weight * score;
+
+            if v != 0.0 {
+                let row = &mut self.information.values[i * n..][..n];
+                row.iter_mut()
+                    .zip(self.scores.iter())
+                    .for_each(|(x, score)| *x += v * score);
+            }
+        }
+    }
+}
+
+/// The running state for calculating marginal information.
+///
+/// Holds the information along with buffers to avoid allocating for each site.
+struct MarginalAccumulator<const D: usize> {
+    information: MarginalInformation<D>,
+    posterior: USfs<D>,
+    buf: USfs<D>,
+}
+
+impl<const D: usize> MarginalAccumulator<D> {
+    fn new(sfs: &Sfs<D>) -> Self {
+        Self {
+            information: MarginalInformation::zeros(sfs.shape),
+            posterior: USfs::zeros(sfs.shape),
+            buf: USfs::zeros(sfs.shape),
+        }
+    }
+
+    fn add_site<T>(&mut self, sfs: &Sfs<D>, site: T, weight: f64)
+    where
+        T: EmSite<D>,
+    {
+        if weight == 0.0 {
+            return;
+        }
+
+        self.posterior.iter_mut().for_each(|x| *x = 0.0);
+        site.posterior_into(sfs, &mut self.posterior, &mut self.buf);
+
+        // When the remaining values are rescaled to keep the sum at one, the derivative of the
+        // site log-likelihood with respect to a value in the SFS compares the posterior of the
+        // value to the posterior of the remaining values
+        self.information
+            .values
+            .iter_mut()
+            .zip(self.posterior.iter().zip(sfs.iter()))
+            .for_each(|(information, (&posterior, &sfs))| {
+                let score = if sfs < 1.0 {
+                    posterior / sfs - (1.0 - posterior) / (1.0 - sfs)
+                } else {
+                    0.0
+                };
+
+                *information += weight * score * score;
+            });
+    }
+}
+
+/// Returns the inverse of a symmetric positive definite `n`x`n` matrix in row-major order.
+///
+/// Uses the Cholesky decomposition, and returns `None` if the matrix is not positive definite.
+fn invert_positive_definite(matrix: Vec<f64>, n: usize) -> Option<Vec<f64>> {
+    // Cholesky decomposition into lower triangular l, such that matrix = l * l^T
+    let mut l = vec![0.0f64; n * n];
+    for j in 0..n {
+        let d = matrix[j * n + j] - (0..j).map(|k| l[j * n + k].powi(2)).sum::<f64>();
+
+        if d.is_nan() || d <= f64::EPSILON * matrix[j * n + j] {
+            return None;
+        }
+
+        let d = d.sqrt();
+        l[j * n + j] = d;
+
+        for i in j + 1..n {
+            let s = (0..j).map(|k| l[i * n + k] * l[j * n + k]).sum::<f64>();
+            l[i * n + j] = (matrix[i * n + j] - s) / d;
+        }
+    }
+
+    // Inverse of the lower triangular l, which is itself lower triangular
+    let mut l_inv = vec![0.0; n * n];
+    for i in 0..n {
+        l_inv[i * n + i] = 1.0 / l[i * n + i];
+
+        for j in 0..i {
+            let s = (j..i).map(|k| l[i * n + k] * l_inv[k * n + j]).sum::<f64>();
+            l_inv[i * n + j] = -s / l[i * n + i];
+        }
+    }
+
+    // Inverse of matrix is then l_inv^T * l_inv
+    let mut inverse = vec![0.0; n * n];
+    for i in 0..n {
+        for j in 0..=i {
+            let v = (i..n)
+                .map(|k| l_inv[k * n + i] * l_inv[k * n + j])
+                .sum::<f64>();
+            inverse[i * n + j] = v;
+            inverse[j * n + i] = v;
+        }
+    }
+
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{saf::Saf, saf1d, sfs1d};
+
+    #[test]
+    fn test_invert_positive_definite() {
+        #[rustfmt::skip]
+        let matrix = vec![
+            4., 2., 0.6,
+            2., 5., 1.,
+            0.6, 1., 3.,
+        ];
+
+        let inverse = invert_positive_definite(matrix.clone(), 3).unwrap();
+
+        for i in 0..3 {
+            for j in 0..3 {
+                let v = (0..3)
+                    .map(|k| matrix[i * 3 + k] * inverse[k * 3 + j])
+                    .sum::<f64>();
+                let expected = if i == j { 1. } else { 0. };
+                assert!((v - expected).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invert_not_positive_definite() {
+        assert!(invert_positive_definite(vec![1., 2., 2., 1.], 2).is_none());
+        assert!(invert_positive_definite(vec![1., 1., 1., 1.], 2).is_none());
+    }
+
+    #[test]
+    fn test_covariance_fully_observed_is_multinomial() {
+        let saf = saf1d![
+            [1., 0., 0., 0.],
+            [1., 0., 0., 0.],
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+            [0., 0., 0., 1.],
+        ];
+        let sfs = sfs1d![3., 2., 1., 2.].normalise();
+
+        let covariance = sfs
+            .clone()
+            .par_observed_information(saf.view())
+            .unwrap()
+            .covariance()
+            .unwrap();
+
+        let sites = saf.sites() as f64;
+        for i in 0..4 {
+            for j in 0..4 {
+                let (pi, pj) = (sfs.as_slice()[i], sfs.as_slice()[j]);
+                let expected = if i == j { pi * (1. - pi) } else { -pi * pj } / sites;
+                assert!((covariance.get(i, j) - expected).abs() < 1e-12);
+            }
+        }
+
+        let standard_errors = covariance.standard_errors();
+        assert!((standard_errors[[0]] - (0.375f64 * 0.625 / sites).sqrt()).abs() < 1e-12);
+
+        let marginal_standard_errors = sfs.par_marginal_information(saf.view()).standard_errors();
+        for (x, y) in marginal_standard_errors.iter().zip(standard_errors.iter()) {
+            assert!((x - y).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_par_observed_information_matches_sequential() {
+        let saf = saf1d![
+            [1., 0.5, 0.1],
+            [0.2, 1., 0.3],
+            [0.1, 0.1, 1.],
+            [1., 0.9, 0.],
+        ]
+        .with_weights(vec![1., 2., 0.5, 1.])
+        .unwrap();
+        let sfs = sfs1d![5., 2., 1.].normalise();
+
+        let information = sfs.clone().observed_information(saf.view()).unwrap();
+        let par_information = sfs.clone().par_observed_information(saf.view()).unwrap();
+
+        for (x, y) in information
+            .as_slice()
+            .iter()
+            .zip(par_information.as_slice())
+        {
+            assert!((x - y).abs() < 1e-12);
+        }
+
+        let marginal = sfs.clone().marginal_information(saf.view());
+        let par_marginal = sfs.par_marginal_information(saf.view());
+
+        for (x, y) in marginal.as_slice().iter().zip(par_marginal.as_slice()) {
+            assert!((x - y).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_observed_information_too_large() {
+        let n = MAX_INFORMATION_VALUES + 1;
+        let saf = Saf::new(vec![1.; n], [n]).unwrap();
+        let sfs = Sfs::uniform([n]);
+
+        assert!(sfs.clone().observed_information(saf.view()).is_err());
+        assert!(sfs.clone().par_observed_information(saf.view()).is_err());
+        assert_eq!(sfs.marginal_information(saf.view()).shape(), [n]);
+    }
+
+    #[test]
+    fn test_covariance_singular() {
+        // The data has no information to separate the last two values
+        let saf = saf1d![[1., 0., 0.], [0., 1., 1.]];
+        let sfs = sfs1d![1., 1., 1.].normalise();
+
+        assert!(sfs
+            .observed_information(saf.view())
+            .unwrap()
+            .covariance()
+            .is_err());
+    }
+}
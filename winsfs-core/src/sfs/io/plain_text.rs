@@ -8,6 +8,14 @@
 //! In other words, the plain text format is like the format output by realSFS,
 //! except with the addition of a header line so that the SFS can be read without
 //! passing the shape separately.
+//!
+//! The header line may be followed by a space-separated `#FOLDED` flag to mark that
+//! the SFS is folded. This is informative only, and is ignored when reading.
+//!
+//! The header line may also contain a space-separated `#CONTIG=<[name]>` field giving the name
+//! of the contig the SFS was estimated from. Several SFS written one after another in this way
+//! form a multi-SFS stream, see [`write_sfs_with_header`]. Any other fields in the header line
+//! are ignored when reading.
 
 use std::{error::Error, fmt, fs::File, io, path::Path, str::FromStr};
 
@@ -62,6 +70,36 @@ where
     N: Normalisation,
 {
     let header = Header::new(sfs.shape().as_ref().to_vec().into_boxed_slice());
+    write_sfs_with_header(writer, &header, sfs)
+}
+
+/// Writes a folded SFS in plain text format to a writer.
+///
+/// This is like [`write_sfs`], except that the header is marked as folded. Note that the SFS
+/// is not folded by this function, see [`SfsBase::fold`].
+pub fn write_folded_sfs<W, S, N>(writer: &mut W, sfs: &SfsBase<S, N>) -> io::Result<()>
+where
+    W: io::Write,
+    S: Shape,
+    N: Normalisation,
+{
+    let header = Header::new(sfs.shape().as_ref().to_vec().into_boxed_slice()).folded();
+    write_sfs_with_header(writer, &header, sfs)
+}
+
+/// Writes an SFS in plain text format with the provided header to a writer.
+///
+/// The shape of the header is assumed to match the shape of the SFS.
+pub fn write_sfs_with_header<W, S, N>(
+    writer: &mut W,
+    header: &Header,
+    sfs: &SfsBase<S, N>,
+) -> io::Result<()>
+where
+    W: io::Write,
+    S: Shape,
+    N: Normalisation,
+{
     header.write(writer)?;
 
     writeln!(writer, "{}", sfs.format_flat(" ", 6))
@@ -82,14 +120,36 @@ where
 
 /// A plain text SFS header.
 #[derive(Clone, Debug)]
-struct Header {
+pub struct Header {
     shape: DynShape,
+    folded: bool,
+    contig: Option<String>,
 }
 
 impl Header {
+    /// Returns the contig name of the header, if any.
+    pub fn contig(&self) -> Option<&str> {
+        self.contig.as_deref()
+    }
+
+    /// Marks the header as folded.
+    pub fn folded(mut self) -> Self {
+        self.folded = true;
+        self
+    }
+
+    /// Returns `true` if the header is marked as folded.
+    pub fn is_folded(&self) -> bool {
+        self.folded
+    }
+
     /// Creates a new header.
     pub fn new(shape: DynShape) -> Self {
-        Self { shape }
+        Self {
+            shape,
+            folded: false,
+            contig: None,
+        }
     }
 
     /// Reads a header from a reader.
@@ -106,6 +166,22 @@ impl Header {
         Self::from_str(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
+    /// Returns the shape of the header.
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// Adds a contig name to the header.
+    ///
+    /// The name should not contain whitespace or the '>' character.
+    pub fn with_contig<S>(mut self, contig: S) -> Self
+    where
+        S: ToString,
+    {
+        self.contig = Some(contig.to_string());
+        self
+    }
+
     /// Writes a header to a stream.
     pub fn write<W>(&self, writer: &mut W) -> io::Result<()>
     where
@@ -124,7 +200,17 @@ impl fmt::Display for Header {
             .collect::<Vec<_>>()
             .join("/");
 
-        write!(f, "#SHAPE=<{shape_fmt}>")
+        write!(f, "#SHAPE=<{shape_fmt}>")?;
+
+        if self.folded {
+            f.write_str(" #FOLDED")?;
+        }
+
+        if let Some(contig) = &self.contig {
+            write!(f, " #CONTIG=<{contig}>")?;
+        }
+
+        Ok(())
     }
 }
 
@@ -132,14 +218,35 @@ impl FromStr for Header {
     type Err = ParseHeaderError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.trim_start_matches(|c: char| !c.is_numeric())
+        let mut fields = s.split_ascii_whitespace();
+
+        let mut header = fields
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches(|c: char| !c.is_numeric())
             .trim_end_matches(|c: char| !c.is_numeric())
             .split('/')
             .map(usize::from_str)
             .collect::<Result<Vec<_>, _>>()
             .map_err(|_| ParseHeaderError(String::from(s)))
             .map(Vec::into_boxed_slice)
-            .map(Header::new)
+            .map(Header::new)?;
+
+        for field in fields {
+            match field {
+                "#FOLDED" => header = header.folded(),
+                _ if field.starts_with("#CONTIG=") => match field
+                    .strip_prefix("#CONTIG=<")
+                    .and_then(|rest| rest.strip_suffix('>'))
+                {
+                    Some(contig) if !contig.is_empty() => header = header.with_contig(contig),
+                    _ => return Err(ParseHeaderError(String::from(s))),
+                },
+                _ => (),
+            }
+        }
+
+        Ok(header)
     }
 }
 
@@ -170,6 +277,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_folded_header() {
+        let header = Header::from_str("#SHAPE=<11/13> #FOLDED\n").unwrap();
+        assert_eq!(header.shape.as_ref(), &[11, 13]);
+        assert!(header.folded);
+
+        assert!(!Header::from_str("#SHAPE=<3>").unwrap().folded);
+    }
+
+    #[test]
+    fn test_parse_header_ignores_unknown_fields() {
+        let header = Header::from_str("#SHAPE=<11/13> #UNKNOWN #FOLDED\n").unwrap();
+        assert_eq!(header.shape(), &[11, 13]);
+        assert!(header.is_folded());
+
+        let header = Header::from_str("#SHAPE=<3> #UNKNOWN=<x>").unwrap();
+        assert_eq!(header.shape(), &[3]);
+        assert!(!header.is_folded());
+    }
+
+    #[test]
+    fn test_display_folded_header() {
+        assert_eq!(
+            Header::new(Box::new([7, 9])).folded().to_string(),
+            "#SHAPE=<7/9> #FOLDED"
+        );
+    }
+
+    #[test]
+    fn test_parse_contig_header() {
+        let header = Header::from_str("#SHAPE=<11/13> #FOLDED #CONTIG=<chr1>\n").unwrap();
+        assert_eq!(header.shape(), &[11, 13]);
+        assert!(header.is_folded());
+        assert_eq!(header.contig(), Some("chr1"));
+
+        assert_eq!(Header::from_str("#SHAPE=<3>").unwrap().contig(), None);
+        assert!(Header::from_str("#SHAPE=<3> #CONTIG=<>").is_err());
+        assert!(Header::from_str("#SHAPE=<3> #CONTIG=chr1").is_err());
+    }
+
+    #[test]
+    fn test_display_contig_header() {
+        assert_eq!(
+            Header::new(Box::new([7, 9]))
+                .with_contig("chr1")
+                .to_string(),
+            "#SHAPE=<7/9> #CONTIG=<chr1>"
+        );
+        assert_eq!(
+            Header::new(Box::new([7, 9]))
+                .folded()
+                .with_contig("chr1")
+                .to_string(),
+            "#SHAPE=<7/9> #FOLDED #CONTIG=<chr1>"
+        );
+    }
+
     #[test]
     fn test_display_header() {
         assert_eq!(Header::new(Box::new([25])).to_string(), "#SHAPE=<25>");